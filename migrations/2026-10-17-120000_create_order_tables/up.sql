@@ -0,0 +1,24 @@
+-- "order" is a reserved word, so the table name must always be quoted in raw SQL.
+CREATE TABLE "order" (
+    id SERIAL PRIMARY KEY,
+    session_id UUID NOT NULL,
+    subtotal BIGINT NOT NULL,
+    discount_total BIGINT NOT NULL DEFAULT 0,
+    total BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX idx_order_session ON "order"(session_id);
+
+-- One row per cart line, snapshotted at checkout so later product edits don't rewrite history.
+CREATE TABLE order_line (
+    id SERIAL PRIMARY KEY,
+    order_id INT NOT NULL REFERENCES "order"(id) ON DELETE CASCADE,
+    product_id INT REFERENCES product(id) ON DELETE SET NULL,
+    product_name TEXT NOT NULL,
+    unit_price_original BIGINT NOT NULL,
+    unit_price BIGINT NOT NULL,
+    quantity INT NOT NULL,
+    line_total BIGINT NOT NULL
+);
+CREATE INDEX idx_order_line_order ON order_line(order_id);
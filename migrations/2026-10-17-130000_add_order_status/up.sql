@@ -0,0 +1,16 @@
+-- Valid values mirror `orders::OrderStatus`; transitions are enforced in Rust.
+ALTER TABLE "order"
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'pending_payment'
+    CHECK (status IN ('pending_payment', 'paid', 'packed', 'shipped', 'delivered', 'cancelled', 'refunded'));
+CREATE INDEX idx_order_status ON "order"(status);
+
+CREATE TABLE order_status_history (
+    id SERIAL PRIMARY KEY,
+    order_id INT NOT NULL REFERENCES "order"(id) ON DELETE CASCADE,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    changed_by TEXT NOT NULL,
+    note TEXT,
+    changed_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX idx_order_status_history_order ON order_status_history(order_id);
@@ -0,0 +1,3 @@
+ALTER TABLE "order"
+    DROP COLUMN payment_provider,
+    DROP COLUMN payment_reference;
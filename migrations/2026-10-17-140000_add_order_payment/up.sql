@@ -0,0 +1,5 @@
+-- Which `pay::PaymentProvider` handled the order, and its reference for the payment (eg a Stripe PaymentIntent ID).
+ALTER TABLE "order"
+    ADD COLUMN payment_provider TEXT,
+    ADD COLUMN payment_reference TEXT;
+CREATE UNIQUE INDEX idx_order_payment_reference ON "order"(payment_provider, payment_reference);
@@ -0,0 +1,10 @@
+-- Every webhook event we've acted on, so redelivered events are ignored.
+CREATE TABLE payment_webhook_event (
+    provider TEXT NOT NULL,
+    event_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    payment_reference TEXT NOT NULL,
+    order_id INT REFERENCES "order"(id) ON DELETE SET NULL,
+    received_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY (provider, event_id)
+);
@@ -0,0 +1,7 @@
+ALTER TABLE "order"
+    DROP COLUMN shipping_total,
+    DROP COLUMN shipping_method,
+    DROP COLUMN shipping_address,
+    DROP COLUMN contact_email;
+
+ALTER TABLE session DROP COLUMN checkout_data;
@@ -0,0 +1,8 @@
+-- In-progress checkout (address, shipping method) kept next to the cart
+ALTER TABLE session ADD COLUMN checkout_data JSONB;
+
+ALTER TABLE "order"
+    ADD COLUMN contact_email TEXT,
+    ADD COLUMN shipping_address JSONB,
+    ADD COLUMN shipping_method TEXT,
+    ADD COLUMN shipping_total BIGINT NOT NULL DEFAULT 0;
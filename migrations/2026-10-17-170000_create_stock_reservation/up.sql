@@ -0,0 +1,12 @@
+-- Inventory held for a session's cart while it checks out
+CREATE TABLE stock_reservation (
+    id SERIAL PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES session(session_id) ON DELETE CASCADE,
+    product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL,
+    UNIQUE (session_id, product_id)
+);
+CREATE INDEX idx_stock_reservation_product ON stock_reservation(product_id, expires_at);
+CREATE INDEX idx_stock_reservation_expires ON stock_reservation(expires_at);
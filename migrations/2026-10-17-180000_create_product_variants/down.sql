@@ -0,0 +1,23 @@
+UPDATE session SET cart_data = (
+    SELECT COALESCE(jsonb_agg(item - 'variant_id'), '[]'::jsonb)
+    FROM jsonb_array_elements(session.cart_data) AS item
+)
+WHERE jsonb_typeof(cart_data) = 'array';
+
+DELETE FROM stock_reservation;
+ALTER TABLE stock_reservation
+    DROP COLUMN variant_id,
+    ADD COLUMN product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    ADD UNIQUE (session_id, product_id);
+CREATE INDEX idx_stock_reservation_product ON stock_reservation(product_id, expires_at);
+
+ALTER TABLE order_line
+    DROP COLUMN sku,
+    DROP COLUMN variant_id;
+
+DROP TRIGGER product_variant_inventory ON product_variant;
+DROP FUNCTION sync_product_inventory();
+DROP TABLE product_variant_value;
+DROP TABLE product_variant;
+DROP TABLE product_option_value;
+DROP TABLE product_option;
@@ -0,0 +1,81 @@
+-- Option types (eg "Size") and their values (eg "M") per product
+CREATE TABLE product_option (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    position INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (product_id, name)
+);
+
+CREATE TABLE product_option_value (
+    id SERIAL PRIMARY KEY,
+    option_id INTEGER NOT NULL REFERENCES product_option(id) ON DELETE CASCADE,
+    value TEXT NOT NULL,
+    position INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (option_id, value)
+);
+
+-- What actually gets sold and stocked. Products without options have a single default variant.
+CREATE TABLE product_variant (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    sku TEXT NOT NULL UNIQUE,
+    price BIGINT CHECK (price >= 0), -- NULL: use the product's price
+    inventory INTEGER NOT NULL DEFAULT 0 CHECK (inventory >= 0),
+    position INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX idx_product_variant_product ON product_variant(product_id);
+
+CREATE TABLE product_variant_value (
+    variant_id INTEGER NOT NULL REFERENCES product_variant(id) ON DELETE CASCADE,
+    option_value_id INTEGER NOT NULL REFERENCES product_option_value(id) ON DELETE CASCADE,
+    PRIMARY KEY (variant_id, option_value_id)
+);
+
+-- product.inventory becomes the total over its variants, kept up to date here
+CREATE FUNCTION sync_product_inventory() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') THEN
+        UPDATE product SET inventory = COALESCE(
+            (SELECT SUM(inventory) FROM product_variant WHERE product_id = OLD.product_id), 0)
+        WHERE id = OLD.product_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') THEN
+        UPDATE product SET inventory = COALESCE(
+            (SELECT SUM(inventory) FROM product_variant WHERE product_id = NEW.product_id), 0)
+        WHERE id = NEW.product_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER product_variant_inventory
+    AFTER INSERT OR DELETE OR UPDATE OF inventory, product_id ON product_variant
+    FOR EACH ROW EXECUTE FUNCTION sync_product_inventory();
+
+-- Every existing product gets a default variant holding its stock
+INSERT INTO product_variant (product_id, sku, inventory)
+SELECT id, 'P' || id, inventory FROM product ORDER BY id;
+
+-- Order lines remember which variant was sold
+ALTER TABLE order_line
+    ADD COLUMN variant_id INTEGER REFERENCES product_variant(id) ON DELETE SET NULL,
+    ADD COLUMN sku TEXT;
+UPDATE order_line l SET variant_id = v.id, sku = v.sku
+FROM product_variant v WHERE v.product_id = l.product_id;
+
+-- Reservations hold variants now (they're short-lived, so just drop what's there)
+DELETE FROM stock_reservation;
+ALTER TABLE stock_reservation
+    DROP COLUMN product_id,
+    ADD COLUMN variant_id INTEGER NOT NULL REFERENCES product_variant(id) ON DELETE CASCADE,
+    ADD UNIQUE (session_id, variant_id);
+CREATE INDEX idx_stock_reservation_variant ON stock_reservation(variant_id, expires_at);
+
+-- Carts saved in sessions point at the default variant of each product
+UPDATE session SET cart_data = (
+    SELECT COALESCE(jsonb_agg(item || jsonb_build_object('variant_id', v.id)), '[]'::jsonb)
+    FROM jsonb_array_elements(session.cart_data) AS item
+    JOIN product_variant v ON v.product_id = (item->>'product_id')::int
+)
+WHERE jsonb_typeof(cart_data) = 'array';
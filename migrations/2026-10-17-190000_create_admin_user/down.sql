@@ -0,0 +1,2 @@
+DROP TABLE admin_session;
+DROP TABLE admin_user;
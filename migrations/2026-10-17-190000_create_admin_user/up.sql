@@ -0,0 +1,18 @@
+-- Staff accounts for /admin; passwords are Argon2 PHC strings
+CREATE TABLE admin_user (
+    id SERIAL PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'staff' CHECK (role IN ('owner', 'staff', 'read_only')),
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_login_at TIMESTAMP
+);
+
+-- Logged-in admin browsers, keyed by the `admin_session` cookie
+CREATE TABLE admin_session (
+    token UUID PRIMARY KEY,
+    admin_user_id INTEGER NOT NULL REFERENCES admin_user(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP NOT NULL
+);
+CREATE INDEX idx_admin_session_expires ON admin_session(expires_at);
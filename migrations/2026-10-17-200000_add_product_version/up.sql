@@ -0,0 +1,2 @@
+-- Bumped by every admin edit, so an edit form can tell if the product changed since it was loaded
+ALTER TABLE product ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,17 @@
+-- Uploaded product photos. The files themselves live in `media::storage` under these keys:
+-- the upload as sent, plus resized copies for thumbnails and product pages.
+CREATE TABLE product_image (
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    storage_key TEXT NOT NULL,
+    thumb_key TEXT NOT NULL,
+    medium_key TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    byte_size INTEGER NOT NULL,
+    alt_text TEXT NOT NULL DEFAULT '',
+    position INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX idx_product_image_product ON product_image(product_id, position);
@@ -0,0 +1,2 @@
+DROP TABLE remote_image;
+DROP TABLE image_rendition;
@@ -0,0 +1,23 @@
+-- Responsive copies of product images (several widths, in AVIF/WebP and a JPEG/PNG fallback)
+-- for `srcset`. `source` is what they were made from: an upload's storage key, or the URL of
+-- an image hosted elsewhere (`product.thumbnail_url`/`gallery_urls`).
+CREATE TABLE image_rendition (
+    id SERIAL PRIMARY KEY,
+    source TEXT NOT NULL,
+    format TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    storage_key TEXT NOT NULL,
+    byte_size INTEGER NOT NULL,
+    UNIQUE (source, format, width)
+);
+
+-- Each external image URL the ingester has tried to fetch, so it isn't fetched again
+-- (failures are retried after a while).
+CREATE TABLE remote_image (
+    url TEXT PRIMARY KEY,
+    width INTEGER,
+    height INTEGER,
+    error TEXT,
+    fetched_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,18 @@
+ALTER TABLE product ADD COLUMN tags TEXT, ADD COLUMN keywords TEXT, ADD COLUMN gallery_urls TEXT;
+
+UPDATE product SET tags = (
+    SELECT string_agg(tag.name, ',' ORDER BY tag.name)
+    FROM product_tag JOIN tag ON tag.id = product_tag.tag_id
+    WHERE product_tag.product_id = product.id
+);
+UPDATE product SET keywords = (
+    SELECT string_agg(keyword, ',' ORDER BY keyword) FROM product_keyword WHERE product_id = product.id
+);
+UPDATE product SET gallery_urls = (
+    SELECT string_agg(url, ',' ORDER BY position) FROM product_gallery_url WHERE product_id = product.id
+);
+
+DROP TABLE product_gallery_url;
+DROP TABLE product_keyword;
+DROP TABLE product_tag;
+DROP TABLE tag;
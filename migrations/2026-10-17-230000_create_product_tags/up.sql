@@ -0,0 +1,51 @@
+-- Tags, search keywords and gallery URLs move out of the comma separated columns on product.
+-- Tags are shared: names are unique regardless of case, and a product links to each once.
+CREATE TABLE tag (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL CHECK (name <> '')
+);
+CREATE UNIQUE INDEX idx_tag_name ON tag (lower(name));
+
+CREATE TABLE product_tag (
+    product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    tag_id INTEGER NOT NULL REFERENCES tag(id) ON DELETE CASCADE,
+    PRIMARY KEY (product_id, tag_id)
+);
+CREATE INDEX idx_product_tag_tag ON product_tag(tag_id);
+
+CREATE TABLE product_keyword (
+    product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    keyword TEXT NOT NULL CHECK (keyword <> ''),
+    PRIMARY KEY (product_id, keyword)
+);
+
+CREATE TABLE product_gallery_url (
+    product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (product_id, position)
+);
+
+-- Split the existing lists. The first spelling of a tag (by product ID) names it.
+INSERT INTO tag (name)
+SELECT DISTINCT ON (lower(t.name)) t.name
+FROM product p, LATERAL (SELECT trim(item) AS name FROM unnest(string_to_array(p.tags, ',')) AS item) t
+WHERE t.name <> ''
+ORDER BY lower(t.name), p.id;
+
+INSERT INTO product_tag (product_id, tag_id)
+SELECT DISTINCT p.id, tag.id
+FROM product p, unnest(string_to_array(p.tags, ',')) AS item
+JOIN tag ON lower(tag.name) = lower(trim(item));
+
+INSERT INTO product_keyword (product_id, keyword)
+SELECT DISTINCT p.id, trim(item)
+FROM product p, unnest(string_to_array(p.keywords, ',')) AS item
+WHERE trim(item) <> '';
+
+INSERT INTO product_gallery_url (product_id, position, url)
+SELECT p.id, row_number() OVER (PARTITION BY p.id ORDER BY g.n) - 1, trim(g.item)
+FROM product p, unnest(string_to_array(p.gallery_urls, ',')) WITH ORDINALITY AS g(item, n)
+WHERE trim(g.item) <> '';
+
+ALTER TABLE product DROP COLUMN tags, DROP COLUMN keywords, DROP COLUMN gallery_urls;
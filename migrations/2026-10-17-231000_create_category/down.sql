@@ -0,0 +1,5 @@
+ALTER TABLE product ADD COLUMN category TEXT NOT NULL DEFAULT 'Uncategorized';
+UPDATE product SET category = category.name FROM category WHERE category.id = product.category_id;
+ALTER TABLE product ALTER COLUMN category DROP DEFAULT;
+ALTER TABLE product DROP COLUMN category_id;
+DROP TABLE category;
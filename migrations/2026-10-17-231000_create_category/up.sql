@@ -0,0 +1,33 @@
+-- Categories form a tree; products point at one by ID, so renaming or moving a category
+-- doesn't touch product rows. Slugs name them in URLs (/c/{slug}).
+CREATE TABLE category (
+    id SERIAL PRIMARY KEY,
+    parent_id INTEGER REFERENCES category(id) ON DELETE RESTRICT CHECK (parent_id <> id),
+    name TEXT NOT NULL CHECK (name <> ''),
+    slug TEXT NOT NULL UNIQUE CHECK (slug ~ '^[a-z0-9]+(-[a-z0-9]+)*$'),
+    description TEXT,
+    image_url TEXT,
+    position INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX idx_category_parent ON category(parent_id);
+
+-- The old free-text categories become top-level ones. Names that slug the same
+-- (eg "Drinks" and "drinks!") share a category.
+INSERT INTO category (name, slug)
+SELECT DISTINCT ON (slug) name, slug
+FROM (
+    SELECT category AS name,
+           COALESCE(NULLIF(trim(both '-' FROM regexp_replace(lower(category), '[^a-z0-9]+', '-', 'g')), ''), 'category') AS slug
+    FROM product
+    UNION ALL
+    SELECT 'Uncategorized', 'uncategorized'
+) names
+ORDER BY slug, name;
+
+ALTER TABLE product ADD COLUMN category_id INTEGER REFERENCES category(id) ON DELETE RESTRICT;
+UPDATE product SET category_id = category.id
+FROM category
+WHERE category.slug = COALESCE(NULLIF(trim(both '-' FROM regexp_replace(lower(product.category), '[^a-z0-9]+', '-', 'g')), ''), 'category');
+ALTER TABLE product ALTER COLUMN category_id SET NOT NULL;
+ALTER TABLE product DROP COLUMN category;
+CREATE INDEX idx_product_category ON product(category_id);
@@ -0,0 +1,2 @@
+DROP TABLE product_slug_history;
+ALTER TABLE product DROP COLUMN slug;
@@ -0,0 +1,29 @@
+-- Products are named in URLs by slug (/products/{slug}). Old slugs stay in
+-- product_slug_history so links to them redirect after a rename.
+ALTER TABLE product ADD COLUMN slug TEXT;
+
+-- From the name; all-digit slugs would read as IDs, and repeats get the ID appended
+WITH made AS (
+    SELECT id, trim(both '-' FROM regexp_replace(lower(name), '[^a-z0-9]+', '-', 'g')) AS slug
+    FROM product
+), usable AS (
+    SELECT id, CASE WHEN slug ~ '[a-z]' THEN slug ELSE trim(both '-' FROM 'product-' || slug) END AS slug
+    FROM made
+), numbered AS (
+    SELECT id, slug, row_number() OVER (PARTITION BY slug ORDER BY id) AS n
+    FROM usable
+)
+UPDATE product SET slug = CASE WHEN numbered.n = 1 THEN numbered.slug ELSE numbered.slug || '-' || product.id END
+FROM numbered
+WHERE numbered.id = product.id;
+
+ALTER TABLE product ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE product ADD CONSTRAINT product_slug_key UNIQUE (slug);
+ALTER TABLE product ADD CONSTRAINT product_slug_format CHECK (slug ~ '^[a-z0-9]+(-[a-z0-9]+)*$' AND slug ~ '[a-z]');
+
+CREATE TABLE product_slug_history (
+    slug TEXT PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES product(id) ON DELETE CASCADE,
+    retired_at TIMESTAMP NOT NULL DEFAULT now()
+);
+CREATE INDEX idx_product_slug_history_product ON product_slug_history(product_id);
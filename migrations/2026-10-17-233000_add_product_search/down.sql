@@ -0,0 +1,8 @@
+DROP TRIGGER product_keyword_delete ON product_keyword;
+DROP TRIGGER product_keyword_update ON product_keyword;
+DROP TRIGGER product_keyword_insert ON product_keyword;
+DROP FUNCTION product_keyword_search_vector();
+DROP TRIGGER product_search_vector_update ON product;
+DROP FUNCTION product_search_vector_row();
+ALTER TABLE product DROP COLUMN search_vector;
+DROP FUNCTION product_search_vector(INTEGER, TEXT, TEXT, TEXT);
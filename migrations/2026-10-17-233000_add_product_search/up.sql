@@ -0,0 +1,46 @@
+-- Full-text search over products. The document weighs the name most, then the tagline,
+-- keywords and description. Keywords live in their own table, so triggers on both
+-- tables keep it current.
+ALTER TABLE product ADD COLUMN search_vector tsvector NOT NULL DEFAULT ''::tsvector;
+
+CREATE FUNCTION product_search_vector(product_id_val INTEGER, name_val TEXT, tagline_val TEXT, description_val TEXT)
+RETURNS tsvector LANGUAGE sql STABLE AS $$
+    SELECT setweight(to_tsvector('english', coalesce(name_val, '')), 'A')
+        || setweight(to_tsvector('english', coalesce(tagline_val, '')), 'B')
+        || setweight(to_tsvector('english', coalesce(
+               (SELECT string_agg(keyword, ' ') FROM product_keyword WHERE product_id = product_id_val), '')), 'C')
+        || setweight(to_tsvector('english', coalesce(description_val, '')), 'D')
+$$;
+
+CREATE FUNCTION product_search_vector_row() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    NEW.search_vector := product_search_vector(NEW.id, NEW.name, NEW.tagline, NEW.description);
+    RETURN NEW;
+END
+$$;
+
+CREATE TRIGGER product_search_vector_update
+BEFORE INSERT OR UPDATE OF name, tagline, description ON product
+FOR EACH ROW EXECUTE FUNCTION product_search_vector_row();
+
+-- Once per statement: saving a product replaces all of its keywords at once
+CREATE FUNCTION product_keyword_search_vector() RETURNS trigger LANGUAGE plpgsql AS $$
+BEGIN
+    UPDATE product SET search_vector = product_search_vector(id, name, tagline, description)
+    WHERE id IN (SELECT product_id FROM changed);
+    RETURN NULL;
+END
+$$;
+
+CREATE TRIGGER product_keyword_insert AFTER INSERT ON product_keyword
+REFERENCING NEW TABLE AS changed
+FOR EACH STATEMENT EXECUTE FUNCTION product_keyword_search_vector();
+CREATE TRIGGER product_keyword_update AFTER UPDATE ON product_keyword
+REFERENCING NEW TABLE AS changed
+FOR EACH STATEMENT EXECUTE FUNCTION product_keyword_search_vector();
+CREATE TRIGGER product_keyword_delete AFTER DELETE ON product_keyword
+REFERENCING OLD TABLE AS changed
+FOR EACH STATEMENT EXECUTE FUNCTION product_keyword_search_vector();
+
+UPDATE product SET search_vector = product_search_vector(id, name, tagline, description);
+CREATE INDEX idx_product_search ON product USING GIN (search_vector);
@@ -0,0 +1,4 @@
+DROP INDEX idx_category_name_trgm;
+DROP INDEX idx_tag_name_trgm;
+DROP INDEX idx_product_name_trgm;
+-- pg_trgm stays: other databases' objects may use it
@@ -0,0 +1,6 @@
+-- Trigram indexes for typo-tolerant suggestions ("did you mean") over the names
+-- shoppers search for: products, tags and categories.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX idx_product_name_trgm ON product USING GIN (name gin_trgm_ops);
+CREATE INDEX idx_tag_name_trgm ON tag USING GIN (name gin_trgm_ops);
+CREATE INDEX idx_category_name_trgm ON category USING GIN (name gin_trgm_ops);
@@ -0,0 +1,2 @@
+DROP INDEX product_sale_price_idx;
+ALTER TABLE product DROP COLUMN sale_price;
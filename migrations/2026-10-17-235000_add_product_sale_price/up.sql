@@ -0,0 +1,10 @@
+-- What a product sells for after its discount, for sorting and filtering by price. It has
+-- to round like `Price::with_discount_percent`: in float8, half away from zero.
+ALTER TABLE product ADD COLUMN sale_price BIGINT GENERATED ALWAYS AS (
+    CASE WHEN discount_percent > 0 THEN
+        trunc(price * ((100 - discount_percent::float8) / 100)
+              + sign(price * ((100 - discount_percent::float8) / 100)) * 0.5)::bigint
+    ELSE price END
+) STORED;
+
+CREATE INDEX product_sale_price_idx ON product (sale_price, id);
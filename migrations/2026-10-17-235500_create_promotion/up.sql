@@ -0,0 +1,21 @@
+-- Sales that run for a while: a percentage or a fixed amount off, for one product, a
+-- category (and its subcategories), a tag, or the whole store when no target is set.
+-- Where several apply, the highest priority wins; stackable ones combine with each other.
+-- See `promotions::promoted_price` for how prices are worked out.
+CREATE TABLE promotion (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL CHECK (name <> ''),
+    description TEXT,
+    starts_at TIMESTAMP NOT NULL,
+    ends_at TIMESTAMP CHECK (ends_at > starts_at),
+    discount_percent REAL CHECK (discount_percent > 0 AND discount_percent <= 100),
+    discount_cents BIGINT CHECK (discount_cents > 0),
+    product_id INTEGER REFERENCES product(id) ON DELETE CASCADE,
+    category_id INTEGER REFERENCES category(id) ON DELETE CASCADE,
+    tag_id INTEGER REFERENCES tag(id) ON DELETE CASCADE,
+    priority INTEGER NOT NULL DEFAULT 0,
+    stackable BOOLEAN NOT NULL DEFAULT FALSE,
+    CHECK ((discount_percent IS NULL) <> (discount_cents IS NULL)),
+    CHECK (num_nonnulls(product_id, category_id, tag_id) <= 1)
+);
+CREATE INDEX idx_promotion_running ON promotion(starts_at, ends_at);
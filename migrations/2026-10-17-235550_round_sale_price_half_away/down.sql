@@ -0,0 +1,11 @@
+DROP INDEX product_sale_price_idx;
+ALTER TABLE product DROP COLUMN sale_price;
+ALTER TABLE product ADD COLUMN sale_price BIGINT GENERATED ALWAYS AS (
+    CASE WHEN discount_percent > 0 THEN
+        trunc(price * ((100 - discount_percent::float8) / 100)
+              + sign(price * ((100 - discount_percent::float8) / 100)) * 0.5)::bigint
+    ELSE price END
+) STORED;
+
+CREATE INDEX product_sale_price_idx ON product (sale_price, id);
+DROP FUNCTION round_half_away(float8);
@@ -0,0 +1,19 @@
+-- Rounds like Rust's `f64::round` (half away from zero), exactly: `trunc(x + 0.5)` can round
+-- up values just under a half, as adding the half is itself rounded.
+CREATE FUNCTION round_half_away(x float8) RETURNS float8
+    LANGUAGE sql IMMUTABLE STRICT PARALLEL SAFE
+    RETURN sign(x) * (floor(abs(x)) + CASE WHEN abs(x) - floor(abs(x)) >= 0.5 THEN 1 ELSE 0 END);
+
+-- What a product's base price sells for after its own discount, for sorting and filtering by
+-- price. Variants with a price of their own aren't covered; a generated column can only see
+-- its row. It has to round like `Price::with_discount_percent`: in float8, half away from zero.
+-- A generated column's expression can't be changed in place, so it's added again.
+DROP INDEX product_sale_price_idx;
+ALTER TABLE product DROP COLUMN sale_price;
+ALTER TABLE product ADD COLUMN sale_price BIGINT GENERATED ALWAYS AS (
+    CASE WHEN discount_percent > 0 THEN
+        round_half_away(price * ((100 - discount_percent::float8) / 100))::bigint
+    ELSE price END
+) STORED;
+
+CREATE INDEX product_sale_price_idx ON product (sale_price, id);
@@ -0,0 +1,2 @@
+DROP FUNCTION promoted_price(integer, integer, bigint, real, timestamp);
+DROP FUNCTION apply_discount(bigint, real, bigint);
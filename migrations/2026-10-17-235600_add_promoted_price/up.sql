@@ -0,0 +1,52 @@
+-- A percentage or fixed amount off `price`, as `promotions::Discount::apply` does it
+CREATE FUNCTION apply_discount(price bigint, percent real, cents bigint) RETURNS bigint
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE
+    RETURN CASE
+        WHEN percent > 0 THEN round_half_away(price * ((100 - percent::float8) / 100))::bigint
+        WHEN percent IS NULL THEN greatest(price - coalesce(cents, 0), 0)
+        ELSE price
+    END;
+
+-- What a product sells for at `at`, with the promotions running then: the same rules as
+-- `promotions::promoted_price`, for sorting and filtering by price while a sale is on.
+-- Looks promotions up for each row, so use `product.sale_price` when none are running.
+CREATE FUNCTION promoted_price(
+    product_id_val integer,
+    category_id_val integer,
+    list bigint,
+    own_percent real,
+    at timestamp
+) RETURNS bigint LANGUAGE plpgsql STABLE PARALLEL SAFE AS $$
+DECLARE
+    own bigint := apply_discount(list, own_percent, 0);
+    promoted bigint;
+    p record;
+BEGIN
+    FOR p IN
+        WITH RECURSIVE ancestor(id) AS (
+            SELECT category_id_val
+            UNION
+            SELECT c.parent_id FROM category c JOIN ancestor a ON c.id = a.id WHERE c.parent_id IS NOT NULL
+        )
+        SELECT promotion.* FROM promotion
+        WHERE starts_at <= at AND (ends_at IS NULL OR at < ends_at)
+          AND (num_nonnulls(product_id, category_id, tag_id) = 0
+               OR product_id = product_id_val
+               OR category_id IN (SELECT id FROM ancestor)
+               OR tag_id IN (SELECT tag_id FROM product_tag WHERE product_tag.product_id = product_id_val))
+        ORDER BY priority DESC, id
+    LOOP
+        IF promoted IS NULL AND NOT p.stackable THEN
+            -- The first decides: this one alone, on the list price
+            promoted := apply_discount(list, p.discount_percent, p.discount_cents);
+            EXIT;
+        ELSIF promoted IS NULL THEN
+            promoted := own;
+        END IF;
+        IF p.stackable THEN
+            promoted := apply_discount(promoted, p.discount_percent, p.discount_cents);
+        END IF;
+    END LOOP;
+    RETURN least(coalesce(promoted, own), own);
+END;
+$$;
@@ -0,0 +1,3 @@
+ALTER TABLE "order"
+    DROP COLUMN refund_key,
+    DROP COLUMN refund_requested_at;
@@ -0,0 +1,5 @@
+-- A refund asked of the payment provider but maybe not recorded yet: the idempotency key it was
+-- sent with, so retrying after a failure or a second click can't give the money back twice.
+ALTER TABLE "order"
+    ADD COLUMN refund_key TEXT,
+    ADD COLUMN refund_requested_at TIMESTAMP;
@@ -0,0 +1,280 @@
+//! Admin accounts: roles, Argon2 password hashing, and the guard in front of `/admin`.
+//! Accounts and logins are stored in `admin_user` / `admin_session`; see `db::admin_users`.
+//! `require_admin` wraps the admin scope and handlers take an `AdminIdentity` to see who's in.
+
+use actix_web::body::BoxBody;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, Method};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use futures_util::future::{ready, FutureExt, LocalBoxFuture, Ready};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use uuid::Uuid;
+use crate::db::{admin_users, DbPool};
+use crate::errors::BeedleError;
+
+/// Cookie holding the `admin_session` token; only sent to /admin pages.
+pub const ADMIN_COOKIE: &str = "admin_session";
+
+pub const MIN_PASSWORD_LEN: usize = 10;
+
+/// owner > staff > read_only. Stored as TEXT in `admin_user.role`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Everything, including managing admin accounts
+    Owner,
+    /// Day-to-day running of the shop: products, variants, orders
+    Staff,
+    /// Can look at everything but change nothing
+    ReadOnly,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Owner, Role::Staff, Role::ReadOnly];
+
+    /// Value as stored in the DB / submitted by forms, eg "read_only"
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Staff => "staff",
+            Role::ReadOnly => "read_only",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Owner => "Owner",
+            Role::Staff => "Staff",
+            Role::ReadOnly => "Read-only",
+        }
+    }
+
+    /// May submit forms that change the shop (anything but GET)
+    pub fn can_edit(self) -> bool {
+        matches!(self, Role::Owner | Role::Staff)
+    }
+
+    pub fn can_manage_users(self) -> bool {
+        self == Role::Owner
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or_else(|| format!("Unknown role: {s}"))
+    }
+}
+
+impl ToSql<Text, Pg> for Role {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for Role {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let raw = <String as FromSql<Text, Pg>>::from_sql(bytes)?;
+        raw.parse().map_err(Into::into)
+    }
+}
+
+/// Argon2id with the crate's default parameters, as a PHC string (salt included).
+pub fn hash_password(password: &str) -> Result<String, BeedleError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| BeedleError::AccountError(format!("Hashing password failed: {e}")))
+}
+
+/// False for a wrong password and for a malformed stored hash alike.
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(e) => {
+            log::error!("Stored password hash is malformed: {e}");
+            false
+        }
+    }
+}
+
+/// The admin making this request. Put into the request by `require_admin`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminIdentity {
+    pub id: i32,
+    pub username: String,
+    pub role: Role,
+    pub role_label: &'static str,
+    pub can_edit: bool,
+    pub can_manage_users: bool,
+}
+
+impl AdminIdentity {
+    fn new(user: &crate::models::AdminUser) -> Self {
+        AdminIdentity {
+            id: user.id,
+            username: user.username.clone(),
+            role: user.role,
+            role_label: user.role.label(),
+            can_edit: user.role.can_edit(),
+            can_manage_users: user.role.can_manage_users(),
+        }
+    }
+
+    /// For `order_status_history.changed_by` and the like, eg "admin:alice"
+    pub fn actor(&self) -> String {
+        format!("admin:{}", self.username)
+    }
+
+    pub fn require_owner(&self) -> Result<(), BeedleError> {
+        if self.can_manage_users {
+            Ok(())
+        } else {
+            log::warn!("{} ({}) tried to manage admin accounts", self.username, self.role);
+            Err(BeedleError::Forbidden("Only owners can manage admin accounts".into()))
+        }
+    }
+}
+
+impl actix_web::FromRequest for AdminIdentity {
+    type Error = BeedleError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        // Only missing if a handler was registered outside the guarded scope
+        ready(
+            req.extensions()
+                .get::<AdminIdentity>()
+                .cloned()
+                .ok_or_else(|| BeedleError::AuthError("Not logged in".into())),
+        )
+    }
+}
+
+/// Where the login page may send someone back to: only admin pages, never another site.
+pub fn safe_admin_redirect(next: Option<&str>) -> &str {
+    match next {
+        Some(path) if (path == "/admin" || path.starts_with("/admin/")) && !path.contains("//") => path,
+        _ => "/admin/products",
+    }
+}
+
+/// Guard for the `/admin` scope (use with `Scope::wrap_fn`):
+/// - no valid `admin_session` cookie: redirect to the login page
+/// - read-only account sending anything but GET/HEAD: 403
+/// - otherwise: stash an `AdminIdentity` for the handlers and carry on
+pub fn require_admin<S>(req: ServiceRequest, srv: &S) -> LocalBoxFuture<'static, Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    let token = req.cookie(ADMIN_COOKIE).and_then(|c| Uuid::parse_str(c.value()).ok());
+    let user = match (token, req.app_data::<web::Data<DbPool>>()) {
+        (Some(token), Some(pool)) => pool
+            .get()
+            .map_err(BeedleError::from)
+            .and_then(|mut conn| admin_users::find_admin_by_session(&mut conn, token))
+            .map(|user| user.as_ref().map(AdminIdentity::new)),
+        (None, _) => Ok(None),
+        (Some(_), None) => Err(BeedleError::ConfigError("DB pool missing in app_data!".into())),
+    };
+
+    match user {
+        Ok(Some(admin)) if admin.can_edit || matches!(*req.method(), Method::GET | Method::HEAD) => {
+            req.extensions_mut().insert(admin);
+            srv.call(req).boxed_local()
+        }
+        Ok(Some(admin)) => {
+            log::warn!("Read-only admin {} tried {} {}", admin.username, req.method(), req.path());
+            let resp = HttpResponse::Forbidden().body("Your account is read-only");
+            ready(Ok(req.into_response(resp))).boxed_local()
+        }
+        Ok(None) => {
+            log::info!("Unauthenticated request for {}; redirecting to login", req.path());
+            let location = format!("/admin/login?next={}", urlencoding::encode(req.path()));
+            let resp = HttpResponse::SeeOther().append_header((header::LOCATION, location)).finish();
+            ready(Ok(req.into_response(resp))).boxed_local()
+        }
+        Err(e) => ready(Err(e.into())).boxed_local(),
+    }
+}
+
+/// Sets (or with `None`, clears) the admin login cookie on a response.
+pub fn set_admin_cookie(mut res: HttpResponse, token: Option<Uuid>, max_age_hours: i64) -> HttpResponse {
+    // .secure(true) should be enabled in production (HTTPS).
+    let mut cookie = Cookie::build(ADMIN_COOKIE, token.map(|t| t.to_string()).unwrap_or_default())
+        .path("/admin")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        // .secure(true) // prod only
+        .finish();
+    match token {
+        Some(_) => cookie.set_max_age(actix_web::cookie::time::Duration::hours(max_age_hours)),
+        None => cookie.make_removal(),
+    }
+    if let Err(e) = res.add_cookie(&cookie) {
+        log::error!("Adding admin_session cookie failed: {e}");
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_hash_roundtrip() {
+        let hash = hash_password("correct horse battery").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse battery", &hash));
+        assert!(!verify_password("correct horse battery!", &hash));
+        assert!(!verify_password("anything", "not a hash"));
+        // Salted: the same password hashes differently each time
+        assert_ne!(hash, hash_password("correct horse battery").unwrap());
+    }
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(Role::Owner.can_edit() && Role::Owner.can_manage_users());
+        assert!(Role::Staff.can_edit() && !Role::Staff.can_manage_users());
+        assert!(!Role::ReadOnly.can_edit() && !Role::ReadOnly.can_manage_users());
+        for role in Role::ALL {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+        }
+    }
+
+    #[test]
+    fn test_login_redirect_stays_in_admin() {
+        assert_eq!(safe_admin_redirect(Some("/admin/orders/3")), "/admin/orders/3");
+        assert_eq!(safe_admin_redirect(Some("https://evil.example/admin")), "/admin/products");
+        assert_eq!(safe_admin_redirect(Some("//evil.example")), "/admin/products");
+        assert_eq!(safe_admin_redirect(Some("/admin//evil.example")), "/admin/products");
+        assert_eq!(safe_admin_redirect(Some("/administrator")), "/admin/products");
+        assert_eq!(safe_admin_redirect(None), "/admin/products");
+    }
+}
@@ -0,0 +1,280 @@
+//! Multi-step checkout: contact/shipping address -> shipping method -> review -> pay.
+//! The in-progress `CheckoutState` lives on the `session` row (`checkout_data`) next to the cart;
+//! see `routes::checkout` for the pages and `db::session::update_session_checkout` for persistence.
+
+use crate::price::Price;
+use crate::validation::{optional, required, FieldErrors};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Where (and to whom) an order ships. Validated; see `AddressInput::validate`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShippingAddress {
+    pub email: String,
+    pub full_name: String,
+    pub phone: Option<String>,
+    pub line1: String,
+    pub line2: Option<String>,
+    pub city: String,
+    pub region: Option<String>,
+    pub postal_code: String,
+    pub country: String,
+}
+
+/// Raw address form fields, kept as typed so the form can be re-rendered on error.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AddressInput {
+    pub email: String,
+    pub full_name: String,
+    pub phone: String,
+    pub line1: String,
+    pub line2: String,
+    pub city: String,
+    pub region: String,
+    pub postal_code: String,
+    pub country: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShippingMethod {
+    Standard,
+    Express,
+    /// Collect in person; still needs contact details but costs nothing
+    Pickup,
+}
+
+/// Checkout progress saved between steps. Fields fill in as the shopper moves forward.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CheckoutState {
+    pub address: Option<ShippingAddress>,
+    pub shipping_method: Option<ShippingMethod>,
+    /// Why the last payment attempt failed; shown on the review page until the next attempt
+    pub payment_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckoutStep {
+    Address,
+    Shipping,
+    Review,
+}
+
+/// Deliberately loose: something@something.tld, no spaces. The payment provider/email
+/// delivery is the real check.
+fn looks_like_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.contains(char::is_whitespace)
+                && !domain.contains('@')
+        }
+        None => false,
+    }
+}
+
+impl AddressInput {
+    pub fn validate(&self) -> Result<ShippingAddress, FieldErrors> {
+        let mut errors = FieldErrors::new();
+
+        let email = required(&mut errors, "email", &self.email, 254);
+        if !errors.contains_key("email") && !looks_like_email(&email) {
+            errors.insert("email", "Enter a valid email address".to_owned());
+        }
+        let address = ShippingAddress {
+            email,
+            full_name: required(&mut errors, "full_name", &self.full_name, 200),
+            phone: optional(&mut errors, "phone", &self.phone, 40),
+            line1: required(&mut errors, "line1", &self.line1, 200),
+            line2: optional(&mut errors, "line2", &self.line2, 200),
+            city: required(&mut errors, "city", &self.city, 100),
+            region: optional(&mut errors, "region", &self.region, 100),
+            postal_code: required(&mut errors, "postal_code", &self.postal_code, 20),
+            country: required(&mut errors, "country", &self.country, 100),
+        };
+
+        if errors.is_empty() {
+            Ok(address)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl From<&ShippingAddress> for AddressInput {
+    fn from(address: &ShippingAddress) -> Self {
+        AddressInput {
+            email: address.email.clone(),
+            full_name: address.full_name.clone(),
+            phone: address.phone.clone().unwrap_or_default(),
+            line1: address.line1.clone(),
+            line2: address.line2.clone().unwrap_or_default(),
+            city: address.city.clone(),
+            region: address.region.clone().unwrap_or_default(),
+            postal_code: address.postal_code.clone(),
+            country: address.country.clone(),
+        }
+    }
+}
+
+impl ShippingMethod {
+    pub const ALL: [ShippingMethod; 3] = [ShippingMethod::Standard, ShippingMethod::Express, ShippingMethod::Pickup];
+
+    /// Standard shipping is free from this (discounted) cart total up
+    pub const FREE_STANDARD_THRESHOLD: Price = Price::from_cents(5000);
+
+    /// Value as stored in the DB / submitted by forms, eg "express"
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ShippingMethod::Standard => "standard",
+            ShippingMethod::Express => "express",
+            ShippingMethod::Pickup => "pickup",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ShippingMethod::Standard => "Standard shipping (3-5 business days)",
+            ShippingMethod::Express => "Express shipping (1-2 business days)",
+            ShippingMethod::Pickup => "Local pickup",
+        }
+    }
+
+    /// Shipping charge for a cart whose items (after discounts) total `items_total`.
+    pub fn cost(self, items_total: Price) -> Price {
+        match self {
+            ShippingMethod::Standard if items_total >= Self::FREE_STANDARD_THRESHOLD => Price::default(),
+            ShippingMethod::Standard => Price::from_cents(500),
+            ShippingMethod::Express => Price::from_cents(1500),
+            ShippingMethod::Pickup => Price::default(),
+        }
+    }
+}
+
+impl FromStr for ShippingMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ShippingMethod::ALL
+            .into_iter()
+            .find(|m| m.as_str() == s)
+            .ok_or_else(|| format!("Unknown shipping method: {s}"))
+    }
+}
+
+impl CheckoutState {
+    /// The first step the shopper still has to fill in (or `Review` once everything is set).
+    pub fn next_step(&self) -> CheckoutStep {
+        match (&self.address, self.shipping_method) {
+            (None, _) => CheckoutStep::Address,
+            (Some(_), None) => CheckoutStep::Shipping,
+            (Some(_), Some(_)) => CheckoutStep::Review,
+        }
+    }
+
+    /// Address and shipping method, if both steps are done.
+    pub fn completed(&self) -> Option<(&ShippingAddress, ShippingMethod)> {
+        Some((self.address.as_ref()?, self.shipping_method?))
+    }
+}
+
+impl CheckoutStep {
+    pub fn path(self) -> &'static str {
+        match self {
+            CheckoutStep::Address => "/checkout/address",
+            CheckoutStep::Shipping => "/checkout/shipping",
+            CheckoutStep::Review => "/checkout/review",
+        }
+    }
+
+    /// Steps must be done in order; you can go back to any step that's reachable.
+    pub fn is_reachable(self, state: &CheckoutState) -> bool {
+        self as u8 <= state.next_step() as u8
+    }
+}
+
+#[cfg(test)]
+impl ShippingAddress {
+    pub fn sample() -> Self {
+        ShippingAddress {
+            email: "shopper@example.com".to_owned(),
+            full_name: "Test Shopper".to_owned(),
+            phone: None,
+            line1: "1 Main Street".to_owned(),
+            line2: None,
+            city: "Springfield".to_owned(),
+            region: None,
+            postal_code: "12345".to_owned(),
+            country: "US".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_validation_reports_each_field() {
+        let input = AddressInput {
+            email: "not-an-email".to_owned(),
+            full_name: "  ".to_owned(),
+            postal_code: "x".repeat(21),
+            ..AddressInput::default()
+        };
+        let errors = input.validate().unwrap_err();
+        assert!(errors.contains_key("email"));
+        assert!(errors.contains_key("full_name"));
+        assert!(errors.contains_key("postal_code"));
+        assert!(errors.contains_key("line1"));
+        assert!(!errors.contains_key("phone")); // optional
+    }
+
+    #[test]
+    fn test_address_validation_trims_and_roundtrips() {
+        let sample = ShippingAddress::sample();
+        let mut input = AddressInput::from(&sample);
+        input.city = format!("  {}  ", input.city);
+        assert_eq!(input.validate().unwrap(), sample);
+    }
+
+    #[test]
+    fn test_email_check() {
+        assert!(looks_like_email("a@b.co"));
+        assert!(!looks_like_email("a@b"));
+        assert!(!looks_like_email("@b.co"));
+        assert!(!looks_like_email("a b@c.co"));
+        assert!(!looks_like_email("a@b@c.co"));
+    }
+
+    #[test]
+    fn test_shipping_cost() {
+        assert_eq!(ShippingMethod::Standard.cost(Price::from_cents(4999)), Price::from_cents(500));
+        assert_eq!(ShippingMethod::Standard.cost(Price::from_cents(5000)), Price::default());
+        assert_eq!(ShippingMethod::Express.cost(Price::from_cents(9999)), Price::from_cents(1500));
+        assert_eq!(ShippingMethod::Pickup.cost(Price::from_cents(1)), Price::default());
+        assert_eq!("express".parse::<ShippingMethod>(), Ok(ShippingMethod::Express));
+        assert!("teleport".parse::<ShippingMethod>().is_err());
+    }
+
+    #[test]
+    fn test_steps_follow_progress() {
+        let mut state = CheckoutState::default();
+        assert_eq!(state.next_step(), CheckoutStep::Address);
+        assert!(!CheckoutStep::Shipping.is_reachable(&state));
+
+        state.address = Some(ShippingAddress::sample());
+        assert_eq!(state.next_step(), CheckoutStep::Shipping);
+        assert!(state.completed().is_none());
+
+        state.shipping_method = Some(ShippingMethod::Pickup);
+        assert_eq!(state.next_step(), CheckoutStep::Review);
+        assert!(CheckoutStep::Address.is_reachable(&state));
+        assert!(state.completed().is_some());
+    }
+}
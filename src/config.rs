@@ -5,6 +5,141 @@ use std::fs;
 pub struct Config {
     pub site_name: String,
     pub root_domain: String,
+    #[serde(default)]
+    pub payment: PaymentConfig,
+    #[serde(default)]
+    pub checkout: CheckoutConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub media: MediaConfig,
+    #[serde(default)]
+    pub catalog: CatalogConfig,
+}
+
+/// The product listings at /products and /c/{slug}; see `routes::products`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CatalogConfig {
+    /// Products per page, unless the shopper picks another `?per_page=`
+    pub per_page: usize,
+    /// The most `?per_page=` can ask for
+    pub max_per_page: usize,
+    /// Listings up to this many pages long get numbered page links; longer ones get
+    /// next/previous links that page by cursor (see `db::products::Cursor`)
+    pub numbered_pages: usize,
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        Self { per_page: 12, max_per_page: 48, numbered_pages: 10 }
+    }
+}
+
+/// Product images, uploaded or fetched; see `media`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MediaConfig {
+    /// Where `media::storage::LocalStorage` writes files
+    pub storage_dir: String,
+    /// URL the storage dir is served under
+    pub public_url: String,
+    pub max_upload_bytes: usize,
+    /// Larger images (in either direction) are refused rather than decoded
+    pub max_dimension: u32,
+    /// Widths of the `srcset` copies; see `media::responsive`
+    pub responsive_widths: Vec<u32>,
+    /// Also make AVIF copies (smallest, but slow to encode)
+    pub avif: bool,
+    /// How often to look for external image URLs to fetch and resize; 0 turns it off
+    pub remote_ingest_secs: u64,
+    pub remote_fetch_timeout_secs: u64,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            storage_dir: "./static/uploads".to_owned(),
+            public_url: "/static/uploads".to_owned(),
+            max_upload_bytes: 10 * 1024 * 1024,
+            max_dimension: 8000,
+            responsive_widths: vec![120, 240, 480, 960, 1600],
+            avif: true,
+            remote_ingest_secs: 60,
+            remote_fetch_timeout_secs: 15,
+        }
+    }
+}
+
+/// Admin logins; see `auth`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// How long an admin stays logged in
+    pub session_hours: i64,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self { session_hours: 12 }
+    }
+}
+
+/// Stock reservations held while a shopper checks out; see `db::reservations`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CheckoutConfig {
+    /// How long a cart's stock stays held once checkout begins
+    pub reservation_minutes: i64,
+    /// How often the background task deletes expired reservations
+    pub reservation_sweep_secs: u64,
+}
+
+impl Default for CheckoutConfig {
+    fn default() -> Self {
+        Self {
+            reservation_minutes: 15,
+            reservation_sweep_secs: 60,
+        }
+    }
+}
+
+/// Which `pay::PaymentProvider` to use. API keys come from env vars (eg `STRIPE_SECRET_KEY`).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PaymentConfig {
+    pub provider: PaymentProviderKind,
+    pub currency: String,
+    /// Shown to shoppers who pay manually (bank transfer, cash on delivery...)
+    pub manual_instructions: String,
+    pub stripe_api_base: String,
+    /// How long the mock gateway takes to confirm delayed / 3-D Secure payments
+    pub mock_confirm_delay_secs: u64,
+    /// Where the mock gateway delivers its webhooks, eg "http://127.0.0.1:8080/webhooks/payments/mock".
+    /// Unset means delayed mock payments are only visible through `fetch_status`.
+    pub mock_webhook_url: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentProviderKind {
+    Manual,
+    Stripe,
+    /// Offline fake gateway for development and tests; see `pay::mock`
+    Mock,
+}
+
+impl Default for PaymentConfig {
+    fn default() -> Self {
+        Self {
+            provider: PaymentProviderKind::Manual,
+            currency: "usd".to_owned(),
+            manual_instructions: "We'll contact you with bank transfer details, or you can pay cash on delivery.".to_owned(),
+            stripe_api_base: "https://api.stripe.com".to_owned(),
+            mock_confirm_delay_secs: 10,
+            mock_webhook_url: None,
+        }
+    }
 }
 
 impl Config {
@@ -13,4 +148,4 @@ impl Config {
         let config: Config = serde_json::from_str(&config_str)?;
         Ok(config)
     }
-}
\ No newline at end of file
+}
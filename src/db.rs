@@ -1,18 +1,28 @@
 use crate::db::cache::initialize_caches;
 use crate::errors::BeedleError;
-use crate::models::{NewProduct};
 use r2d2::{Pool, PooledConnection};
 use diesel::{
-    {QueryDsl,RunQueryDsl},
     r2d2::{self, ConnectionManager},
     pg::PgConnection,
 };
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 pub type Conn = PooledConnection<ConnectionManager<PgConnection>>;
 
+pub mod admin_users;
 pub mod cache;
+pub mod categories;
+pub mod facets;
+pub mod images;
+pub mod orders;
 pub mod products;
+pub mod promotions;
+pub mod renditions;
+pub mod reservations;
+pub mod search;
 pub mod session;
+pub mod tags;
+pub mod variants;
+pub mod webhooks;
 
 pub fn establish_connection() -> Result<DbPool, BeedleError> {
     let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set!");
@@ -26,6 +36,48 @@ pub fn init_db(conn: &mut Conn) -> Result<(), BeedleError> {
     Ok(())
 }
 
+/// Shared connection pool for DB-backed unit tests (expects a migrated + seeded `DATABASE_URL`).
+#[cfg(test)]
+pub(crate) fn test_pool() -> &'static DbPool {
+    use once_cell::sync::Lazy;
+
+    static POOL: Lazy<DbPool> = Lazy::new(|| {
+        dotenv::dotenv().ok();
+        establish_connection().expect("Failed to create pool.")
+    });
+    &POOL
+}
+
+#[cfg(test)]
+pub(crate) fn test_conn() -> Conn {
+    test_pool().get().expect("Failed to get a connection from the pool")
+}
+
+/// A product for tests to insert (see `products::insert_product`), slugged from its name.
+/// Names need to be unique across tests, which run in parallel.
+#[cfg(test)]
+pub(crate) fn test_product(
+    name_val: &str,
+    category_id_val: i32,
+    cents: i64,
+    stock: i32,
+    discount: Option<f32>,
+) -> crate::models::NewProduct {
+    crate::models::NewProduct {
+        name: name_val.to_owned(),
+        slug: crate::validation::slugify(name_val),
+        price: cents,
+        inventory: stock,
+        category_id: category_id_val,
+        thumbnail_url: None,
+        tagline: None,
+        description: None,
+        discount_percent: discount,
+        added_date: None,
+        restock_date: None,
+    }
+}
+
 /*
 // UNIT TESTING
 #[cfg(test)]
@@ -0,0 +1,233 @@
+//! Admin accounts (`admin_user`) and their logins (`admin_session`).
+//! Password hashing and roles live in `crate::auth`.
+
+use crate::auth::{hash_password, verify_password, Role, MIN_PASSWORD_LEN};
+use crate::errors::BeedleError;
+use crate::models::{AdminUser, NewAdminSession, NewAdminUser};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use once_cell::sync::Lazy;
+use uuid::Uuid;
+
+use super::Conn;
+
+fn validate_username(username_val: &str) -> Result<&str, BeedleError> {
+    let username_val = username_val.trim();
+    if username_val.is_empty() || username_val.chars().count() > 64 {
+        return Err(BeedleError::AccountError("Username must be 1-64 characters".into()));
+    }
+    if username_val.contains(char::is_whitespace) {
+        return Err(BeedleError::AccountError("Username can't contain spaces".into()));
+    }
+    Ok(username_val)
+}
+
+fn validate_password(password: &str) -> Result<(), BeedleError> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(BeedleError::AccountError(format!(
+            "Password must be at least {MIN_PASSWORD_LEN} characters"
+        )));
+    }
+    Ok(())
+}
+
+pub fn create_admin_user(
+    conn: &mut Conn,
+    username_val: &str,
+    password: &str,
+    role_val: Role,
+) -> Result<AdminUser, BeedleError> {
+    let username_val = validate_username(username_val)?;
+    validate_password(password)?;
+    let hash = hash_password(password)?;
+
+    use crate::schema::admin_user::dsl::*;
+    diesel::insert_into(admin_user)
+        .values(&NewAdminUser { username: username_val, password_hash: &hash, role: role_val })
+        .get_result(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => {
+                BeedleError::AccountError(format!("Username '{}' is taken", username_val))
+            }
+            e => {
+                log::error!("Creating admin user {} failed: {e}", username_val);
+                BeedleError::DatabaseError(e.to_string())
+            }
+        })
+}
+
+pub fn load_admin_users(conn: &mut Conn) -> Result<Vec<AdminUser>, BeedleError> {
+    use crate::schema::admin_user::dsl::*;
+    admin_user.order(username).load::<AdminUser>(conn).map_err(|e| {
+        log::error!("Loading admin users failed: {e}");
+        BeedleError::DatabaseError(e.to_string())
+    })
+}
+
+pub fn count_admin_users(conn: &mut Conn) -> Result<i64, BeedleError> {
+    use crate::schema::admin_user::dsl::*;
+    Ok(admin_user.count().get_result(conn)?)
+}
+
+/// Refuse to leave the shop with nobody able to manage accounts.
+fn ensure_other_owner(conn: &mut Conn, user_id_val: i32) -> Result<(), BeedleError> {
+    use crate::schema::admin_user::dsl::*;
+    let other_owners: i64 = admin_user
+        .filter(role.eq(Role::Owner))
+        .filter(id.ne(user_id_val))
+        .count()
+        .get_result(conn)?;
+    if other_owners == 0 {
+        return Err(BeedleError::AccountError("There must always be at least one owner".into()));
+    }
+    Ok(())
+}
+
+pub fn set_admin_role(conn: &mut Conn, user_id_val: i32, role_val: Role) -> Result<(), BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        use crate::schema::admin_user::dsl::*;
+        let current: Role = admin_user.find(user_id_val).select(role).for_update().first(conn)?;
+        if current == Role::Owner && role_val != Role::Owner {
+            ensure_other_owner(conn, user_id_val)?;
+        }
+        diesel::update(admin_user.find(user_id_val)).set(role.eq(role_val)).execute(conn)?;
+        log::info!("Admin user {} is now {}", user_id_val, role_val);
+        Ok(())
+    })
+}
+
+/// Change a password and log that account out everywhere.
+pub fn set_admin_password(conn: &mut Conn, user_id_val: i32, password: &str) -> Result<(), BeedleError> {
+    validate_password(password)?;
+    let hash = hash_password(password)?;
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        {
+            use crate::schema::admin_user::dsl::*;
+            let updated = diesel::update(admin_user.find(user_id_val)).set(password_hash.eq(&hash)).execute(conn)?;
+            if updated == 0 {
+                return Err(BeedleError::AccountError(format!("No admin user with id {}", user_id_val)));
+            }
+        }
+        use crate::schema::admin_session::dsl::*;
+        diesel::delete(admin_session.filter(admin_user_id.eq(user_id_val))).execute(conn)?;
+        Ok(())
+    })
+}
+
+pub fn delete_admin_user(conn: &mut Conn, user_id_val: i32) -> Result<(), BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        use crate::schema::admin_user::dsl::*;
+        let current: Role = admin_user.find(user_id_val).select(role).for_update().first(conn)?;
+        if current == Role::Owner {
+            ensure_other_owner(conn, user_id_val)?;
+        }
+        // Cascades to their sessions
+        diesel::delete(admin_user.find(user_id_val)).execute(conn)?;
+        log::info!("Deleted admin user {}", user_id_val);
+        Ok(())
+    })
+}
+
+/// Check a username/password pair. Unknown usernames take as long as wrong passwords,
+/// so response times don't reveal which accounts exist.
+pub fn authenticate(conn: &mut Conn, username_val: &str, password: &str) -> Result<Option<AdminUser>, BeedleError> {
+    static DUMMY_HASH: Lazy<String> =
+        Lazy::new(|| hash_password("not anybody's password").expect("Hashing a constant failed"));
+
+    let user = {
+        use crate::schema::admin_user::dsl::*;
+        admin_user.filter(username.eq(username_val.trim())).first::<AdminUser>(conn).optional()?
+    };
+    match user {
+        Some(user) if verify_password(password, &user.password_hash) => Ok(Some(user)),
+        Some(_) => Ok(None),
+        None => {
+            verify_password(password, &DUMMY_HASH);
+            Ok(None)
+        }
+    }
+}
+
+/// Log an admin in for `ttl`; returns the token for the `admin_session` cookie.
+pub fn start_admin_session(conn: &mut Conn, user_id_val: i32, ttl: Duration) -> Result<Uuid, BeedleError> {
+    let now = Utc::now().naive_utc();
+    let token_val = Uuid::new_v4();
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        {
+            use crate::schema::admin_session::dsl::*;
+            // Opportunistic cleanup; there are few enough admins that this stays cheap
+            diesel::delete(admin_session.filter(expires_at.le(now))).execute(conn)?;
+            diesel::insert_into(admin_session)
+                .values(&NewAdminSession { token: token_val, admin_user_id: user_id_val, expires_at: now + ttl })
+                .execute(conn)?;
+        }
+        use crate::schema::admin_user::dsl::*;
+        diesel::update(admin_user.find(user_id_val)).set(last_login_at.eq(now)).execute(conn)?;
+        Ok(token_val)
+    })
+}
+
+/// The admin logged in with `token_val`, if the login hasn't expired.
+pub fn find_admin_by_session(conn: &mut Conn, token_val: Uuid) -> Result<Option<AdminUser>, BeedleError> {
+    use crate::schema::{admin_session, admin_user};
+    admin_session::table
+        .inner_join(admin_user::table)
+        .filter(admin_session::token.eq(token_val))
+        .filter(admin_session::expires_at.gt(Utc::now().naive_utc()))
+        .select(admin_user::all_columns)
+        .first::<AdminUser>(conn)
+        .optional()
+        .map_err(|e| {
+            log::error!("Finding admin session failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+pub fn end_admin_session(conn: &mut Conn, token_val: Uuid) -> Result<(), BeedleError> {
+    use crate::schema::admin_session::dsl::*;
+    diesel::delete(admin_session.find(token_val)).execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod admin_users_tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    #[test]
+    fn test_login_sessions_and_last_owner() {
+        let mut conn = test_conn();
+        let name = |s: &str| format!("test-{}-{}", s, Uuid::new_v4().simple());
+        let owner = create_admin_user(&mut conn, &name("owner"), "owner password", Role::Owner).unwrap();
+        let staff = create_admin_user(&mut conn, &name("staff"), "staff password", Role::Staff).unwrap();
+        assert!(create_admin_user(&mut conn, &owner.username, "another password", Role::Staff).is_err());
+        assert!(create_admin_user(&mut conn, &name("short"), "short", Role::Staff).is_err());
+
+        assert!(authenticate(&mut conn, &staff.username, "wrong password").unwrap().is_none());
+        assert!(authenticate(&mut conn, "nobody-at-all", "staff password").unwrap().is_none());
+        let user = authenticate(&mut conn, &staff.username, "staff password").unwrap().unwrap();
+        assert_eq!(user.role, Role::Staff);
+
+        let token = start_admin_session(&mut conn, user.id, Duration::hours(1)).unwrap();
+        assert_eq!(find_admin_by_session(&mut conn, token).unwrap().map(|u| u.id), Some(staff.id));
+        let expired = start_admin_session(&mut conn, user.id, Duration::seconds(-1)).unwrap();
+        assert!(find_admin_by_session(&mut conn, expired).unwrap().is_none());
+        // A new password logs the account out
+        set_admin_password(&mut conn, staff.id, "new staff password").unwrap();
+        assert!(find_admin_by_session(&mut conn, token).unwrap().is_none());
+
+        // Other owners may exist in the DB already, so only check the guard once ours is the last
+        let owners: Vec<AdminUser> = load_admin_users(&mut conn).unwrap().into_iter().filter(|u| u.role == Role::Owner).collect();
+        if owners.len() == 1 {
+            assert!(set_admin_role(&mut conn, owner.id, Role::Staff).is_err());
+            assert!(delete_admin_user(&mut conn, owner.id).is_err());
+        }
+        set_admin_role(&mut conn, staff.id, Role::Owner).unwrap();
+        set_admin_role(&mut conn, owner.id, Role::ReadOnly).unwrap();
+
+        delete_admin_user(&mut conn, owner.id).unwrap();
+        // Bypasses the last-owner guard, which may (rightly) refuse this
+        use crate::schema::admin_user::dsl::*;
+        diesel::delete(admin_user.find(staff.id)).execute(&mut conn).unwrap();
+    }
+}
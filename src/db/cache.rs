@@ -1,52 +1,37 @@
+use crate::db::categories::{load_tree, CategoryTree};
 use crate::errors::BeedleError;
-use diesel::{QueryDsl,RunQueryDsl};
 use once_cell::sync::Lazy;
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
-pub static DATA: Lazy<RwLock<Vec<String>>> = Lazy::new(|| RwLock::new(Vec::new()));
+pub static DATA: Lazy<RwLock<Arc<CategoryTree>>> = Lazy::new(|| RwLock::new(Arc::new(CategoryTree::default())));
 pub struct CategoriesCache;
 
 pub fn initialize_caches(conn: &mut crate::db::Conn) -> Result<(), BeedleError> {
-    match load_categories_from_db(conn) {
-        Ok(categories) => {
-            CategoriesCache::initialize(categories);
-            Ok(())
-        }
-        Err(e) => Err(e),
-    }
-}
-
-/// Load all unique categories (strings) from DB via Diesel
-fn load_categories_from_db(conn: &mut crate::db::Conn) -> Result<Vec<String>, BeedleError> {
-    use crate::schema::product::dsl::*;
-    // select distinct category from product;
-    product
-        .select(category)
-        .distinct()
-        .load::<String>(conn)
-        .map_err(|e| BeedleError::DatabaseError(e.to_string()))
+    CategoriesCache::refresh(conn)
 }
 
 impl CategoriesCache {
-    pub fn initialize(data: Vec<String>) {
+    pub fn initialize(tree: CategoryTree) {
         let mut cache = DATA.write().unwrap();
-        *cache = data;
+        *cache = Arc::new(tree);
     }
 
-    pub fn get_categories() -> std::sync::RwLockReadGuard<'static, Vec<String>> {
-        DATA.read().unwrap()
+    /// The tree as of the last load; cheap to call, and safe to hold across a request.
+    pub fn get() -> Arc<CategoryTree> {
+        DATA.read().unwrap().clone()
     }
 
-    pub fn invalidate() {
-        let mut write_guard = DATA.write().unwrap();
-        write_guard.clear();
+    /// Reload from the DB, eg after an admin changed a category.
+    pub fn refresh(conn: &mut crate::db::Conn) -> Result<(), BeedleError> {
+        CategoriesCache::initialize(load_tree(conn)?);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod cache_tests {
     use super::*;
-    use crate::db::{Conn, DbPool, establish_connection, init_db};
+    use crate::db::{Conn, DbPool, init_db};
     use diesel::{PgConnection, r2d2::ConnectionManager};
     use once_cell::sync::Lazy;
     use std::env;
@@ -75,22 +60,20 @@ mod cache_tests {
         initialize_caches(&mut conn).expect("Failed to cache from DB");
 
         // Ensure cache is initialized with correct data
-        let cached_categories = CategoriesCache::get_categories();
-        assert!(!cached_categories.is_empty());
-        assert!(cached_categories.contains(&"Produce".to_string()));
+        let cached_categories = CategoriesCache::get();
+        assert!(!cached_categories.flatten().is_empty());
+        assert_eq!(cached_categories.by_slug("produce").map(|c| c.name.as_str()), Some("Produce"));
     }
 
     #[test]
-    fn test_cache_invalidation() {
+    fn test_cache_refresh() {
         let mut conn = get_test_conn();
 
         init_db(&mut conn).expect("Failed to initialize DB");
-        //seed_example_products(&mut conn).expect("Failed to seed DB with test data");
-        // removed this function; now depends on db to be pre seeded with test data
-        initialize_caches(&mut conn).expect("Failed to cache from DB");
-
-        CategoriesCache::invalidate();
-        let cached_categories = CategoriesCache::get_categories();
-        assert!(cached_categories.is_empty());
+        let made = crate::db::categories::test_category(&mut conn, "Test Cache Refresh");
+        CategoriesCache::refresh(&mut conn).expect("Failed to refresh cache");
+        assert_eq!(CategoriesCache::get().get(made).map(|c| c.slug.as_str()), Some("test-cache-refresh"));
+        crate::db::categories::delete_category(&mut conn, made).unwrap();
+        CategoriesCache::refresh(&mut conn).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,204 @@
+//! The category tree: each `category` has an optional parent, and products belong to one
+//! category by ID. The whole tree is small and read on most pages, so it's kept in memory
+//! (`cache::CategoriesCache`) and reloaded after every admin change.
+
+use crate::errors::BeedleError;
+use crate::models::{Category, NewCategory};
+use crate::schema::category::dsl::*;
+use diesel::dsl::count;
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+use super::Conn;
+
+/// All categories, siblings in display order.
+#[derive(Debug, Clone, Default)]
+pub struct CategoryTree {
+    categories: Vec<Category>,
+}
+
+impl CategoryTree {
+    pub fn new(mut categories: Vec<Category>) -> Self {
+        categories.sort_by(|a, b| (a.position, &a.name).cmp(&(b.position, &b.name)));
+        CategoryTree { categories }
+    }
+
+    pub fn get(&self, id_val: i32) -> Option<&Category> {
+        self.categories.iter().find(|c| c.id == id_val)
+    }
+
+    pub fn by_slug(&self, slug_val: &str) -> Option<&Category> {
+        self.categories.iter().find(|c| c.slug == slug_val)
+    }
+
+    /// Direct children of `parent`, or the top-level categories for `None`
+    pub fn children(&self, parent: Option<i32>) -> impl Iterator<Item = &Category> {
+        self.categories.iter().filter(move |c| c.parent_id == parent)
+    }
+
+    /// From the top-level ancestor down to the category itself; empty if there's no such category.
+    pub fn path(&self, id_val: i32) -> Vec<&Category> {
+        let mut path = Vec::new();
+        let mut next = self.get(id_val);
+        while let Some(current) = next {
+            if path.iter().any(|c: &&Category| c.id == current.id) {
+                break; // a cycle; shouldn't happen, see `would_cycle`
+            }
+            path.push(current);
+            next = current.parent_id.and_then(|parent| self.get(parent));
+        }
+        path.reverse();
+        path
+    }
+
+    /// The category and everything below it
+    pub fn subtree_ids(&self, id_val: i32) -> Vec<i32> {
+        let mut ids = vec![id_val];
+        let mut i = 0;
+        while i < ids.len() {
+            let children: Vec<i32> = self.children(Some(ids[i])).map(|c| c.id).filter(|child| !ids.contains(child)).collect();
+            ids.extend(children);
+            i += 1;
+        }
+        ids
+    }
+
+    /// Whether making `parent` the parent of `id_val` would put it inside its own subtree
+    pub fn would_cycle(&self, id_val: i32, parent: i32) -> bool {
+        self.subtree_ids(id_val).contains(&parent)
+    }
+
+    /// Every category depth first, with its depth (0 for top level), for nested lists and selects
+    pub fn flatten(&self) -> Vec<(usize, &Category)> {
+        fn walk<'a>(tree: &'a CategoryTree, parent: Option<i32>, depth: usize, out: &mut Vec<(usize, &'a Category)>) {
+            for child in tree.children(parent) {
+                if out.iter().any(|(_, seen)| seen.id == child.id) {
+                    continue;
+                }
+                out.push((depth, child));
+                walk(tree, Some(child.id), depth + 1, out);
+            }
+        }
+        let mut out = Vec::new();
+        walk(self, None, 0, &mut out);
+        out
+    }
+}
+
+/// Load every category into a tree.
+pub fn load_tree(conn: &mut Conn) -> Result<CategoryTree, BeedleError> {
+    let categories = category.load::<Category>(conn).map_err(|e| {
+        log::error!("Loading categories failed: {e}");
+        BeedleError::DatabaseError(e.to_string())
+    })?;
+    Ok(CategoryTree::new(categories))
+}
+
+pub fn insert_category(conn: &mut Conn, new_category: &NewCategory) -> Result<Category, BeedleError> {
+    diesel::insert_into(category).values(new_category).get_result(conn).map_err(|e| {
+        log::error!("Inserting category {:?} failed: {e}", new_category.name);
+        BeedleError::DatabaseError(e.to_string())
+    })
+}
+
+/// Save an edited category. Its products follow it, being linked by ID.
+pub fn save_category(conn: &mut Conn, category_in: &Category) -> Result<(), BeedleError> {
+    diesel::update(category.find(category_in.id)).set(category_in).execute(conn).map_err(|e| {
+        log::error!("Saving category {} failed: {e}", category_in.id);
+        BeedleError::DatabaseError(e.to_string())
+    })?;
+    Ok(())
+}
+
+/// How many products are directly in a category (not counting subcategories)
+pub fn count_products_in(conn: &mut Conn, category_id_val: i32) -> Result<i64, BeedleError> {
+    use crate::schema::product::dsl as p;
+    p::product.filter(p::category_id.eq(category_id_val)).count().get_result(conn).map_err(|e| {
+        log::error!("Counting products of category {} failed: {e}", category_id_val);
+        BeedleError::DatabaseError(e.to_string())
+    })
+}
+
+/// Products directly in each category that has any
+pub fn product_counts(conn: &mut Conn) -> Result<HashMap<i32, i64>, BeedleError> {
+    use crate::schema::product::dsl as p;
+    let counts = p::product
+        .group_by(p::category_id)
+        .select((p::category_id, count(p::id)))
+        .load::<(i32, i64)>(conn)
+        .map_err(|e| {
+            log::error!("Counting products per category failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+    Ok(counts.into_iter().collect())
+}
+
+/// Delete a category. The database refuses while products or subcategories are in it,
+/// so check `count_products_in` and the tree first for a friendlier message.
+pub fn delete_category(conn: &mut Conn, category_id_val: i32) -> Result<(), BeedleError> {
+    let affected = diesel::delete(category.find(category_id_val)).execute(conn).map_err(|e| {
+        log::error!("Deleting category {} failed: {e}", category_id_val);
+        BeedleError::DatabaseError(e.to_string())
+    })?;
+    if affected == 0 {
+        return Err(BeedleError::DatabaseError(format!("No category with id {}", category_id_val)));
+    }
+    Ok(())
+}
+
+/// A top-level category for tests to put products in, made if missing.
+#[cfg(test)]
+pub(crate) fn test_category(conn: &mut Conn, name_val: &str) -> i32 {
+    let slug_val = crate::validation::slugify(name_val);
+    diesel::insert_into(category)
+        .values((name.eq(name_val), slug.eq(&slug_val)))
+        .on_conflict(slug)
+        .do_nothing()
+        .execute(conn)
+        .unwrap();
+    category.filter(slug.eq(&slug_val)).select(id).first(conn).unwrap()
+}
+
+#[cfg(test)]
+mod categories_tests {
+    use super::*;
+
+    fn node(id_val: i32, parent: Option<i32>, name_val: &str, position_val: i32) -> Category {
+        Category {
+            id: id_val,
+            parent_id: parent,
+            name: name_val.to_owned(),
+            slug: name_val.to_lowercase(),
+            description: None,
+            image_url: None,
+            position: position_val,
+        }
+    }
+
+    #[test]
+    fn test_category_tree() {
+        let tree = CategoryTree::new(vec![
+            node(1, None, "Drinks", 0),
+            node(2, Some(1), "Tea", 0),
+            node(3, Some(2), "Green", 0),
+            node(4, Some(1), "Coffee", 0),
+            node(5, None, "Bakery", 1),
+            node(6, Some(1), "Juice", -1),
+        ]);
+        let flat: Vec<(usize, &str)> = tree.flatten().iter().map(|(depth, c)| (*depth, c.name.as_str())).collect();
+        assert_eq!(flat, [(0, "Drinks"), (1, "Juice"), (1, "Coffee"), (1, "Tea"), (2, "Green"), (0, "Bakery")]);
+
+        let path: Vec<&str> = tree.path(3).iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(path, ["Drinks", "Tea", "Green"]);
+        assert!(tree.path(99).is_empty());
+
+        let mut below = tree.subtree_ids(1);
+        below.sort();
+        assert_eq!(below, [1, 2, 3, 4, 6]);
+        assert_eq!(tree.subtree_ids(5), [5]);
+        assert!(tree.would_cycle(1, 3));
+        assert!(tree.would_cycle(2, 2));
+        assert!(!tree.would_cycle(2, 5));
+        assert_eq!(tree.by_slug("tea").map(|c| c.id), Some(2));
+    }
+}
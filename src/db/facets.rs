@@ -0,0 +1,143 @@
+//! Counts for the catalog's filter sidebar. Each dimension is counted with every other
+//! filter applied but its own left out, so ticking a second category or tag shows how
+//! many products that would add rather than only those already listed.
+
+use crate::errors::BeedleError;
+use diesel::dsl::count;
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+use super::products::{filtered, sale_price, ProductFilter, ProductQuery};
+use super::tags::{tag_counts, TagCount};
+use super::Conn;
+
+/// Where the price ranges split, in cents: under $5, $5-$9.99, ..., $50 and up
+pub const PRICE_BREAKS: [i64; 4] = [500, 1000, 2500, 5000];
+
+/// Products whose sale price is `min..=max` (in cents; `None` is unbounded)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceRange {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub products: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProductFacets {
+    /// Products directly in each category that has any; add up a subtree for nested counts
+    pub categories: HashMap<i32, i64>,
+    pub tags: Vec<TagCount>,
+    /// Every range between `PRICE_BREAKS`, cheapest first, empty ones included
+    pub price_ranges: Vec<PriceRange>,
+    /// Products that would be left by ticking "in stock" or "on sale"
+    pub in_stock: i64,
+    pub on_sale: i64,
+}
+
+/// Facet counts for the products matching `filter`.
+pub fn product_facets(conn: &mut Conn, filter: &ProductFilter) -> Result<ProductFacets, BeedleError> {
+    use crate::schema::product::dsl::*;
+
+    let any_category = ProductFilter { category_ids: None, ..filter.clone() };
+    let categories = product
+        .filter(id.eq_any(filtered(&any_category).select(id)))
+        .group_by(category_id)
+        .select((category_id, count(id)))
+        .load::<(i32, i64)>(conn)
+        .map_err(|e| {
+            log::error!("Counting products per category with filter failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+
+    let any_price = ProductFilter { min_price: None, max_price: None, ..filter.clone() };
+    let prices = filtered(&any_price).select(sale_price(filter.promotions_at)).load::<i64>(conn).map_err(|e| {
+        log::error!("Loading prices with filter failed: {e}");
+        BeedleError::DatabaseError(e.to_string())
+    })?;
+
+    Ok(ProductFacets {
+        categories: categories.into_iter().collect(),
+        tags: tag_counts(conn, filter)?,
+        price_ranges: price_ranges(&prices),
+        in_stock: ProductQuery::new(ProductFilter { in_stock: true, ..filter.clone() }).count(conn)?,
+        on_sale: ProductQuery::new(ProductFilter { on_sale: true, ..filter.clone() }).count(conn)?,
+    })
+}
+
+/// Sort prices into the `PRICE_BREAKS` ranges
+fn price_ranges(prices: &[i64]) -> Vec<PriceRange> {
+    let mut bounds = vec![None];
+    bounds.extend(PRICE_BREAKS.iter().copied().map(Some));
+    bounds.push(None);
+    bounds
+        .windows(2)
+        .map(|pair| {
+            let (min, max) = (pair[0], pair[1].map(|next| next - 1));
+            let products = prices
+                .iter()
+                .filter(|p| min.is_none_or(|m| **p >= m) && max.is_none_or(|m| **p <= m))
+                .count() as i64;
+            PriceRange { min, max, products }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod facets_tests {
+    use super::*;
+    use crate::db::categories::{delete_category, test_category};
+    use crate::db::products::{delete_product, insert_product};
+    use crate::db::{test_conn, test_product};
+    use crate::models::ProductLists;
+
+    #[test]
+    fn test_price_ranges() {
+        let ranges: Vec<(Option<i64>, Option<i64>, i64)> =
+            price_ranges(&[100, 499, 500, 2500, 9999]).into_iter().map(|r| (r.min, r.max, r.products)).collect();
+        assert_eq!(
+            ranges,
+            [(None, Some(499), 2), (Some(500), Some(999), 1), (Some(1000), Some(2499), 0), (Some(2500), Some(4999), 1), (Some(5000), None, 1)]
+        );
+    }
+
+    #[test]
+    fn test_facets_leave_out_their_own_filter() {
+        let mut conn = test_conn();
+        let jams = test_category(&mut conn, "Test Facet Jams");
+        let teas = test_category(&mut conn, "Test Facet Teas");
+        let tagged = |tags: &[&str]| ProductLists { tags: tags.iter().map(|t| t.to_string()).collect(), ..ProductLists::default() };
+        let products = [
+            insert_product(&mut conn, &test_product("Test Facet Plum Jam", jams, 450, 3, None), &tagged(&["TestFacetSweet"])).unwrap(),
+            insert_product(&mut conn, &test_product("Test Facet Fig Jam", jams, 1200, 0, Some(10.0)), &tagged(&["TestFacetSweet"])).unwrap(),
+            insert_product(&mut conn, &test_product("Test Facet Mint Tea", teas, 800, 5, Some(25.0)), &tagged(&[])).unwrap(),
+        ];
+
+        let both = ProductFilter { category_ids: Some(vec![jams, teas]), ..ProductFilter::default() };
+        assert_eq!(ProductQuery::new(ProductFilter { min_price: Some(500), max_price: Some(1200), ..both.clone() }).count(&mut conn).unwrap(), 2);
+        assert_eq!(ProductQuery::new(ProductFilter { in_stock: true, on_sale: true, ..both.clone() }).count(&mut conn).unwrap(), 1);
+
+        // Only jams are listed, but the category counts still show the teas
+        let jams_only = ProductFilter { category_ids: Some(vec![jams]), ..ProductFilter::default() };
+        let facets = product_facets(&mut conn, &ProductFilter { in_stock: true, ..jams_only.clone() }).unwrap();
+        assert_eq!(facets.categories.get(&jams), Some(&1));
+        assert_eq!(facets.categories.get(&teas), Some(&1));
+        assert_eq!(facets.in_stock, 1);
+        assert_eq!(facets.on_sale, 0);
+        let tags: Vec<(&str, i64)> = facets.tags.iter().map(|t| (t.name.as_str(), t.products)).collect();
+        assert_eq!(tags, [("TestFacetSweet", 1)]);
+
+        let facets = product_facets(&mut conn, &ProductFilter { max_price: Some(499), ..jams_only }).unwrap();
+        let counts: Vec<i64> = facets.price_ranges.iter().map(|r| r.products).collect();
+        assert_eq!(counts, [1, 0, 1, 0, 0]);
+        assert_eq!(facets.on_sale, 0);
+
+        for made in products {
+            delete_product(&mut conn, made.id).unwrap();
+        }
+        if let Some(tag) = crate::db::tags::find_tag(&mut conn, "TestFacetSweet").unwrap() {
+            crate::db::tags::delete_tag(&mut conn, tag.id).unwrap();
+        }
+        delete_category(&mut conn, jams).unwrap();
+        delete_category(&mut conn, teas).unwrap();
+    }
+}
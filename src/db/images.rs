@@ -0,0 +1,200 @@
+//! Uploaded product images (`product_image`): ordering, alt text, removal.
+//! The files are handled by `media`; these helpers only touch the rows.
+
+use crate::errors::BeedleError;
+use crate::models::{NewImageRendition, NewProductImage, ProductImage};
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+use super::Conn;
+
+/// A product's images, first (the main photo) to last.
+pub fn load_images(conn: &mut Conn, product_id_val: i32) -> Result<Vec<ProductImage>, BeedleError> {
+    use crate::schema::product_image::dsl::*;
+    product_image
+        .filter(product_id.eq(product_id_val))
+        .order((position, id))
+        .load::<ProductImage>(conn)
+        .map_err(|e| {
+            log::error!("Loading images of product {} failed: {e}", product_id_val);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Images for a page of products in one query, keyed by product ID.
+pub fn load_images_by_product(
+    conn: &mut Conn,
+    product_ids: &[i32],
+) -> Result<HashMap<i32, Vec<ProductImage>>, BeedleError> {
+    use crate::schema::product_image::dsl::*;
+    let images = product_image
+        .filter(product_id.eq_any(product_ids))
+        .order((product_id, position, id))
+        .load::<ProductImage>(conn)
+        .map_err(|e| {
+            log::error!("Loading product images failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+    let mut by_product: HashMap<i32, Vec<ProductImage>> = HashMap::new();
+    for image in images {
+        by_product.entry(image.product_id).or_default().push(image);
+    }
+    Ok(by_product)
+}
+
+pub fn load_image(conn: &mut Conn, image_id_val: i32) -> Result<Option<ProductImage>, BeedleError> {
+    use crate::schema::product_image::dsl::*;
+    Ok(product_image.find(image_id_val).first::<ProductImage>(conn).optional()?)
+}
+
+/// Add an image after the product's existing ones, with its `srcset` copies.
+pub fn insert_image(
+    conn: &mut Conn,
+    new_image: NewProductImage,
+    new_renditions: &[NewImageRendition],
+) -> Result<ProductImage, BeedleError> {
+    use crate::schema::product_image::dsl::*;
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let last: Option<i32> = product_image
+            .filter(product_id.eq(new_image.product_id))
+            .select(diesel::dsl::max(position))
+            .first(conn)?;
+        let inserted = diesel::insert_into(product_image)
+            .values(&NewProductImage { position: last.map_or(0, |p| p + 1), ..new_image })
+            .get_result::<ProductImage>(conn)?;
+        super::renditions::replace_renditions(conn, &inserted.storage_key, new_renditions)?;
+        log::info!("Added image {} to product {}", inserted.id, inserted.product_id);
+        Ok(inserted)
+    })
+}
+
+pub fn update_image_alt(conn: &mut Conn, image_id_val: i32, alt_val: &str) -> Result<(), BeedleError> {
+    use crate::schema::product_image::dsl::*;
+    let updated = diesel::update(product_image.find(image_id_val))
+        .set(alt_text.eq(alt_val.trim()))
+        .execute(conn)?;
+    if updated == 0 {
+        return Err(BeedleError::MediaError(format!("No image with id {}", image_id_val)));
+    }
+    Ok(())
+}
+
+/// Swap an image with its neighbour; `earlier` moves it towards the front.
+/// Moving the first image earlier (or the last one later) does nothing.
+pub fn move_image(conn: &mut Conn, image_id_val: i32, earlier: bool) -> Result<(), BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let Some(image) = load_image(conn, image_id_val)? else {
+            return Err(BeedleError::MediaError(format!("No image with id {}", image_id_val)));
+        };
+        let mut images = load_images(conn, image.product_id)?;
+        let Some(at) = images.iter().position(|i| i.id == image_id_val) else {
+            return Ok(());
+        };
+        let other = match (earlier, at) {
+            (true, 0) => return Ok(()),
+            (true, at) => at - 1,
+            (false, at) if at + 1 == images.len() => return Ok(()),
+            (false, at) => at + 1,
+        };
+        images.swap(at, other);
+
+        // Renumber the lot, which also repairs any gaps or duplicate positions
+        use crate::schema::product_image::dsl::*;
+        for (new_position, img) in images.iter().enumerate() {
+            diesel::update(product_image.find(img.id))
+                .set(position.eq(new_position as i32))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+/// Remove an image's row and its renditions' rows, and return the keys of all their files
+/// for the caller to delete.
+pub fn delete_image(conn: &mut Conn, image_id_val: i32) -> Result<(ProductImage, Vec<String>), BeedleError> {
+    use crate::schema::product_image::dsl::*;
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let image = diesel::delete(product_image.find(image_id_val))
+            .get_result::<ProductImage>(conn)
+            .optional()?
+            .ok_or_else(|| BeedleError::MediaError(format!("No image with id {}", image_id_val)))?;
+        let keys = stored_keys(conn, &image)?;
+        Ok((image, keys))
+    })
+}
+
+/// Every file of a product's uploads, deleting their renditions' rows; for when the product
+/// itself is deleted (its `product_image` rows cascade).
+pub fn delete_renditions_of_product(conn: &mut Conn, product_id_val: i32) -> Result<Vec<String>, BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let mut keys = Vec::new();
+        for image in load_images(conn, product_id_val)? {
+            keys.extend(stored_keys(conn, &image)?);
+        }
+        Ok(keys)
+    })
+}
+
+/// The upload's own files plus its renditions', whose rows are deleted.
+fn stored_keys(conn: &mut Conn, image: &ProductImage) -> Result<Vec<String>, BeedleError> {
+    let renditions = super::renditions::delete_renditions(conn, &image.storage_key)?;
+    let own = [&image.storage_key, &image.thumb_key, &image.medium_key].map(String::clone);
+    Ok(own.into_iter().chain(renditions.into_iter().map(|r| r.storage_key)).collect())
+}
+
+#[cfg(test)]
+mod images_tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    fn new_image(product_id_val: i32, key: &str) -> NewProductImage<'_> {
+        NewProductImage {
+            product_id: product_id_val,
+            storage_key: key,
+            thumb_key: key,
+            medium_key: key,
+            content_type: "image/png",
+            width: 10,
+            height: 10,
+            byte_size: 100,
+            alt_text: "",
+            position: 0,
+        }
+    }
+
+    #[test]
+    fn test_images_keep_their_order() {
+        let mut conn = test_conn();
+        // Product 10 is otherwise unused by tests
+        let rendition = NewImageRendition {
+            source: "test/a.png",
+            format: "webp",
+            width: 5,
+            height: 5,
+            storage_key: "test/a-5w.webp",
+            byte_size: 10,
+        };
+        let a = insert_image(&mut conn, new_image(10, "test/a.png"), &[rendition]).unwrap();
+        let b = insert_image(&mut conn, new_image(10, "test/b.png"), &[]).unwrap();
+        let c = insert_image(&mut conn, new_image(10, "test/c.png"), &[]).unwrap();
+        assert!(a.position < b.position && b.position < c.position);
+
+        move_image(&mut conn, c.id, true).unwrap();
+        move_image(&mut conn, a.id, true).unwrap();
+        let order: Vec<i32> = load_images(&mut conn, 10).unwrap().iter().map(|i| i.id).collect();
+        assert_eq!(order, [a.id, c.id, b.id]);
+        assert_eq!(load_images_by_product(&mut conn, &[10, 11]).unwrap()[&10].len(), 3);
+
+        update_image_alt(&mut conn, b.id, "  Side view ").unwrap();
+        assert_eq!(load_image(&mut conn, b.id).unwrap().unwrap().alt_text, "Side view");
+
+        let (deleted, keys) = delete_image(&mut conn, a.id).unwrap();
+        assert_eq!(deleted.storage_key, "test/a.png");
+        assert_eq!(keys, ["test/a.png", "test/a.png", "test/a.png", "test/a-5w.webp"]);
+        assert_eq!(delete_renditions_of_product(&mut conn, 10).unwrap().len(), 6);
+        for image in [b, c] {
+            assert_eq!(delete_image(&mut conn, image.id).unwrap().0.storage_key, image.storage_key);
+        }
+        assert!(delete_image(&mut conn, 0).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+//! Order database helpers: recording a sale at checkout and loading orders back.
+//! Each order line snapshots product name/price so later product edits don't rewrite history.
+
+use crate::checkout::{ShippingAddress, ShippingMethod};
+use crate::db::cache::CategoriesCache;
+use crate::db::products::{restock_inventory, update_inventory};
+use crate::db::promotions::active_promotions;
+use crate::db::variants::load_variants_by_ids;
+use crate::errors::BeedleError;
+use crate::models::{CartItem, NewOrder, NewOrderLine, NewOrderStatusHistory, Order, OrderLine, OrderStatusHistory, Product};
+use crate::orders::OrderStatus;
+use crate::price::Price;
+use crate::promotions::promoted_price;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::Conn;
+
+/// Record an order for the given cart, starting in `PendingPayment`.
+/// Decrements inventory and inserts the order + its lines in a single transaction,
+/// so either the whole sale is recorded or nothing changes.
+/// Items are priced with the promotions running now, as the cart showed them; the total
+/// includes shipping, priced from the discounted item total.
+pub fn create_order(
+    conn: &mut Conn,
+    session_id_val: Uuid,
+    cart: &[CartItem],
+    address: &ShippingAddress,
+    method: ShippingMethod,
+) -> Result<Order, BeedleError> {
+    if cart.is_empty() {
+        return Err(BeedleError::InventoryError("Cannot create an order from an empty cart".into()));
+    }
+
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        // Nested transaction (savepoint); rolls back with us if anything below fails
+        update_inventory(conn, session_id_val, cart)?;
+
+        let variant_ids: Vec<i32> = cart.iter().map(|item| item.variant_id).collect();
+        let variants = load_variants_by_ids(conn, &variant_ids)?;
+        let ids: Vec<i32> = variants.iter().map(|v| v.variant.product_id).collect();
+        let products = {
+            use crate::schema::product::dsl::*;
+            product.filter(id.eq_any(&ids)).load::<Product>(conn)?
+        };
+        let active = active_promotions(conn, &products, &CategoriesCache::get(), chrono::Utc::now().naive_utc())?;
+
+        let mut subtotal = Price::default();
+        let mut total = Price::default();
+        let mut lines = Vec::with_capacity(cart.len());
+        for item in cart {
+            let variant = variants
+                .iter()
+                .find(|v| v.variant.id == item.variant_id)
+                .ok_or_else(|| BeedleError::InventoryError(format!("Variant {} not found", item.variant_id)))?;
+            let prod = products
+                .iter()
+                .find(|p| p.id == variant.variant.product_id)
+                .ok_or_else(|| BeedleError::InventoryError(format!("Product {} not found", variant.variant.product_id)))?;
+            let unit_price_original = variant.price_original(prod);
+            let (unit_price, _) = promoted_price(unit_price_original, prod.discount_percent, active.of(prod.id));
+            let line_total = unit_price * item.quantity as i64;
+
+            subtotal = subtotal + unit_price_original * item.quantity as i64;
+            total = total + line_total;
+            lines.push((prod, variant, item.quantity, unit_price_original, unit_price, line_total));
+        }
+
+        let shipping = method.cost(total);
+        let address_json = serde_json::to_value(address)
+            .map_err(|e| BeedleError::OrderError(format!("Address serialization error: {e}")))?;
+        let new_order = NewOrder {
+            session_id: session_id_val,
+            subtotal: subtotal.as_cents(),
+            discount_total: (subtotal - total).as_cents(),
+            total: (total + shipping).as_cents(),
+            status: OrderStatus::PendingPayment,
+            contact_email: Some(address.email.clone()),
+            shipping_address: Some(address_json),
+            shipping_method: Some(method.as_str().to_owned()),
+            shipping_total: shipping.as_cents(),
+        };
+        let order_row: Order = {
+            use crate::schema::order::dsl::*;
+            diesel::insert_into(order).values(&new_order).get_result(conn)?
+        };
+
+        let new_lines: Vec<NewOrderLine> = lines
+            .into_iter()
+            .map(|(prod, variant, qty, unit_price_original, unit_price, line_total)| NewOrderLine {
+                order_id: order_row.id,
+                product_id: Some(prod.id),
+                product_name: variant.display_name(prod),
+                unit_price_original: unit_price_original.as_cents(),
+                unit_price: unit_price.as_cents(),
+                quantity: qty as i32,
+                line_total: line_total.as_cents(),
+                variant_id: Some(variant.variant.id),
+                sku: Some(variant.variant.sku.clone()),
+            })
+            .collect();
+        {
+            use crate::schema::order_line::dsl::*;
+            diesel::insert_into(order_line).values(&new_lines).execute(conn)?;
+        }
+        insert_status_history(conn, order_row.id, None, order_row.status, "checkout", None)?;
+
+        log::info!(
+            "Created order {} for session {} ({} lines, total {})",
+            order_row.id, session_id_val, new_lines.len(), Price::from_cents(order_row.total).debug_string()
+        );
+        Ok(order_row)
+    })
+    .map_err(|e| {
+        log::error!("Creating order for session {} failed (rollback): {e}", session_id_val);
+        e
+    })
+}
+
+/// Move an order to a new status, recording who did it in `order_status_history`.
+/// Rejects transitions not allowed by `OrderStatus::allowed_transitions`.
+/// Side effects (eg restocking on cancellation) happen in the same transaction.
+pub fn transition_order_status(
+    conn: &mut Conn,
+    order_id_val: i32,
+    next: OrderStatus,
+    changed_by_val: &str,
+    note_val: Option<&str>,
+) -> Result<Order, BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let current = lock_for_transition(conn, order_id_val, next)?;
+        record_transition(conn, &current, next, changed_by_val, note_val)
+    })
+}
+
+/// Lock the order row, so two staff members can't move the same order at once, and check
+/// it may move to `next`.
+fn lock_for_transition(conn: &mut Conn, order_id_val: i32, next: OrderStatus) -> Result<Order, BeedleError> {
+    let current: Order = {
+        use crate::schema::order::dsl::*;
+        order
+            .filter(id.eq(order_id_val))
+            .for_update()
+            .first::<Order>(conn)
+            .optional()?
+            .ok_or_else(|| BeedleError::OrderError(format!("No order with id {}", order_id_val)))?
+    };
+
+    if !current.status.can_transition_to(next) {
+        log::warn!("Rejected order {} transition {} -> {}", order_id_val, current.status, next);
+        return Err(BeedleError::OrderError(format!(
+            "Order {} cannot go from {} to {}",
+            order_id_val, current.status.label(), next.label()
+        )));
+    }
+    Ok(current)
+}
+
+/// The move itself, for a locked and checked order: status, history and side effects.
+fn record_transition(
+    conn: &mut Conn,
+    current: &Order,
+    next: OrderStatus,
+    changed_by_val: &str,
+    note_val: Option<&str>,
+) -> Result<Order, BeedleError> {
+    let updated: Order = {
+        use crate::schema::order::dsl::*;
+        diesel::update(order.filter(id.eq(current.id)))
+            .set((status.eq(next), updated_at.eq(chrono::Utc::now().naive_utc())))
+            .get_result(conn)?
+    };
+    insert_status_history(conn, current.id, Some(current.status), next, changed_by_val, note_val)?;
+
+    if next.restocks_inventory() {
+        let items: Vec<CartItem> = load_order_lines(conn, current)?
+            .iter()
+            .filter_map(|line| line.variant_id.map(|vid| CartItem {
+                product_id: line.product_id.unwrap_or_default(),
+                variant_id: vid,
+                quantity: line.quantity as u32,
+            }))
+            .collect();
+        restock_inventory(conn, &items)?;
+    }
+
+    log::info!("Order {} moved {} -> {} by {}", current.id, current.status, next, changed_by_val);
+    Ok(updated)
+}
+
+/// Note that an order's money is about to go back on its way to `next`, and return the order
+/// with the idempotency key to send the payment provider. Asking again (a retry after a failed
+/// refund, or a second click) gets the same key, so the provider only refunds once.
+/// Only the check and the note happen under the order lock: call the provider afterwards,
+/// then record the move with `transition_order_status`.
+pub fn request_refund(conn: &mut Conn, order_id_val: i32, next: OrderStatus) -> Result<(Order, String), BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let current = lock_for_transition(conn, order_id_val, next)?;
+        if !current.status.refunds_payment(next) {
+            return Err(BeedleError::OrderError(format!(
+                "Order {} going from {} to {} isn't refunded",
+                order_id_val, current.status.label(), next.label()
+            )));
+        }
+        if let Some(key) = current.refund_key.clone() {
+            return Ok((current, key));
+        }
+
+        let key = format!("beedle-order-{}-refund-{}", current.id, Uuid::new_v4());
+        let updated: Order = {
+            use crate::schema::order::dsl::*;
+            let now = chrono::Utc::now().naive_utc();
+            diesel::update(order.filter(id.eq(current.id)))
+                .set((refund_key.eq(&key), refund_requested_at.eq(now), updated_at.eq(now)))
+                .get_result(conn)?
+        };
+        log::info!("Order {} refund requested on the way to {} ({})", current.id, next, key);
+        Ok((updated, key))
+    })
+}
+
+/// Remember which provider/payment reference belongs to an order (for captures, refunds and webhooks).
+pub fn set_order_payment(
+    conn: &mut Conn,
+    order_id_val: i32,
+    provider_name: &str,
+    reference: &str,
+) -> Result<(), BeedleError> {
+    use crate::schema::order::dsl::*;
+    diesel::update(order.filter(id.eq(order_id_val)))
+        .set((
+            payment_provider.eq(provider_name),
+            payment_reference.eq(reference),
+            updated_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)
+        .map_err(|e| {
+            log::error!("Saving payment reference for order {} failed: {e}", order_id_val);
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+    Ok(())
+}
+
+fn insert_status_history(
+    conn: &mut Conn,
+    order_id_val: i32,
+    from: Option<OrderStatus>,
+    to: OrderStatus,
+    changed_by_val: &str,
+    note_val: Option<&str>,
+) -> Result<(), BeedleError> {
+    use crate::schema::order_status_history::dsl::*;
+    diesel::insert_into(order_status_history)
+        .values(&NewOrderStatusHistory {
+            order_id: order_id_val,
+            from_status: from,
+            to_status: to,
+            changed_by: changed_by_val,
+            note: note_val,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Load the status history of an order, oldest first.
+pub fn load_order_history(conn: &mut Conn, order_in: &Order) -> Result<Vec<OrderStatusHistory>, BeedleError> {
+    use crate::schema::order_status_history::dsl::*;
+    OrderStatusHistory::belonging_to(order_in)
+        .order((changed_at.asc(), id.asc()))
+        .load::<OrderStatusHistory>(conn)
+        .map_err(|e| {
+            log::error!("Loading history for order {} failed: {e}", order_in.id);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Find an order by its ID. Returns Ok(None) if not found.
+pub fn load_order_by_id(conn: &mut Conn, order_id_val: i32) -> Result<Option<Order>, BeedleError> {
+    use crate::schema::order::dsl::*;
+    order
+        .filter(id.eq(order_id_val))
+        .first::<Order>(conn)
+        .optional()
+        .map_err(|e| {
+            log::error!("Loading order id {} failed: {e}", order_id_val);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Find the order paid for by a given provider payment (eg a Stripe PaymentIntent ID).
+pub fn load_order_by_payment_reference(
+    conn: &mut Conn,
+    provider_name: &str,
+    reference: &str,
+) -> Result<Option<Order>, BeedleError> {
+    use crate::schema::order::dsl::*;
+    order
+        .filter(payment_provider.eq(provider_name))
+        .filter(payment_reference.eq(reference))
+        .first::<Order>(conn)
+        .optional()
+        .map_err(|e| {
+            log::error!("Loading order for {} payment {} failed: {e}", provider_name, reference);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Load all lines of an order, in the order they were added to the cart.
+pub fn load_order_lines(conn: &mut Conn, order_in: &Order) -> Result<Vec<OrderLine>, BeedleError> {
+    use crate::schema::order_line::dsl::*;
+    OrderLine::belonging_to(order_in)
+        .order(id.asc())
+        .load::<OrderLine>(conn)
+        .map_err(|e| {
+            log::error!("Loading lines for order {} failed: {e}", order_in.id);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Load all orders, newest first.
+pub fn load_orders(conn: &mut Conn) -> Result<Vec<Order>, BeedleError> {
+    use crate::schema::order::dsl::*;
+    order.order(created_at.desc()).load::<Order>(conn).map_err(|e| {
+        log::error!("Loading all orders failed: {e}");
+        BeedleError::DatabaseError(e.to_string())
+    })
+}
+
+#[cfg(test)]
+mod orders_tests {
+    use super::*;
+    use crate::db::{categories::test_category, products::{insert_product, load_product_by_id}, test_conn, test_product, variants::cart_item};
+    use crate::models::ProductLists;
+
+    #[test]
+    fn test_create_order_snapshots_cart() {
+        let mut conn = test_conn();
+        let sid = Uuid::new_v4();
+        let category_id_val = test_category(&mut conn, "Test Ordered");
+        let apple = insert_product(&mut conn, &test_product("Test Ordered Apple", category_id_val, 120, 5, Some(10.0)), &ProductLists::default())
+            .unwrap();
+        let cart = vec![cart_item(&mut conn, apple.id, 2)];
+
+        let created = create_order(&mut conn, sid, &cart, &ShippingAddress::sample(), ShippingMethod::Standard)
+            .expect("Order creation failed");
+        let lines = load_order_lines(&mut conn, &created).unwrap();
+        let after = load_product_by_id(&mut conn, apple.id).unwrap().unwrap();
+
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].product_name, apple.name);
+        assert_eq!(lines[0].variant_id, Some(cart[0].variant_id));
+        assert_eq!(lines[0].quantity, 2);
+        assert_eq!(lines[0].unit_price, Price::from_cents(apple.price).with_discount_percent(apple.discount_percent).as_cents());
+        assert_eq!(created.shipping_total, ShippingMethod::Standard.cost(Price::from_cents(lines[0].line_total)).as_cents());
+        assert_eq!(created.total, lines[0].line_total + created.shipping_total);
+        assert_eq!(created.subtotal - created.discount_total + created.shipping_total, created.total);
+        assert_eq!(created.contact_email.as_deref(), Some(ShippingAddress::sample().email.as_str()));
+        assert_eq!(after.inventory, 3);
+        assert_eq!(created.status, OrderStatus::PendingPayment);
+
+        diesel::delete(crate::schema::order::table.find(created.id)).execute(&mut conn).unwrap();
+        crate::db::products::delete_product(&mut conn, apple.id).unwrap();
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_create_order_rolls_back_on_insufficient_stock() {
+        let mut conn = test_conn();
+        let sid = Uuid::new_v4();
+        let category_id_val = test_category(&mut conn, "Test Unordered");
+        let pear = insert_product(&mut conn, &test_product("Test Unordered Pear", category_id_val, 100, 1, None), &ProductLists::default())
+            .unwrap();
+        let plum = insert_product(&mut conn, &test_product("Test Unordered Plum", category_id_val, 100, 1, None), &ProductLists::default())
+            .unwrap();
+        let cart = vec![
+            cart_item(&mut conn, pear.id, 1),
+            cart_item(&mut conn, plum.id, 2),
+        ];
+
+        assert!(create_order(&mut conn, sid, &cart, &ShippingAddress::sample(), ShippingMethod::Pickup).is_err());
+        let after = load_product_by_id(&mut conn, pear.id).unwrap().unwrap();
+        assert_eq!(after.inventory, 1);
+
+        {
+            use crate::schema::order::dsl::*;
+            let count: i64 = order.filter(session_id.eq(sid)).count().get_result(&mut conn).unwrap();
+            assert_eq!(count, 0);
+        }
+        for made in [pear, plum] {
+            crate::db::products::delete_product(&mut conn, made.id).unwrap();
+        }
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_cancelling_order_restocks_and_records_history() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Cancelled Order");
+        let tea = insert_product(&mut conn, &test_product("Test Cancelled Tea", category_id_val, 300, 4, None), &ProductLists::default())
+            .unwrap();
+        let cart = vec![cart_item(&mut conn, tea.id, 1)];
+        let created = create_order(&mut conn, Uuid::new_v4(), &cart, &ShippingAddress::sample(), ShippingMethod::Pickup)
+            .expect("Order creation failed");
+
+        transition_order_status(&mut conn, created.id, OrderStatus::Paid, "test", None).unwrap();
+        assert!(transition_order_status(&mut conn, created.id, OrderStatus::Delivered, "test", None).is_err());
+        let cancelled = transition_order_status(&mut conn, created.id, OrderStatus::Cancelled, "test", Some("changed mind")).unwrap();
+
+        let after = load_product_by_id(&mut conn, tea.id).unwrap().unwrap();
+        let history = load_order_history(&mut conn, &cancelled).unwrap();
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert_eq!(after.inventory, 4);
+        let steps: Vec<_> = history.iter().map(|h| (h.from_status, h.to_status)).collect();
+        assert_eq!(steps, vec![
+            (None, OrderStatus::PendingPayment),
+            (Some(OrderStatus::PendingPayment), OrderStatus::Paid),
+            (Some(OrderStatus::Paid), OrderStatus::Cancelled),
+        ]);
+
+        diesel::delete(crate::schema::order::table.find(created.id)).execute(&mut conn).unwrap();
+        crate::db::products::delete_product(&mut conn, tea.id).unwrap();
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_refund_request_keeps_its_key_until_recorded() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Refund Request");
+        let jam = insert_product(&mut conn, &test_product("Test Refunded Jam", category_id_val, 500, 2, None), &ProductLists::default())
+            .unwrap();
+        let cart = vec![cart_item(&mut conn, jam.id, 1)];
+        let created = create_order(&mut conn, Uuid::new_v4(), &cart, &ShippingAddress::sample(), ShippingMethod::Pickup)
+            .expect("Order creation failed");
+
+        // Nothing to give back before it's paid, and no going straight to delivered after
+        assert!(request_refund(&mut conn, created.id, OrderStatus::Cancelled).is_err());
+        transition_order_status(&mut conn, created.id, OrderStatus::Paid, "test", None).unwrap();
+        assert!(request_refund(&mut conn, created.id, OrderStatus::Delivered).is_err());
+
+        // Asking twice, eg after the provider call failed, sends the same key; the status waits
+        let (requested, key) = request_refund(&mut conn, created.id, OrderStatus::Cancelled).unwrap();
+        assert_eq!(request_refund(&mut conn, created.id, OrderStatus::Cancelled).unwrap().1, key);
+        assert_eq!(requested.status, OrderStatus::Paid);
+        assert_eq!(requested.refund_key.as_deref(), Some(key.as_str()));
+        assert!(requested.refund_requested_at.is_some());
+
+        let cancelled = transition_order_status(&mut conn, created.id, OrderStatus::Cancelled, "test", Some("refunded")).unwrap();
+        assert_eq!(cancelled.refund_key.as_deref(), Some(key.as_str()));
+        assert_eq!(load_order_history(&mut conn, &cancelled).unwrap().len(), 3);
+
+        diesel::delete(crate::schema::order::table.find(created.id)).execute(&mut conn).unwrap();
+        crate::db::products::delete_product(&mut conn, jam.id).unwrap();
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+}
@@ -1,14 +1,21 @@
 //! Product database helpers: loading, CRUD, inventory adjustment, etc.
 
 use crate::errors::BeedleError;
-use crate::models::{CartItem, NewProduct, Product};
+use crate::models::{CartItem, NewProduct, Product, ProductLists, ProductVariant};
 use crate::schema::product::dsl::*;
 use diesel::{
+    pg::Pg,
     prelude::*,
-    {ExpressionMethods, QueryDsl, RunQueryDsl, TextExpressionMethods},
+    {ExpressionMethods, QueryDsl, RunQueryDsl},
 };
+use diesel::dsl::sql;
+use diesel::sql_types::{BigInt, Float4, Integer, Nullable, Timestamp};
+use std::collections::HashMap;
 
-use super::Conn;
+use super::search;
+use super::tags::{lower, tag_ids};
+use super::{reservations::reserved_by_others, variants::create_default_variant, Conn};
+use uuid::Uuid;
 
 /// Load all products, ordered by ID ascending.
 pub fn load_products(conn: &mut Conn) -> Result<Vec<Product>, BeedleError> {
@@ -18,93 +25,408 @@ pub fn load_products(conn: &mut Conn) -> Result<Vec<Product>, BeedleError> {
     })
 }
 
-/// Filter and page products by category/tag/search/sort.
-/// Accepts optional filters and paginates with limit/offset.
+/// What the catalog is narrowed down to. Empty/`None` fields don't filter.
+#[derive(Debug, Clone, Default)]
+pub struct ProductFilter {
+    /// Products in any of these categories. Pass a category with its subcategories
+    /// (`CategoryTree::subtree_ids`); an empty list matches nothing.
+    pub category_ids: Option<Vec<i32>>,
+    /// Whole tag names, ignoring case
+    pub tags: Vec<String>,
+    /// Products need every one of `tags`, rather than any
+    pub all_tags: bool,
+    /// Full-text search (see `db::search`)
+    pub search: Option<String>,
+    /// Base sale price (see `sale_price`) in cents, inclusive
+    pub min_price: Option<i64>,
+    pub max_price: Option<i64>,
+    /// Only products with stock
+    pub in_stock: bool,
+    /// Only products whose discount takes something off
+    pub on_sale: bool,
+    /// Price with the promotions running at this time (see `sale_price`) for the price
+    /// filters, sort and `on_sale`. Slower, so only set while some are running.
+    pub promotions_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ProductFilter {
+    /// The search terms, if there are any
+    pub fn search_terms(&self) -> Option<&str> {
+        self.search.as_deref().map(str::trim).filter(|s| !s.is_empty())
+    }
+}
+
+// What a product sells for with the promotions running `at`; see the migration
+diesel::define_sql_function!(fn promoted_price(product_id_val: Integer, category_id_val: Integer, list: BigInt, own_percent: Nullable<Float4>, at: Timestamp) -> BigInt);
+
+/// What a product's base price sells for, in cents, so what's sorted and filtered on is what
+/// `ProductView` shows in listings. Variants' own prices aren't covered.
 ///
-/// # Parameters
-/// * `category_opt` - Optional category filter
-/// * `tag_opt` - Optional tag filter
-/// * `search_opt` - Optional substring/full-text search
-/// * `sort_opt` - Optional sort order ("alpha", "price_low", etc)
-/// * `limit_opt`, `offset_opt` - Pagination controls
-pub fn filter_products(
-    conn: &mut Conn,
-    category_opt: Option<&str>,
-    tag_opt: Option<&str>,
-    search_opt: Option<&str>,
-    sort_opt: Option<&str>,
-    limit_opt: usize,
-    offset_opt: usize,
-) -> Result<Vec<Product>, BeedleError> {
+/// Usually `product.sale_price`: the price after `discount_percent`, a generated column
+/// rounded the same as `Price::with_discount_percent`, and indexed. Promotions come and go
+/// with the clock, so a column can't hold them; given `promotions_at`, it's the
+/// `promoted_price` SQL function instead, which works out `promotions::promoted_price` for
+/// each row with the promotions running then. Left out of `schema.rs` so `Product` needn't
+/// load it.
+pub(super) fn sale_price(
+    promotions_at: Option<chrono::NaiveDateTime>,
+) -> Box<dyn BoxableExpression<crate::schema::product::table, Pg, SqlType = BigInt>> {
+    match promotions_at {
+        Some(at) => Box::new(promoted_price(id, category_id, price, discount_percent, at)),
+        None => Box::new(sql::<BigInt>("product.sale_price")),
+    }
+}
+
+/// Products matching the catalog filters, unsorted.
+pub(super) fn filtered(filter: &ProductFilter) -> crate::schema::product::BoxedQuery<'_, Pg> {
     let mut query = product.into_boxed();
 
-    if let Some(cat) = category_opt.filter(|c| !c.trim().is_empty()) {
-        query = query.filter(category.eq(cat));
+    if let Some(ids) = &filter.category_ids {
+        query = query.filter(category_id.eq_any(ids));
     }
 
-    if let Some(tag_val) = tag_opt.filter(|t| !t.trim().is_empty()) {
-        query = query.filter(tags.like(format!("%{}%", tag_val)));
+    let tag_names: Vec<String> =
+        filter.tags.iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect();
+    if !tag_names.is_empty() {
+        use crate::schema::{product_tag, tag};
+        let tagged = |names: Vec<String>| {
+            product_tag::table
+                .inner_join(tag::table)
+                .filter(lower(tag::name).eq_any(names))
+                .select(product_tag::product_id)
+        };
+        if filter.all_tags {
+            for tag_name in tag_names {
+                query = query.filter(id.eq_any(tagged(vec![tag_name])));
+            }
+        } else {
+            query = query.filter(id.eq_any(tagged(tag_names)));
+        }
     }
 
     // Text search 
-    let like_expr = search_opt
-        .filter(|s| !s.trim().is_empty())
-        .map(|search_str| format!("%{}%", search_str));
-    if let Some(ref like_expr) = like_expr {
-        query = query.filter(
-            name.ilike(like_expr.clone())
-                .or(description.ilike(like_expr.clone()))
-                .or(tagline.ilike(like_expr.clone()))
-        );
-    }
-
-    // Sorting
-    query = match sort_opt {
-        Some("alpha")           => query.order(name.asc()),
-        Some("price_low")       => query.order(price.asc()),
-        Some("price_high")      => query.order(price.desc()),
-        Some("newest")          => query.order(added_date.desc()),
-        Some("oldest")          => query.order(added_date.asc()),
-        _=> query.order(added_date.desc()), // Default: newest
-    };
+    if let Some(search_str) = filter.search_terms() {
+        query = query.filter(search::matches(search_str));
+    }
 
+    if let Some(min) = filter.min_price {
+        query = query.filter(sale_price(filter.promotions_at).ge(min));
+    }
+    if let Some(max) = filter.max_price {
+        query = query.filter(sale_price(filter.promotions_at).le(max));
+    }
+    if filter.in_stock {
+        query = query.filter(inventory.gt(0));
+    }
+    if filter.on_sale {
+        query = query.filter(sale_price(filter.promotions_at).lt(price));
+    }
     query
-        .limit(limit_opt as i64)
-        .offset(offset_opt as i64)
-        .load::<Product>(conn)
-        .map_err(|e| {
+}
+
+/// Catalog sort orders, from `?sort=`. Ties are broken by ID so every order is total,
+/// which keyset paging (see `Cursor`) relies on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProductSort {
+    /// Best search matches first; only when searching
+    Relevance,
+    Alpha,
+    PriceLow,
+    PriceHigh,
+    #[default]
+    Newest,
+    Oldest,
+}
+
+impl ProductSort {
+    /// "alpha", "price_low", "price_high", "newest" or "oldest". Blank or "relevance" is
+    /// relevance when `searching`; anything else is newest first.
+    pub fn parse(sort_opt: Option<&str>, searching: bool) -> Self {
+        match sort_opt.map(str::trim) {
+            Some("relevance") | Some("") | None if searching => ProductSort::Relevance,
+            Some("alpha") => ProductSort::Alpha,
+            Some("price_low") => ProductSort::PriceLow,
+            Some("price_high") => ProductSort::PriceHigh,
+            Some("oldest") => ProductSort::Oldest,
+            _ => ProductSort::Newest,
+        }
+    }
+
+    /// Whether the sort key goes up down the page
+    fn ascending(self) -> bool {
+        matches!(self, ProductSort::Alpha | ProductSort::PriceLow | ProductSort::Oldest)
+    }
+
+    /// Where `row`, which sells for `sold_for` (its `sale_price` with the promotions running
+    /// at `promotions_at`), falls in this order, for a cursor. Relevance can't be paged by keyset.
+    fn cursor(self, row: &Product, sold_for: i64, promotions_at: Option<chrono::NaiveDateTime>) -> Option<Cursor> {
+        let key = match self {
+            ProductSort::Relevance => return None,
+            ProductSort::Alpha => SortKey::Name(row.name.clone()),
+            ProductSort::PriceLow | ProductSort::PriceHigh => SortKey::Price(sold_for, promotions_at),
+            ProductSort::Newest | ProductSort::Oldest => SortKey::Added(row.added_date),
+        };
+        Some(Cursor { key, id: row.id })
+    }
+}
+
+/// A row's sort key, as saved in a `Cursor`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortKey {
+    Name(String),
+    /// Sale price, in cents, and the time of the promotions it was priced with, if any.
+    /// Later pages price by those same promotions, so one starting or ending meanwhile
+    /// doesn't reorder the listing under the cursor.
+    Price(i64, Option<chrono::NaiveDateTime>),
+    Added(chrono::NaiveDateTime),
+}
+
+/// A position in a sorted listing: the sort key and ID of a row. Pages continue from one
+/// with `WHERE (key, id) > (…)` rather than OFFSET, so they stay quick deep into the
+/// catalog and don't skip or repeat products when new ones are added meanwhile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub key: SortKey,
+    pub id: i32,
+}
+
+impl Cursor {
+    /// An opaque URL-safe token, eg for `?after=`
+    pub fn token(&self) -> String {
+        let text = match &self.key {
+            SortKey::Name(name_val) => format!("n{}:{}", self.id, name_val),
+            SortKey::Price(cents, None) => format!("p{}:{}", self.id, cents),
+            SortKey::Price(cents, Some(at)) => format!("p{}:{}@{}", self.id, cents, at.and_utc().timestamp_micros()),
+            SortKey::Added(added) => format!("a{}:{}", self.id, added.and_utc().timestamp_micros()),
+        };
+        hex::encode(text)
+    }
+
+    /// Read a `token`; `None` if it isn't one
+    pub fn from_token(token: &str) -> Option<Self> {
+        let text = String::from_utf8(hex::decode(token.trim()).ok()?).ok()?;
+        let mut chars = text.chars();
+        let kind = chars.next()?;
+        let (id_text, value) = chars.as_str().split_once(':')?;
+        let key = match kind {
+            'n' => SortKey::Name(value.to_owned()),
+            'p' => match value.split_once('@') {
+                Some((cents, at)) => {
+                    let at = chrono::DateTime::from_timestamp_micros(at.parse().ok()?)?.naive_utc();
+                    SortKey::Price(cents.parse().ok()?, Some(at))
+                }
+                None => SortKey::Price(value.parse().ok()?, None),
+            },
+            'a' => SortKey::Added(chrono::DateTime::from_timestamp_micros(value.parse().ok()?)?.naive_utc()),
+            _ => return None,
+        };
+        Some(Cursor { key, id: id_text.parse().ok()? })
+    }
+
+    /// Whether this cursor came from a listing in `sort`'s order
+    fn fits(&self, sort: ProductSort) -> bool {
+        matches!(
+            (&self.key, sort),
+            (SortKey::Name(_), ProductSort::Alpha)
+                | (SortKey::Price(..), ProductSort::PriceLow | ProductSort::PriceHigh)
+                | (SortKey::Added(_), ProductSort::Newest | ProductSort::Oldest)
+        )
+    }
+}
+
+/// Which side of a cursor a page is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seek {
+    After,
+    Before,
+}
+
+/// One page of a `ProductQuery`'s results
+#[derive(Debug, Clone, Default)]
+pub struct ProductPage {
+    pub products: Vec<Product>,
+    /// Products matching the filter, on every page
+    pub total: i64,
+    /// For the pages either side, `Seek::After` this and `Seek::Before` that. `None` at
+    /// either end of the listing, and always for relevance order.
+    pub next: Option<Cursor>,
+    pub previous: Option<Cursor>,
+}
+
+/// A catalog listing: which products (see `ProductFilter`), in what order, and which
+/// page of them: by offset, or by keyset from a `Cursor`. The page and the total count
+/// are built from the same filtered query, so a new filter only needs adding to `filtered`.
+///
+/// ```ignore
+/// let page = ProductQuery::new(filter).sorted(Some("price_low")).page(20, 40).load(conn)?;
+/// let next = ProductQuery::new(filter).sorted(Some("price_low")).page(20, 0).seek(Seek::After, page.next).load(conn)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProductQuery {
+    pub filter: ProductFilter,
+    pub sort: ProductSort,
+    /// Page size; `None` for every product
+    pub limit: Option<usize>,
+    pub offset: usize,
+    /// Start from a cursor instead of `offset`
+    pub cursor: Option<(Seek, Cursor)>,
+}
+
+impl ProductQuery {
+    pub fn new(filter: ProductFilter) -> Self {
+        ProductQuery { filter, ..ProductQuery::default() }
+    }
+
+    /// See `ProductSort::parse`
+    pub fn sorted(mut self, sort_opt: Option<&str>) -> Self {
+        self.sort = ProductSort::parse(sort_opt, self.filter.search_terms().is_some());
+        self
+    }
+
+    pub fn page(mut self, limit_val: usize, offset_val: usize) -> Self {
+        self.limit = Some(limit_val);
+        self.offset = offset_val;
+        self
+    }
+
+    /// Page from `cursor` rather than the offset. A cursor from another sort order is
+    /// ignored, so a stale link lands on the first page instead of a wrong one. One by price
+    /// prices by the promotions it was made with (see `SortKey::Price`), not the filter's.
+    pub fn seek(mut self, seek: Seek, cursor: Option<Cursor>) -> Self {
+        self.cursor = cursor.filter(|c| c.fits(self.sort)).map(|c| (seek, c));
+        if let Some((_, Cursor { key: SortKey::Price(_, promotions_at), .. })) = &self.cursor {
+            self.filter.promotions_at = *promotions_at;
+        }
+        self
+    }
+
+    /// The filtered products in order (or reverse order, for paging backwards)
+    fn ordered(&self, reversed: bool) -> crate::schema::product::BoxedQuery<'_, Pg> {
+        let query = filtered(&self.filter);
+        let ascending = self.sort.ascending() != reversed;
+        match (self.sort, self.filter.search_terms()) {
+            (ProductSort::Relevance, Some(search_str)) => {
+                query.order((search::rank(search_str).desc(), added_date.desc(), id.desc()))
+            }
+            (ProductSort::Alpha, _) if ascending => query.order((name.asc(), id.asc())),
+            (ProductSort::Alpha, _) => query.order((name.desc(), id.desc())),
+            (ProductSort::PriceLow | ProductSort::PriceHigh, _) if ascending => {
+                query.order((sale_price(self.filter.promotions_at).asc(), id.asc()))
+            }
+            (ProductSort::PriceLow | ProductSort::PriceHigh, _) => query.order((sale_price(self.filter.promotions_at).desc(), id.desc())),
+            _ if ascending => query.order((added_date.asc(), id.asc())),
+            _ => query.order((added_date.desc(), id.desc())), // Default: newest
+        }
+    }
+
+    /// Rows past `cursor` in `ordered(reversed)` order
+    fn past<'a>(
+        query: crate::schema::product::BoxedQuery<'a, Pg>,
+        cursor: &'a Cursor,
+        ascending: bool,
+        promotions_at: Option<chrono::NaiveDateTime>,
+    ) -> crate::schema::product::BoxedQuery<'a, Pg> {
+        let after_id = id.gt(cursor.id);
+        let before_id = id.lt(cursor.id);
+        let sale_price = || sale_price(promotions_at);
+        match (&cursor.key, ascending) {
+            (SortKey::Name(v), true) => query.filter(name.gt(v).or(name.eq(v).and(after_id))),
+            (SortKey::Name(v), false) => query.filter(name.lt(v).or(name.eq(v).and(before_id))),
+            (SortKey::Price(v, _), true) => query.filter(sale_price().gt(*v).or(sale_price().eq(*v).and(after_id))),
+            (SortKey::Price(v, _), false) => query.filter(sale_price().lt(*v).or(sale_price().eq(*v).and(before_id))),
+            (SortKey::Added(v), true) => query.filter(added_date.gt(v).or(added_date.eq(v).and(after_id))),
+            (SortKey::Added(v), false) => query.filter(added_date.lt(v).or(added_date.eq(v).and(before_id))),
+        }
+    }
+
+    /// Load the page. By offset, the page and the total come in one round trip:
+    /// `count(*) OVER ()` is worked out before LIMIT/OFFSET, so every row carries the full
+    /// count (a page past the end has no rows to carry it and falls back to `count`).
+    /// From a cursor, the rows before it are filtered out first, so that takes a `count`.
+    pub fn load(&self, conn: &mut Conn) -> Result<ProductPage, BeedleError> {
+        match &self.cursor {
+            Some((seek, cursor)) => self.load_from(conn, *seek, cursor),
+            None => self.load_offset(conn),
+        }
+    }
+
+    fn load_offset(&self, conn: &mut Conn) -> Result<ProductPage, BeedleError> {
+        let mut query = self
+            .ordered(false)
+            .select((
+                crate::schema::product::all_columns,
+                sale_price(self.filter.promotions_at),
+                sql::<BigInt>("count(*) OVER ()"),
+            ))
+            .offset(i64::try_from(self.offset).unwrap_or(i64::MAX));
+        if let Some(limit_val) = self.limit {
+            query = query.limit(limit_val as i64);
+        }
+        let rows = query.load::<(Product, i64, i64)>(conn).map_err(|e| {
             log::error!("Filtering products failed: {e}");
             BeedleError::DatabaseError(e.to_string())
+        })?;
+
+        let total = match rows.first() {
+            Some((_, _, total)) => *total,
+            None if self.offset == 0 => 0,
+            None => self.count(conn)?,
+        };
+        let more = (self.offset.saturating_add(rows.len()) as i64) < total;
+        Ok(ProductPage {
+            next: rows.last().filter(|_| more).and_then(|(p, sold_for, _)| self.sort.cursor(p, *sold_for, self.filter.promotions_at)),
+            previous: rows.first().filter(|_| self.offset > 0).and_then(|(p, sold_for, _)| self.sort.cursor(p, *sold_for, self.filter.promotions_at)),
+            products: rows.into_iter().map(|(p, _, _)| p).collect(),
+            total,
         })
-}
+    }
 
-/// Counts total number of products matching the given filters.
-pub fn count_filtered_products(
-    conn: &mut Conn,
-    category_opt: Option<&str>,
-    tag_opt: Option<&str>,
-    search_opt: Option<&str>,
-) -> Result<i64, BeedleError> {
-    use crate::schema::product::dsl::*;
-    let mut query = product.into_boxed();
+    fn load_from(&self, conn: &mut Conn, seek: Seek, cursor: &Cursor) -> Result<ProductPage, BeedleError> {
+        let reversed = seek == Seek::Before;
+        let ascending = self.sort.ascending() != reversed;
+        let mut query = Self::past(self.ordered(reversed), cursor, ascending, self.filter.promotions_at);
+        // One extra row says whether there's another page beyond this one
+        if let Some(limit_val) = self.limit {
+            query = query.limit(limit_val as i64 + 1);
+        }
+        let mut rows = query
+            .select((crate::schema::product::all_columns, sale_price(self.filter.promotions_at)))
+            .load::<(Product, i64)>(conn)
+            .map_err(|e| {
+                log::error!("Paging products from a cursor failed: {e}");
+                BeedleError::DatabaseError(e.to_string())
+            })?;
+        let more = self.limit.is_some_and(|limit_val| rows.len() > limit_val);
+        if let Some(limit_val) = self.limit {
+            rows.truncate(limit_val);
+        }
+        if reversed {
+            rows.reverse();
+        }
 
-    if let Some(cat) = category_opt.filter(|s| !s.trim().is_empty()) {
-        query = query.filter(category.eq(cat));
-    }
-    if let Some(tag_val) = tag_opt.filter(|s| !s.trim().is_empty()) {
-        query = query.filter(tags.like(format!("%{}%", tag_val)));
+        // The cursor's own row is on the side we came from
+        let (more_after, more_before) = if reversed { (true, more) } else { (more, true) };
+        Ok(ProductPage {
+            next: rows.last().filter(|_| more_after).and_then(|(p, sold_for)| self.sort.cursor(p, *sold_for, self.filter.promotions_at)),
+            previous: rows.first().filter(|_| more_before).and_then(|(p, sold_for)| self.sort.cursor(p, *sold_for, self.filter.promotions_at)),
+            total: self.count(conn)?,
+            products: rows.into_iter().map(|(p, _)| p).collect(),
+        })
     }
-    if let Some(search_str) = search_opt.filter(|s| !s.trim().is_empty()) {
-        let like_expr = format!("%{}%", search_str);
-        query = query.filter(
-            name.ilike(like_expr.clone())
-                .or(description.ilike(like_expr.clone()))
-                .or(tagline.ilike(like_expr.clone())),
-        );
+
+    /// How many products match the filter, ignoring the page
+    pub fn count(&self, conn: &mut Conn) -> Result<i64, BeedleError> {
+        filtered(&self.filter).count().get_result(conn).map_err(|e| {
+            log::error!("Product count with filter failed: {}", e);
+            BeedleError::DatabaseError(e.to_string())
+        })
     }
+}
 
-    query.count().get_result(conn).map_err(|e| {
-        log::error!("Product count with filter failed: {}", e);
+/// The products with these IDs, ordered by ID ascending. Missing ones are skipped.
+pub fn load_products_by_ids(conn: &mut Conn, product_ids: &[i32]) -> Result<Vec<Product>, BeedleError> {
+    product.filter(id.eq_any(product_ids)).order(id.asc()).load::<Product>(conn).map_err(|e| {
+        log::error!("Loading products {:?} failed: {e}", product_ids);
         BeedleError::DatabaseError(e.to_string())
     })
 }
@@ -121,45 +443,198 @@ pub fn load_product_by_id(conn: &mut Conn, product_id_val: i32) -> Result<Option
         })
 }
 
-/// Update/save an existing product. Returns error if product ID not found or update fails.
-/// Used for admin/product-edit (not needed for cart/browse).
-pub fn save_product(conn: &mut Conn, product_in: &Product) -> Result<(), BeedleError> {
-    let updated_rows = diesel::update(product.filter(id.eq(product_in.id)))
-        .set((
-            name.eq(&product_in.name),
-            price.eq(&product_in.price),
-            inventory.eq(product_in.inventory),
-            category.eq(&product_in.category),
-            tags.eq(&product_in.tags),
-            keywords.eq(&product_in.keywords),
-            thumbnail_url.eq(&product_in.thumbnail_url),
-            gallery_urls.eq(&product_in.gallery_urls),
-            tagline.eq(&product_in.tagline),
-            description.eq(&product_in.description),
-            discount_percent.eq(&product_in.discount_percent),
-        ))
-        .execute(conn)
+/// Find a product by its current slug. Returns Ok(None) if no product has it.
+pub fn load_product_by_slug(conn: &mut Conn, slug_val: &str) -> Result<Option<Product>, BeedleError> {
+    product
+        .filter(slug.eq(slug_val))
+        .first::<Product>(conn)
+        .optional()
         .map_err(|e| {
-            log::error!("Failed to update product {}: {e}", product_in.id);
+            log::error!("Loading product by slug {:?} failed: {e}", slug_val);
             BeedleError::DatabaseError(e.to_string())
-        })?;
-    if updated_rows == 0 {
-        Err(BeedleError::DatabaseError("No product rows updated (id not found)".to_string()))
-    } else {
-        log::info!("Product {} updated", product_in.id);
-        Ok(())
+        })
+}
+
+/// The current slug of the product that used to be called `old_slug`, for redirecting.
+pub fn renamed_slug(conn: &mut Conn, old_slug: &str) -> Result<Option<String>, BeedleError> {
+    use crate::schema::product_slug_history as history;
+    history::table
+        .inner_join(product)
+        .filter(history::slug.eq(old_slug))
+        .select(slug)
+        .first::<String>(conn)
+        .optional()
+        .map_err(|e| {
+            log::error!("Looking up old product slug {:?} failed: {e}", old_slug);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// ID of the product currently using `slug_val`, if any. Slugs other products used to
+/// have are free to take; their old links then lead to the new owner.
+pub fn slug_owner(conn: &mut Conn, slug_val: &str) -> Result<Option<i32>, BeedleError> {
+    product
+        .filter(slug.eq(slug_val))
+        .select(id)
+        .first::<i32>(conn)
+        .optional()
+        .map_err(|e| {
+            log::error!("Looking up product slug {:?} failed: {e}", slug_val);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Keep `old_slug` leading to the product now called `new_slug`, and take `new_slug`
+/// out of the history in case it was someone's old one.
+fn retire_slug(conn: &mut Conn, product_id_val: i32, old_slug: Option<&str>, new_slug: &str) -> Result<(), diesel::result::Error> {
+    use crate::schema::product_slug_history as history;
+    diesel::delete(history::table.filter(history::slug.eq(new_slug))).execute(conn)?;
+    if let Some(old_slug) = old_slug.filter(|old| *old != new_slug) {
+        let row = (history::slug.eq(old_slug), history::product_id.eq(product_id_val));
+        diesel::insert_into(history::table)
+            .values(row)
+            .on_conflict(history::slug)
+            .do_update()
+            .set((history::product_id.eq(product_id_val), history::retired_at.eq(diesel::dsl::now)))
+            .execute(conn)?;
     }
+    Ok(())
 }
 
-/// Create a new product and returns it.
-pub fn insert_product(conn: &mut Conn, new_product: &NewProduct) -> Result<Product, BeedleError> {
-    diesel::insert_into(product)
-        .values(new_product)
-        .get_result(conn)
+/// Save an admin edit of a product, including its dates.
+/// Inventory isn't saved here: it's the sum of the variants' stock (see `db::variants`).
+///
+/// `product_in.version` must be the version the edit started from; if anyone saved the
+/// product since, nothing is written and `EditConflict` is returned. Returns the new version.
+/// A changed slug keeps the old one redirecting (`product_slug_history`).
+pub fn save_product(conn: &mut Conn, product_in: &Product, lists: &ProductLists) -> Result<i32, BeedleError> {
+    let saved_version = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let old_slug = product.find(product_in.id).select(slug).for_update().first::<String>(conn).optional()?;
+            let saved_version = diesel::update(product.filter(id.eq(product_in.id)).filter(version.eq(product_in.version)))
+                .set((
+                    name.eq(&product_in.name),
+                    price.eq(&product_in.price),
+                    category_id.eq(&product_in.category_id),
+                    slug.eq(&product_in.slug),
+                    thumbnail_url.eq(&product_in.thumbnail_url),
+                    tagline.eq(&product_in.tagline),
+                    description.eq(&product_in.description),
+                    discount_percent.eq(&product_in.discount_percent),
+                    added_date.eq(&product_in.added_date),
+                    restock_date.eq(&product_in.restock_date),
+                    version.eq(version + 1),
+                ))
+                .returning(version)
+                .get_result::<i32>(conn)
+                .optional()?;
+            if saved_version.is_some() {
+                replace_product_lists(conn, product_in.id, lists)?;
+                retire_slug(conn, product_in.id, old_slug.as_deref(), &product_in.slug)?;
+            }
+            Ok(saved_version)
+        })
         .map_err(|e| {
-            log::error!("Insert product failed: {e}");
+            log::error!("Failed to update product {}: {e}", product_in.id);
             BeedleError::DatabaseError(e.to_string())
+        })?;
+
+    match saved_version {
+        Some(saved_version) => {
+            log::info!("Product {} updated to version {}", product_in.id, saved_version);
+            Ok(saved_version)
+        }
+        None if load_product_by_id(conn, product_in.id)?.is_some() => {
+            log::warn!("Product {} changed since version {} was loaded; not saving", product_in.id, product_in.version);
+            Err(BeedleError::EditConflict(format!(
+                "Product {} was changed by someone else since you opened it",
+                product_in.id
+            )))
+        }
+        None => Err(BeedleError::DatabaseError("No product rows updated (id not found)".to_string())),
+    }
+}
+
+/// Create a new product, along with a default variant holding its inventory, and return it.
+pub fn insert_product(conn: &mut Conn, new_product: &NewProduct, lists: &ProductLists) -> Result<Product, BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let inserted: Product = diesel::insert_into(product).values(new_product).get_result(conn)?;
+        create_default_variant(conn, inserted.id, new_product.inventory)?;
+        replace_product_lists(conn, inserted.id, lists)?;
+        retire_slug(conn, inserted.id, None, &inserted.slug)?;
+        Ok(inserted)
+    })
+    .map_err(|e| {
+        log::error!("Insert product failed: {e}");
+        BeedleError::DatabaseError(e.to_string())
+    })
+}
+
+/// The tags, keywords and gallery URLs of each of `product_ids` (missing if it has none).
+pub fn load_product_lists(conn: &mut Conn, product_ids: &[i32]) -> Result<HashMap<i32, ProductLists>, BeedleError> {
+    use crate::schema::{product_gallery_url, product_keyword, product_tag, tag};
+    let tag_names: Vec<(i32, String)> = product_tag::table
+        .inner_join(tag::table)
+        .filter(product_tag::product_id.eq_any(product_ids))
+        .order((product_tag::product_id, lower(tag::name)))
+        .select((product_tag::product_id, tag::name))
+        .load(conn)?;
+    let keywords: Vec<(i32, String)> = product_keyword::table
+        .filter(product_keyword::product_id.eq_any(product_ids))
+        .order((product_keyword::product_id, product_keyword::keyword))
+        .load(conn)?;
+    let urls: Vec<(i32, String)> = product_gallery_url::table
+        .filter(product_gallery_url::product_id.eq_any(product_ids))
+        .order((product_gallery_url::product_id, product_gallery_url::position))
+        .select((product_gallery_url::product_id, product_gallery_url::url))
+        .load(conn)?;
+
+    let mut lists: HashMap<i32, ProductLists> = HashMap::new();
+    for (product_id, tag_name) in tag_names {
+        lists.entry(product_id).or_default().tags.push(tag_name);
+    }
+    for (product_id, keyword) in keywords {
+        lists.entry(product_id).or_default().keywords.push(keyword);
+    }
+    for (product_id, url) in urls {
+        lists.entry(product_id).or_default().gallery_urls.push(url);
+    }
+    Ok(lists)
+}
+
+/// Swap a product's tags, keywords and gallery URLs for `lists`, creating any new tags.
+fn replace_product_lists(conn: &mut Conn, product_id_val: i32, lists: &ProductLists) -> Result<(), diesel::result::Error> {
+    use crate::schema::{product_gallery_url, product_keyword, product_tag};
+    diesel::delete(product_tag::table.filter(product_tag::product_id.eq(product_id_val))).execute(conn)?;
+    let tag_rows: Vec<_> = tag_ids(conn, &lists.tags)?
+        .into_iter()
+        .map(|tag_id| (product_tag::product_id.eq(product_id_val), product_tag::tag_id.eq(tag_id)))
+        .collect();
+    diesel::insert_into(product_tag::table).values(&tag_rows).on_conflict_do_nothing().execute(conn)?;
+
+    diesel::delete(product_keyword::table.filter(product_keyword::product_id.eq(product_id_val))).execute(conn)?;
+    let keyword_rows: Vec<_> = lists
+        .keywords
+        .iter()
+        .map(|keyword| (product_keyword::product_id.eq(product_id_val), product_keyword::keyword.eq(keyword)))
+        .collect();
+    diesel::insert_into(product_keyword::table).values(&keyword_rows).on_conflict_do_nothing().execute(conn)?;
+
+    diesel::delete(product_gallery_url::table.filter(product_gallery_url::product_id.eq(product_id_val))).execute(conn)?;
+    let url_rows: Vec<_> = lists
+        .gallery_urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            (
+                product_gallery_url::product_id.eq(product_id_val),
+                product_gallery_url::position.eq(i as i32),
+                product_gallery_url::url.eq(url),
+            )
         })
+        .collect();
+    diesel::insert_into(product_gallery_url::table).values(&url_rows).execute(conn)?;
+    Ok(())
 }
 
 /// Remove a product by ID.
@@ -180,28 +655,269 @@ pub fn delete_product(conn: &mut Conn, product_id_val: i32) -> Result<(), Beedle
     }
 }
 
-/// Atomically decrement inventory for all cart items. Rolls back if any product would go negative inventory.
-pub fn update_inventory(conn: &mut Conn, cart: &[CartItem]) -> Result<(), BeedleError> {
-    use crate::schema::product::dsl::*;
+/// Return cart items to their variants' stock (eg when an order is cancelled).
+/// Variants that no longer exist are skipped.
+pub fn restock_inventory(conn: &mut Conn, cart: &[CartItem]) -> Result<(), BeedleError> {
+    use crate::schema::product_variant::dsl::*;
     conn.transaction::<_, diesel::result::Error, _>(|conn| {
         for item in cart {
-            let prod = product.filter(id.eq(item.product_id)).first::<Product>(conn)?;
-            if prod.inventory >= item.quantity as i32 {
-                let new_inv = prod.inventory - item.quantity as i32;
-                diesel::update(product.filter(id.eq(item.product_id)))
+            let updated = diesel::update(product_variant.filter(id.eq(item.variant_id)))
+                .set(inventory.eq(inventory + item.quantity as i32))
+                .execute(conn)?;
+            if updated == 0 {
+                log::warn!("Restock skipped for missing variant id {}", item.variant_id);
+            }
+        }
+        Ok(())
+    })
+    .map_err(|e| {
+        log::error!("Inventory restock failed (rollback): {e}");
+        BeedleError::DatabaseError(e.to_string())
+    })
+}
+
+/// Atomically decrement each cart item's variant stock, consuming the session's stock reservations.
+/// Stock other sessions have reserved is off limits; rolls back if any variant would run short.
+pub fn update_inventory(conn: &mut Conn, session_id_val: Uuid, cart: &[CartItem]) -> Result<(), BeedleError> {
+    use crate::schema::product_variant::dsl::*;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let mut ids: Vec<i32> = cart.iter().map(|item| item.variant_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        // Lock every row before counting what's held, so no reservation lands in between
+        // (same lock order as reserve_cart)
+        let variants = product_variant.filter(id.eq_any(&ids)).order(id).for_update().load::<ProductVariant>(conn)?;
+        let held = reserved_by_others(conn, session_id_val, &ids)?;
+
+        let mut remaining: HashMap<i32, i32> = variants.iter().map(|v| (v.id, v.inventory)).collect();
+        for item in cart {
+            let Some(variant) = variants.iter().find(|v| v.id == item.variant_id) else {
+                log::warn!("Attempted to purchase missing variant {}", item.variant_id);
+                return Err(diesel::result::Error::RollbackTransaction);
+            };
+            let stock = remaining[&variant.id];
+            let available = stock - held.get(&item.variant_id).copied().unwrap_or(0);
+            if available >= item.quantity as i32 {
+                let new_inv = stock - item.quantity as i32;
+                diesel::update(product_variant.filter(id.eq(item.variant_id)))
                     .set(inventory.eq(new_inv))
                     .execute(conn)?;
+                remaining.insert(variant.id, new_inv);
             } else {
-                log::warn!("Attempted to purchase more than inventory for product id {}: wanted {}, in stock {}", 
-                    item.product_id, item.quantity, prod.inventory);
+                log::warn!("Attempted to purchase more than available for variant {} ({}): wanted {}, in stock {}, available {}", 
+                    item.variant_id, variant.sku, item.quantity, stock, available);
                 // Abort transaction!
                 return Err(diesel::result::Error::RollbackTransaction);
             }
         }
+
+        // The held units are now sold; the reservation has done its job
+        use crate::schema::stock_reservation::dsl as res;
+        diesel::delete(res::stock_reservation.filter(res::session_id.eq(session_id_val)).filter(res::variant_id.eq_any(&ids)))
+            .execute(conn)?;
         Ok(())
     })
     .map_err(|e| {
         log::error!("Inventory update failed (rollback): {e}");
         BeedleError::DatabaseError(e.to_string())
     })
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod products_tests {
+    use super::*;
+    use crate::db::{categories::test_category, test_conn, test_product};
+    use crate::price::Price;
+
+    #[test]
+    fn test_save_product_rejects_stale_edits() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Uncategorized");
+        let mut loaded = insert_product(&mut conn, &test_product("Test Typo Prodcut", category_id_val, 500, 3, None), &ProductLists::default())
+        .unwrap();
+        let stale = loaded.clone();
+
+        loaded.name = "Test Typo Product".to_owned();
+        loaded.slug = "test-typo-product".to_owned();
+        loaded.restock_date = Some(loaded.added_date + chrono::Duration::days(7));
+        let lists = ProductLists {
+            tags: vec![],
+            keywords: vec!["typo".to_owned()],
+            gallery_urls: vec!["/static/b.png".to_owned(), "/static/a.png".to_owned()],
+        };
+        assert_eq!(save_product(&mut conn, &loaded, &lists).unwrap(), loaded.version + 1);
+        let saved = load_product_by_id(&mut conn, loaded.id).unwrap().unwrap();
+        assert_eq!(saved.name, "Test Typo Product");
+        assert_eq!(saved.restock_date, loaded.restock_date);
+        assert_eq!(saved.inventory, 3);
+        assert_eq!(load_product_lists(&mut conn, &[loaded.id]).unwrap()[&loaded.id], lists);
+        // The old slug leads to the new one
+        assert_eq!(load_product_by_slug(&mut conn, "test-typo-product").unwrap().map(|p| p.id), Some(loaded.id));
+        assert!(load_product_by_slug(&mut conn, "test-typo-prodcut").unwrap().is_none());
+        assert_eq!(renamed_slug(&mut conn, "test-typo-prodcut").unwrap().as_deref(), Some("test-typo-product"));
+        assert_eq!(slug_owner(&mut conn, "test-typo-prodcut").unwrap(), None);
+
+        // Someone else's form was loaded before that save
+        let mut other = stale;
+        other.price = 1;
+        let other_lists = ProductLists::default();
+        assert!(matches!(save_product(&mut conn, &other, &other_lists), Err(BeedleError::EditConflict(_))));
+        assert_eq!(load_product_by_id(&mut conn, loaded.id).unwrap().unwrap().price, 500);
+        assert_eq!(load_product_lists(&mut conn, &[loaded.id]).unwrap()[&loaded.id], lists);
+
+        delete_product(&mut conn, loaded.id).unwrap();
+        assert!(save_product(&mut conn, &saved, &lists).is_err());
+        assert_eq!(renamed_slug(&mut conn, "test-typo-prodcut").unwrap(), None);
+    }
+
+    #[test]
+    fn test_update_inventory_respects_other_reservations() {
+        use crate::db::reservations::reserve_cart;
+        use crate::db::session::create_new_session;
+        use crate::db::variants::cart_item;
+
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Reserved Stock");
+        let plenty = insert_product(&mut conn, &test_product("Test Reserved Plenty", category_id_val, 100, 10, None), &ProductLists::default()).unwrap();
+        let scarce = insert_product(&mut conn, &test_product("Test Reserved Scarce", category_id_val, 100, 3, None), &ProductLists::default()).unwrap();
+        let ours = create_new_session(&mut conn, "127.0.0.1", "test").unwrap().session_id;
+        let theirs = create_new_session(&mut conn, "127.0.0.1", "test").unwrap().session_id;
+
+        // They hold 2 of the 3, leaving 1 for a cart that wants 2
+        let held = vec![cart_item(&mut conn, scarce.id, 2)];
+        assert!(reserve_cart(&mut conn, theirs, &held, chrono::Duration::minutes(5)).unwrap().is_empty());
+        let cart = vec![cart_item(&mut conn, plenty.id, 1), cart_item(&mut conn, scarce.id, 2)];
+        assert!(update_inventory(&mut conn, ours, &cart).is_err());
+
+        // Nothing sold, not even the item that was in stock
+        let stock = |conn: &mut Conn, product_id_val| crate::db::variants::load_variants(conn, product_id_val).unwrap()[0].variant.inventory;
+        assert_eq!((stock(&mut conn, plenty.id), stock(&mut conn, scarce.id)), (10, 3));
+        let fits = vec![cart_item(&mut conn, plenty.id, 1), cart_item(&mut conn, scarce.id, 1)];
+        update_inventory(&mut conn, ours, &fits).unwrap();
+        assert_eq!((stock(&mut conn, plenty.id), stock(&mut conn, scarce.id)), (9, 2));
+
+        {
+            use crate::schema::session::dsl::*;
+            diesel::delete(session.filter(session_id.eq_any([ours, theirs]))).execute(&mut conn).unwrap();
+        }
+        for made in [plenty, scarce] {
+            delete_product(&mut conn, made.id).unwrap();
+        }
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_sale_price_matches_price_model() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Sale Price");
+        let cheap = insert_product(&mut conn, &test_product("Test Sale Cheap", category_id_val, 300, 1, None), &ProductLists::default()).unwrap();
+        let marked_down =
+            insert_product(&mut conn, &test_product("Test Sale Marked Down", category_id_val, 1000, 1, Some(80.0)), &ProductLists::default()).unwrap();
+
+        // Sorted and filtered by what it sells for, not its list price
+        let in_category = ProductFilter { category_ids: Some(vec![category_id_val]), ..ProductFilter::default() };
+        let by_price = ProductQuery::new(in_category.clone()).sorted(Some("price_low")).load(&mut conn).unwrap();
+        assert_eq!(by_price.products.iter().map(|p| p.id).collect::<Vec<_>>(), [marked_down.id, cheap.id]);
+        let under_250 = ProductQuery::new(ProductFilter { max_price: Some(250), ..in_category.clone() }).load(&mut conn).unwrap();
+        assert_eq!(under_250.products.iter().map(|p| p.id).collect::<Vec<_>>(), [marked_down.id]);
+        assert_eq!(ProductQuery::new(ProductFilter { on_sale: true, ..in_category }).count(&mut conn).unwrap(), 1);
+
+        for made in [cheap, marked_down] {
+            delete_product(&mut conn, made.id).unwrap();
+        }
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_sale_price_rounds_like_price() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Sale Rounding");
+        let mut cents_list: Vec<i64> = (0..200).chain((201..5000).step_by(97)).collect();
+        cents_list.extend([9_999, 12_345, 99_999, 123_457, 1_000_001, 987_654_321]);
+        let made: Vec<Product> = cents_list
+            .iter()
+            .map(|&cents| {
+                let new_product = test_product(&format!("Test Sale Rounding {cents}"), category_id_val, cents, 0, None);
+                insert_product(&mut conn, &new_product, &ProductLists::default()).unwrap()
+            })
+            .collect();
+
+        // Every tenth of a percent, and some that aren't exact in an f32
+        let mut percents: Vec<f32> = (1..=1000).map(|tenths| tenths as f32 / 10.0).collect();
+        percents.extend([0.05, 12.345, 33.333, 66.667, 99.99]);
+        for percent in percents {
+            let sold_for: Vec<(i64, i64)> = diesel::update(product.filter(category_id.eq(category_id_val)))
+                .set(discount_percent.eq(percent))
+                .returning((price, sale_price(None)))
+                .get_results(&mut conn)
+                .unwrap();
+            for (cents, sold) in sold_for {
+                let expected = Price::from_cents(cents).with_discount_percent(Some(percent)).as_cents();
+                assert_eq!(sold, expected, "{cents} cents less {percent}%");
+            }
+        }
+
+        for made in made {
+            delete_product(&mut conn, made.id).unwrap();
+        }
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_cursor_tokens() {
+        let added = chrono::DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap().naive_utc();
+        for key in [SortKey::Name("Tea: green".to_owned()), SortKey::Price(-5, None), SortKey::Price(250, Some(added)), SortKey::Added(added)] {
+            let cursor = Cursor { key, id: 42 };
+            assert_eq!(Cursor::from_token(&cursor.token()), Some(cursor));
+        }
+        for garbage in ["", "zz", "6e", &hex::encode("x1:2"), &hex::encode("p1:two"), &hex::encode("p1:2@soon")] {
+            assert_eq!(Cursor::from_token(garbage), None, "{garbage}");
+        }
+        // A cursor from another sort order is dropped
+        let by_price = Cursor { key: SortKey::Price(100, None), id: 1 };
+        assert!(ProductQuery::default().sorted(Some("alpha")).seek(Seek::After, Some(by_price.clone())).cursor.is_none());
+        assert!(ProductQuery::default().sorted(Some("price_high")).seek(Seek::After, Some(by_price)).cursor.is_some());
+    }
+
+    #[test]
+    fn test_keyset_pages_survive_new_products() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Keyset");
+        let add = |conn: &mut Conn, name_val: &str, cents: i64| {
+            insert_product(conn, &test_product(name_val, category_id_val, cents, 1, None), &ProductLists::default())
+            .unwrap()
+            .id
+        };
+        let a = add(&mut conn, "Test Keyset A", 300);
+        let b = add(&mut conn, "Test Keyset B", 100);
+        let c = add(&mut conn, "Test Keyset C", 200);
+        let d = add(&mut conn, "Test Keyset D", 200);
+        let e = add(&mut conn, "Test Keyset E", 500);
+        let query = ProductQuery::new(ProductFilter { category_ids: Some(vec![category_id_val]), ..ProductFilter::default() })
+            .sorted(Some("price_low"))
+            .page(2, 0);
+        let ids = |page: &ProductPage| page.products.iter().map(|p| p.id).collect::<Vec<i32>>();
+
+        let first = query.load(&mut conn).unwrap();
+        assert_eq!((ids(&first), first.total, first.previous.is_some()), (vec![b, c], 5, false));
+        let second = query.clone().seek(Seek::After, first.next).load(&mut conn).unwrap();
+        assert_eq!(ids(&second), [d, a]);
+
+        // Something cheaper turns up meanwhile: an offset would now repeat A
+        let f = add(&mut conn, "Test Keyset F", 50);
+        let third = query.clone().seek(Seek::After, second.next).load(&mut conn).unwrap();
+        assert_eq!((ids(&third), third.total, third.next.is_none()), (vec![e], 6, true));
+
+        let back = query.clone().seek(Seek::Before, third.previous).load(&mut conn).unwrap();
+        assert_eq!(ids(&back), [d, a]);
+        let back = query.clone().seek(Seek::Before, back.previous).load(&mut conn).unwrap();
+        assert_eq!(ids(&back), [b, c]);
+        let back = query.clone().seek(Seek::Before, back.previous).load(&mut conn).unwrap();
+        assert_eq!((ids(&back), back.previous.is_none()), (vec![f], true));
+
+        for made in [a, b, c, d, e, f] {
+            delete_product(&mut conn, made).unwrap();
+        }
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+}
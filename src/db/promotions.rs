@@ -0,0 +1,390 @@
+//! Scheduled sales (`promotion`): the admin's list and edits, the promotions running for a
+//! page of products, and what's on now or coming up for the homepage.
+//! How they change prices is up to `promotions`.
+
+use crate::db::categories::CategoryTree;
+use crate::errors::BeedleError;
+use crate::models::{NewPromotion, Product, Promotion};
+use crate::promotions::ActivePromotions;
+use crate::schema::{product, product_tag, promotion, tag};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+use super::Conn;
+
+/// A promotion, with the names of the product or tag it targets for showing it
+#[derive(Debug, Clone)]
+pub struct SalesEvent {
+    pub promotion: Promotion,
+    pub product_name: Option<String>,
+    pub product_slug: Option<String>,
+    pub tag_name: Option<String>,
+}
+
+fn load_events(conn: &mut Conn, running_after: Option<NaiveDateTime>) -> Result<Vec<SalesEvent>, BeedleError> {
+    let mut query = promotion::table
+        .left_join(product::table)
+        .left_join(tag::table)
+        .select((promotion::all_columns, product::name.nullable(), product::slug.nullable(), tag::name.nullable()))
+        .into_boxed();
+    if let Some(now) = running_after {
+        query = query.filter(promotion::ends_at.is_null().or(promotion::ends_at.gt(now)));
+    }
+    let rows = query
+        .order((promotion::starts_at.desc(), promotion::id.desc()))
+        .load::<(Promotion, Option<String>, Option<String>, Option<String>)>(conn)
+        .map_err(|e| {
+            log::error!("Loading promotions failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+    Ok(rows
+        .into_iter()
+        .map(|(promotion, product_name, product_slug, tag_name)| SalesEvent { promotion, product_name, product_slug, tag_name })
+        .collect())
+}
+
+/// Every promotion, past ones included, latest start first
+pub fn load_promotions(conn: &mut Conn) -> Result<Vec<SalesEvent>, BeedleError> {
+    load_events(conn, None)
+}
+
+/// What's on at `now` (ending soonest first) and then what's coming up (starting soonest
+/// first), at most `limit` of them.
+pub fn sales_events(conn: &mut Conn, now: NaiveDateTime, limit: usize) -> Result<Vec<SalesEvent>, BeedleError> {
+    let mut events = load_events(conn, Some(now))?;
+    events.sort_by_key(|e| {
+        let p = &e.promotion;
+        if p.starts_at <= now {
+            // Open-ended ones after those that end
+            (false, p.ends_at.is_none(), p.ends_at.unwrap_or(p.starts_at), p.id)
+        } else {
+            (true, false, p.starts_at, p.id)
+        }
+    });
+    events.truncate(limit);
+    Ok(events)
+}
+
+pub fn load_promotion(conn: &mut Conn, promotion_id_val: i32) -> Result<Option<Promotion>, BeedleError> {
+    promotion::table
+        .find(promotion_id_val)
+        .first::<Promotion>(conn)
+        .optional()
+        .map_err(|e| {
+            log::error!("Loading promotion {} failed: {e}", promotion_id_val);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+pub fn insert_promotion(conn: &mut Conn, new_promotion: &NewPromotion) -> Result<Promotion, BeedleError> {
+    let made = diesel::insert_into(promotion::table).values(new_promotion).get_result::<Promotion>(conn).map_err(|e| {
+        log::error!("Inserting promotion {:?} failed: {e}", new_promotion.name);
+        BeedleError::DatabaseError(e.to_string())
+    })?;
+    log::info!("Added promotion {} ({:?})", made.id, made.name);
+    Ok(made)
+}
+
+pub fn save_promotion(conn: &mut Conn, promotion_in: &Promotion) -> Result<(), BeedleError> {
+    diesel::update(promotion::table.find(promotion_in.id)).set(promotion_in).execute(conn).map_err(|e| {
+        log::error!("Saving promotion {} failed: {e}", promotion_in.id);
+        BeedleError::DatabaseError(e.to_string())
+    })?;
+    log::info!("Saved promotion {} ({:?})", promotion_in.id, promotion_in.name);
+    Ok(())
+}
+
+pub fn delete_promotion(conn: &mut Conn, promotion_id_val: i32) -> Result<(), BeedleError> {
+    let deleted = diesel::delete(promotion::table.find(promotion_id_val)).execute(conn).map_err(|e| {
+        log::error!("Deleting promotion {} failed: {e}", promotion_id_val);
+        BeedleError::DatabaseError(e.to_string())
+    })?;
+    if deleted == 0 {
+        return Err(BeedleError::DatabaseError(format!("No promotion with id {}", promotion_id_val)));
+    }
+    log::info!("Deleted promotion {}", promotion_id_val);
+    Ok(())
+}
+
+/// Whether any promotion is running at `now`, so listings know to price by them
+pub fn promotions_running(conn: &mut Conn, now: NaiveDateTime) -> Result<bool, BeedleError> {
+    diesel::select(diesel::dsl::exists(
+        promotion::table
+            .filter(promotion::starts_at.le(now))
+            .filter(promotion::ends_at.is_null().or(promotion::ends_at.gt(now))),
+    ))
+    .get_result(conn)
+    .map_err(|e| {
+        log::error!("Checking for running promotions failed: {e}");
+        BeedleError::DatabaseError(e.to_string())
+    })
+}
+
+/// The promotions running at `now` that cover each of `products`. Pricing anything shown
+/// or sold should go through this, so the cart charges what the product page said.
+pub fn active_promotions(
+    conn: &mut Conn,
+    products: &[Product],
+    tree: &CategoryTree,
+    now: NaiveDateTime,
+) -> Result<ActivePromotions, BeedleError> {
+    if products.is_empty() {
+        return Ok(ActivePromotions::default());
+    }
+    let running = promotion::table
+        .filter(promotion::starts_at.le(now))
+        .filter(promotion::ends_at.is_null().or(promotion::ends_at.gt(now)))
+        .load::<Promotion>(conn)
+        .map_err(|e| {
+            log::error!("Loading running promotions failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+
+    // Only the tags some promotion is aimed at matter
+    let tag_ids: Vec<i32> = running.iter().filter_map(|p| p.tag_id).collect();
+    let mut product_tags: HashMap<i32, Vec<i32>> = HashMap::new();
+    if !tag_ids.is_empty() {
+        let ids: Vec<i32> = products.iter().map(|p| p.id).collect();
+        let rows = product_tag::table
+            .filter(product_tag::product_id.eq_any(&ids))
+            .filter(product_tag::tag_id.eq_any(&tag_ids))
+            .select((product_tag::product_id, product_tag::tag_id))
+            .load::<(i32, i32)>(conn)
+            .map_err(|e| {
+                log::error!("Loading tags for promotions failed: {e}");
+                BeedleError::DatabaseError(e.to_string())
+            })?;
+        for (product_id, tag_id) in rows {
+            product_tags.entry(product_id).or_default().push(tag_id);
+        }
+    }
+    Ok(ActivePromotions::new(running, products, &product_tags, tree))
+}
+
+#[cfg(test)]
+mod promotions_tests {
+    use super::*;
+    use crate::db::categories::{delete_category, load_tree, test_category};
+    use crate::db::facets::product_facets;
+    use crate::db::products::{delete_product, insert_product, sale_price, ProductFilter, ProductQuery, Seek};
+    use crate::db::tags::{delete_tag, find_tag};
+    use crate::db::{test_conn, test_product};
+    use crate::models::ProductLists;
+    use crate::price::Price;
+    use crate::promotions::{promoted_price, Discount};
+    use chrono::{Duration, Utc};
+
+    fn new_promotion(name_val: &str, starts_in_days: i64, ends_in_days: Option<i64>) -> NewPromotion {
+        let now = Utc::now().naive_utc();
+        NewPromotion {
+            name: name_val.to_owned(),
+            description: None,
+            starts_at: now + Duration::days(starts_in_days),
+            ends_at: ends_in_days.map(|days| now + Duration::days(days)),
+            discount_percent: Some(10.0),
+            discount_cents: None,
+            product_id: None,
+            category_id: None,
+            tag_id: None,
+            priority: 0,
+            stackable: false,
+        }
+    }
+
+    #[test]
+    fn test_running_promotions_price_products() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Promotions");
+        let honey = test_product("Test Promoted Honey", category_id_val, 1000, 1, Some(5.0));
+        let made = insert_product(&mut conn, &honey, &ProductLists { tags: vec!["TestPromotedTag".to_owned()], ..ProductLists::default() })
+        .unwrap();
+        let tag = find_tag(&mut conn, "TestPromotedTag").unwrap().unwrap();
+
+        let promotions = [
+            NewPromotion { product_id: Some(made.id), stackable: true, priority: 1, ..new_promotion("Test Honey Week", -1, Some(6)) },
+            NewPromotion {
+                tag_id: Some(tag.id),
+                stackable: true,
+                discount_percent: None,
+                discount_cents: Some(100),
+                ..new_promotion("Test Tagged Sale", -1, None)
+            },
+            NewPromotion { product_id: Some(made.id), ..new_promotion("Test Honey Next Week", 6, Some(13)) },
+            NewPromotion { product_id: Some(made.id), ..new_promotion("Test Honey Last Week", -8, Some(-1)) },
+        ]
+        .map(|p| insert_promotion(&mut conn, &p).unwrap());
+
+        let now = Utc::now().naive_utc();
+        let tree = load_tree(&mut conn).unwrap();
+        let active = active_promotions(&mut conn, std::slice::from_ref(&made), &tree, now).unwrap();
+        let running: Vec<i32> = active.of(made.id).iter().map(|p| p.id).collect();
+        assert_eq!(running, [promotions[0].id, promotions[1].id]);
+        // 5% own, then 10%, then $1 off: 1000 -> 950 -> 855 -> 755
+        let (price, applied) = promoted_price(Price::from_cents(made.price), made.discount_percent, active.of(made.id));
+        assert_eq!((price.as_cents(), applied.len()), (755, 2));
+        // Listings work it out in SQL, and must agree
+        let listed: i64 = product::table.find(made.id).select(sale_price(Some(now))).first(&mut conn).unwrap();
+        assert_eq!(listed, 755);
+
+        // Running ones (ending soonest first) then upcoming ones; not ended ones
+        let ours: Vec<i32> = promotions.iter().map(|p| p.id).collect();
+        let events: Vec<(i32, Option<String>, Option<String>)> = sales_events(&mut conn, now, 1000)
+            .unwrap()
+            .into_iter()
+            .filter(|e| ours.contains(&e.promotion.id))
+            .map(|e| (e.promotion.id, e.product_slug, e.tag_name))
+            .collect();
+        let honey = Some("test-promoted-honey".to_owned());
+        assert_eq!(events, [
+            (promotions[0].id, honey.clone(), None),
+            (promotions[1].id, None, Some("TestPromotedTag".to_owned())),
+            (promotions[2].id, honey, None),
+        ]);
+
+        let mut edited = promotions[0].clone();
+        edited.ends_at = Some(now - Duration::hours(1));
+        edited.starts_at = now - Duration::days(2);
+        save_promotion(&mut conn, &edited).unwrap();
+        assert_eq!(active_promotions(&mut conn, std::slice::from_ref(&made), &tree, now).unwrap().of(made.id).len(), 1);
+        delete_promotion(&mut conn, promotions[1].id).unwrap();
+        assert!(load_promotion(&mut conn, promotions[1].id).unwrap().is_none());
+        assert!(matches!(delete_promotion(&mut conn, promotions[1].id), Err(BeedleError::DatabaseError(_))));
+
+        // Deleting the product takes its promotions with it
+        delete_product(&mut conn, made.id).unwrap();
+        assert!(load_promotion(&mut conn, promotions[0].id).unwrap().is_none());
+        delete_tag(&mut conn, tag.id).unwrap();
+        delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_sql_promoted_price_matches_rust() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Promotion Parity");
+        // The cases of `promotions::promotions_tests::test_stacking_rules`: priority, stackable, discount
+        let exclusive = (5, false, Discount::Percent(20.0));
+        let stacks = (3, true, Discount::Percent(10.0));
+        let stacks_too = (1, true, Discount::Fixed(Price::from_cents(100)));
+        let cases = [
+            ("Test Parity Exclusive First", Some(5.0), vec![exclusive, stacks, stacks_too], 800),
+            ("Test Parity Stackable First", Some(5.0), vec![(5, true, stacks.2), (3, false, exclusive.2), stacks_too], 755),
+            ("Test Parity Own Discount", Some(30.0), vec![exclusive, stacks, stacks_too], 700),
+            ("Test Parity Unpromoted", None, vec![], 1000),
+        ];
+
+        let now = Utc::now().naive_utc();
+        let tree = load_tree(&mut conn).unwrap();
+        let mut made = Vec::new();
+        for (name_val, own_percent, promotions, expected) in cases {
+            let priced = insert_product(&mut conn, &test_product(name_val, category_id_val, 1000, 1, own_percent), &ProductLists::default())
+                .unwrap();
+            for (priority, stackable, discount) in promotions {
+                let (discount_percent, discount_cents) = match discount {
+                    Discount::Percent(percent) => (Some(percent), None),
+                    Discount::Fixed(amount) => (None, Some(amount.as_cents())),
+                };
+                let promotion_val = NewPromotion {
+                    product_id: Some(priced.id),
+                    priority,
+                    stackable,
+                    discount_percent,
+                    discount_cents,
+                    ..new_promotion(name_val, -1, Some(1))
+                };
+                insert_promotion(&mut conn, &promotion_val).unwrap();
+            }
+
+            let active = active_promotions(&mut conn, std::slice::from_ref(&priced), &tree, now).unwrap();
+            let (in_rust, _) = promoted_price(Price::from_cents(priced.price), priced.discount_percent, active.of(priced.id));
+            let in_sql: i64 = product::table.find(priced.id).select(sale_price(Some(now))).first(&mut conn).unwrap();
+            assert_eq!((in_rust.as_cents(), in_sql), (expected, expected), "{name_val}");
+            made.push(priced);
+        }
+
+        // Deleting the products takes their promotions with them
+        for priced in made {
+            delete_product(&mut conn, priced.id).unwrap();
+        }
+        delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_listings_price_by_running_promotions() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Promoted Listings");
+        let jam = insert_product(&mut conn, &test_product("Test Promoted Jam", category_id_val, 1000, 1, None), &ProductLists::default())
+            .unwrap();
+        let tea = insert_product(&mut conn, &test_product("Test Unpromoted Tea", category_id_val, 700, 1, None), &ProductLists::default())
+            .unwrap();
+        let half_off = NewPromotion { product_id: Some(jam.id), discount_percent: Some(50.0), ..new_promotion("Test Jam Half Off", -1, Some(1)) };
+        let half_off = insert_promotion(&mut conn, &half_off).unwrap();
+        let now = Utc::now().naive_utc();
+        assert!(promotions_running(&mut conn, now).unwrap());
+
+        let in_category = |promotions: bool| ProductFilter {
+            category_ids: Some(vec![category_id_val]),
+            promotions_at: promotions.then_some(now),
+            ..ProductFilter::default()
+        };
+        let mut under_600 = |promotions: bool| -> Vec<i32> {
+            let filter = ProductFilter { max_price: Some(600), ..in_category(promotions) };
+            ProductQuery::new(filter).load(&mut conn).unwrap().products.iter().map(|p| p.id).collect()
+        };
+        // The jam sells for $5 while the promotion runs; the column alone still says $10
+        assert_eq!(under_600(true), [jam.id]);
+        assert!(under_600(false).is_empty());
+        let cheapest = ProductQuery::new(in_category(true)).sorted(Some("price_low")).load(&mut conn).unwrap();
+        assert_eq!(cheapest.products.iter().map(|p| p.id).collect::<Vec<_>>(), [jam.id, tea.id]);
+        let cheapest = ProductQuery::new(in_category(false)).sorted(Some("price_low")).load(&mut conn).unwrap();
+        assert_eq!(cheapest.products.iter().map(|p| p.id).collect::<Vec<_>>(), [tea.id, jam.id]);
+        assert_eq!(ProductQuery::new(ProductFilter { on_sale: true, ..in_category(true) }).count(&mut conn).unwrap(), 1);
+        assert_eq!(ProductQuery::new(ProductFilter { on_sale: true, ..in_category(false) }).count(&mut conn).unwrap(), 0);
+
+        let mut priced = |promotions: bool| -> Vec<(Option<i64>, i64)> {
+            let facets = product_facets(&mut conn, &in_category(promotions)).unwrap();
+            facets.price_ranges.iter().filter(|r| r.products > 0).map(|r| (r.min, r.products)).collect()
+        };
+        assert_eq!(priced(true), [(Some(500), 2)]);
+        assert_eq!(priced(false), [(Some(500), 1), (Some(1000), 1)]);
+        assert_eq!(product_facets(&mut conn, &in_category(true)).unwrap().on_sale, 1);
+
+        delete_promotion(&mut conn, half_off.id).unwrap();
+        delete_product(&mut conn, jam.id).unwrap();
+        delete_product(&mut conn, tea.id).unwrap();
+        delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_price_cursor_keeps_its_promotions() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Promoted Pages");
+        let add = |conn: &mut Conn, name_val: &str, cents: i64| {
+            insert_product(conn, &test_product(name_val, category_id_val, cents, 1, None), &ProductLists::default()).unwrap()
+        };
+        let (jam, pie, tea) = (add(&mut conn, "Test Paged Jam", 1000), add(&mut conn, "Test Paged Pie", 600), add(&mut conn, "Test Paged Tea", 700));
+        // Half off the jam yesterday only
+        let yesterday = NewPromotion { product_id: Some(jam.id), discount_percent: Some(50.0), ..new_promotion("Test Paged Jam Sale", -2, Some(-1)) };
+        let yesterday = insert_promotion(&mut conn, &yesterday).unwrap();
+
+        // A listing started while it ran, paged after it ended: the jam stays first, not repeated last
+        let during = Utc::now().naive_utc() - Duration::hours(36);
+        let in_category = |promotions_at| ProductFilter { category_ids: Some(vec![category_id_val]), promotions_at, ..ProductFilter::default() };
+        let first = ProductQuery::new(in_category(Some(during))).sorted(Some("price_low")).page(1, 0).load(&mut conn).unwrap();
+        assert_eq!(first.products[0].id, jam.id);
+        let mut seen = vec![jam.id];
+        let mut next = first.next;
+        while let Some(cursor) = next {
+            let page = ProductQuery::new(in_category(None)).sorted(Some("price_low")).page(1, 0).seek(Seek::After, Some(cursor)).load(&mut conn).unwrap();
+            seen.extend(page.products.iter().map(|p| p.id));
+            next = page.next;
+        }
+        assert_eq!(seen, [jam.id, pie.id, tea.id]);
+
+        delete_promotion(&mut conn, yesterday.id).unwrap();
+        for made in [jam, pie, tea] {
+            delete_product(&mut conn, made.id).unwrap();
+        }
+        delete_category(&mut conn, category_id_val).unwrap();
+    }
+}
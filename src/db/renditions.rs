@@ -0,0 +1,189 @@
+//! `srcset` copies of images (`image_rendition`) and the external image URLs fetched to
+//! make them (`remote_image`). The files are handled by `media::responsive`/`media::remote`.
+
+use crate::errors::BeedleError;
+use crate::models::{ImageRendition, NewImageRendition};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use super::Conn;
+
+/// A failed fetch of an external image is tried again after this long
+pub const REMOTE_RETRY_HOURS: i64 = 24;
+
+/// Renditions of each source (upload storage key or external URL), narrowest first.
+pub fn load_renditions(
+    conn: &mut Conn,
+    sources: &[String],
+) -> Result<HashMap<String, Vec<ImageRendition>>, BeedleError> {
+    use crate::schema::image_rendition::dsl::*;
+    let renditions = image_rendition
+        .filter(source.eq_any(sources))
+        .order((source, width, id))
+        .load::<ImageRendition>(conn)
+        .map_err(|e| {
+            log::error!("Loading image renditions failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+    let mut by_source: HashMap<String, Vec<ImageRendition>> = HashMap::new();
+    for rendition in renditions {
+        by_source.entry(rendition.source.clone()).or_default().push(rendition);
+    }
+    Ok(by_source)
+}
+
+/// Swap a source's renditions for `new_renditions`; returns the old rows, whose files
+/// the caller should delete.
+pub fn replace_renditions(
+    conn: &mut Conn,
+    source_val: &str,
+    new_renditions: &[NewImageRendition],
+) -> Result<Vec<ImageRendition>, BeedleError> {
+    use crate::schema::image_rendition::dsl::*;
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let old = diesel::delete(image_rendition.filter(source.eq(source_val))).get_results::<ImageRendition>(conn)?;
+        diesel::insert_into(image_rendition).values(new_renditions).execute(conn)?;
+        Ok(old)
+    })
+}
+
+/// Remove a source's renditions (eg when its upload is deleted) and return them.
+pub fn delete_renditions(conn: &mut Conn, source_val: &str) -> Result<Vec<ImageRendition>, BeedleError> {
+    use crate::schema::image_rendition::dsl::*;
+    Ok(diesel::delete(image_rendition.filter(source.eq(source_val))).get_results::<ImageRendition>(conn)?)
+}
+
+/// Note the outcome of fetching an external image: its size, or why it failed.
+pub fn record_remote_image(conn: &mut Conn, url_val: &str, outcome: Result<(i32, i32), &str>) -> Result<(), BeedleError> {
+    use crate::schema::remote_image::dsl::*;
+    let (size, error_val) = match outcome {
+        Ok(size) => (Some(size), None),
+        Err(message) => (None, Some(message)),
+    };
+    let row = (
+        url.eq(url_val),
+        width.eq(size.map(|s| s.0)),
+        height.eq(size.map(|s| s.1)),
+        error.eq(error_val),
+        fetched_at.eq(Utc::now().naive_utc()),
+    );
+    diesel::insert_into(remote_image)
+        .values(row)
+        .on_conflict(url)
+        .do_update()
+        .set(row)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// A fetched external image: its size and new renditions. Returns the renditions it replaced.
+pub fn save_remote_image(
+    conn: &mut Conn,
+    url_val: &str,
+    size: (i32, i32),
+    new_renditions: &[NewImageRendition],
+) -> Result<Vec<ImageRendition>, BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let old = replace_renditions(conn, url_val, new_renditions)?;
+        record_remote_image(conn, url_val, Ok(size))?;
+        Ok(old)
+    })
+}
+
+/// External image URLs used by products that haven't been fetched yet, or whose last
+/// fetch failed more than `REMOTE_RETRY_HOURS` ago.
+pub fn pending_remote_urls(conn: &mut Conn) -> Result<Vec<String>, BeedleError> {
+    let thumbs: Vec<Option<String>> = {
+        use crate::schema::product::dsl::*;
+        product.select(thumbnail_url).load(conn)?
+    };
+    let gallery: Vec<String> = {
+        use crate::schema::product_gallery_url::dsl::*;
+        product_gallery_url.select(url).load(conn)?
+    };
+    let wanted: BTreeSet<String> = thumbs
+        .into_iter()
+        .flatten()
+        .chain(gallery)
+        .map(|u| u.trim().to_owned())
+        .filter(|u| u.starts_with("http://") || u.starts_with("https://"))
+        .collect();
+    if wanted.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    use crate::schema::remote_image::dsl::*;
+    let retry_before = Utc::now().naive_utc() - Duration::hours(REMOTE_RETRY_HOURS);
+    let settled: HashSet<String> = remote_image
+        .filter(url.eq_any(&wanted))
+        .filter(error.is_null().or(fetched_at.gt(retry_before)))
+        .select(url)
+        .load::<String>(conn)?
+        .into_iter()
+        .collect();
+    Ok(wanted.into_iter().filter(|u| !settled.contains(u)).collect())
+}
+
+#[cfg(test)]
+mod renditions_tests {
+    use super::*;
+    use crate::db::test_conn;
+
+    fn new_rendition<'a>(source_val: &'a str, key: &'a str, width_val: i32) -> NewImageRendition<'a> {
+        NewImageRendition { source: source_val, format: "webp", width: width_val, height: width_val, storage_key: key, byte_size: 10 }
+    }
+
+    #[test]
+    fn test_replace_and_load_renditions() {
+        let mut conn = test_conn();
+        let source = "test/renditions.png";
+        replace_renditions(&mut conn, source, &[new_rendition(source, "test/a-480w.webp", 480), new_rendition(source, "test/a-120w.webp", 120)]).unwrap();
+        let loaded = load_renditions(&mut conn, &[source.to_owned()]).unwrap();
+        let widths: Vec<i32> = loaded[source].iter().map(|r| r.width).collect();
+        assert_eq!(widths, [120, 480]);
+
+        let old = replace_renditions(&mut conn, source, &[new_rendition(source, "test/b-240w.webp", 240)]).unwrap();
+        assert_eq!(old.len(), 2);
+        assert_eq!(delete_renditions(&mut conn, source).unwrap()[0].storage_key, "test/b-240w.webp");
+        assert!(load_renditions(&mut conn, &[source.to_owned()]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_remote_images_are_fetched_once() {
+        use crate::db::{categories::test_category, products::{delete_product, insert_product}, test_product};
+        use crate::models::{NewProduct, ProductLists};
+        use crate::schema::remote_image::dsl::*;
+        let mut conn = test_conn();
+        let thumb = "https://example.com/beedle-test-thumb.png";
+        let pending = |conn: &mut Conn| pending_remote_urls(conn).unwrap().contains(&thumb.to_owned());
+        let category_id_val = test_category(&mut conn, "Uncategorized");
+        let with_thumb = NewProduct { thumbnail_url: Some(thumb.to_owned()), ..test_product("Test Remote Images", category_id_val, 100, 0, None) };
+        let product = insert_product(&mut conn, &with_thumb, &ProductLists {
+            gallery_urls: vec!["/static/local.png".to_owned(), "https://example.com/beedle-test-gallery.png".to_owned()],
+            ..ProductLists::default()
+        })
+        .unwrap();
+        let wanted = pending_remote_urls(&mut conn).unwrap();
+        assert!(wanted.contains(&"https://example.com/beedle-test-gallery.png".to_owned()));
+        assert!(!wanted.contains(&"/static/local.png".to_owned()));
+        assert!(pending(&mut conn));
+
+        // A recent failure waits for the retry period
+        record_remote_image(&mut conn, thumb, Err("timed out")).unwrap();
+        assert!(!pending(&mut conn));
+        diesel::update(remote_image.find(thumb))
+            .set(fetched_at.eq(Utc::now().naive_utc() - Duration::hours(REMOTE_RETRY_HOURS + 1)))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(pending(&mut conn));
+
+        record_remote_image(&mut conn, thumb, Ok((640, 480))).unwrap();
+        let row: (Option<i32>, Option<String>) = remote_image.find(thumb).select((width, error)).first(&mut conn).unwrap();
+        assert_eq!(row, (Some(640), None));
+        assert!(!pending(&mut conn));
+
+        delete_product(&mut conn, product.id).unwrap();
+        diesel::delete(remote_image.find(thumb)).execute(&mut conn).unwrap();
+    }
+}
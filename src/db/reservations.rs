@@ -0,0 +1,222 @@
+//! Stock reservations: inventory held for a session's cart while it checks out.
+//! Reservations are per variant: `product_variant.inventory` is what's physically left, and what a
+//! session can actually buy is that minus everyone else's unexpired reservations.
+//! `products::update_inventory` consumes them.
+
+use crate::db::{Conn, DbPool};
+use crate::errors::BeedleError;
+use crate::models::{CartItem, NewStockReservation, ProductVariant};
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A cart item that couldn't be reserved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortage {
+    pub product_id: i32,
+    pub variant_id: i32,
+    pub requested: u32,
+    /// What this session could have had (0 if the variant is gone)
+    pub available: i32,
+}
+
+/// Units of each variant held by sessions other than `session_id_val` and not yet expired.
+pub(crate) fn reserved_by_others(
+    conn: &mut Conn,
+    session_id_val: Uuid,
+    variant_ids: &[i32],
+) -> QueryResult<HashMap<i32, i32>> {
+    use crate::schema::stock_reservation::dsl::*;
+    let rows: Vec<(i32, Option<i64>)> = stock_reservation
+        .filter(variant_id.eq_any(variant_ids))
+        .filter(session_id.ne(session_id_val))
+        .filter(expires_at.gt(Utc::now().naive_utc()))
+        .group_by(variant_id)
+        .select((variant_id, diesel::dsl::sum(quantity)))
+        .load(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|(vid, held)| (vid, held.unwrap_or(0) as i32))
+        .collect())
+}
+
+/// How many units of a variant `session_id_val` could reserve or buy right now.
+pub fn available_inventory(conn: &mut Conn, session_id_val: Uuid, variant_id_val: i32) -> Result<i32, BeedleError> {
+    let stock = {
+        use crate::schema::product_variant::dsl::*;
+        product_variant.filter(id.eq(variant_id_val)).select(inventory).first::<i32>(conn).optional()?
+    };
+    let held = reserved_by_others(conn, session_id_val, &[variant_id_val])?;
+    Ok((stock.unwrap_or(0) - held.get(&variant_id_val).copied().unwrap_or(0)).max(0))
+}
+
+/// Hold stock for the whole cart for `ttl`, replacing any earlier reservations of this session
+/// (so calling it again refreshes the hold).
+/// All or nothing: if any item is short, nothing is reserved and the shortages are returned.
+pub fn reserve_cart(
+    conn: &mut Conn,
+    session_id_val: Uuid,
+    cart: &[CartItem],
+    ttl: Duration,
+) -> Result<Vec<Shortage>, BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let mut ids: Vec<i32> = cart.iter().map(|item| item.variant_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        // Row locks serialize shoppers racing for the same variants (ordered to avoid deadlocks)
+        let variants = {
+            use crate::schema::product_variant::dsl::*;
+            product_variant.filter(id.eq_any(&ids)).order(id).for_update().load::<ProductVariant>(conn)?
+        };
+        let held = reserved_by_others(conn, session_id_val, &ids)?;
+
+        let shortages: Vec<Shortage> = cart
+            .iter()
+            .filter_map(|item| {
+                let stock = variants.iter().find(|v| v.id == item.variant_id).map_or(0, |v| v.inventory);
+                let available = (stock - held.get(&item.variant_id).copied().unwrap_or(0)).max(0);
+                (available < item.quantity as i32).then_some(Shortage {
+                    product_id: item.product_id,
+                    variant_id: item.variant_id,
+                    requested: item.quantity,
+                    available,
+                })
+            })
+            .collect();
+        if !shortages.is_empty() {
+            log::info!("Could not reserve stock for session {}: {:?}", session_id_val, shortages);
+            return Ok(shortages);
+        }
+
+        let expires = Utc::now().naive_utc() + ttl;
+        let rows: Vec<NewStockReservation> = cart
+            .iter()
+            .map(|item| NewStockReservation {
+                session_id: session_id_val,
+                variant_id: item.variant_id,
+                quantity: item.quantity as i32,
+                expires_at: expires,
+            })
+            .collect();
+        {
+            use crate::schema::stock_reservation::dsl::*;
+            diesel::delete(stock_reservation.filter(session_id.eq(session_id_val))).execute(conn)?;
+            diesel::insert_into(stock_reservation).values(&rows).execute(conn)?;
+        }
+        log::debug!("Reserved {} cart item(s) for session {} until {}", rows.len(), session_id_val, expires);
+        Ok(Vec::new())
+    })
+}
+
+/// Drop everything a session holds (eg its cart changed, so the hold no longer matches).
+pub fn release_session_reservations(conn: &mut Conn, session_id_val: Uuid) -> Result<usize, BeedleError> {
+    use crate::schema::stock_reservation::dsl::*;
+    diesel::delete(stock_reservation.filter(session_id.eq(session_id_val)))
+        .execute(conn)
+        .map_err(|e| {
+            log::error!("Releasing reservations for session {} failed: {e}", session_id_val);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Delete expired reservations. They already stop counting once expired; this keeps the table small.
+pub fn release_expired_reservations(conn: &mut Conn) -> Result<usize, BeedleError> {
+    use crate::schema::stock_reservation::dsl::*;
+    diesel::delete(stock_reservation.filter(expires_at.le(Utc::now().naive_utc())))
+        .execute(conn)
+        .map_err(|e| {
+            log::error!("Releasing expired reservations failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Background task: sweep expired reservations every `every`.
+pub fn spawn_reservation_sweeper(pool: DbPool, every: std::time::Duration) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(every);
+        loop {
+            interval.tick().await;
+            let released = pool
+                .get()
+                .map_err(BeedleError::from)
+                .and_then(|mut conn| release_expired_reservations(&mut conn));
+            match released {
+                Ok(0) => {}
+                Ok(n) => log::info!("Released {} expired stock reservation(s)", n),
+                Err(e) => log::error!("Reservation sweep failed: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod reservations_tests {
+    use super::*;
+    use crate::db::categories::{delete_category, test_category};
+    use crate::db::products::{delete_product, insert_product, update_inventory};
+    use crate::db::session::create_new_session;
+    use crate::db::{test_conn, test_product};
+    use crate::db::variants::load_variants;
+    use crate::models::{Product, ProductLists};
+
+    fn new_session(conn: &mut Conn) -> Uuid {
+        create_new_session(conn, "127.0.0.1", "test").unwrap().session_id
+    }
+
+    fn drop_sessions(conn: &mut Conn, ids: &[Uuid]) {
+        use crate::schema::session::dsl::*;
+        // Cascades to their reservations
+        diesel::delete(session.filter(session_id.eq_any(ids))).execute(conn).unwrap();
+    }
+
+    /// A product of its own (in a category of its own) with `stock` to reserve.
+    fn new_product(conn: &mut Conn, name_val: &str, stock: i32) -> Product {
+        let category_id_val = test_category(conn, name_val);
+        insert_product(conn, &test_product(name_val, category_id_val, 500, stock, None), &ProductLists::default()).unwrap()
+    }
+
+    fn drop_product(conn: &mut Conn, made: Product) {
+        delete_product(conn, made.id).unwrap();
+        delete_category(conn, made.category_id).unwrap();
+    }
+
+    #[test]
+    fn test_reservation_blocks_other_sessions() {
+        let mut conn = test_conn();
+        let (first, second) = (new_session(&mut conn), new_session(&mut conn));
+        let pie = new_product(&mut conn, "Test Reserved Bumble Pie", 3);
+        let variant = load_variants(&mut conn, pie.id).unwrap()[0].variant.id;
+        let everything = vec![CartItem { product_id: pie.id, variant_id: variant, quantity: 3 }];
+        let one = vec![CartItem { product_id: pie.id, variant_id: variant, quantity: 1 }];
+
+        assert!(reserve_cart(&mut conn, first, &everything, Duration::minutes(5)).unwrap().is_empty());
+        let shortages = reserve_cart(&mut conn, second, &one, Duration::minutes(5)).unwrap();
+        assert_eq!(shortages, vec![Shortage { product_id: pie.id, variant_id: variant, requested: 1, available: 0 }]);
+        assert!(update_inventory(&mut conn, second, &one).is_err());
+        assert_eq!(available_inventory(&mut conn, first, variant).unwrap(), 3);
+
+        release_session_reservations(&mut conn, first).unwrap();
+        assert!(reserve_cart(&mut conn, second, &one, Duration::minutes(5)).unwrap().is_empty());
+
+        drop_sessions(&mut conn, &[first, second]);
+        drop_product(&mut conn, pie);
+    }
+
+    #[test]
+    fn test_expired_reservations_do_not_hold_stock() {
+        let mut conn = test_conn();
+        let (first, second) = (new_session(&mut conn), new_session(&mut conn));
+        let pie = new_product(&mut conn, "Test Reserved Kernberry Pie", 2);
+        let variant = load_variants(&mut conn, pie.id).unwrap()[0].variant.id;
+        let everything = vec![CartItem { product_id: pie.id, variant_id: variant, quantity: 2 }];
+
+        assert!(reserve_cart(&mut conn, first, &everything, Duration::seconds(-1)).unwrap().is_empty());
+        assert_eq!(available_inventory(&mut conn, second, variant).unwrap(), 2);
+        assert!(release_expired_reservations(&mut conn).unwrap() >= 1);
+
+        drop_sessions(&mut conn, &[first, second]);
+        drop_product(&mut conn, pie);
+    }
+}
@@ -0,0 +1,312 @@
+//! Full-text product search. `product.search_vector` is a weighted tsvector (name, then
+//! tagline, keywords, description) kept current by triggers; see the
+//! `add_product_search` migration. Queries are parsed with `websearch_to_tsquery`, so
+//! shoppers can write `"green tea" -decaf` or `pie or cookie`.
+//!
+//! Suggestions (autocomplete, "did you mean") compare trigrams (`pg_trgm`) of what was
+//! typed with product, tag and category names, so they survive typos.
+//!
+//! The column isn't in `schema.rs`: `Product` loads every column there, and nothing
+//! outside this module reads the vector.
+
+use crate::errors::BeedleError;
+use crate::schema::product::dsl::*;
+use diesel::dsl::sql;
+use diesel::expression::{AsExpression, SqlLiteral};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Float4, Text};
+use diesel::IntoSql;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::Conn;
+
+/// Postgres' own full-text types, which Diesel doesn't ship
+pub mod sql_types {
+    #[derive(diesel::sql_types::SqlType, diesel::query_builder::QueryId)]
+    #[diesel(postgres_type(name = "tsvector"))]
+    pub struct TsVector;
+
+    #[derive(diesel::sql_types::SqlType, diesel::query_builder::QueryId)]
+    #[diesel(postgres_type(name = "tsquery"))]
+    pub struct TsQuery;
+
+    #[derive(diesel::sql_types::SqlType, diesel::query_builder::QueryId)]
+    #[diesel(postgres_type(name = "regconfig"))]
+    pub struct Regconfig;
+}
+use sql_types::{Regconfig, TsQuery, TsVector};
+
+diesel::define_sql_function!(fn websearch_to_tsquery(config: Regconfig, query: Text) -> TsQuery);
+diesel::define_sql_function!(fn ts_rank_cd(vector: TsVector, query: TsQuery) -> Float4);
+diesel::define_sql_function!(fn ts_headline(config: Regconfig, document: Text, query: TsQuery, options: Text) -> Text);
+diesel::infix_operator!(Matches, " @@ ", backend: Pg);
+diesel::define_sql_function!(fn similarity(a: Text, b: Text) -> Float4);
+diesel::define_sql_function!(fn word_similarity(a: Text, b: Text) -> Float4);
+// `similarity` and `word_similarity` over pg_trgm's thresholds; these can use the indexes
+diesel::infix_operator!(Similar, " % ", backend: Pg);
+diesel::infix_operator!(WordSimilar, " <% ", backend: Pg);
+
+/// Lowest `word_similarity` for an autocomplete suggestion. The pg_trgm default (0.6)
+/// drops common typos like "aple" for "Red Apple".
+const SUGGEST_THRESHOLD: f32 = 0.4;
+
+/// Marks around matched words in `ts_headline`'s output, from the Unicode private use
+/// area so they can't come from product text. `snippets` turns them into `<mark>`s.
+const MATCH_START: &str = "\u{E000}";
+const MATCH_END: &str = "\u{E001}";
+
+/// The text search configuration the migration builds `search_vector` with
+fn config() -> SqlLiteral<Regconfig> {
+    sql::<Regconfig>("'english'")
+}
+
+fn search_vector() -> SqlLiteral<TsVector> {
+    sql::<TsVector>("product.search_vector")
+}
+
+fn query_for(terms: &str) -> websearch_to_tsquery<SqlLiteral<Regconfig>, <String as AsExpression<Text>>::Expression> {
+    websearch_to_tsquery(config(), terms.trim().to_owned())
+}
+
+/// Whether a product matches the search terms. Terms that are all stop words ("the")
+/// match nothing.
+pub fn matches(terms: &str) -> Box<dyn BoxableExpression<crate::schema::product::table, Pg, SqlType = Bool>> {
+    Box::new(Matches::new(search_vector(), query_for(terms)))
+}
+
+/// How well a product matches, for sorting by relevance: higher is better
+pub fn rank(terms: &str) -> Box<dyn BoxableExpression<crate::schema::product::table, Pg, SqlType = Float4>> {
+    Box::new(ts_rank_cd(search_vector(), query_for(terms)))
+}
+
+/// An excerpt of each product's description (or tagline, or name) around the words
+/// matching `terms`. HTML-escaped, with matches wrapped in `<mark>`; safe to render as is.
+pub fn snippets(conn: &mut Conn, product_ids: &[i32], terms: &str) -> Result<HashMap<i32, String>, BeedleError> {
+    if product_ids.is_empty() || terms.trim().is_empty() {
+        return Ok(HashMap::new());
+    }
+    let document = sql::<Text>("coalesce(nullif(product.description, ''), nullif(product.tagline, ''), product.name)");
+    let options = format!("StartSel={MATCH_START}, StopSel={MATCH_END}, MaxWords=30, MinWords=12, MaxFragments=2");
+    let rows = product
+        .filter(id.eq_any(product_ids))
+        .select((id, ts_headline(config(), document, query_for(terms), options)))
+        .load::<(i32, String)>(conn)
+        .map_err(|e| {
+            log::error!("Making search snippets failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+    Ok(rows.into_iter().map(|(product_id, headline)| (product_id, highlight(&headline))).collect())
+}
+
+/// What a suggestion names
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuggestionKind {
+    Product,
+    Category,
+    Tag,
+}
+
+/// A name close to what a shopper typed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub name: String,
+    /// The product's or category's slug; `None` for tags
+    pub slug: Option<String>,
+    /// 0 to 1, higher is closer
+    pub score: f32,
+}
+
+fn suggestion_error(e: diesel::result::Error) -> BeedleError {
+    log::error!("Looking for search suggestions failed: {e}");
+    BeedleError::DatabaseError(e.to_string())
+}
+
+/// Up to `limit` products, categories and tags whose names contain something like
+/// `terms` ("aple" finds "Red Apple"), best first. For autocomplete.
+pub fn suggest(conn: &mut Conn, terms: &str, limit: usize) -> Result<Vec<Suggestion>, BeedleError> {
+    use crate::schema::{category, tag};
+    let terms = terms.trim().to_owned();
+    if terms.is_empty() || limit == 0 {
+        return Ok(Vec::new());
+    }
+    let max = limit as i64;
+    let found = conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::sql_query(format!("SET LOCAL pg_trgm.word_similarity_threshold = {SUGGEST_THRESHOLD}")).execute(conn)?;
+        let score = word_similarity(terms.clone(), name);
+        let products = product
+            .filter(WordSimilar::new(terms.clone().into_sql::<Text>(), name))
+            .order((score.clone().desc(), name))
+            .limit(max)
+            .select((name, slug, score))
+            .load::<(String, String, f32)>(conn)?;
+        let score = word_similarity(terms.clone(), category::name);
+        let categories = category::table
+            .filter(WordSimilar::new(terms.clone().into_sql::<Text>(), category::name))
+            .order((score.clone().desc(), category::name))
+            .limit(max)
+            .select((category::name, category::slug, score))
+            .load::<(String, String, f32)>(conn)?;
+        let score = word_similarity(terms.clone(), tag::name);
+        let tags = tag::table
+            .filter(WordSimilar::new(terms.clone().into_sql::<Text>(), tag::name))
+            .order((score.clone().desc(), tag::name))
+            .limit(max)
+            .select((tag::name, score))
+            .load::<(String, f32)>(conn)?;
+        Ok((products, categories, tags))
+    });
+    let (products, categories, tags) = found.map_err(suggestion_error)?;
+
+    let suggestion = |kind, name_val, slug_val, score| Suggestion { kind, name: name_val, slug: slug_val, score };
+    let mut suggestions: Vec<Suggestion> = products
+        .into_iter()
+        .map(|(n, s, score)| suggestion(SuggestionKind::Product, n, Some(s), score))
+        .chain(categories.into_iter().map(|(n, s, score)| suggestion(SuggestionKind::Category, n, Some(s), score)))
+        .chain(tags.into_iter().map(|(n, score)| suggestion(SuggestionKind::Tag, n, None, score)))
+        .collect();
+    // Stable, so equally close products come before categories before tags
+    suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+    suggestions.truncate(limit);
+    Ok(suggestions)
+}
+
+/// The product, category or tag name most like the whole of `terms`, for offering
+/// "did you mean ...?" when a search finds nothing.
+pub fn did_you_mean(conn: &mut Conn, terms: &str) -> Result<Option<Suggestion>, BeedleError> {
+    use crate::schema::{category, tag};
+    let terms = terms.trim().to_owned();
+    if terms.is_empty() {
+        return Ok(None);
+    }
+    let score = similarity(terms.clone(), name);
+    let best_product = product
+        .filter(Similar::new(terms.clone().into_sql::<Text>(), name))
+        .order((score.clone().desc(), name))
+        .select((name, slug, score))
+        .first::<(String, String, f32)>(conn)
+        .optional()
+        .map_err(suggestion_error)?
+        .map(|(n, s, score)| Suggestion { kind: SuggestionKind::Product, name: n, slug: Some(s), score });
+    let score = similarity(terms.clone(), category::name);
+    let best_category = category::table
+        .filter(Similar::new(terms.clone().into_sql::<Text>(), category::name))
+        .order((score.clone().desc(), category::name))
+        .select((category::name, category::slug, score))
+        .first::<(String, String, f32)>(conn)
+        .optional()
+        .map_err(suggestion_error)?
+        .map(|(n, s, score)| Suggestion { kind: SuggestionKind::Category, name: n, slug: Some(s), score });
+    let score = similarity(terms.clone(), tag::name);
+    let best_tag = tag::table
+        .filter(Similar::new(terms.clone().into_sql::<Text>(), tag::name))
+        .order((score.clone().desc(), tag::name))
+        .select((tag::name, score))
+        .first::<(String, f32)>(conn)
+        .optional()
+        .map_err(suggestion_error)?
+        .map(|(n, score)| Suggestion { kind: SuggestionKind::Tag, name: n, slug: None, score });
+
+    let mut best: Option<Suggestion> = None;
+    for candidate in [best_product, best_category, best_tag].into_iter().flatten() {
+        if best.as_ref().is_none_or(|b| candidate.score > b.score) {
+            best = Some(candidate);
+        }
+    }
+    Ok(best)
+}
+
+/// Escape a headline and swap its match markers for `<mark>` tags
+fn highlight(headline: &str) -> String {
+    tera::escape_html(headline)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+#[cfg(test)]
+mod search_tests {
+    use super::*;
+    use crate::db::categories::test_category;
+    use crate::db::products::{delete_product, insert_product, save_product, ProductFilter, ProductQuery};
+    use crate::db::{test_conn, test_product};
+    use crate::models::{NewProduct, ProductLists};
+
+    #[test]
+    fn test_search_ranks_and_highlights() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Search");
+        let searching = |terms: &str| ProductFilter {
+            category_ids: Some(vec![category_id_val]),
+            search: Some(terms.to_owned()),
+            ..ProductFilter::default()
+        };
+        let new_product = |name_val: &str, description_val: &str| NewProduct {
+            description: Some(description_val.to_owned()),
+            ..test_product(name_val, category_id_val, 100, 0, None)
+        };
+        let keywords = |words: &[&str]| ProductLists { keywords: words.iter().map(|w| w.to_string()).collect(), ..ProductLists::default() };
+        let in_name = insert_product(&mut conn, &new_product("Test Zorbleberry Jam", "Spread <b>thickly</b>"), &ProductLists::default()).unwrap();
+        let in_description =
+            insert_product(&mut conn, &new_product("Test Toast", "Great with zorbleberries & butter"), &ProductLists::default()).unwrap();
+        let in_keywords = insert_product(&mut conn, &new_product("Test Scone", "Crumbly"), &keywords(&["zorbleberry"])).unwrap();
+
+        let query = ProductQuery::new(searching("zorbleberry")).sorted(Some("relevance"));
+        let found = query.clone().page(10, 0).load(&mut conn).unwrap();
+        let ids: Vec<i32> = found.products.iter().map(|p| p.id).collect();
+        assert_eq!(ids, [in_name.id, in_keywords.id, in_description.id]);
+        assert_eq!(found.total, 3);
+        // The total covers every page, including ones past the end
+        let last = query.clone().page(2, 2).load(&mut conn).unwrap();
+        assert_eq!((last.products.len(), last.total), (1, 3));
+        let past_end = query.page(2, 4).load(&mut conn).unwrap();
+        assert_eq!((past_end.products.len(), past_end.total), (0, 3));
+        assert_eq!(ProductQuery::new(searching("zorbleberry -jam")).count(&mut conn).unwrap(), 2);
+        assert_eq!(ProductQuery::new(searching("the")).count(&mut conn).unwrap(), 0);
+
+        let snippets = snippets(&mut conn, &ids, "zorbleberry").unwrap();
+        assert_eq!(snippets[&in_description.id], "Great with <mark>zorbleberries</mark> &amp; butter");
+        // No match in its description to mark, and no markup of its own
+        assert_eq!(snippets[&in_name.id].trim(), "Spread  thickly");
+
+        // Keywords saved with the product are searchable straight away
+        let mut toast = in_description.clone();
+        toast.name = "Test Plain Toast".to_owned();
+        save_product(&mut conn, &toast, &keywords(&["crumpet"])).unwrap();
+        assert_eq!(ProductQuery::new(searching("crumpet plain")).count(&mut conn).unwrap(), 1);
+
+        for made in [in_name, in_description, in_keywords] {
+            delete_product(&mut conn, made.id).unwrap();
+        }
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[test]
+    fn test_suggestions_tolerate_typos() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Quibbleware");
+        let tarts = ProductLists { tags: vec!["TestQuibblefruity".to_owned()], ..ProductLists::default() };
+        let tart = insert_product(&mut conn, &test_product("Test Quibblefruit Tart", category_id_val, 100, 0, None), &tarts).unwrap();
+
+        let found = suggest(&mut conn, "quiblefruit", 8).unwrap();
+        let kinds: Vec<(SuggestionKind, &str)> = found.iter().map(|s| (s.kind, s.name.as_str())).collect();
+        assert!(kinds.contains(&(SuggestionKind::Product, "Test Quibblefruit Tart")), "{kinds:?}");
+        assert!(kinds.contains(&(SuggestionKind::Tag, "TestQuibblefruity")), "{kinds:?}");
+        assert!(found.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert_eq!(suggest(&mut conn, "quiblefruit", 1).unwrap().len(), 1);
+        assert!(suggest(&mut conn, "  ", 8).unwrap().is_empty());
+
+        let best = did_you_mean(&mut conn, "test quiblefruit tart").unwrap().unwrap();
+        assert_eq!((best.kind, best.slug.as_deref()), (SuggestionKind::Product, Some("test-quibblefruit-tart")));
+        let best = did_you_mean(&mut conn, "Test Quibbleware").unwrap().unwrap();
+        assert_eq!((best.kind, best.name.as_str()), (SuggestionKind::Category, "Test Quibbleware"));
+        assert!(did_you_mean(&mut conn, "xyzzyplugh").unwrap().is_none());
+
+        delete_product(&mut conn, tart.id).unwrap();
+        let tag = crate::db::tags::find_tag(&mut conn, "TestQuibblefruity").unwrap().unwrap();
+        crate::db::tags::delete_tag(&mut conn, tag.id).unwrap();
+        crate::db::categories::delete_category(&mut conn, category_id_val).unwrap();
+    }
+}
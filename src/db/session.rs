@@ -1,6 +1,7 @@
 //! Database access for web sessions via the `session` table.
 //! CRUD for session rows and shopping cart storage.
 
+use crate::checkout::CheckoutState;
 use crate::errors::*;
 use crate::db::Conn;
 use crate::models::{SessionRow, CartItem};
@@ -56,6 +57,7 @@ pub fn create_new_session(
         ip_address: Some(ip.to_owned()),
         user_agent: Some(user_agent_str.to_owned()),
         cart_data: Some(serde_json::json!([])), // Empty cart as default
+        checkout_data: None,
     };
     let inserted_count = diesel::insert_into(session)
         .values(&new_session)
@@ -107,6 +109,37 @@ pub fn update_session_cart(
     Ok(())
 }
 
+/// Save in-progress checkout details for a session (see `checkout::CheckoutState`).
+/// Passing `CheckoutState::default()` clears them.
+pub fn update_session_checkout(
+    conn: &mut Conn,
+    session_id_val: Uuid,
+    state: &CheckoutState,
+) -> Result<(), BeedleError> {
+    use crate::schema::session::dsl::*;
+
+    let state_json = serde_json::to_value(state).map_err(|e| {
+        log::error!("Checkout serialization failed for session {}: {e}", session_id_val);
+        BeedleError::SessionError(format!("Checkout serialization error: {e}"))
+    })?;
+
+    let rows_updated = diesel::update(session.filter(session_id.eq(session_id_val)))
+        .set((checkout_data.eq(state_json), updated_at.eq(Utc::now().naive_utc())))
+        .execute(conn)
+        .map_err(|e| {
+            log::error!("DB error on checkout update for session {}: {e}", session_id_val);
+            BeedleError::DatabaseError(format!("Session DB error: {e}"))
+        })?;
+
+    if rows_updated == 0 {
+        log::warn!("Failed to update checkout for session_id {}", session_id_val);
+        return Err(BeedleError::DatabaseError(format!(
+            "Possibly missing session row for session_id: {}", session_id_val
+        )));
+    }
+
+    Ok(())
+}
 
 // TODO: associate an existing session with a user ID 
 // pub fn set_session_user_id(...)
\ No newline at end of file
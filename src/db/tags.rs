@@ -0,0 +1,194 @@
+//! Tags shared between products (`tag`, linked through `product_tag`): looking them up by
+//! name, product counts for the catalog's tag facets, and the admin's rename/merge/delete.
+//! A product's own tags are loaded and saved with it; see `db::products::load_product_lists`.
+
+use crate::errors::BeedleError;
+use crate::models::Tag;
+use crate::schema::{product_tag, tag};
+use diesel::dsl::count;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use serde::Serialize;
+
+use super::products::{filtered, ProductFilter};
+use super::Conn;
+
+diesel::define_sql_function!(fn lower(x: Text) -> Text);
+
+/// A tag and how many products have it
+#[derive(Queryable, Debug, Clone, Serialize)]
+pub struct TagCount {
+    pub id: i32,
+    pub name: String,
+    pub products: i64,
+}
+
+/// IDs of the tags called `names` (ignoring case), creating those that don't exist yet.
+/// New tags keep the spelling given here.
+pub(super) fn tag_ids(conn: &mut Conn, names: &[String]) -> Result<Vec<i32>, diesel::result::Error> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rows: Vec<_> = names.iter().map(|n| tag::name.eq(n)).collect();
+    diesel::insert_into(tag::table).values(&rows).on_conflict_do_nothing().execute(conn)?;
+    let lowered: Vec<String> = names.iter().map(|n| n.to_lowercase()).collect();
+    tag::table
+        .filter(lower(tag::name).eq_any(&lowered))
+        .select(tag::id)
+        .load(conn)
+}
+
+/// Find a tag by name, ignoring case.
+pub fn find_tag(conn: &mut Conn, name_val: &str) -> Result<Option<Tag>, BeedleError> {
+    tag::table
+        .filter(lower(tag::name).eq(lower(name_val.trim())))
+        .first::<Tag>(conn)
+        .optional()
+        .map_err(|e| {
+            log::error!("Looking up tag {:?} failed: {e}", name_val);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Tags of the products matching the catalog filters other than tags, most used first,
+/// for narrowing the listing down. Tags no matching product has are left out.
+pub fn tag_counts(conn: &mut Conn, filter: &ProductFilter) -> Result<Vec<TagCount>, BeedleError> {
+    let others = ProductFilter { tags: Vec::new(), ..filter.clone() };
+    let matching = filtered(&others).select(crate::schema::product::id);
+    product_tag::table
+        .inner_join(tag::table)
+        .filter(product_tag::product_id.eq_any(matching))
+        .group_by((tag::id, tag::name))
+        .select((tag::id, tag::name, count(product_tag::product_id)))
+        .order((count(product_tag::product_id).desc(), lower(tag::name)))
+        .load::<TagCount>(conn)
+        .map_err(|e| {
+            log::error!("Counting tagged products failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Every tag, alphabetical, with how many products have it (possibly none).
+pub fn list_tags(conn: &mut Conn) -> Result<Vec<TagCount>, BeedleError> {
+    tag::table
+        .left_join(product_tag::table)
+        .group_by((tag::id, tag::name))
+        .select((tag::id, tag::name, count(product_tag::product_id.nullable())))
+        .order(lower(tag::name))
+        .load::<TagCount>(conn)
+        .map_err(|e| {
+            log::error!("Listing tags failed: {e}");
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Rename a tag. If another tag already has the new name, the two are merged: its
+/// products get that tag instead and this one is removed. Returns the surviving tag's ID.
+pub fn rename_tag(conn: &mut Conn, tag_id_val: i32, new_name: &str) -> Result<i32, BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let renamed = tag::table.find(tag_id_val).for_update().first::<Tag>(conn).optional()?;
+        let Some(renamed) = renamed else {
+            return Err(BeedleError::DatabaseError(format!("No tag with id {}", tag_id_val)));
+        };
+        let existing = tag::table
+            .filter(lower(tag::name).eq(lower(new_name)))
+            .filter(tag::id.ne(tag_id_val))
+            .first::<Tag>(conn)
+            .optional()?;
+
+        let Some(existing) = existing else {
+            diesel::update(tag::table.find(tag_id_val)).set(tag::name.eq(new_name)).execute(conn)?;
+            log::info!("Renamed tag {:?} to {:?}", renamed.name, new_name);
+            return Ok(tag_id_val);
+        };
+        let product_ids: Vec<i32> = product_tag::table
+            .filter(product_tag::tag_id.eq(tag_id_val))
+            .select(product_tag::product_id)
+            .load(conn)?;
+        let rows: Vec<_> = product_ids
+            .iter()
+            .map(|p| (product_tag::product_id.eq(p), product_tag::tag_id.eq(existing.id)))
+            .collect();
+        diesel::insert_into(product_tag::table).values(&rows).on_conflict_do_nothing().execute(conn)?;
+        diesel::delete(tag::table.find(tag_id_val)).execute(conn)?;
+        log::info!("Merged tag {:?} into {:?}", renamed.name, existing.name);
+        Ok(existing.id)
+    })
+}
+
+/// Remove a tag from every product and delete it.
+pub fn delete_tag(conn: &mut Conn, tag_id_val: i32) -> Result<(), BeedleError> {
+    let deleted = diesel::delete(tag::table.find(tag_id_val))
+        .get_result::<Tag>(conn)
+        .optional()
+        .map_err(|e| {
+            log::error!("Deleting tag {} failed: {e}", tag_id_val);
+            BeedleError::DatabaseError(e.to_string())
+        })?;
+    match deleted {
+        Some(deleted) => {
+            log::info!("Deleted tag {:?}", deleted.name);
+            Ok(())
+        }
+        None => Err(BeedleError::DatabaseError(format!("No tag with id {}", tag_id_val))),
+    }
+}
+
+#[cfg(test)]
+mod tags_tests {
+    use super::*;
+    use crate::db::categories::test_category;
+    use crate::db::products::{delete_product, insert_product, load_product_lists, ProductQuery};
+    use crate::db::{test_conn, test_product};
+    use crate::models::ProductLists;
+
+    fn lists(tags: &[&str]) -> ProductLists {
+        ProductLists { tags: tags.iter().map(|t| t.to_string()).collect(), ..ProductLists::default() }
+    }
+
+    #[test]
+    fn test_tags_match_whole_names() {
+        let mut conn = test_conn();
+        let category_id = test_category(&mut conn, "Test Tags");
+        let grapefruit = insert_product(&mut conn, &test_product("Test Grapefruit", category_id, 100, 0, None), &lists(&["TestGrapefruit"])).unwrap();
+        let fruit = insert_product(&mut conn, &test_product("Test Fruit", category_id, 100, 0, None), &lists(&["TestFruit", "TestCitrus"])).unwrap();
+        let both = insert_product(&mut conn, &test_product("Test Lemon", category_id, 100, 0, None), &lists(&["testfruit", "TestCitrus"])).unwrap();
+
+        let in_category = ProductFilter { category_ids: Some(vec![category_id]), ..ProductFilter::default() };
+        let tagged = |tags: &[&str], all_tags: bool| ProductFilter {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            all_tags,
+            ..in_category.clone()
+        };
+        assert_eq!(ProductQuery::new(tagged(&["TestFruit"], false)).count(&mut conn).unwrap(), 2);
+        assert_eq!(ProductQuery::new(tagged(&["testgrapefruit"], false)).count(&mut conn).unwrap(), 1);
+        assert_eq!(ProductQuery::new(tagged(&["Fruit"], false)).count(&mut conn).unwrap(), 0);
+        // Any of the tags, or all of them
+        assert_eq!(ProductQuery::new(tagged(&["TestGrapefruit", "TestCitrus"], false)).count(&mut conn).unwrap(), 3);
+        assert_eq!(ProductQuery::new(tagged(&["TestFruit", "TestCitrus"], true)).count(&mut conn).unwrap(), 2);
+        assert_eq!(ProductQuery::new(tagged(&["TestGrapefruit", "TestCitrus"], true)).count(&mut conn).unwrap(), 0);
+        // "testfruit" reused the existing tag rather than making a second one
+        assert_eq!(load_product_lists(&mut conn, &[both.id]).unwrap()[&both.id].tags, ["TestCitrus", "TestFruit"]);
+
+        let counts: Vec<(String, i64)> =
+            tag_counts(&mut conn, &tagged(&["TestGrapefruit"], true)).unwrap().into_iter().map(|c| (c.name, c.products)).collect();
+        assert_eq!(counts, [("TestCitrus".to_owned(), 2), ("TestFruit".to_owned(), 2), ("TestGrapefruit".to_owned(), 1)]);
+
+        // Renaming onto an existing name merges the two
+        let citrus = find_tag(&mut conn, "testcitrus").unwrap().unwrap();
+        let grapefruit_tag = find_tag(&mut conn, "TestGrapefruit").unwrap().unwrap();
+        assert_eq!(rename_tag(&mut conn, grapefruit_tag.id, "TESTCITRUS").unwrap(), citrus.id);
+        assert!(find_tag(&mut conn, "TestGrapefruit").unwrap().is_none());
+        assert_eq!(ProductQuery::new(tagged(&["TestCitrus"], false)).count(&mut conn).unwrap(), 3);
+
+        for product in [grapefruit, fruit, both] {
+            delete_product(&mut conn, product.id).unwrap();
+        }
+        for name_val in ["TestCitrus", "TestFruit"] {
+            let found = find_tag(&mut conn, name_val).unwrap().unwrap();
+            delete_tag(&mut conn, found.id).unwrap();
+        }
+        assert!(matches!(delete_tag(&mut conn, citrus.id), Err(BeedleError::DatabaseError(_))));
+        crate::db::categories::delete_category(&mut conn, category_id).unwrap();
+    }
+}
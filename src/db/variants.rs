@@ -0,0 +1,318 @@
+//! Product variants: option types (eg "Size"), their values (eg "M"), and the
+//! `product_variant` rows that carry SKU, price override and stock.
+//! Products without options have a single default variant with no option values.
+
+use crate::errors::BeedleError;
+use crate::models::{
+    NewProductOption, NewProductOptionValue, NewProductVariant, Product, ProductOption, ProductOptionValue,
+    ProductVariant,
+};
+use crate::price::Price;
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+use super::Conn;
+
+/// A variant plus its option values as (option name, value), in option order.
+#[derive(Debug, Clone)]
+pub struct VariantDetail {
+    pub variant: ProductVariant,
+    pub options: Vec<(String, String)>,
+}
+
+impl VariantDetail {
+    /// Eg "M / Red"; empty for a default variant
+    pub fn label(&self) -> String {
+        self.options.iter().map(|(_, value)| value.as_str()).collect::<Vec<_>>().join(" / ")
+    }
+
+    /// Eg "T-Shirt (M / Red)", or just the product name for a default variant
+    pub fn display_name(&self, product: &Product) -> String {
+        match self.label() {
+            label if label.is_empty() => product.name.clone(),
+            label => format!("{} ({})", product.name, label),
+        }
+    }
+
+    /// Undiscounted unit price: the variant's own price, or the product's.
+    pub fn price_original(&self, product: &Product) -> Price {
+        Price::from_cents(self.variant.price.unwrap_or(product.price))
+    }
+}
+
+/// Attach option values to variants, keeping the variants' order.
+fn with_options(conn: &mut Conn, variants: Vec<ProductVariant>) -> Result<Vec<VariantDetail>, BeedleError> {
+    use crate::schema::{product_option, product_option_value, product_variant_value};
+
+    let ids: Vec<i32> = variants.iter().map(|v| v.id).collect();
+    let rows: Vec<(i32, String, String)> = product_variant_value::table
+        .inner_join(product_option_value::table.inner_join(product_option::table))
+        .filter(product_variant_value::variant_id.eq_any(&ids))
+        .order((product_option::position, product_option::id))
+        .select((product_variant_value::variant_id, product_option::name, product_option_value::value))
+        .load(conn)?;
+
+    let mut options: HashMap<i32, Vec<(String, String)>> = HashMap::new();
+    for (variant_id, name, value) in rows {
+        options.entry(variant_id).or_default().push((name, value));
+    }
+    Ok(variants
+        .into_iter()
+        .map(|variant| VariantDetail { options: options.remove(&variant.id).unwrap_or_default(), variant })
+        .collect())
+}
+
+/// All variants of a product, in display order.
+pub fn load_variants(conn: &mut Conn, product_id_val: i32) -> Result<Vec<VariantDetail>, BeedleError> {
+    let variants = {
+        use crate::schema::product_variant::dsl::*;
+        product_variant
+            .filter(product_id.eq(product_id_val))
+            .order((position, id))
+            .load::<ProductVariant>(conn)?
+    };
+    with_options(conn, variants)
+}
+
+/// Variants by ID, eg for everything in a cart. Missing IDs are skipped.
+pub fn load_variants_by_ids(conn: &mut Conn, ids: &[i32]) -> Result<Vec<VariantDetail>, BeedleError> {
+    let variants = {
+        use crate::schema::product_variant::dsl::*;
+        product_variant.filter(id.eq_any(ids)).order(id).load::<ProductVariant>(conn)?
+    };
+    with_options(conn, variants)
+}
+
+pub fn load_variant_by_id(conn: &mut Conn, variant_id_val: i32) -> Result<Option<VariantDetail>, BeedleError> {
+    Ok(load_variants_by_ids(conn, &[variant_id_val])?.pop())
+}
+
+/// Option types of a product with their values, eg [("Size", [S, M, L])].
+pub fn load_options(conn: &mut Conn, product_id_val: i32) -> Result<Vec<(ProductOption, Vec<ProductOptionValue>)>, BeedleError> {
+    let options = {
+        use crate::schema::product_option::dsl::*;
+        product_option
+            .filter(product_id.eq(product_id_val))
+            .order((position, id))
+            .load::<ProductOption>(conn)?
+    };
+    let values = {
+        use crate::schema::product_option_value::dsl::*;
+        ProductOptionValue::belonging_to(&options)
+            .order((position, id))
+            .load::<ProductOptionValue>(conn)?
+            .grouped_by(&options)
+    };
+    Ok(options.into_iter().zip(values).collect())
+}
+
+/// The variant every product starts with: no options, the product's own price.
+pub fn create_default_variant(
+    conn: &mut Conn,
+    product_id_val: i32,
+    inventory_val: i32,
+) -> Result<ProductVariant, BeedleError> {
+    use crate::schema::product_variant::dsl::*;
+    diesel::insert_into(product_variant)
+        .values(&NewProductVariant {
+            product_id: product_id_val,
+            sku: &format!("P{}", product_id_val),
+            price: None,
+            inventory: inventory_val,
+            position: 0,
+        })
+        .get_result(conn)
+        .map_err(|e| {
+            log::error!("Creating default variant for product {} failed: {e}", product_id_val);
+            BeedleError::DatabaseError(e.to_string())
+        })
+}
+
+/// Add a variant with the given (option name, value) pairs, creating options/values as needed.
+/// Every variant of a product must use the same option names, and no two may share the same
+/// combination of values; so a product's option-less default variant has to go before
+/// optioned ones can be added.
+pub fn create_variant(
+    conn: &mut Conn,
+    product_id_val: i32,
+    sku_val: &str,
+    price_val: Option<i64>,
+    inventory_val: i32,
+    option_values: &[(String, String)],
+) -> Result<ProductVariant, BeedleError> {
+    if sku_val.trim().is_empty() {
+        return Err(BeedleError::InventoryError("SKU is required".into()));
+    }
+    if inventory_val < 0 || price_val.is_some_and(|p| p < 0) {
+        return Err(BeedleError::InventoryError("Price and inventory can't be negative".into()));
+    }
+
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let existing = load_variants(conn, product_id_val)?;
+        let names = |opts: &[(String, String)]| {
+            let mut names: Vec<String> = opts.iter().map(|(name, _)| name.to_lowercase()).collect();
+            names.sort();
+            names
+        };
+        if let Some(other) = existing.first() {
+            if other.options.is_empty() && !option_values.is_empty() {
+                return Err(BeedleError::InventoryError(
+                    "This product has a variant without options; delete it before adding options".into(),
+                ));
+            }
+            if names(&other.options) != names(option_values) {
+                return Err(BeedleError::InventoryError(format!(
+                    "Variants of this product use options: {}",
+                    other.options.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>().join(", ")
+                )));
+            }
+        }
+        let same_combination = |v: &VariantDetail| {
+            let mut a: Vec<_> = v.options.iter().map(|(n, val)| (n.to_lowercase(), val.to_lowercase())).collect();
+            let mut b: Vec<_> = option_values.iter().map(|(n, val)| (n.to_lowercase(), val.to_lowercase())).collect();
+            a.sort();
+            b.sort();
+            a == b
+        };
+        if existing.iter().any(same_combination) {
+            return Err(BeedleError::InventoryError("A variant with these options already exists".into()));
+        }
+
+        let variant: ProductVariant = {
+            use crate::schema::product_variant::dsl::*;
+            diesel::insert_into(product_variant)
+                .values(&NewProductVariant {
+                    product_id: product_id_val,
+                    sku: sku_val.trim(),
+                    price: price_val,
+                    inventory: inventory_val,
+                    position: existing.len() as i32,
+                })
+                .get_result(conn)?
+        };
+
+        for (name, value) in option_values {
+            let option_id = find_or_create_option(conn, product_id_val, name.trim())?;
+            let value_id = find_or_create_option_value(conn, option_id, value.trim())?;
+            use crate::schema::product_variant_value::dsl as pvv;
+            diesel::insert_into(pvv::product_variant_value)
+                .values((pvv::variant_id.eq(variant.id), pvv::option_value_id.eq(value_id)))
+                .execute(conn)?;
+        }
+
+        log::info!("Created variant {} ({}) for product {}", variant.id, variant.sku, product_id_val);
+        Ok(variant)
+    })
+}
+
+fn find_or_create_option(conn: &mut Conn, product_id_val: i32, name_val: &str) -> Result<i32, BeedleError> {
+    use crate::schema::product_option::dsl::*;
+    let existing = product_option
+        .filter(product_id.eq(product_id_val))
+        .filter(name.ilike(name_val))
+        .select(id)
+        .first::<i32>(conn)
+        .optional()?;
+    if let Some(found) = existing {
+        return Ok(found);
+    }
+    let count: i64 = product_option.filter(product_id.eq(product_id_val)).count().get_result(conn)?;
+    Ok(diesel::insert_into(product_option)
+        .values(&NewProductOption { product_id: product_id_val, name: name_val, position: count as i32 })
+        .returning(id)
+        .get_result(conn)?)
+}
+
+fn find_or_create_option_value(conn: &mut Conn, option_id_val: i32, value_val: &str) -> Result<i32, BeedleError> {
+    use crate::schema::product_option_value::dsl::*;
+    let existing = product_option_value
+        .filter(option_id.eq(option_id_val))
+        .filter(value.ilike(value_val))
+        .select(id)
+        .first::<i32>(conn)
+        .optional()?;
+    if let Some(found) = existing {
+        return Ok(found);
+    }
+    let count: i64 = product_option_value.filter(option_id.eq(option_id_val)).count().get_result(conn)?;
+    Ok(diesel::insert_into(product_option_value)
+        .values(&NewProductOptionValue { option_id: option_id_val, value: value_val, position: count as i32 })
+        .returning(id)
+        .get_result(conn)?)
+}
+
+/// Set a variant's price override and stock level (product inventory follows via trigger).
+pub fn update_variant(
+    conn: &mut Conn,
+    variant_id_val: i32,
+    price_val: Option<i64>,
+    inventory_val: i32,
+) -> Result<(), BeedleError> {
+    if inventory_val < 0 || price_val.is_some_and(|p| p < 0) {
+        return Err(BeedleError::InventoryError("Price and inventory can't be negative".into()));
+    }
+    use crate::schema::product_variant::dsl::*;
+    let updated = diesel::update(product_variant.find(variant_id_val))
+        .set((price.eq(price_val), inventory.eq(inventory_val)))
+        .execute(conn)?;
+    if updated == 0 {
+        return Err(BeedleError::InventoryError(format!("No variant with id {}", variant_id_val)));
+    }
+    Ok(())
+}
+
+/// Remove a variant. Past order lines keep their snapshot; the option values stay for reuse.
+pub fn delete_variant(conn: &mut Conn, variant_id_val: i32) -> Result<(), BeedleError> {
+    use crate::schema::product_variant::dsl::*;
+    let deleted = diesel::delete(product_variant.find(variant_id_val)).execute(conn)?;
+    if deleted == 0 {
+        return Err(BeedleError::InventoryError(format!("No variant with id {}", variant_id_val)));
+    }
+    log::info!("Deleted variant {}", variant_id_val);
+    Ok(())
+}
+
+/// A cart line for a product's first variant (the default one, for seed products).
+#[cfg(test)]
+pub fn cart_item(conn: &mut Conn, product_id_val: i32, quantity_val: u32) -> crate::models::CartItem {
+    let variant = load_variants(conn, product_id_val).unwrap().remove(0).variant;
+    crate::models::CartItem { product_id: product_id_val, variant_id: variant.id, quantity: quantity_val }
+}
+
+#[cfg(test)]
+mod variants_tests {
+    use super::*;
+    use crate::db::products::{delete_product, insert_product, load_product_by_id};
+    use crate::db::{categories::test_category, test_conn, test_product};
+    use crate::models::ProductLists;
+
+    #[test]
+    fn test_variants_track_their_own_stock() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Uncategorized");
+        let shirt = insert_product(&mut conn, &test_product("Test T-Shirt", category_id_val, 2000, 0, None), &ProductLists::default()).unwrap();
+        let default = load_variants(&mut conn, shirt.id).unwrap();
+        assert_eq!(default.len(), 1);
+        delete_variant(&mut conn, default[0].variant.id).unwrap();
+
+        let opts = |size: &str, colour: &str| vec![("Size".to_owned(), size.to_owned()), ("Colour".to_owned(), colour.to_owned())];
+        let sku = |s: &str| format!("TEST-{}-{}", shirt.id, s);
+        create_variant(&mut conn, shirt.id, &sku("M-RED"), None, 3, &opts("M", "Red")).unwrap();
+        create_variant(&mut conn, shirt.id, &sku("L-RED"), Some(2200), 5, &opts("L", "Red")).unwrap();
+        // Same combination, and a mismatched option set, are both rejected
+        assert!(create_variant(&mut conn, shirt.id, &sku("M-RED-2"), None, 1, &opts("m", "red")).is_err());
+        assert!(create_variant(&mut conn, shirt.id, &sku("XL"), None, 1, &[("Size".to_owned(), "XL".to_owned())]).is_err());
+
+        let variants = load_variants(&mut conn, shirt.id).unwrap();
+        assert_eq!(variants.iter().map(|v| v.label()).collect::<Vec<_>>(), vec!["M / Red", "L / Red"]);
+        let options = load_options(&mut conn, shirt.id).unwrap();
+        assert_eq!(options.iter().map(|(o, vals)| (o.name.as_str(), vals.len())).collect::<Vec<_>>(), vec![("Size", 2), ("Colour", 1)]);
+        // product.inventory is the sum over variants
+        assert_eq!(load_product_by_id(&mut conn, shirt.id).unwrap().unwrap().inventory, 8);
+
+        update_variant(&mut conn, variants[0].variant.id, Some(1800), 0).unwrap();
+        assert_eq!(load_product_by_id(&mut conn, shirt.id).unwrap().unwrap().inventory, 5);
+
+        delete_product(&mut conn, shirt.id).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Payment webhook bookkeeping: remembers processed events (idempotency) and advances orders.
+
+use crate::db::orders::{load_order_by_payment_reference, transition_order_status};
+use crate::errors::BeedleError;
+use crate::models::NewPaymentWebhookEvent;
+use crate::orders::OrderStatus;
+use crate::pay::webhook::{WebhookEvent, WebhookEventKind};
+use diesel::prelude::*;
+
+use super::Conn;
+
+/// Apply a verified webhook event exactly once.
+/// Returns Ok(false) if this event was already processed (providers redeliver freely).
+/// Recording the event and moving the order happen in one transaction, so a failure here
+/// leaves the event unrecorded and the provider's retry gets another go.
+pub fn process_webhook_event(conn: &mut Conn, provider_name: &str, event: &WebhookEvent) -> Result<bool, BeedleError> {
+    conn.transaction::<_, BeedleError, _>(|conn| {
+        let order = load_order_by_payment_reference(conn, provider_name, &event.intent_id)?;
+
+        let event_type_val = match &event.kind {
+            WebhookEventKind::PaymentSucceeded => "payment_succeeded",
+            WebhookEventKind::PaymentFailed { .. } => "payment_failed",
+            WebhookEventKind::Refunded => "refunded",
+            WebhookEventKind::Other(other) => other.as_str(),
+        };
+        let inserted = {
+            use crate::schema::payment_webhook_event::dsl::*;
+            diesel::insert_into(payment_webhook_event)
+                .values(&NewPaymentWebhookEvent {
+                    provider: provider_name,
+                    event_id: &event.id,
+                    event_type: event_type_val,
+                    payment_reference: &event.intent_id,
+                    order_id: order.as_ref().map(|o| o.id),
+                })
+                .on_conflict_do_nothing()
+                .execute(conn)?
+        };
+        if inserted == 0 {
+            log::info!("Ignoring duplicate {} webhook event {}", provider_name, event.id);
+            return Ok(false);
+        }
+
+        let Some(order) = order else {
+            log::warn!("{} webhook {} refers to unknown payment {}", provider_name, event.id, event.intent_id);
+            return Ok(true);
+        };
+
+        let (next, note) = match &event.kind {
+            WebhookEventKind::PaymentSucceeded => (OrderStatus::Paid, None),
+            WebhookEventKind::PaymentFailed { reason } => (OrderStatus::Cancelled, reason.clone()),
+            WebhookEventKind::Refunded => (OrderStatus::Refunded, None),
+            WebhookEventKind::Other(other) => {
+                log::debug!("Ignoring {} webhook event type {}", provider_name, other);
+                return Ok(true);
+            }
+        };
+        // Success/failure only matter while we're still waiting for the money
+        let applies = match next {
+            OrderStatus::Refunded => order.status.can_transition_to(next),
+            _ => order.status == OrderStatus::PendingPayment,
+        };
+        if applies {
+            let changed_by = format!("webhook:{provider_name}");
+            transition_order_status(conn, order.id, next, &changed_by, note.as_deref())?;
+        } else {
+            log::info!("{} webhook {} ({}) doesn't apply to order {} in state {}",
+                provider_name, event.id, event_type_val, order.id, order.status);
+        }
+        Ok(true)
+    })
+}
+
+#[cfg(test)]
+mod webhooks_tests {
+    use super::*;
+    use crate::checkout::{ShippingAddress, ShippingMethod};
+    use crate::db::orders::{create_order, load_order_by_id, set_order_payment};
+    use crate::db::categories::{delete_category, test_category};
+    use crate::db::products::{delete_product, insert_product};
+    use crate::db::{test_conn, test_product};
+    use crate::db::variants::cart_item;
+    use crate::models::ProductLists;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_webhook_event_applied_once() {
+        let mut conn = test_conn();
+        let category_id_val = test_category(&mut conn, "Test Webhook Paid");
+        let tea = insert_product(&mut conn, &test_product("Test Webhook Tea", category_id_val, 400, 1, None), &ProductLists::default())
+            .unwrap();
+        let cart = vec![cart_item(&mut conn, tea.id, 1)];
+        let created = create_order(&mut conn, Uuid::new_v4(), &cart, &ShippingAddress::sample(), ShippingMethod::Pickup).unwrap();
+        let reference = format!("test_{}", Uuid::new_v4().simple());
+        set_order_payment(&mut conn, created.id, "mock", &reference).unwrap();
+
+        let event = WebhookEvent {
+            id: format!("evt_{}", Uuid::new_v4().simple()),
+            intent_id: reference,
+            kind: WebhookEventKind::PaymentSucceeded,
+        };
+        assert!(process_webhook_event(&mut conn, "mock", &event).unwrap());
+        assert!(!process_webhook_event(&mut conn, "mock", &event).unwrap());
+
+        let paid = load_order_by_id(&mut conn, created.id).unwrap().unwrap();
+        assert_eq!(paid.status, OrderStatus::Paid);
+
+        diesel::delete(crate::schema::order::table.find(created.id)).execute(&mut conn).unwrap();
+        delete_product(&mut conn, tea.id).unwrap();
+        delete_category(&mut conn, category_id_val).unwrap();
+    }
+}
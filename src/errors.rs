@@ -4,6 +4,7 @@ use thiserror::Error;
 use std::io;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum BeedleError {
     #[error("Database error: {0}")]
     DatabaseError(String),
@@ -29,6 +30,30 @@ pub enum BeedleError {
     #[error("Inventory error: {0}")]
     InventoryError(String),
 
+    #[error("Order error: {0}")]
+    OrderError(String),
+
+    #[error("Payment error: {0}")]
+    PaymentError(String),
+
+    #[error("Webhook error: {0}")]
+    WebhookError(String),
+
+    #[error("Authentication error: {0}")]
+    AuthError(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Account error: {0}")]
+    AccountError(String),
+
+    #[error("Edit conflict: {0}")]
+    EditConflict(String),
+
+    #[error("Media error: {0}")]
+    MediaError(String),
+
     #[error("I/O error: {0}")]
     IOError(#[from] io::Error),
 
@@ -51,6 +76,14 @@ impl ResponseError for BeedleError {
             BeedleError::ConfigError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             BeedleError::SessionError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             BeedleError::InventoryError(_) => StatusCode::BAD_REQUEST,
+            BeedleError::OrderError(_) => StatusCode::CONFLICT,
+            BeedleError::PaymentError(_) => StatusCode::PAYMENT_REQUIRED,
+            BeedleError::WebhookError(_) => StatusCode::BAD_REQUEST,
+            BeedleError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            BeedleError::Forbidden(_) => StatusCode::FORBIDDEN,
+            BeedleError::AccountError(_) => StatusCode::BAD_REQUEST,
+            BeedleError::EditConflict(_) => StatusCode::CONFLICT,
+            BeedleError::MediaError(_) => StatusCode::BAD_REQUEST,
             BeedleError::IOError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             BeedleError::ResponseError(_) => StatusCode::TOO_MANY_REQUESTS, // ????
             BeedleError::PoolError(_) => StatusCode::LOCKED, // ????
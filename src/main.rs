@@ -2,19 +2,24 @@
 
 use actix_csrf::CsrfMiddleware;
 use actix_files::Files;
-use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-use actix_web::{cookie::Key, middleware, web::to, web::Data, App, HttpServer};
+use actix_web::{middleware, web::to, web::Data, App, HttpServer};
 use tera::Tera;
 
+mod auth;
+mod checkout;
 mod config;
 mod db;
 mod errors;
+mod media;
 mod models;
+mod orders;
 mod pay;
 mod price;
+mod promotions;
 mod routes;
 mod schema;
 mod session;
+mod validation;
 mod views;
 
 use crate::errors::BeedleError;
@@ -25,15 +30,6 @@ fn init_environment() {
     env_logger::init();
 }
 
-/// Loads secret key for cookies/sessions from env or fails clearly.
-fn get_secret_key() -> Result<Key, BeedleError> {
-    let key_hex = std::env::var("SESSION_KEY")
-        .map_err(|e| BeedleError::ConfigError(format!("SESSION_KEY is missing: {e}")))?;
-    let key_bytes = hex::decode(key_hex)
-        .map_err(|e| BeedleError::ConfigError(format!("SESSION_KEY isn't valid hex: {e}")))?;
-    Ok(Key::derive_from(&key_bytes))
-}
-
 /// Loads and validates global configuration (JSON + env vars).
 fn load_config() -> Result<config::Config, BeedleError> {
     config::Config::from_file("config.json").map_err(|e| BeedleError::ConfigError(e.to_string()))
@@ -61,37 +57,91 @@ fn setup_database() -> Result<db::DbPool, BeedleError> {
     Ok(pool)
 }
 
+/// `beedle create-admin <username> [owner|staff|read_only]`: make an admin account from the
+/// command line (how the first owner gets in). The password comes from `BEEDLE_ADMIN_PASSWORD`,
+/// or is read from stdin.
+fn create_admin_from_args(args: &[String]) -> Result<(), BeedleError> {
+    let usage = || BeedleError::ConfigError("Usage: beedle create-admin <username> [owner|staff|read_only]".into());
+    let username = args.first().ok_or_else(usage)?;
+    let role = match args.get(1) {
+        Some(role) => role.parse::<auth::Role>().map_err(|_| usage())?,
+        None => auth::Role::Owner,
+    };
+    let password = match std::env::var("BEEDLE_ADMIN_PASSWORD") {
+        Ok(password) => password,
+        Err(_) => {
+            println!("Password for {} (at least {} characters):", username, auth::MIN_PASSWORD_LEN);
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            line.trim_end_matches(['\r', '\n']).to_owned()
+        }
+    };
+
+    let pool = db::establish_connection()?;
+    let mut conn = pool.get()?;
+    let user = db::admin_users::create_admin_user(&mut conn, username, &password, role)?;
+    println!("Created {} account '{}'", user.role.label(), user.username);
+    Ok(())
+}
+
 #[actix_web::main]
 async fn main() -> Result<(), BeedleError> {
     init_environment();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("create-admin") {
+        return create_admin_from_args(&args[1..]);
+    }
+
     let config = load_config()?;
     let tera = load_tera_templates()?;
-    let secret_key = get_secret_key()?;
     let (host, port) = get_server_bind();
 
     log::info!("Starting on http://{}:{}", host, port);
 
     let pool = setup_database()?;
+    db::reservations::spawn_reservation_sweeper(
+        pool.clone(),
+        std::time::Duration::from_secs(config.checkout.reservation_sweep_secs),
+    );
+    let payment_provider = pay::build_provider(&config)?;
+    let media_storage = media::storage::build_storage(&config.media);
+    media::remote::spawn_remote_ingester(pool.clone(), media_storage.clone(), config.media.clone());
 
     let server = HttpServer::new(move || {
         let csrf = CsrfMiddleware::with_rng(rand::rngs::OsRng)
             .set_cookie(actix_web::http::Method::GET, "/cart")
             .set_cookie(actix_web::http::Method::GET, "/products")
-            .set_cookie(actix_web::http::Method::GET, "/products/{product_id}");
+            .set_cookie(actix_web::http::Method::GET, "/products/{product_ref}")
+            .set_cookie(actix_web::http::Method::GET, "/c/{slug}")
+            .set_cookie(actix_web::http::Method::GET, "/checkout/address")
+            .set_cookie(actix_web::http::Method::GET, "/checkout/shipping")
+            .set_cookie(actix_web::http::Method::GET, "/checkout/review")
+            .set_cookie(actix_web::http::Method::GET, "/admin/login")
+            .set_cookie(actix_web::http::Method::GET, "/admin/products")
+            .set_cookie(actix_web::http::Method::GET, "/admin/add_product")
+            .set_cookie(actix_web::http::Method::GET, "/admin/products/{product_id}/edit")
+            .set_cookie(actix_web::http::Method::GET, "/admin/products/{product_id}/variants")
+            .set_cookie(actix_web::http::Method::GET, "/admin/products/{product_id}/images")
+            .set_cookie(actix_web::http::Method::GET, "/admin/categories")
+            .set_cookie(actix_web::http::Method::GET, "/admin/categories/{category_id}/edit")
+            .set_cookie(actix_web::http::Method::GET, "/admin/promotions")
+            .set_cookie(actix_web::http::Method::GET, "/admin/promotions/{promotion_id}/edit")
+            .set_cookie(actix_web::http::Method::GET, "/admin/tags")
+            .set_cookie(actix_web::http::Method::GET, "/admin/orders")
+            .set_cookie(actix_web::http::Method::GET, "/admin/orders/{order_id}")
+            .set_cookie(actix_web::http::Method::GET, "/admin/users");
 
         App::new()
             .app_data(Data::new(pool.clone()))
             .app_data(Data::new(config.clone()))
             .app_data(Data::new(tera.clone()))
+            .app_data(Data::from(payment_provider.clone()))
+            .app_data(Data::from(media_storage.clone()))
             .configure(routes::init)
             .default_service(
             to(crate::routes::not_found_handler)
             )
-            .wrap(SessionMiddleware::new(
-                CookieSessionStore::default(),
-                secret_key.clone(),
-            ))
             .wrap(csrf)
             .wrap(middleware::Logger::default())
             .service(Files::new("/static", "./static").show_files_listing())
@@ -0,0 +1,273 @@
+//! Product images: checking uploads, making resized copies, and storing them.
+//! Uploads are identified by their bytes (never the client's filename or Content-Type),
+//! decoded under size limits, and stored with their renditions via `storage::MediaStorage`.
+//! Rows in `product_image` (see `db::images`) record where each file went, and
+//! `responsive` adds the `srcset` copies, also for images hosted elsewhere (`remote`).
+
+use crate::config::MediaConfig;
+use crate::errors::BeedleError;
+use actix_web::web;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use responsive::Derivative;
+use std::io::Cursor;
+use uuid::Uuid;
+
+pub mod remote;
+pub mod responsive;
+pub mod storage;
+
+/// Width of the small copy used in listings and gallery strips
+pub const THUMB_WIDTH: u32 = 240;
+/// Width of the copy shown on product pages
+pub const MEDIUM_WIDTH: u32 = 960;
+
+const JPEG_QUALITY: u8 = 85;
+/// For the full-size copy, which is re-encoded to drop its metadata
+const ORIGINAL_JPEG_QUALITY: u8 = 92;
+
+/// One file to store: the upload itself or a resized copy of it.
+#[derive(Debug)]
+pub struct StoredFile {
+    pub key: String,
+    pub content_type: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+/// A checked upload, ready to store.
+#[derive(Debug)]
+pub struct ProcessedImage {
+    pub original: StoredFile,
+    pub thumb: StoredFile,
+    pub medium: StoredFile,
+    /// For `srcset`; see `responsive`
+    pub derivatives: Vec<Derivative>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ProcessedImage {
+    pub fn files(&self) -> Vec<&StoredFile> {
+        let renditions = [&self.original, &self.thumb, &self.medium];
+        renditions.into_iter().chain(self.derivatives.iter().map(|d| &d.file)).collect()
+    }
+}
+
+/// What the bytes actually are, for the formats we accept.
+pub fn sniff_format(bytes: &[u8]) -> Result<ImageFormat, BeedleError> {
+    match image::guess_format(bytes) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP)) => Ok(format),
+        _ => Err(BeedleError::MediaError("Upload a JPEG, PNG, GIF or WebP image".into())),
+    }
+}
+
+fn extension(format: ImageFormat) -> &'static str {
+    format.extensions_str().first().copied().unwrap_or("bin")
+}
+
+/// Decodes an upload (refusing anything too big) and makes its resized copies.
+/// CPU-heavy: call from `web::block`.
+pub fn process_upload(bytes: Vec<u8>, product_id: i32, config: &MediaConfig) -> Result<ProcessedImage, BeedleError> {
+    let (format, image) = decode(&bytes, config).map_err(|e| {
+        log::warn!("Rejected image upload for product {}: {e}", product_id);
+        e
+    })?;
+
+    // Keys are ours alone, so a re-upload of "photo.jpg" never overwrites anything
+    let base = format!("products/{}/{}", product_id, Uuid::new_v4().simple());
+    let (width, height) = (image.width(), image.height());
+    Ok(ProcessedImage {
+        thumb: rendition(&image, THUMB_WIDTH, format!("{base}-thumb"))?,
+        medium: rendition(&image, MEDIUM_WIDTH, format!("{base}-medium"))?,
+        derivatives: responsive::derivatives(&image, &base, config)?,
+        original: StoredFile {
+            key: format!("{base}.{}", extension(format)),
+            content_type: format.to_mime_type(),
+            bytes: strip_metadata(format, &image, bytes)?,
+        },
+        width,
+        height,
+    })
+}
+
+/// The upload without its metadata (camera EXIF, GPS position and the like), by encoding the
+/// decoded pixels again in the same format. GIFs have no EXIF and are kept as uploaded, so
+/// animations survive.
+fn strip_metadata(format: ImageFormat, image: &DynamicImage, bytes: Vec<u8>) -> Result<Vec<u8>, BeedleError> {
+    let mut stripped = Vec::new();
+    let encoded = match format {
+        ImageFormat::Gif => return Ok(bytes),
+        ImageFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut stripped, ORIGINAL_JPEG_QUALITY)),
+        ImageFormat::WebP if image.color().has_alpha() => {
+            image.to_rgba8().write_with_encoder(WebPEncoder::new_lossless(&mut stripped))
+        }
+        ImageFormat::WebP => image.to_rgb8().write_with_encoder(WebPEncoder::new_lossless(&mut stripped)),
+        other => image.write_to(&mut Cursor::new(&mut stripped), other),
+    };
+    encoded.map_err(|e| BeedleError::MediaError(format!("Re-encoding image failed: {e}")))?;
+    Ok(stripped)
+}
+
+/// Checks the size and format of untrusted image bytes, then decodes them within
+/// `max_dimension`, turned upright if the camera said it was held sideways.
+fn decode(bytes: &[u8], config: &MediaConfig) -> Result<(ImageFormat, DynamicImage), BeedleError> {
+    if bytes.len() > config.max_upload_bytes {
+        return Err(too_large(config));
+    }
+    let format = sniff_format(bytes)?;
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.max_dimension);
+    limits.max_image_height = Some(config.max_dimension);
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let unreadable = |e: image::ImageError| BeedleError::MediaError(format!("Couldn't read that image: {e}"));
+    let mut decoder = reader.into_decoder().map_err(unreadable)?;
+    // Read before decoding, as that consumes the decoder; stripping the EXIF loses it
+    let orientation = decoder.orientation().map_err(unreadable)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(unreadable)?;
+    image.apply_orientation(orientation);
+    Ok((format, image))
+}
+
+/// `process_upload` on the blocking thread pool.
+pub async fn process_upload_blocking(
+    bytes: Vec<u8>,
+    product_id: i32,
+    config: &MediaConfig,
+) -> Result<ProcessedImage, BeedleError> {
+    let config = config.clone();
+    blocking(move || process_upload(bytes, product_id, &config)).await
+}
+
+/// Runs image work on the blocking thread pool. Its errors come back as `MediaError`s:
+/// BeedleError isn't Send, so only the message crosses threads.
+async fn blocking<T, F>(work: F) -> Result<T, BeedleError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, BeedleError> + Send + 'static,
+{
+    web::block(move || {
+        work().map_err(|e| match e {
+            BeedleError::MediaError(msg) => msg,
+            other => other.to_string(),
+        })
+    })
+    .await
+    .map_err(|e| BeedleError::MediaError(format!("Image processing task failed: {e}")))?
+    .map_err(BeedleError::MediaError)
+}
+
+/// A copy at most `max_width` wide (never enlarged): JPEG, or PNG to keep transparency.
+fn rendition(image: &DynamicImage, max_width: u32, key_stem: String) -> Result<StoredFile, BeedleError> {
+    let (format, bytes) = encode_fallback(&shrink(image, max_width))?;
+    Ok(StoredFile {
+        key: format!("{key_stem}.{}", extension(format)),
+        content_type: format.to_mime_type(),
+        bytes,
+    })
+}
+
+/// `image` scaled down to `max_width`, keeping its aspect ratio
+fn shrink(image: &DynamicImage, max_width: u32) -> DynamicImage {
+    if image.width() > max_width {
+        image.resize(max_width, u32::MAX, image::imageops::FilterType::Lanczos3)
+    } else {
+        image.clone()
+    }
+}
+
+/// The format every browser can show: JPEG, or PNG if the image has transparency.
+fn encode_fallback(image: &DynamicImage) -> Result<(ImageFormat, Vec<u8>), BeedleError> {
+    let mut bytes = Vec::new();
+    let encoded = if image.color().has_alpha() {
+        image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).map(|_| ImageFormat::Png)
+    } else {
+        image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY))
+            .map(|_| ImageFormat::Jpeg)
+    };
+    let format = encoded.map_err(|e| BeedleError::MediaError(format!("Resizing image failed: {e}")))?;
+    Ok((format, bytes))
+}
+
+pub fn too_large(config: &MediaConfig) -> BeedleError {
+    BeedleError::MediaError(format!(
+        "Images can be at most {} MB",
+        config.max_upload_bytes / (1024 * 1024)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn encode(image: DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_process_upload_makes_renditions() {
+        // AVIF is covered by `responsive`'s test; it's too slow for a large image in debug builds
+        let config = MediaConfig { avif: false, ..MediaConfig::default() };
+        let photo = encode(DynamicImage::ImageRgb8(RgbImage::from_pixel(1200, 600, Rgb([200, 10, 10]))), ImageFormat::Png);
+        let processed = process_upload(photo, 7, &config).unwrap();
+        assert_eq!((processed.width, processed.height), (1200, 600));
+        assert!(processed.original.key.starts_with("products/7/") && processed.original.key.ends_with(".png"));
+        assert_eq!(processed.original.content_type, "image/png");
+        assert!(processed.thumb.key.ends_with("-thumb.jpg"));
+        let widths: Vec<(&str, u32)> = processed.derivatives.iter().map(|d| (d.format, d.width)).collect();
+        assert_eq!(widths[..2], [("webp", 120), ("jpeg", 120)]);
+        assert_eq!(processed.derivatives.last().map(|d| d.width), Some(960));
+        assert_eq!(processed.files().len(), 3 + 2 * 4);
+        let thumb = image::load_from_memory(&processed.thumb.bytes).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (THUMB_WIDTH, THUMB_WIDTH / 2));
+
+        // Small, transparent images keep their size and their alpha
+        let icon = encode(DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 100, Rgba([0, 0, 0, 0]))), ImageFormat::Png);
+        let processed = process_upload(icon, 7, &config).unwrap();
+        assert!(processed.medium.key.ends_with("-medium.png"));
+        let medium = image::load_from_memory(&processed.medium.bytes).unwrap();
+        assert_eq!(medium.width(), 100);
+    }
+
+    #[test]
+    fn test_process_upload_strips_exif() {
+        let config = MediaConfig { avif: false, ..MediaConfig::default() };
+        let jpeg = encode(DynamicImage::ImageRgb8(RgbImage::from_pixel(40, 20, Rgb([10, 200, 10]))), ImageFormat::Jpeg);
+        // An APP1 segment right after the start marker: EXIF saying "rotate 90° to view"
+        let tiff: &[u8] = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+        let exif = [b"Exif\0\0".as_slice(), tiff].concat();
+        let length = (exif.len() + 2) as u16;
+        let photo = [&jpeg[..2], &[0xFF, 0xE1], &length.to_be_bytes(), &exif, &jpeg[2..]].concat();
+
+        let processed = process_upload(photo, 7, &config).unwrap();
+        assert!(!processed.original.bytes.windows(4).any(|w| w == b"Exif"));
+        assert_eq!(processed.original.content_type, "image/jpeg");
+        // Stored upright, since the EXIF that said how to turn it is gone
+        assert_eq!((processed.width, processed.height), (20, 40));
+        let original = image::load_from_memory(&processed.original.bytes).unwrap();
+        assert_eq!((original.width(), original.height()), (20, 40));
+    }
+
+    #[test]
+    fn test_process_upload_rejects_bad_files() {
+        let config = MediaConfig { max_upload_bytes: 50_000, max_dimension: 500, ..MediaConfig::default() };
+        // Claims nothing; the bytes say what it is
+        assert!(matches!(process_upload(b"<html>not an image</html>".to_vec(), 1, &config), Err(BeedleError::MediaError(_))));
+        assert!(process_upload(vec![0; 50_001], 1, &config).is_err());
+        let huge = encode(DynamicImage::ImageRgb8(RgbImage::new(501, 10)), ImageFormat::Png);
+        assert!(process_upload(huge, 1, &config).is_err());
+        // Right magic bytes, garbage after them
+        let mut truncated = encode(DynamicImage::ImageRgb8(RgbImage::new(50, 50)), ImageFormat::Png);
+        truncated.truncate(40);
+        assert!(process_upload(truncated, 1, &config).is_err());
+    }
+}
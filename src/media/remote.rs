@@ -0,0 +1,269 @@
+//! Images hosted elsewhere (`product.thumbnail_url`, `product_gallery_url`). A background task fetches
+//! each such URL once, checks it like an upload, and stores `responsive` copies under "cache/"
+//! so storefront pages can serve a `srcset` instead of the full-size original.
+//!
+//! The URLs come from admins, but the server is the one fetching them: only public addresses
+//! are contacted (`PublicResolver`, `check_url`), so they can't be used to reach the database,
+//! cloud metadata endpoints or anything else on the private network, redirects included.
+
+use super::responsive::{self, Derivative};
+use super::storage::{delete_all, MediaStorage};
+use super::{blocking, decode, too_large};
+use crate::config::MediaConfig;
+use crate::db::{renditions, DbPool};
+use crate::errors::BeedleError;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{redirect, Client, Url};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Redirects followed before giving up on a URL
+const MAX_REDIRECTS: usize = 5;
+
+/// A fetched image's size and copies
+#[derive(Debug)]
+pub struct RemoteImage {
+    pub width: u32,
+    pub height: u32,
+    pub derivatives: Vec<Derivative>,
+}
+
+/// Storage key prefix for a URL's copies. Stable, so fetching it again overwrites them.
+pub fn cache_key_base(url: &str) -> String {
+    format!("cache/{}", &hex::encode(Sha256::digest(url.as_bytes()))[..32])
+}
+
+/// Checks and resizes fetched bytes. CPU-heavy: call from `web::block`.
+pub fn process_remote(bytes: &[u8], url: &str, config: &MediaConfig) -> Result<RemoteImage, BeedleError> {
+    let (_, image) = decode(bytes, config)?;
+    Ok(RemoteImage {
+        width: image.width(),
+        height: image.height(),
+        derivatives: responsive::derivatives(&image, &cache_key_base(url), config)?,
+    })
+}
+
+/// Whether `ip` is on the public internet, rather than this machine, the private network,
+/// or a range nothing should be fetched from.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => !(v6.is_loopback() || v6.is_unspecified() || v6.is_multicast() || v6.is_unique_local() || v6.is_unicast_link_local()),
+        },
+    }
+}
+
+/// Refuses URLs that aren't http(s), or that name a non-public address outright. Host names
+/// are checked when they're resolved, by `PublicResolver`.
+fn check_url(url: &Url) -> Result<(), BeedleError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(BeedleError::MediaError(format!("Not an http(s) URL: {url}")));
+    }
+    let host = url.host_str().ok_or_else(|| BeedleError::MediaError(format!("No host in {url}")))?;
+    // IPv6 hosts come in brackets
+    let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() else {
+        return Ok(());
+    };
+    if !is_public(ip) {
+        return Err(BeedleError::MediaError(format!("Not a public address: {url}")));
+    }
+    Ok(())
+}
+
+/// Looks host names up as usual, dropping any non-public addresses. Checking the addresses
+/// actually connected to (rather than looking the name up once beforehand) means a name
+/// can't resolve to a public address for the check and a private one for the request.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_owned();
+            let public: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if public.is_empty() {
+                log::warn!("Refusing to fetch images from {}: no public address", host);
+                return Err(format!("{host} has no public address").into());
+            }
+            Ok(Box::new(public.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// The client for fetching images: public addresses only, and every redirect checked the
+/// same way as the URL it came from.
+pub fn build_client(config: &MediaConfig) -> Result<Client, BeedleError> {
+    let redirects = redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if let Err(e) = check_url(attempt.url()) {
+            attempt.error(e.to_string())
+        } else {
+            attempt.follow()
+        }
+    });
+    Client::builder()
+        .timeout(Duration::from_secs(config.remote_fetch_timeout_secs))
+        .user_agent(concat!("beedle/", env!("CARGO_PKG_VERSION")))
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(redirects)
+        .build()
+        .map_err(|e| BeedleError::MediaError(format!("Can't build HTTP client for product images: {e}")))
+}
+
+/// Downloads an image, refusing anything over `max_upload_bytes`. Use a client from
+/// `build_client`, which keeps it to public addresses.
+async fn fetch(client: &Client, url: &str, config: &MediaConfig) -> Result<Vec<u8>, BeedleError> {
+    let parsed = Url::parse(url).map_err(|e| BeedleError::MediaError(format!("Bad URL {url}: {e}")))?;
+    check_url(&parsed)?;
+    let mut response = client.get(parsed).send().await?.error_for_status()?;
+    if response.content_length().is_some_and(|len| len > config.max_upload_bytes as u64) {
+        return Err(too_large(config));
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > config.max_upload_bytes {
+            return Err(too_large(config));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+async fn fetch_and_store(
+    storage: &dyn MediaStorage,
+    client: &Client,
+    config: &MediaConfig,
+    url: &str,
+) -> Result<RemoteImage, BeedleError> {
+    let bytes = fetch(client, url, config).await?;
+    let (owned_url, owned_config) = (url.to_owned(), config.clone());
+    let image = blocking(move || process_remote(&bytes, &owned_url, &owned_config)).await?;
+    for derivative in &image.derivatives {
+        storage.put(&derivative.file.key, derivative.file.bytes.clone(), derivative.file.content_type).await?;
+    }
+    Ok(image)
+}
+
+/// Fetches one URL and records the outcome; a failure is noted in `remote_image`
+/// (to be retried later) rather than returned.
+pub async fn ingest(
+    pool: &DbPool,
+    storage: &dyn MediaStorage,
+    client: &Client,
+    config: &MediaConfig,
+    url: &str,
+) -> Result<(), BeedleError> {
+    let fetched = fetch_and_store(storage, client, config, url).await;
+    let mut conn = pool.get()?;
+    let image = match fetched {
+        Ok(image) => image,
+        Err(e) => {
+            log::warn!("Fetching product image {} failed: {e}", url);
+            return renditions::record_remote_image(&mut conn, url, Err(&e.to_string()));
+        }
+    };
+
+    let rows = responsive::rows(url, &image.derivatives);
+    let replaced = renditions::save_remote_image(&mut conn, url, (image.width as i32, image.height as i32), &rows)?;
+    // Same keys are overwritten in place; only drop widths that are no longer made
+    let stale: Vec<&str> = replaced
+        .iter()
+        .map(|r| r.storage_key.as_str())
+        .filter(|key| !rows.iter().any(|row| row.storage_key == *key))
+        .collect();
+    delete_all(storage, &stale).await;
+    log::info!("Cached {} copies of {}", rows.len(), url);
+    Ok(())
+}
+
+/// Background task: every `remote_ingest_secs`, fetch external image URLs that products
+/// use and haven't been cached yet.
+pub fn spawn_remote_ingester(pool: DbPool, storage: Arc<dyn MediaStorage>, config: MediaConfig) {
+    if config.remote_ingest_secs == 0 {
+        log::info!("Not caching external product images (remote_ingest_secs is 0)");
+        return;
+    }
+    actix_rt::spawn(async move {
+        let client = match build_client(&config) {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("{e}");
+                return;
+            }
+        };
+        let mut interval = actix_rt::time::interval(Duration::from_secs(config.remote_ingest_secs));
+        loop {
+            interval.tick().await;
+            let pending = pool
+                .get()
+                .map_err(BeedleError::from)
+                .and_then(|mut conn| renditions::pending_remote_urls(&mut conn));
+            match pending {
+                Ok(urls) => {
+                    for url in urls {
+                        if let Err(e) = ingest(&pool, storage.as_ref(), &client, &config, &url).await {
+                            log::error!("Caching product image {} failed: {e}", url);
+                        }
+                    }
+                }
+                Err(e) => log::error!("Looking for product images to cache failed: {e}"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_fetch_only_http_images() {
+        let config = MediaConfig::default();
+        assert_eq!(cache_key_base("https://example.com/a.jpg"), cache_key_base("https://example.com/a.jpg"));
+        assert_ne!(cache_key_base("https://example.com/a.jpg"), cache_key_base("https://example.com/b.jpg"));
+        for url in ["file:///etc/passwd", "/static/images/a.jpg", "ftp://example.com/a.jpg"] {
+            assert!(matches!(fetch(&Client::new(), url, &config).await, Err(BeedleError::MediaError(_))), "{url}");
+        }
+        assert!(process_remote(b"GIF89a but not really", "https://example.com/a.gif", &config).is_err());
+    }
+
+    #[test]
+    fn test_only_public_addresses() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0", "100.64.0.1", "::1", "::", "fe80::1", "fd00::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_fetch_refuses_private_hosts() {
+        let config = MediaConfig::default();
+        let client = build_client(&config).unwrap();
+        // Literal addresses are refused before connecting; names once they resolve
+        for url in ["http://127.0.0.1:5432/", "http://169.254.169.254/latest/meta-data/", "http://[::1]/a.jpg", "http://localhost:8080/a.jpg"] {
+            assert!(fetch(&client, url, &config).await.is_err(), "{url}");
+        }
+        assert!(PublicResolver.resolve("localhost".parse().unwrap()).await.is_err());
+    }
+}
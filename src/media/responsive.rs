@@ -0,0 +1,146 @@
+//! `srcset` copies of an image: one per configured width the image is wide enough for
+//! (`MediaConfig::responsive_widths`), each as AVIF (optional), lossy WebP, and a JPEG/PNG
+//! fallback. Rows in `image_rendition` (see `db::renditions`) record them by source.
+
+use super::{encode_fallback, extension, shrink, StoredFile};
+use crate::config::MediaConfig;
+use crate::errors::BeedleError;
+use crate::models::NewImageRendition;
+use image::codecs::avif::AvifEncoder;
+use image::DynamicImage;
+
+const WEBP_QUALITY: f32 = 80.0;
+/// 1 (slowest, smallest) to 10 (fastest)
+const AVIF_SPEED: u8 = 8;
+const AVIF_QUALITY: u8 = 70;
+
+/// One `srcset` candidate
+#[derive(Debug)]
+pub struct Derivative {
+    /// "avif", "webp", "jpeg" or "png", as in the content type
+    pub format: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub file: StoredFile,
+}
+
+/// The configured widths up to the image's own; an image narrower than all of them gets
+/// one copy at its own width. Copies are never enlarged.
+pub fn widths_for(image_width: u32, config: &MediaConfig) -> Vec<u32> {
+    let mut widths: Vec<u32> = config.responsive_widths.iter().copied().filter(|w| *w <= image_width).collect();
+    if widths.is_empty() {
+        widths.push(image_width);
+    }
+    widths.sort_unstable();
+    widths.dedup();
+    widths
+}
+
+/// Every copy of `image`, keyed "{key_base}-{width}w.{ext}". CPU-heavy (AVIF especially):
+/// call from `web::block`.
+pub fn derivatives(image: &DynamicImage, key_base: &str, config: &MediaConfig) -> Result<Vec<Derivative>, BeedleError> {
+    let mut derivatives = Vec::new();
+    for width in widths_for(image.width(), config) {
+        let resized = shrink(image, width);
+        let stem = format!("{key_base}-{width}w");
+        let (width, height) = (resized.width(), resized.height());
+        let mut push = |format: &'static str, ext: &str, bytes: Vec<u8>| {
+            derivatives.push(Derivative {
+                format,
+                width,
+                height,
+                file: StoredFile { key: format!("{stem}.{ext}"), content_type: content_type(format), bytes },
+            })
+        };
+
+        if config.avif {
+            push("avif", "avif", encode_avif(&resized)?);
+        }
+        push("webp", "webp", encode_webp(&resized)?);
+        let (format, bytes) = encode_fallback(&resized)?;
+        let name = if format == image::ImageFormat::Png { "png" } else { "jpeg" };
+        push(name, extension(format), bytes);
+    }
+    Ok(derivatives)
+}
+
+/// `image_rendition` rows for `derivatives` of `source`
+pub fn rows<'a>(source: &'a str, derivatives: &'a [Derivative]) -> Vec<NewImageRendition<'a>> {
+    derivatives
+        .iter()
+        .map(|d| NewImageRendition {
+            source,
+            format: d.format,
+            width: d.width as i32,
+            height: d.height as i32,
+            storage_key: &d.file.key,
+            byte_size: d.file.bytes.len() as i32,
+        })
+        .collect()
+}
+
+/// "webp" -> "image/webp", for the formats `derivatives` makes
+pub fn content_type(format: &str) -> &'static str {
+    match format {
+        "avif" => "image/avif",
+        "webp" => "image/webp",
+        "png" => "image/png",
+        _ => "image/jpeg",
+    }
+}
+
+fn encode_webp(image: &DynamicImage) -> Result<Vec<u8>, BeedleError> {
+    // image's own WebP encoder is lossless only, which is far too big for photos
+    let encoded = if image.color().has_alpha() {
+        let rgba = image.to_rgba8();
+        webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode_simple(false, WEBP_QUALITY).map(|m| m.to_vec())
+    } else {
+        let rgb = image.to_rgb8();
+        webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode_simple(false, WEBP_QUALITY).map(|m| m.to_vec())
+    };
+    encoded.map_err(|e| BeedleError::MediaError(format!("WebP encoding failed: {e:?}")))
+}
+
+fn encode_avif(image: &DynamicImage) -> Result<Vec<u8>, BeedleError> {
+    let mut bytes = Vec::new();
+    let encoder = AvifEncoder::new_with_speed_quality(&mut bytes, AVIF_SPEED, AVIF_QUALITY);
+    let encoded = if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(image.to_rgba8()).write_with_encoder(encoder)
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)
+    };
+    encoded.map_err(|e| BeedleError::MediaError(format!("AVIF encoding failed: {e}")))?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_derivatives_per_width_and_format() {
+        let config = MediaConfig { responsive_widths: vec![64, 128, 4000], ..MediaConfig::default() };
+        assert_eq!(widths_for(200, &config), [64, 128]);
+        assert_eq!(widths_for(50, &config), [50]);
+
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(200, 100, Rgb([10, 200, 10])));
+        let made = derivatives(&image, "cache/abc", &config).unwrap();
+        let summary: Vec<(&str, u32, &str)> = made.iter().map(|d| (d.format, d.width, d.file.key.as_str())).collect();
+        assert_eq!(
+            summary,
+            [
+                ("avif", 64, "cache/abc-64w.avif"),
+                ("webp", 64, "cache/abc-64w.webp"),
+                ("jpeg", 64, "cache/abc-64w.jpg"),
+                ("avif", 128, "cache/abc-128w.avif"),
+                ("webp", 128, "cache/abc-128w.webp"),
+                ("jpeg", 128, "cache/abc-128w.jpg"),
+            ]
+        );
+        assert_eq!(made[4].height, 64);
+        for derivative in &made {
+            assert_eq!(image::guess_format(&derivative.file.bytes).unwrap().to_mime_type(), derivative.file.content_type);
+        }
+    }
+}
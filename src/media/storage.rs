@@ -0,0 +1,113 @@
+//! Where image files live. `LocalStorage` writes under `static/` so actix-files serves them;
+//! another backend (eg an S3-compatible bucket such as MinIO) only has to implement
+//! `MediaStorage` and be returned from `build_storage`.
+
+use crate::config::MediaConfig;
+use crate::errors::BeedleError;
+use actix_web::web;
+use futures_util::future::BoxFuture;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A place to put files under keys like "products/12/ab34.jpg".
+/// Methods return boxed futures so backends can be used as `dyn MediaStorage`.
+pub trait MediaStorage: Send + Sync {
+    fn put<'a>(&'a self, key: &'a str, bytes: Vec<u8>, content_type: &'a str) -> BoxFuture<'a, Result<(), BeedleError>>;
+
+    /// Deleting a key that isn't there is not an error.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), BeedleError>>;
+
+    /// Where browsers can fetch the file
+    fn url(&self, key: &str) -> String;
+}
+
+/// Files on local disk, served as static files.
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>, public_url: &str) -> Self {
+        Self { root: root.into(), public_url: public_url.trim_end_matches('/').to_owned() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, BeedleError> {
+        // Keys are generated by `media`, but never let one climb out of the root
+        if key.is_empty() || key.starts_with('/') || key.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+            return Err(BeedleError::MediaError(format!("Bad storage key: {key:?}")));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl MediaStorage for LocalStorage {
+    fn put<'a>(&'a self, key: &'a str, bytes: Vec<u8>, _content_type: &'a str) -> BoxFuture<'a, Result<(), BeedleError>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            web::block(move || {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(&path, bytes)
+            })
+            .await
+            .map_err(|e| BeedleError::MediaError(format!("Storage task failed: {e}")))??;
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), BeedleError>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            web::block(move || match std::fs::remove_file(&path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            })
+            .await
+            .map_err(|e| BeedleError::MediaError(format!("Storage task failed: {e}")))??;
+            Ok(())
+        })
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+}
+
+/// The storage backend for this config. Only local disk for now.
+pub fn build_storage(config: &MediaConfig) -> Arc<dyn MediaStorage> {
+    log::info!("Storing uploaded images in {}", config.storage_dir);
+    Arc::new(LocalStorage::new(&config.storage_dir, &config.public_url))
+}
+
+/// Best-effort removal of files that are no longer referenced, eg after a failed insert.
+pub async fn delete_all(storage: &dyn MediaStorage, keys: &[&str]) {
+    for key in keys {
+        if let Err(e) = storage.delete(key).await {
+            log::error!("Deleting stored file {} failed: {e}", key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_local_storage_roundtrip() {
+        let root = std::env::temp_dir().join(format!("beedle-media-{}", uuid::Uuid::new_v4().simple()));
+        let storage = LocalStorage::new(&root, "/static/uploads/");
+        storage.put("products/1/a.jpg", b"jpeg".to_vec(), "image/jpeg").await.unwrap();
+        assert_eq!(std::fs::read(root.join("products/1/a.jpg")).unwrap(), b"jpeg");
+        assert_eq!(storage.url("products/1/a.jpg"), "/static/uploads/products/1/a.jpg");
+
+        storage.delete("products/1/a.jpg").await.unwrap();
+        storage.delete("products/1/a.jpg").await.unwrap();
+        assert!(!root.join("products/1/a.jpg").exists());
+        for bad in ["../escape.jpg", "/etc/passwd", "products//a.jpg", ""] {
+            assert!(storage.put(bad, Vec::new(), "image/jpeg").await.is_err(), "{bad:?} should be refused");
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
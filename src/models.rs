@@ -1,4 +1,6 @@
-use diesel::{AsChangeset, Queryable, Insertable};
+use diesel::{AsChangeset, Associations, Identifiable, Queryable, Insertable};
+use crate::auth::Role;
+use crate::orders::OrderStatus;
 use crate::schema::*;
 use serde::{Serialize, Deserialize};
 
@@ -8,16 +10,17 @@ pub(crate) struct Product {
     pub name: String,
     pub price: i64,
     pub inventory: i32,
-    pub category: String,
-    pub tags: Option<String>,
-    pub keywords: Option<String>,
     pub thumbnail_url: Option<String>,
-    pub gallery_urls: Option<String>,
     pub tagline: Option<String>,
     pub description: Option<String>,
     pub discount_percent: Option<f32>,
     pub added_date: chrono::NaiveDateTime,
     pub restock_date: Option<chrono::NaiveDateTime>,
+    /// Bumped on every save; see `db::products::save_product`
+    pub version: i32,
+    pub category_id: i32,
+    /// Names it in URLs: /products/{slug}. Old ones are kept in `product_slug_history`.
+    pub slug: String,
 }
 
 #[derive(Insertable, Debug, Clone, Serialize, Deserialize)]
@@ -26,21 +29,33 @@ pub(crate) struct NewProduct {
     pub name: String,
     pub price: i64,
     pub inventory: i32,
-    pub category: String,
-    pub tags: Option<String>,
-    pub keywords: Option<String>,
     pub thumbnail_url: Option<String>,
-    pub gallery_urls: Option<String>,
     pub tagline: Option<String>,
     pub description: Option<String>,
     pub discount_percent: Option<f32>,
     pub added_date: Option<chrono::NaiveDateTime>, 
     pub restock_date: Option<chrono::NaiveDateTime>,
+    pub category_id: i32,
+    pub slug: String,
+}
+
+/// A product's list fields, each kept in its own table (`product_tag`, `product_keyword`,
+/// `product_gallery_url`); see `db::products::load_product_lists`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ProductLists {
+    /// Tag names, alphabetical
+    pub tags: Vec<String>,
+    /// Extra words to find the product by
+    pub keywords: Vec<String>,
+    /// External image URLs, in order
+    pub gallery_urls: Vec<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct CartItem {
     pub product_id: i32,
+    /// The `product_variant` actually being bought; stock is tracked per variant
+    pub variant_id: i32,
     pub quantity: u32,
 }
 
@@ -55,4 +70,332 @@ pub(crate) struct SessionRow {
     pub ip_address: Option<String>,
     pub user_agent: Option<String>,
     pub cart_data: Option<serde_json::Value>,
-}
\ No newline at end of file
+    pub checkout_data: Option<serde_json::Value>,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[diesel(table_name = order)]
+pub(crate) struct Order {
+    pub id: i32,
+    pub session_id: uuid::Uuid,
+    pub subtotal: i64,
+    pub discount_total: i64,
+    pub total: i64,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    pub status: OrderStatus,
+    pub payment_provider: Option<String>,
+    pub payment_reference: Option<String>,
+    pub contact_email: Option<String>,
+    pub shipping_address: Option<serde_json::Value>,
+    pub shipping_method: Option<String>,
+    pub shipping_total: i64,
+    pub refund_key: Option<String>,
+    pub refund_requested_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = order)]
+pub(crate) struct NewOrder {
+    pub session_id: uuid::Uuid,
+    pub subtotal: i64,
+    pub discount_total: i64,
+    pub total: i64,
+    pub status: OrderStatus,
+    pub contact_email: Option<String>,
+    pub shipping_address: Option<serde_json::Value>,
+    pub shipping_method: Option<String>,
+    pub shipping_total: i64,
+}
+
+#[derive(Queryable, Identifiable, Associations, Debug, Clone, Serialize)]
+#[diesel(belongs_to(Order))]
+#[diesel(table_name = order_line)]
+pub(crate) struct OrderLine {
+    pub id: i32,
+    pub order_id: i32,
+    pub product_id: Option<i32>,
+    pub product_name: String,
+    pub unit_price_original: i64,
+    pub unit_price: i64,
+    pub quantity: i32,
+    pub line_total: i64,
+    pub variant_id: Option<i32>,
+    pub sku: Option<String>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = order_line)]
+pub(crate) struct NewOrderLine {
+    pub order_id: i32,
+    pub product_id: Option<i32>,
+    pub product_name: String,
+    pub unit_price_original: i64,
+    pub unit_price: i64,
+    pub quantity: i32,
+    pub line_total: i64,
+    pub variant_id: Option<i32>,
+    pub sku: Option<String>,
+}
+
+#[derive(Queryable, Identifiable, Associations, Debug, Clone, Serialize)]
+#[diesel(belongs_to(Order))]
+#[diesel(table_name = order_status_history)]
+pub(crate) struct OrderStatusHistory {
+    pub id: i32,
+    pub order_id: i32,
+    pub from_status: Option<OrderStatus>,
+    pub to_status: OrderStatus,
+    pub changed_by: String,
+    pub note: Option<String>,
+    pub changed_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = order_status_history)]
+pub(crate) struct NewOrderStatusHistory<'a> {
+    pub order_id: i32,
+    pub from_status: Option<OrderStatus>,
+    pub to_status: OrderStatus,
+    pub changed_by: &'a str,
+    pub note: Option<&'a str>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = payment_webhook_event)]
+pub(crate) struct NewPaymentWebhookEvent<'a> {
+    pub provider: &'a str,
+    pub event_id: &'a str,
+    pub event_type: &'a str,
+    pub payment_reference: &'a str,
+    pub order_id: Option<i32>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = stock_reservation)]
+pub(crate) struct NewStockReservation {
+    pub session_id: uuid::Uuid,
+    pub variant_id: i32,
+    pub quantity: i32,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[diesel(table_name = product_option)]
+pub(crate) struct ProductOption {
+    pub id: i32,
+    pub product_id: i32,
+    pub name: String,
+    pub position: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = product_option)]
+pub(crate) struct NewProductOption<'a> {
+    pub product_id: i32,
+    pub name: &'a str,
+    pub position: i32,
+}
+
+#[derive(Queryable, Identifiable, Associations, Debug, Clone, Serialize)]
+#[diesel(belongs_to(ProductOption, foreign_key = option_id))]
+#[diesel(table_name = product_option_value)]
+pub(crate) struct ProductOptionValue {
+    pub id: i32,
+    pub option_id: i32,
+    pub value: String,
+    pub position: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = product_option_value)]
+pub(crate) struct NewProductOptionValue<'a> {
+    pub option_id: i32,
+    pub value: &'a str,
+    pub position: i32,
+}
+
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[diesel(table_name = product_variant)]
+pub(crate) struct ProductVariant {
+    pub id: i32,
+    pub product_id: i32,
+    pub sku: String,
+    /// Overrides `Product::price` when set
+    pub price: Option<i64>,
+    pub inventory: i32,
+    pub position: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = product_variant)]
+pub(crate) struct NewProductVariant<'a> {
+    pub product_id: i32,
+    pub sku: &'a str,
+    pub price: Option<i64>,
+    pub inventory: i32,
+    pub position: i32,
+}
+
+/// A node of the category tree; see `db::categories::CategoryTree`.
+#[derive(Queryable, Identifiable, AsChangeset, Debug, Clone, Serialize)]
+#[diesel(table_name = category, treat_none_as_null = true)]
+pub(crate) struct Category {
+    pub id: i32,
+    /// `None` for top-level categories
+    pub parent_id: Option<i32>,
+    pub name: String,
+    /// Names it in URLs: /c/{slug}
+    pub slug: String,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    /// Sort order among its siblings, then by name
+    pub position: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = category)]
+pub(crate) struct NewCategory {
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub slug: String,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub position: i32,
+}
+
+/// Shared by products through `product_tag`. Names are unique regardless of case.
+#[derive(Queryable, Identifiable, Debug, Clone, Serialize)]
+#[diesel(table_name = tag)]
+pub(crate) struct Tag {
+    pub id: i32,
+    pub name: String,
+}
+
+/// A sale that runs from `starts_at` until `ends_at`; see `promotions` for how it's applied.
+#[derive(Queryable, Identifiable, AsChangeset, Debug, Clone, Serialize)]
+#[diesel(table_name = promotion, treat_none_as_null = true)]
+pub(crate) struct Promotion {
+    pub id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    pub starts_at: chrono::NaiveDateTime,
+    /// `None` runs until it's ended by hand
+    pub ends_at: Option<chrono::NaiveDateTime>,
+    /// Exactly one of `discount_percent` and `discount_cents` is set
+    pub discount_percent: Option<f32>,
+    pub discount_cents: Option<i64>,
+    /// At most one target is set; none means the whole store
+    pub product_id: Option<i32>,
+    pub category_id: Option<i32>,
+    pub tag_id: Option<i32>,
+    /// Higher goes first
+    pub priority: i32,
+    /// Whether it combines with other stackable promotions and the product's own discount
+    pub stackable: bool,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = promotion)]
+pub(crate) struct NewPromotion {
+    pub name: String,
+    pub description: Option<String>,
+    pub starts_at: chrono::NaiveDateTime,
+    pub ends_at: Option<chrono::NaiveDateTime>,
+    pub discount_percent: Option<f32>,
+    pub discount_cents: Option<i64>,
+    pub product_id: Option<i32>,
+    pub category_id: Option<i32>,
+    pub tag_id: Option<i32>,
+    pub priority: i32,
+    pub stackable: bool,
+}
+
+/// An uploaded photo of a product; see `media` for the files behind the keys.
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = product_image)]
+pub(crate) struct ProductImage {
+    pub id: i32,
+    pub product_id: i32,
+    /// The file as uploaded
+    pub storage_key: String,
+    pub thumb_key: String,
+    pub medium_key: String,
+    /// Sniffed from the file, not taken from the upload's headers
+    pub content_type: String,
+    pub width: i32,
+    pub height: i32,
+    pub byte_size: i32,
+    pub alt_text: String,
+    pub position: i32,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = product_image)]
+pub(crate) struct NewProductImage<'a> {
+    pub product_id: i32,
+    pub storage_key: &'a str,
+    pub thumb_key: &'a str,
+    pub medium_key: &'a str,
+    pub content_type: &'a str,
+    pub width: i32,
+    pub height: i32,
+    pub byte_size: i32,
+    pub alt_text: &'a str,
+    pub position: i32,
+}
+
+/// A resized/re-encoded copy of an upload or external image; see `media::responsive`.
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = image_rendition)]
+pub(crate) struct ImageRendition {
+    pub id: i32,
+    /// The upload's storage key, or the external image's URL
+    pub source: String,
+    /// "avif", "webp", "jpeg" or "png"
+    pub format: String,
+    pub width: i32,
+    pub height: i32,
+    pub storage_key: String,
+    pub byte_size: i32,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = image_rendition)]
+pub(crate) struct NewImageRendition<'a> {
+    pub source: &'a str,
+    pub format: &'a str,
+    pub width: i32,
+    pub height: i32,
+    pub storage_key: &'a str,
+    pub byte_size: i32,
+}
+
+/// Deliberately not `Serialize`: keeps the password hash out of templates and logs.
+#[derive(Queryable, Identifiable, Debug, Clone)]
+#[diesel(table_name = admin_user)]
+pub(crate) struct AdminUser {
+    pub id: i32,
+    pub username: String,
+    pub password_hash: String,
+    pub role: Role,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_login_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = admin_user)]
+pub(crate) struct NewAdminUser<'a> {
+    pub username: &'a str,
+    pub password_hash: &'a str,
+    pub role: Role,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = admin_session)]
+pub(crate) struct NewAdminSession {
+    pub token: uuid::Uuid,
+    pub admin_user_id: i32,
+    pub expires_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,182 @@
+//! Order lifecycle: the `OrderStatus` state machine and which transitions are allowed.
+//! Stored as TEXT in the `order` table; see `db::orders::transition_order_status` for persistence.
+
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+/// pending_payment -> paid -> packed -> shipped -> delivered,
+/// with cancelled/refunded as terminal side exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    PendingPayment,
+    Paid,
+    Packed,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Refunded,
+}
+
+impl OrderStatus {
+    pub const ALL: [OrderStatus; 7] = [
+        OrderStatus::PendingPayment,
+        OrderStatus::Paid,
+        OrderStatus::Packed,
+        OrderStatus::Shipped,
+        OrderStatus::Delivered,
+        OrderStatus::Cancelled,
+        OrderStatus::Refunded,
+    ];
+
+    /// Value as stored in the DB, eg "pending_payment"
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::PendingPayment => "pending_payment",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Packed => "packed",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+        }
+    }
+
+    /// Human-readable name for templates, eg "Pending payment"
+    pub fn label(self) -> &'static str {
+        match self {
+            OrderStatus::PendingPayment => "Pending payment",
+            OrderStatus::Paid => "Paid",
+            OrderStatus::Packed => "Packed",
+            OrderStatus::Shipped => "Shipped",
+            OrderStatus::Delivered => "Delivered",
+            OrderStatus::Cancelled => "Cancelled",
+            OrderStatus::Refunded => "Refunded",
+        }
+    }
+
+    /// Statuses this one may move to next.
+    pub fn allowed_transitions(self) -> &'static [OrderStatus] {
+        use OrderStatus::*;
+        match self {
+            PendingPayment => &[Paid, Cancelled],
+            Paid => &[Packed, Cancelled, Refunded],
+            Packed => &[Shipped, Cancelled, Refunded],
+            Shipped => &[Delivered, Refunded],
+            Delivered => &[Refunded],
+            Cancelled | Refunded => &[],
+        }
+    }
+
+    pub fn can_transition_to(self, next: OrderStatus) -> bool {
+        self.allowed_transitions().contains(&next)
+    }
+
+    /// True once nothing further can happen to the order.
+    pub fn is_terminal(self) -> bool {
+        self.allowed_transitions().is_empty()
+    }
+
+    /// Whether entering this status should put the ordered items back into stock.
+    /// Only cancellation does; refunded goods may never come back (or come back damaged).
+    pub fn restocks_inventory(self) -> bool {
+        self == OrderStatus::Cancelled
+    }
+
+    /// Whether the customer's money has been taken and not given back.
+    pub fn is_paid(self) -> bool {
+        matches!(self, OrderStatus::Paid | OrderStatus::Packed | OrderStatus::Shipped | OrderStatus::Delivered)
+    }
+
+    /// Whether moving from this status to `next` should give the customer their money back:
+    /// a paid order that's cancelled or refunded.
+    pub fn refunds_payment(self, next: OrderStatus) -> bool {
+        self.is_paid() && matches!(next, OrderStatus::Cancelled | OrderStatus::Refunded)
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OrderStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("Unknown order status: {s}"))
+    }
+}
+
+impl ToSql<Text, Pg> for OrderStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for OrderStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let raw = <String as FromSql<Text, Pg>>::from_sql(bytes)?;
+        raw.parse().map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderStatus::{self, *};
+
+    #[test]
+    fn test_order_status_happy_path() {
+        let path = [PendingPayment, Paid, Packed, Shipped, Delivered];
+        for pair in path.windows(2) {
+            assert!(pair[0].can_transition_to(pair[1]), "{} -> {} should be allowed", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_order_status_rejects_skips_and_reversals() {
+        assert!(!PendingPayment.can_transition_to(Shipped));
+        assert!(!Shipped.can_transition_to(Paid));
+        assert!(!Delivered.can_transition_to(Cancelled));
+        assert!(!Paid.can_transition_to(Paid));
+    }
+
+    #[test]
+    fn test_order_status_terminal_states() {
+        assert!(Cancelled.is_terminal());
+        assert!(Refunded.is_terminal());
+        assert!(!Delivered.is_terminal());
+        assert!(OrderStatus::ALL.iter().all(|s| !s.can_transition_to(PendingPayment)));
+    }
+
+    #[test]
+    fn test_order_status_refunds_payment() {
+        assert!(Paid.refunds_payment(Cancelled));
+        assert!(Packed.refunds_payment(Cancelled));
+        assert!(Shipped.refunds_payment(Refunded));
+        assert!(!PendingPayment.refunds_payment(Cancelled));
+        assert!(!Paid.refunds_payment(Packed));
+        assert!(!Cancelled.is_paid() && !Refunded.is_paid());
+    }
+
+    #[test]
+    fn test_order_status_string_roundtrip() {
+        for status in OrderStatus::ALL {
+            assert_eq!(status.as_str().parse::<OrderStatus>(), Ok(status));
+        }
+        assert!("shipping".parse::<OrderStatus>().is_err());
+    }
+}
@@ -1,21 +1,118 @@
-use reqwest::Client;
-use serde_json::json;
-
-pub async fn process_payment(total_amount: i32, payment_token: &str) -> Result<(), reqwest::Error> {
-    let client = Client::new();
-    let response = client.post("https://api.stripe.com/v1/charges")
-        .header("Authorization", format!("Bearer {}", "your_api_key"))
-        .form(&json!({
-            "amount": total_amount,
-            "currency": "usd",
-            "source": payment_token,
-        }))
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        Ok(())
-    } else {
-        Err(response.error_for_status().unwrap_err())
+//! Payment providers behind a common `PaymentProvider` trait.
+//! The active provider is picked from `Config.payment` at startup and shared via `app_data`.
+
+use crate::config::{Config, PaymentProviderKind};
+use crate::errors::BeedleError;
+use crate::price::Price;
+use futures_util::future::BoxFuture;
+use webhook::WebhookEvent;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod manual;
+pub mod mock;
+pub mod stripe;
+pub mod webhook;
+
+/// What the shop asks a provider to charge for one order.
+#[derive(Debug, Clone)]
+pub struct PaymentRequest {
+    pub order_id: i32,
+    pub amount: Price,
+    pub currency: String,
+    /// Provider-specific token from the checkout form (eg a Stripe PaymentMethod ID).
+    pub payment_token: Option<String>,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    /// Shopper must do something else first (eg 3-D Secure).
+    RequiresAction,
+    /// Authorized; funds are held until `capture` is called.
+    RequiresCapture,
+    /// Waiting on something outside our control (bank transfer, async confirmation...).
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentIntent {
+    /// Provider's reference for this payment; stored on the order.
+    pub id: String,
+    pub amount: Price,
+    pub status: PaymentStatus,
+    /// Where to send the shopper when `status == RequiresAction`.
+    pub next_action_url: Option<String>,
+    /// Text to show the shopper, eg bank transfer details.
+    pub instructions: Option<String>,
+    pub failure_reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Refund {
+    pub id: String,
+    pub amount: Price,
+}
+
+/// A payment backend. Methods return boxed futures so providers can be used as `dyn PaymentProvider`.
+pub trait PaymentProvider: Send + Sync {
+    /// Short identifier stored on orders, eg "stripe"
+    fn name(&self) -> &'static str;
+
+    /// Start (and, if a token is supplied, confirm) a payment for an order.
+    fn create_intent<'a>(&'a self, request: &'a PaymentRequest) -> BoxFuture<'a, Result<PaymentIntent, BeedleError>>;
+
+    /// Capture a previously authorized payment.
+    fn capture<'a>(&'a self, intent_id: &'a str) -> BoxFuture<'a, Result<PaymentStatus, BeedleError>>;
+
+    /// Refund some or all of a captured payment. Calls with the same `idempotency_key` refund
+    /// only once, so retrying one that failed or timed out is safe.
+    fn refund<'a>(&'a self, intent_id: &'a str, amount: Price, idempotency_key: &'a str) -> BoxFuture<'a, Result<Refund, BeedleError>>;
+
+    /// Ask the provider where a payment currently stands.
+    fn fetch_status<'a>(&'a self, intent_id: &'a str) -> BoxFuture<'a, Result<PaymentStatus, BeedleError>>;
+
+    /// Request header carrying the webhook signature.
+    fn webhook_signature_header(&self) -> &'static str {
+        "X-Beedle-Signature"
+    }
+
+    /// Verify the signature of an incoming webhook and decode it.
+    /// Providers without webhooks reject everything.
+    fn parse_webhook(&self, _signature: &str, _body: &[u8]) -> Result<WebhookEvent, BeedleError> {
+        Err(BeedleError::WebhookError(format!("{} does not send webhooks", self.name())))
+    }
+}
+
+/// Builds the provider selected in config. Secrets come from env vars, never `config.json`.
+pub fn build_provider(config: &Config) -> Result<Arc<dyn PaymentProvider>, BeedleError> {
+    let settings = &config.payment;
+    log::info!("Using payment provider: {:?}", settings.provider);
+    match settings.provider {
+        PaymentProviderKind::Manual => Ok(Arc::new(manual::ManualProvider::new(settings.manual_instructions.clone()))),
+        PaymentProviderKind::Stripe => {
+            let secret_key = std::env::var("STRIPE_SECRET_KEY")
+                .map_err(|e| BeedleError::ConfigError(format!("STRIPE_SECRET_KEY is missing: {e}")))?;
+            let webhook_secret = std::env::var("STRIPE_WEBHOOK_SECRET").ok();
+            if webhook_secret.is_none() {
+                log::warn!("STRIPE_WEBHOOK_SECRET not set; Stripe webhooks will be rejected");
+            }
+            Ok(Arc::new(stripe::StripeProvider::new(settings.stripe_api_base.clone(), secret_key, webhook_secret)))
+        }
+        PaymentProviderKind::Mock => {
+            log::warn!("Mock payment provider enabled; no real money will be collected!");
+            let webhook_secret = std::env::var("MOCK_WEBHOOK_SECRET").ok();
+            if webhook_secret.is_none() {
+                log::warn!("MOCK_WEBHOOK_SECRET not set; mock webhooks won't be sent and will be rejected");
+            }
+            Ok(Arc::new(
+                mock::MockProvider::new(Duration::from_secs(settings.mock_confirm_delay_secs))
+                    .with_webhooks(settings.mock_webhook_url.clone(), webhook_secret),
+            ))
+        }
     }
-}
\ No newline at end of file
+}
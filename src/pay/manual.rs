@@ -0,0 +1,55 @@
+//! "Manual" payments: bank transfer, cash on delivery, etc.
+//! Nothing is charged online; staff mark the order paid from the admin panel once money arrives.
+
+use super::{PaymentIntent, PaymentProvider, PaymentRequest, PaymentStatus, Refund};
+use crate::errors::BeedleError;
+use crate::price::Price;
+use futures_util::future::{BoxFuture, FutureExt};
+
+pub struct ManualProvider {
+    instructions: String,
+}
+
+impl ManualProvider {
+    pub fn new(instructions: String) -> Self {
+        Self { instructions }
+    }
+}
+
+impl PaymentProvider for ManualProvider {
+    fn name(&self) -> &'static str {
+        "manual"
+    }
+
+    fn create_intent<'a>(&'a self, request: &'a PaymentRequest) -> BoxFuture<'a, Result<PaymentIntent, BeedleError>> {
+        async move {
+            Ok(PaymentIntent {
+                id: format!("manual-{}", request.order_id),
+                amount: request.amount,
+                status: PaymentStatus::Pending,
+                next_action_url: None,
+                instructions: Some(self.instructions.clone()),
+                failure_reason: None,
+            })
+        }
+        .boxed()
+    }
+
+    fn capture<'a>(&'a self, _intent_id: &'a str) -> BoxFuture<'a, Result<PaymentStatus, BeedleError>> {
+        // Staff only "capture" once they've actually received the money
+        async move { Ok(PaymentStatus::Succeeded) }.boxed()
+    }
+
+    fn refund<'a>(&'a self, intent_id: &'a str, amount: Price, _idempotency_key: &'a str) -> BoxFuture<'a, Result<Refund, BeedleError>> {
+        async move {
+            log::info!("Manual refund of {} recorded for {}; pay it back by hand", amount.debug_string(), intent_id);
+            Ok(Refund { id: format!("{intent_id}-refund"), amount })
+        }
+        .boxed()
+    }
+
+    fn fetch_status<'a>(&'a self, _intent_id: &'a str) -> BoxFuture<'a, Result<PaymentStatus, BeedleError>> {
+        // We can't know; the order status is the source of truth for manual payments
+        async move { Ok(PaymentStatus::Pending) }.boxed()
+    }
+}
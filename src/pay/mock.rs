@@ -0,0 +1,325 @@
+//! Offline mock gateway for development and tests. No third-party services involved.
+//!
+//! The checkout `payment_token` picks the scenario, Stripe test-card style:
+//! * `mock_success` (or anything unrecognised) - authorized, captures fine
+//! * `mock_decline` - card declined
+//! * `mock_capture_fails` - authorized, but the gateway errors when it's captured
+//! * `mock_requires_action` - needs 3-D Secure style confirmation; completes after the confirm delay
+//! * `mock_delayed` - pending, confirmed "by webhook" after the confirm delay
+//!
+//! If a webhook URL is configured, confirmations are also delivered as signed webhooks,
+//! exactly like a real provider would.
+
+use super::webhook::{signature_header, verify_signature, WebhookEvent, WebhookEventKind};
+use super::{PaymentIntent, PaymentProvider, PaymentRequest, PaymentStatus, Refund};
+use crate::errors::BeedleError;
+use crate::price::Price;
+use futures_util::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockScenario {
+    Success,
+    Decline,
+    CaptureFails,
+    RequiresAction,
+    Delayed,
+}
+
+impl MockScenario {
+    pub fn from_token(token: Option<&str>) -> Self {
+        match token.map(str::trim) {
+            Some("mock_decline") => MockScenario::Decline,
+            Some("mock_capture_fails") => MockScenario::CaptureFails,
+            Some("mock_requires_action") => MockScenario::RequiresAction,
+            Some("mock_delayed") => MockScenario::Delayed,
+            _ => MockScenario::Success,
+        }
+    }
+}
+
+struct MockIntent {
+    amount: Price,
+    status: PaymentStatus,
+    created: Instant,
+    refunded: Price,
+    /// Refunds made so far, by idempotency key
+    refunds: HashMap<String, Refund>,
+    capture_fails: bool,
+}
+
+/// Body of the webhooks the mock gateway sends.
+#[derive(Serialize, Deserialize)]
+pub struct MockEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub intent_id: String,
+}
+
+pub struct MockProvider {
+    confirm_delay: Duration,
+    intents: Arc<Mutex<HashMap<String, MockIntent>>>,
+    webhook_url: Option<String>,
+    webhook_secret: Option<String>,
+}
+
+impl MockProvider {
+    pub fn new(confirm_delay: Duration) -> Self {
+        Self {
+            confirm_delay,
+            intents: Arc::new(Mutex::new(HashMap::new())),
+            webhook_url: None,
+            webhook_secret: None,
+        }
+    }
+
+    /// Deliver confirmations of delayed payments to `url` (if set), signed with `secret`.
+    /// Without a secret no webhooks are sent, and any received are rejected.
+    pub fn with_webhooks(mut self, url: Option<String>, secret: Option<String>) -> Self {
+        self.webhook_url = url;
+        self.webhook_secret = secret;
+        self
+    }
+
+    /// After `confirm_delay`, mark the payment succeeded and POST a signed `payment.succeeded` event.
+    fn schedule_webhook(&self, intent_id: String) {
+        let (Some(url), Some(secret)) = (self.webhook_url.clone(), self.webhook_secret.clone()) else { return };
+        let delay = self.confirm_delay;
+        let intents = Arc::clone(&self.intents);
+
+        actix_rt::spawn(async move {
+            actix_rt::time::sleep(delay).await;
+            if let Some(intent) = intents.lock().unwrap().get_mut(&intent_id) {
+                intent.status = PaymentStatus::Succeeded;
+            }
+            let event = MockEvent {
+                id: format!("evt_{}", uuid::Uuid::new_v4().simple()),
+                event_type: "payment.succeeded".to_owned(),
+                intent_id,
+            };
+            let body = match serde_json::to_vec(&event) {
+                Ok(body) => body,
+                Err(e) => return log::error!("Mock webhook serialization failed: {e}"),
+            };
+            let signature = signature_header(&secret, chrono::Utc::now().timestamp(), &body);
+            let result = reqwest::Client::new()
+                .post(&url)
+                .header("X-Beedle-Signature", signature)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .send()
+                .await;
+            match result {
+                Ok(resp) => log::info!("Mock webhook {} delivered to {}: {}", event.id, url, resp.status()),
+                Err(e) => log::warn!("Mock webhook {} to {} failed: {e}", event.id, url),
+            }
+        });
+    }
+
+    /// Complete a pending/requires-action payment right away, as if the shopper or bank just confirmed it.
+    #[cfg(test)]
+    pub fn confirm(&self, intent_id: &str) -> Result<(), BeedleError> {
+        let mut intents = self.intents.lock().unwrap();
+        let intent = intents
+            .get_mut(intent_id)
+            .ok_or_else(|| BeedleError::PaymentError(format!("Unknown mock payment {intent_id}")))?;
+        intent.status = PaymentStatus::Succeeded;
+        Ok(())
+    }
+
+    /// Current status, promoting delayed payments once `confirm_delay` has passed.
+    fn current_status(&self, intent: &mut MockIntent) -> PaymentStatus {
+        let waiting = matches!(intent.status, PaymentStatus::Pending | PaymentStatus::RequiresAction);
+        if waiting && intent.created.elapsed() >= self.confirm_delay {
+            intent.status = PaymentStatus::Succeeded;
+        }
+        intent.status
+    }
+}
+
+impl PaymentProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn create_intent<'a>(&'a self, request: &'a PaymentRequest) -> BoxFuture<'a, Result<PaymentIntent, BeedleError>> {
+        async move {
+            let id = format!("mock_{}", uuid::Uuid::new_v4().simple());
+            let scenario = MockScenario::from_token(request.payment_token.as_deref());
+            let (status, failure_reason) = match scenario {
+                MockScenario::Success | MockScenario::CaptureFails => (PaymentStatus::RequiresCapture, None),
+                MockScenario::Decline => (PaymentStatus::Failed, Some("Your card was declined (mock)".to_owned())),
+                MockScenario::RequiresAction => (PaymentStatus::RequiresAction, None),
+                MockScenario::Delayed => (PaymentStatus::Pending, None),
+            };
+            log::info!("Mock payment {} for order {}: {:?} -> {:?}", id, request.order_id, scenario, status);
+
+            self.intents.lock().unwrap().insert(id.clone(), MockIntent {
+                amount: request.amount,
+                status,
+                created: Instant::now(),
+                refunded: Price::default(),
+                refunds: HashMap::new(),
+                capture_fails: scenario == MockScenario::CaptureFails,
+            });
+            if matches!(scenario, MockScenario::Delayed | MockScenario::RequiresAction) {
+                self.schedule_webhook(id.clone());
+            }
+
+            Ok(PaymentIntent {
+                instructions: (scenario == MockScenario::Delayed)
+                    .then(|| "Mock payment is processing and will be confirmed shortly.".to_owned()),
+                next_action_url: None,
+                amount: request.amount,
+                failure_reason,
+                status,
+                id,
+            })
+        }
+        .boxed()
+    }
+
+    fn capture<'a>(&'a self, intent_id: &'a str) -> BoxFuture<'a, Result<PaymentStatus, BeedleError>> {
+        async move {
+            let mut intents = self.intents.lock().unwrap();
+            let intent = intents
+                .get_mut(intent_id)
+                .ok_or_else(|| BeedleError::PaymentError(format!("Unknown mock payment {intent_id}")))?;
+            match intent.status {
+                PaymentStatus::RequiresCapture if intent.capture_fails => {
+                    Err(BeedleError::PaymentError(format!("Mock gateway failed to capture {intent_id}")))
+                }
+                PaymentStatus::RequiresCapture | PaymentStatus::Succeeded => {
+                    intent.status = PaymentStatus::Succeeded;
+                    Ok(intent.status)
+                }
+                other => Err(BeedleError::PaymentError(format!("Cannot capture mock payment in state {other:?}"))),
+            }
+        }
+        .boxed()
+    }
+
+    fn refund<'a>(&'a self, intent_id: &'a str, amount: Price, idempotency_key: &'a str) -> BoxFuture<'a, Result<Refund, BeedleError>> {
+        async move {
+            let mut intents = self.intents.lock().unwrap();
+            let intent = intents
+                .get_mut(intent_id)
+                .ok_or_else(|| BeedleError::PaymentError(format!("Unknown mock payment {intent_id}")))?;
+            if let Some(done) = intent.refunds.get(idempotency_key) {
+                return Ok(done.clone());
+            }
+            if self.current_status(intent) != PaymentStatus::Succeeded {
+                return Err(BeedleError::PaymentError("Mock payment was never captured".into()));
+            }
+            if intent.refunded + amount > intent.amount {
+                return Err(BeedleError::PaymentError("Refund exceeds captured amount".into()));
+            }
+            intent.refunded = intent.refunded + amount;
+            let refund = Refund { id: format!("{intent_id}_refund_{}", intent.refunds.len() + 1), amount };
+            intent.refunds.insert(idempotency_key.to_owned(), refund.clone());
+            Ok(refund)
+        }
+        .boxed()
+    }
+
+    fn fetch_status<'a>(&'a self, intent_id: &'a str) -> BoxFuture<'a, Result<PaymentStatus, BeedleError>> {
+        async move {
+            let mut intents = self.intents.lock().unwrap();
+            let intent = intents
+                .get_mut(intent_id)
+                .ok_or_else(|| BeedleError::PaymentError(format!("Unknown mock payment {intent_id}")))?;
+            Ok(self.current_status(intent))
+        }
+        .boxed()
+    }
+
+    fn parse_webhook(&self, signature: &str, body: &[u8]) -> Result<WebhookEvent, BeedleError> {
+        let secret = self.webhook_secret.as_deref()
+            .ok_or_else(|| BeedleError::WebhookError("MOCK_WEBHOOK_SECRET is not configured".into()))?;
+        verify_signature(signature, body, secret, chrono::Utc::now().timestamp())?;
+        let event: MockEvent = serde_json::from_slice(body)
+            .map_err(|e| BeedleError::WebhookError(format!("Malformed mock event: {e}")))?;
+        let kind = match event.event_type.as_str() {
+            "payment.succeeded" => WebhookEventKind::PaymentSucceeded,
+            "payment.failed" => WebhookEventKind::PaymentFailed { reason: Some("Declined (mock)".to_owned()) },
+            "payment.refunded" => WebhookEventKind::Refunded,
+            other => WebhookEventKind::Other(other.to_owned()),
+        };
+        Ok(WebhookEvent { id: event.id, intent_id: event.intent_id, kind })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(token: &str) -> PaymentRequest {
+        PaymentRequest {
+            order_id: 1,
+            amount: Price::from_cents(1234),
+            currency: "usd".to_owned(),
+            payment_token: Some(token.to_owned()),
+            description: "test".to_owned(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_success_then_capture_and_refund() {
+        let mock = MockProvider::new(Duration::from_secs(60));
+        let intent = mock.create_intent(&request("mock_success")).await.unwrap();
+        assert_eq!(intent.status, PaymentStatus::RequiresCapture);
+        assert_eq!(mock.capture(&intent.id).await.unwrap(), PaymentStatus::Succeeded);
+        let refund = mock.refund(&intent.id, Price::from_cents(1000), "refund-1").await.unwrap();
+        // A retry with the same key gets the same refund rather than a second one
+        assert_eq!(mock.refund(&intent.id, Price::from_cents(1000), "refund-1").await.unwrap().id, refund.id);
+        assert!(mock.refund(&intent.id, Price::from_cents(1000), "refund-2").await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_decline() {
+        let mock = MockProvider::new(Duration::from_secs(60));
+        let intent = mock.create_intent(&request("mock_decline")).await.unwrap();
+        assert_eq!(intent.status, PaymentStatus::Failed);
+        assert!(intent.failure_reason.is_some());
+        assert!(mock.capture(&intent.id).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_requires_action_until_confirmed() {
+        let mock = MockProvider::new(Duration::from_secs(60));
+        let intent = mock.create_intent(&request("mock_requires_action")).await.unwrap();
+        assert_eq!(intent.status, PaymentStatus::RequiresAction);
+        assert_eq!(mock.fetch_status(&intent.id).await.unwrap(), PaymentStatus::RequiresAction);
+        mock.confirm(&intent.id).unwrap();
+        assert_eq!(mock.fetch_status(&intent.id).await.unwrap(), PaymentStatus::Succeeded);
+    }
+
+    #[test]
+    fn test_mock_webhook_signature_roundtrip() {
+        let body = br#"{"id":"evt_1","type":"payment.succeeded","intent_id":"mock_1"}"#;
+        let signature = signature_header("whsec_test", chrono::Utc::now().timestamp(), body);
+        // Nothing to check it against
+        assert!(MockProvider::new(Duration::ZERO).parse_webhook(&signature, body).is_err());
+
+        let mock = MockProvider::new(Duration::ZERO).with_webhooks(None, Some("whsec_test".to_owned()));
+
+        let event = mock.parse_webhook(&signature, body).unwrap();
+        assert_eq!(event.intent_id, "mock_1");
+        assert_eq!(event.kind, WebhookEventKind::PaymentSucceeded);
+
+        let forged = signature_header("wrong", chrono::Utc::now().timestamp(), body);
+        assert!(mock.parse_webhook(&forged, body).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_delayed_confirms_after_delay() {
+        let mock = MockProvider::new(Duration::ZERO);
+        let intent = mock.create_intent(&request("mock_delayed")).await.unwrap();
+        assert_eq!(intent.status, PaymentStatus::Pending);
+        assert_eq!(mock.fetch_status(&intent.id).await.unwrap(), PaymentStatus::Succeeded);
+    }
+}
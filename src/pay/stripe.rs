@@ -0,0 +1,251 @@
+//! Stripe card payments via the PaymentIntents API.
+//! Intents are created with `capture_method=manual` so checkout captures explicitly.
+
+use super::webhook::{verify_signature, WebhookEvent, WebhookEventKind};
+use super::{PaymentIntent, PaymentProvider, PaymentRequest, PaymentStatus, Refund};
+use crate::errors::BeedleError;
+use crate::price::Price;
+use futures_util::future::{BoxFuture, FutureExt};
+use reqwest::{Client, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+pub struct StripeProvider {
+    client: Client,
+    api_base: String,
+    secret_key: String,
+    webhook_secret: Option<String>,
+}
+
+/// The parts of a Stripe PaymentIntent object we care about.
+#[derive(Deserialize)]
+struct StripeIntent {
+    id: String,
+    amount: i64,
+    status: String,
+    next_action: Option<StripeNextAction>,
+    last_payment_error: Option<StripeError>,
+}
+
+#[derive(Deserialize)]
+struct StripeNextAction {
+    redirect_to_url: Option<StripeRedirect>,
+}
+
+#[derive(Deserialize)]
+struct StripeRedirect {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct StripeRefund {
+    id: String,
+    amount: i64,
+}
+
+/// A Stripe webhook event; `data.object` is a PaymentIntent or a Charge depending on `type`.
+#[derive(Deserialize)]
+struct StripeEvent {
+    id: String,
+    #[serde(rename = "type")]
+    event_type: String,
+    data: StripeEventData,
+}
+
+#[derive(Deserialize)]
+struct StripeEventData {
+    object: StripeEventObject,
+}
+
+#[derive(Deserialize)]
+struct StripeEventObject {
+    id: String,
+    /// Only set on Charge objects
+    payment_intent: Option<String>,
+    last_payment_error: Option<StripeError>,
+    /// Charge objects: what was charged and how much of it has been refunded so far
+    amount: Option<i64>,
+    amount_refunded: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct StripeErrorBody {
+    error: StripeError,
+}
+
+#[derive(Deserialize)]
+struct StripeError {
+    message: Option<String>,
+}
+
+impl StripeProvider {
+    pub fn new(api_base: String, secret_key: String, webhook_secret: Option<String>) -> Self {
+        Self {
+            client: Client::new(),
+            api_base: api_base.trim_end_matches('/').to_owned(),
+            secret_key,
+            webhook_secret,
+        }
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.client
+            .post(format!("{}{}", self.api_base, path))
+            .bearer_auth(&self.secret_key)
+    }
+
+    /// Send a request and decode the JSON body, turning Stripe's error objects into `PaymentError`s.
+    async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, BeedleError> {
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if status.is_success() {
+            Ok(serde_json::from_str(&body)?)
+        } else {
+            let message = serde_json::from_str::<StripeErrorBody>(&body)
+                .ok()
+                .and_then(|b| b.error.message)
+                .unwrap_or_else(|| format!("Stripe returned {status}"));
+            log::warn!("Stripe request failed ({status}): {message}");
+            Err(BeedleError::PaymentError(message))
+        }
+    }
+
+    fn map_status(intent: &StripeIntent) -> PaymentStatus {
+        match intent.status.as_str() {
+            "succeeded" => PaymentStatus::Succeeded,
+            "requires_capture" => PaymentStatus::RequiresCapture,
+            "requires_action" => PaymentStatus::RequiresAction,
+            "processing" | "requires_confirmation" => PaymentStatus::Pending,
+            // Stripe parks declined intents back in requires_payment_method
+            "requires_payment_method" if intent.last_payment_error.is_some() => PaymentStatus::Failed,
+            "requires_payment_method" => PaymentStatus::RequiresAction,
+            _ => PaymentStatus::Failed, // "canceled" or anything new
+        }
+    }
+
+    fn to_intent(intent: StripeIntent) -> PaymentIntent {
+        PaymentIntent {
+            status: Self::map_status(&intent),
+            amount: Price::from_cents(intent.amount),
+            next_action_url: intent.next_action.and_then(|a| a.redirect_to_url).map(|r| r.url),
+            instructions: None,
+            failure_reason: intent.last_payment_error.and_then(|e| e.message),
+            id: intent.id,
+        }
+    }
+}
+
+impl PaymentProvider for StripeProvider {
+    fn name(&self) -> &'static str {
+        "stripe"
+    }
+
+    fn create_intent<'a>(&'a self, request: &'a PaymentRequest) -> BoxFuture<'a, Result<PaymentIntent, BeedleError>> {
+        async move {
+            let mut params = vec![
+                ("amount", request.amount.as_cents().to_string()),
+                ("currency", request.currency.clone()),
+                ("capture_method", "manual".to_owned()),
+                ("description", request.description.clone()),
+                ("metadata[order_id]", request.order_id.to_string()),
+            ];
+            if let Some(token) = request.payment_token.as_ref().filter(|t| !t.trim().is_empty()) {
+                params.push(("payment_method", token.clone()));
+                params.push(("confirm", "true".to_owned()));
+            }
+            let intent: StripeIntent = Self::send(self.post("/v1/payment_intents").form(&params)).await?;
+            Ok(Self::to_intent(intent))
+        }
+        .boxed()
+    }
+
+    fn capture<'a>(&'a self, intent_id: &'a str) -> BoxFuture<'a, Result<PaymentStatus, BeedleError>> {
+        async move {
+            let path = format!("/v1/payment_intents/{}/capture", urlencoding::encode(intent_id));
+            let intent: StripeIntent = Self::send(self.post(&path)).await?;
+            Ok(Self::map_status(&intent))
+        }
+        .boxed()
+    }
+
+    fn refund<'a>(&'a self, intent_id: &'a str, amount: Price, idempotency_key: &'a str) -> BoxFuture<'a, Result<Refund, BeedleError>> {
+        async move {
+            let params = [
+                ("payment_intent", intent_id.to_owned()),
+                ("amount", amount.as_cents().to_string()),
+            ];
+            let request = self.post("/v1/refunds").header("Idempotency-Key", idempotency_key).form(&params);
+            let refund: StripeRefund = Self::send(request).await?;
+            Ok(Refund { id: refund.id, amount: Price::from_cents(refund.amount) })
+        }
+        .boxed()
+    }
+
+    fn fetch_status<'a>(&'a self, intent_id: &'a str) -> BoxFuture<'a, Result<PaymentStatus, BeedleError>> {
+        async move {
+            let url = format!("{}/v1/payment_intents/{}", self.api_base, urlencoding::encode(intent_id));
+            let request = self.client.get(url).bearer_auth(&self.secret_key);
+            let intent: StripeIntent = Self::send(request).await?;
+            Ok(Self::map_status(&intent))
+        }
+        .boxed()
+    }
+
+    fn webhook_signature_header(&self) -> &'static str {
+        "Stripe-Signature"
+    }
+
+    fn parse_webhook(&self, signature: &str, body: &[u8]) -> Result<WebhookEvent, BeedleError> {
+        let secret = self.webhook_secret.as_deref()
+            .ok_or_else(|| BeedleError::WebhookError("STRIPE_WEBHOOK_SECRET is not configured".into()))?;
+        verify_signature(signature, body, secret, chrono::Utc::now().timestamp())?;
+
+        let event: StripeEvent = serde_json::from_slice(body)
+            .map_err(|e| BeedleError::WebhookError(format!("Malformed Stripe event: {e}")))?;
+        let object = event.data.object;
+        let kind = match event.event_type.as_str() {
+            "payment_intent.succeeded" => WebhookEventKind::PaymentSucceeded,
+            "payment_intent.payment_failed" | "payment_intent.canceled" => WebhookEventKind::PaymentFailed {
+                reason: object.last_payment_error.and_then(|e| e.message),
+            },
+            // Sent for partial refunds too; only a full one refunds the order
+            "charge.refunded" if object.amount.is_some() && object.amount_refunded == object.amount => WebhookEventKind::Refunded,
+            "charge.refunded" => {
+                log::info!("Stripe charge {} partially refunded ({:?} of {:?})", object.id, object.amount_refunded, object.amount);
+                WebhookEventKind::Other(event.event_type.clone())
+            }
+            other => WebhookEventKind::Other(other.to_owned()),
+        };
+        Ok(WebhookEvent {
+            id: event.id,
+            intent_id: object.payment_intent.unwrap_or(object.id),
+            kind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pay::webhook::signature_header;
+
+    fn refunded_event(amount: i64, amount_refunded: i64) -> String {
+        format!(
+            r#"{{"id":"evt_1","type":"charge.refunded","data":{{"object":{{"id":"ch_1","payment_intent":"pi_1","amount":{amount},"amount_refunded":{amount_refunded}}}}}}}"#
+        )
+    }
+
+    #[test]
+    fn test_charge_refunded_only_refunds_in_full() {
+        let stripe = StripeProvider::new("http://localhost".to_owned(), "sk_test".to_owned(), Some("whsec_test".to_owned()));
+        let parse = |body: String| {
+            let signature = signature_header("whsec_test", chrono::Utc::now().timestamp(), body.as_bytes());
+            stripe.parse_webhook(&signature, body.as_bytes()).unwrap()
+        };
+
+        let full = parse(refunded_event(1500, 1500));
+        assert_eq!((full.intent_id.as_str(), full.kind), ("pi_1", WebhookEventKind::Refunded));
+        assert_eq!(parse(refunded_event(1500, 500)).kind, WebhookEventKind::Other("charge.refunded".to_owned()));
+    }
+}
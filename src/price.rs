@@ -1,6 +1,7 @@
 use serde::{Serialize, Deserialize};
 use std::fmt;
 use std::ops::{Add, Sub, Mul, Div};
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
 pub struct Price {
@@ -8,7 +9,7 @@ pub struct Price {
 }
 
 impl Price {
-    pub fn from_cents(cents: i64) -> Self {
+    pub const fn from_cents(cents: i64) -> Self {
         Self { cents }
     }
 
@@ -35,7 +36,7 @@ impl Price {
 
     /// Returns eg "12.00" or "12.34"
     pub fn to_decimal_string(self) -> String {
-        format!("{}.{}", self.dollars_part(), format!("{:02}", self.cents_part()))
+        format!("{}.{:02}", self.dollars_part(), self.cents_part())
     }
 
     /// Returns eg "$12.34" (US format)
@@ -47,6 +48,17 @@ impl Price {
     pub fn debug_string(self) -> String {
         format!("{} cents (${:.2})", self.cents, self.as_dollars_float())
     }
+
+    /// Applies an optional percentage discount (eg `Some(10.0)` = 10% off), rounded to the nearest cent.
+    /// `None` or non-positive percentages leave the price unchanged.
+    pub fn with_discount_percent(self, percent: Option<f32>) -> Price {
+        match percent {
+            Some(p) if p > 0.0 => {
+                Price::from_cents((self.cents as f64 * ((100.0 - p as f64) / 100.0)).round() as i64)
+            }
+            _ => self,
+        }
+    }
 }
 
 // Arithmetic with another Price
@@ -104,14 +116,70 @@ impl Price {
     }
 }
 
+/// Parses money as people type it: "12.50", "12,50", "$12.50", "12.50 €", "1,234.50",
+/// "1.234,50", "1 234,50". Whichever of `.`/`,` comes last with 1-2 digits after it is the
+/// decimal point; other separators group thousands. A lone separator followed by exactly
+/// three digits ("1,250") means a thousand-and-something in one locale and a fraction in
+/// another, so it's rejected, as are more than two decimal places: never guess with money.
+impl FromStr for Price {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Not a price: {s:?}");
+        let text: String = s
+            .chars()
+            .filter(|c| !c.is_whitespace() && !matches!(c, '$' | '€' | '£' | '\''))
+            .collect();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.as_str()),
+        };
+        if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit() || c == '.' || c == ',') {
+            return Err(invalid());
+        }
+
+        let mut decimal_at = None;
+        if let Some(at) = text.rfind(['.', ',']) {
+            let (sep, other) = if text.as_bytes()[at] == b'.' { ('.', ',') } else { (',', '.') };
+            let lone = text.matches(sep).count() == 1;
+            let decimals = text.len() - at - 1;
+            if lone && decimals == 3 && !text.contains(other) {
+                return Err(format!("{s:?} is ambiguous; write eg 1250 or 1250.00 for twelve hundred and fifty"));
+            }
+            if lone {
+                decimal_at = Some(at);
+            }
+        }
+        let (whole, fraction) = match decimal_at {
+            Some(at) => (&text[..at], &text[at + 1..]),
+            None => (text, ""),
+        };
+        if fraction.len() > 2 {
+            return Err(format!("{s:?} has more than 2 decimal places"));
+        }
+        // Thousands groups: "1,234,567" but not "12,34" or ",5"
+        let mut groups = whole.split(['.', ',']);
+        let first = groups.next().unwrap_or_default();
+        if (first.is_empty() && !whole.is_empty()) || groups.any(|g| g.len() != 3) || (whole.is_empty() && fraction.is_empty()) {
+            return Err(invalid());
+        }
+
+        let digits: String = whole.chars().filter(char::is_ascii_digit).collect();
+        let dollars: i64 = if digits.is_empty() { 0 } else { digits.parse().map_err(|_| invalid())? };
+        let cents: i64 = format!("{fraction:0<2}").parse().map_err(|_| invalid())?;
+        let total = dollars.checked_mul(100).and_then(|d| d.checked_add(cents)).ok_or_else(invalid)?;
+        Ok(Price::from_cents(if negative { -total } else { total }))
+    }
+}
+
 impl fmt::Display for Price {
-    /// prints eg "12.34"
+    /// prints eg "$12.34"
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // minus for negatives
         if self.cents < 0 {
-            write!(f, "-{}", self.abs().to_decimal_string())
+            write!(f, "-{}", self.abs().to_usd_string())
         } else {
-            write!(f, "{}", self.to_decimal_string())
+            write!(f, "{}", self.to_usd_string())
         }
     }
 }
@@ -236,6 +304,36 @@ mod tests {
         assert_eq!(format!("{}", n), "-$99.06");
     }
 
+    #[test]
+    fn test_price_with_discount_percent() {
+        let p = Price::from_cents(120);
+        assert_eq!(p.with_discount_percent(Some(10.0)).as_cents(), 108);
+        assert_eq!(p.with_discount_percent(Some(33.0)).as_cents(), 80);
+        assert_eq!(p.with_discount_percent(Some(0.0)), p);
+        assert_eq!(p.with_discount_percent(None), p);
+    }
+
+    #[test]
+    fn test_price_parse() {
+        let cents = |s: &str| s.parse::<Price>().map(Price::as_cents);
+        assert_eq!(cents("12.50"), Ok(1250));
+        assert_eq!(cents("12,50"), Ok(1250));
+        assert_eq!(cents("$12.50"), Ok(1250));
+        assert_eq!(cents(" 12.50 € "), Ok(1250));
+        assert_eq!(cents("12"), Ok(1200));
+        assert_eq!(cents("12.5"), Ok(1250));
+        assert_eq!(cents(".99"), Ok(99));
+        assert_eq!(cents("1,234.56"), Ok(123456));
+        assert_eq!(cents("1.234,56"), Ok(123456));
+        assert_eq!(cents("1 234,56"), Ok(123456));
+        assert_eq!(cents("1,250.00"), Ok(125000));
+        assert_eq!(cents("1,234,567"), Ok(123456700));
+        assert_eq!(cents("-3.10"), Ok(-310));
+        for bad in ["", "$", "abc", "12.345.6", "12,34,56", "1.2.3", "1,234.567", "1,2345", "1,250", "12.501", "12$50x", ","] {
+            assert!(bad.parse::<Price>().is_err(), "{bad:?} should not parse");
+        }
+    }
+
     #[test]
     fn test_price_large() {
         let p = Price::from_cents(1_000_000_000); // $10,000,000.00
@@ -0,0 +1,249 @@
+//! Scheduled sales (`promotion`): what each one takes off, which products it covers, and how
+//! several covering the same product combine. Loaded by `db::promotions`.
+//!
+//! A product's running promotions go highest `priority` first (the one added first on a
+//! tie). If the first isn't stackable it applies alone, to the list price, in place of the
+//! product's own `discount_percent`. Otherwise every stackable one applies in turn on top of
+//! that discount, and the non-stackable ones are left out. Either way nobody pays more than
+//! the product's own discount alone would charge.
+
+use crate::db::categories::CategoryTree;
+use crate::models::{Product, Promotion};
+use crate::price::Price;
+use chrono::NaiveDateTime;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// What a promotion takes off each unit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Discount {
+    /// Eg `Percent(20.0)` = 20% off
+    Percent(f32),
+    /// Off each unit, down to free at most
+    Fixed(Price),
+}
+
+impl Discount {
+    pub fn apply(self, price: Price) -> Price {
+        match self {
+            Discount::Percent(percent) => price.with_discount_percent(Some(percent)),
+            Discount::Fixed(amount) => (price - amount).max(Price::default()),
+        }
+    }
+
+    /// Eg "20% off" or "$2.00 off"
+    pub fn label(self) -> String {
+        match self {
+            Discount::Percent(percent) => format!("{}% off", (percent * 100.0).round() / 100.0),
+            Discount::Fixed(amount) => format!("{} off", amount.to_usd_string()),
+        }
+    }
+}
+
+/// What a promotion covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Storewide,
+    Product(i32),
+    /// The category and its subcategories
+    Category(i32),
+    Tag(i32),
+}
+
+impl Promotion {
+    pub fn discount(&self) -> Discount {
+        match self.discount_percent {
+            Some(percent) => Discount::Percent(percent),
+            None => Discount::Fixed(Price::from_cents(self.discount_cents.unwrap_or_default())),
+        }
+    }
+
+    pub fn target(&self) -> Target {
+        match (self.product_id, self.category_id, self.tag_id) {
+            (Some(id), _, _) => Target::Product(id),
+            (_, Some(id), _) => Target::Category(id),
+            (_, _, Some(id)) => Target::Tag(id),
+            _ => Target::Storewide,
+        }
+    }
+
+    /// Whether it has started and not yet ended at `now`
+    pub fn is_running(&self, now: NaiveDateTime) -> bool {
+        self.starts_at <= now && self.ends_at.is_none_or(|end| now < end)
+    }
+
+    /// Whether it covers `product`, which has the tags `tag_ids`
+    pub fn covers(&self, product: &Product, tag_ids: &[i32], tree: &CategoryTree) -> bool {
+        match self.target() {
+            Target::Storewide => true,
+            Target::Product(id) => product.id == id,
+            Target::Category(id) => product.category_id == id || tree.path(product.category_id).iter().any(|c| c.id == id),
+            Target::Tag(id) => tag_ids.contains(&id),
+        }
+    }
+}
+
+/// The running promotions covering each of some products, in the order they're applied
+#[derive(Debug, Clone, Default)]
+pub struct ActivePromotions {
+    by_product: HashMap<i32, Vec<Promotion>>,
+}
+
+impl ActivePromotions {
+    /// `running` should already be limited to those running now; `product_tags` needs the
+    /// tags of each product only if some promotion targets a tag.
+    pub fn new(mut running: Vec<Promotion>, products: &[Product], product_tags: &HashMap<i32, Vec<i32>>, tree: &CategoryTree) -> Self {
+        running.sort_by_key(|p| (Reverse(p.priority), p.id));
+        let by_product = products
+            .iter()
+            .map(|product| {
+                let tag_ids = product_tags.get(&product.id).map(Vec::as_slice).unwrap_or_default();
+                let covering: Vec<Promotion> = running.iter().filter(|p| p.covers(product, tag_ids, tree)).cloned().collect();
+                (product.id, covering)
+            })
+            .filter(|(_, covering)| !covering.is_empty())
+            .collect();
+        ActivePromotions { by_product }
+    }
+
+    /// The promotions covering a product, empty if none or it wasn't loaded
+    pub fn of(&self, product_id: i32) -> &[Promotion] {
+        self.by_product.get(&product_id).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+/// What one unit listed at `list` sells for, given the product's own `discount_percent` and
+/// the promotions covering it (in order, as from `ActivePromotions::of`), along with those
+/// of the promotions that brought it there. See the module docs for the rules.
+pub fn promoted_price(list: Price, own_percent: Option<f32>, promotions: &[Promotion]) -> (Price, Vec<&Promotion>) {
+    let own = list.with_discount_percent(own_percent);
+    let (promoted, applied) = match promotions.first() {
+        None => return (own, Vec::new()),
+        Some(first) if !first.stackable => (first.discount().apply(list), vec![first]),
+        Some(_) => {
+            let stacked: Vec<&Promotion> = promotions.iter().filter(|p| p.stackable).collect();
+            (stacked.iter().fold(own, |price, p| p.discount().apply(price)), stacked)
+        }
+    };
+    if promoted < own {
+        (promoted, applied)
+    } else {
+        (own, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod promotions_tests {
+    use super::*;
+    use crate::models::Category;
+
+    fn at(day: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 11, day).unwrap().and_hms_opt(0, 0, 0).unwrap()
+    }
+
+    fn promotion(id: i32, priority: i32, stackable: bool, discount: Discount) -> Promotion {
+        let (discount_percent, discount_cents) = match discount {
+            Discount::Percent(percent) => (Some(percent), None),
+            Discount::Fixed(amount) => (None, Some(amount.as_cents())),
+        };
+        Promotion {
+            id,
+            name: format!("Promotion {id}"),
+            description: None,
+            starts_at: at(1),
+            ends_at: Some(at(8)),
+            discount_percent,
+            discount_cents,
+            product_id: None,
+            category_id: None,
+            tag_id: None,
+            priority,
+            stackable,
+        }
+    }
+
+    fn product(id: i32, category_id: i32) -> Product {
+        Product {
+            id,
+            name: format!("Product {id}"),
+            price: 1000,
+            inventory: 1,
+            thumbnail_url: None,
+            tagline: None,
+            description: None,
+            discount_percent: None,
+            added_date: at(1),
+            restock_date: None,
+            version: 1,
+            category_id,
+            slug: format!("product-{id}"),
+        }
+    }
+
+    fn ids(applied: &[&Promotion]) -> Vec<i32> {
+        applied.iter().map(|p| p.id).collect()
+    }
+
+    #[test]
+    fn test_discounts() {
+        let price = Price::from_cents(1000);
+        assert_eq!(Discount::Percent(15.0).apply(price).as_cents(), 850);
+        assert_eq!(Discount::Fixed(Price::from_cents(250)).apply(price).as_cents(), 750);
+        assert_eq!(Discount::Fixed(Price::from_cents(2500)).apply(price).as_cents(), 0);
+        assert_eq!(Discount::Percent(12.5).label(), "12.5% off");
+        assert_eq!(Discount::Fixed(Price::from_cents(200)).label(), "$2.00 off");
+
+        let week = promotion(1, 0, false, Discount::Percent(10.0));
+        assert!(!week.is_running(at(1) - chrono::Duration::seconds(1)));
+        assert!(week.is_running(at(1)));
+        assert!(!week.is_running(at(8)));
+        assert!(Promotion { ends_at: None, ..week }.is_running(at(30)));
+    }
+
+    #[test]
+    fn test_stacking_rules() {
+        let list = Price::from_cents(1000);
+        let exclusive = promotion(1, 5, false, Discount::Percent(20.0));
+        let stacks = promotion(2, 3, true, Discount::Percent(10.0));
+        let stacks_too = promotion(3, 1, true, Discount::Fixed(Price::from_cents(100)));
+
+        // The first decides: exclusive alone, on the list price, instead of the own discount
+        let all = [exclusive.clone(), stacks.clone(), stacks_too.clone()];
+        let (price, applied) = promoted_price(list, Some(5.0), &all);
+        assert_eq!((price.as_cents(), ids(&applied)), (800, vec![1]));
+
+        // Stackable ones combine with each other and the own discount, in order
+        let stacked = [stacks.clone(), exclusive.clone(), stacks_too.clone()];
+        let (price, applied) = promoted_price(list, Some(5.0), &stacked);
+        assert_eq!((price.as_cents(), ids(&applied)), (755, vec![2, 3]));
+
+        // A promotion worse than the product's own discount doesn't apply
+        let (price, applied) = promoted_price(list, Some(30.0), &all);
+        assert_eq!((price.as_cents(), applied.len()), (700, 0));
+        assert_eq!(promoted_price(list, None, &[]).0, list);
+    }
+
+    #[test]
+    fn test_active_promotions_by_target() {
+        let tree = CategoryTree::new(vec![
+            Category { id: 1, parent_id: None, name: "Produce".to_owned(), slug: "produce".to_owned(), description: None, image_url: None, position: 0 },
+            Category { id: 2, parent_id: Some(1), name: "Fruit".to_owned(), slug: "fruit".to_owned(), description: None, image_url: None, position: 0 },
+        ]);
+        let products = [product(10, 2), product(11, 1), product(12, 3)];
+        let tags = HashMap::from([(12, vec![7])]);
+        let running = vec![
+            Promotion { product_id: Some(11), ..promotion(1, 0, true, Discount::Percent(5.0)) },
+            Promotion { category_id: Some(1), ..promotion(2, 0, true, Discount::Percent(5.0)) },
+            Promotion { category_id: Some(2), ..promotion(3, 9, true, Discount::Percent(5.0)) },
+            Promotion { tag_id: Some(7), ..promotion(4, 0, true, Discount::Percent(5.0)) },
+            promotion(5, 0, true, Discount::Percent(5.0)),
+        ];
+        let active = ActivePromotions::new(running, &products, &tags, &tree);
+        let covering = |product_id| active.of(product_id).iter().map(|p| p.id).collect::<Vec<_>>();
+        // Subcategories count, and higher priority comes first
+        assert_eq!(covering(10), [3, 2, 5]);
+        assert_eq!(covering(11), [1, 2, 5]);
+        assert_eq!(covering(12), [4, 5]);
+        assert!(active.of(99).is_empty());
+    }
+}
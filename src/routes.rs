@@ -4,6 +4,7 @@ pub mod product;
 pub mod products;
 pub mod cart;
 pub mod checkout;
+pub mod webhooks;
 
 use actix_web::{web, HttpResponse};
 use tera::Tera;
@@ -15,6 +16,7 @@ pub fn init(cfg: &mut web::ServiceConfig) {
     products::init(cfg);
     cart::init(cfg);
     checkout::init(cfg);
+    webhooks::init(cfg);
 }
 
 // 404 handler
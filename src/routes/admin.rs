@@ -1,40 +1,263 @@
+use crate::auth::{self, AdminIdentity, Role};
 use crate::config::Config;
-use crate::db::{products, DbPool};
+use crate::db::cache::CategoriesCache;
+use crate::db::{admin_users, categories, images, orders, products, promotions, renditions, tags, variants, DbPool};
 use crate::errors::BeedleError;
-use crate::models::NewProduct;
+use crate::media::{self, responsive, storage::MediaStorage};
+use crate::models::NewProductImage;
+use crate::orders::OrderStatus;
+use crate::pay::{PaymentProvider, PaymentStatus};
+use crate::price::Price;
 use crate::session::{create_base_context, SessionInfo};
-use actix_web::{web, HttpResponse};
-use diesel::RunQueryDsl;
+use crate::validation::{self, CategoryInput, FieldErrors, ProductInput, PromotionInput, PromotionTargets};
+use crate::views::{AdminImageView, AdminUserView, CategoryView, OrderView, ProductView, SalesEventView};
+use actix_csrf::extractor::{Csrf, CsrfGuarded, CsrfToken};
+use actix_multipart::Multipart;
+use actix_web::{http::header, web, FromRequest, HttpRequest, HttpResponse};
+use futures_util::future::{ready, Ready};
+use futures_util::TryStreamExt;
 use serde::{Deserialize};
 use tera::Tera;
 
-#[derive(Debug,Deserialize)]
+/// Implements `CsrfGuarded` for admin forms, which all carry a `csrf_token` field.
+macro_rules! csrf_guarded {
+    ($($form:ty),* $(,)?) => {
+        $(impl CsrfGuarded for $form {
+            fn csrf_token(&self) -> &CsrfToken {
+                &self.csrf_token
+            }
+        })*
+    };
+}
+
+/// Add or edit a product; see `ProductInput` for the fields.
+#[derive(Debug, Deserialize)]
 pub struct ProductForm {
-    pub name: String,
-    pub price: i64,
+    #[serde(flatten)]
+    pub product: ProductInput,
+    pub csrf_token: CsrfToken,
+}
+
+/// Add/update a variant. `price` is eg "12.50", left blank to use the product's price.
+#[derive(Debug, Deserialize)]
+pub struct VariantForm {
+    pub sku: Option<String>,
+    pub price: Option<String>,
     pub inventory: i32,
-    pub category: Option<String>,
-    pub tags: Option<Vec<String>>,
-    pub keywords: Option<Vec<String>>,
-    pub thumbnail_url: Option<String>,
-    pub gallery_urls: Option<Vec<String>>,
-    pub tagline: Option<String>,
-    pub description: Option<String>,
-    pub discount_percent: Option<f32>,
-    pub date_added: Option<chrono::NaiveDateTime>,
-    pub date_restock_expected: Option<chrono::NaiveDateTime>
+    /// Eg "Size=M, Colour=Red"; only read when adding
+    pub options: Option<String>,
+    pub csrf_token: CsrfToken,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImageAltForm {
+    pub alt_text: String,
+    pub csrf_token: CsrfToken,
+}
+
+/// Move an image one place "up" (towards the front) or "down"
+#[derive(Debug, Deserialize)]
+pub struct MoveImageForm {
+    pub direction: String,
+    pub csrf_token: CsrfToken,
+}
+
+/// Add or edit a category; see `CategoryInput` for the fields.
+#[derive(Debug, Deserialize)]
+pub struct CategoryForm {
+    #[serde(flatten)]
+    pub category: CategoryInput,
+    pub csrf_token: CsrfToken,
+}
+
+/// Add or edit a promotion; see `PromotionInput` for the fields.
+#[derive(Debug, Deserialize)]
+pub struct PromotionForm {
+    #[serde(flatten)]
+    pub promotion: PromotionInput,
+    pub csrf_token: CsrfToken,
+}
+
+/// Rename a tag; a name another tag already has merges the two
+#[derive(Debug, Deserialize)]
+pub struct TagForm {
+    pub name: String,
+    pub csrf_token: CsrfToken,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrderStatusForm {
+    pub status: OrderStatus,
+    pub note: Option<String>,
+    pub csrf_token: CsrfToken,
+}
+
+/// For buttons that only POST to a URL (delete, sync, log out)
+#[derive(Debug, Deserialize)]
+pub struct ActionForm {
+    pub csrf_token: CsrfToken,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+    pub next: Option<String>,
+    pub csrf_token: CsrfToken,
+}
+
+#[derive(Deserialize)]
+pub struct LoginQuery {
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewAdminUserForm {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+    pub csrf_token: CsrfToken,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleForm {
+    pub role: Role,
+    pub csrf_token: CsrfToken,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordForm {
+    pub password: String,
+    pub csrf_token: CsrfToken,
+}
+
+csrf_guarded!(ProductForm, VariantForm, CategoryForm, PromotionForm, ImageAltForm, MoveImageForm, TagForm, OrderStatusForm, ActionForm, LoginForm, NewAdminUserForm, RoleForm, PasswordForm);
+
+/// CSRF token of a multipart form, sent in the query string: the body is an upload
+/// stream that can't be read before the handler runs. Use as `Csrf<CsrfQuery>`.
+pub struct CsrfQuery(CsrfToken);
+
+impl CsrfGuarded for CsrfQuery {
+    fn csrf_token(&self) -> &CsrfToken {
+        &self.0
+    }
+}
+
+impl FromRequest for CsrfQuery {
+    type Error = BeedleError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(
+            web::Query::<ActionForm>::from_query(req.query_string())
+                .map(|query| CsrfQuery(query.into_inner().csrf_token))
+                .map_err(|_| BeedleError::Forbidden("Missing CSRF token".into())),
+        )
+    }
+}
+
+/// Base context for admin pages: who's logged in, plus the CSRF token for the page's forms.
+fn admin_context(session: &SessionInfo, config: &Config, admin: &AdminIdentity, csrf_token: &CsrfToken) -> tera::Context {
+    let mut ctx = create_base_context(session, config);
+    ctx.insert("admin", admin);
+    ctx.insert("csrf_token", csrf_token.get());
+    ctx
+}
+
+fn render_login(
+    tera: &Tera,
+    session: &SessionInfo,
+    config: &Config,
+    csrf_token: &str,
+    next: Option<&str>,
+    error: Option<&str>,
+    no_accounts: bool,
+) -> Result<String, BeedleError> {
+    let mut ctx = create_base_context(session, config);
+    ctx.insert("csrf_token", csrf_token);
+    ctx.insert("next", auth::safe_admin_redirect(next));
+    ctx.insert("no_accounts", &no_accounts);
+    if let Some(error) = error {
+        ctx.insert("error", error);
+    }
+    Ok(tera.render("admin/login.html", &ctx)?)
+}
+
+async fn login_form(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    csrf_token: CsrfToken,
+    query: web::Query<LoginQuery>,
+) -> Result<HttpResponse, BeedleError> {
+    let mut conn = pool.get()?;
+    let no_accounts = admin_users::count_admin_users(&mut conn)? == 0;
+    let rendered = render_login(&tera, &session, &config, csrf_token.get(), query.next.as_deref(), None, no_accounts)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn login(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    form: Csrf<web::Form<LoginForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+
+    let Some(user) = admin_users::authenticate(&mut conn, &form.username, &form.password)? else {
+        log::warn!("Failed admin login for username {:?}", form.username);
+        let rendered = render_login(
+            &tera,
+            &session,
+            &config,
+            form.csrf_token.get(),
+            form.next.as_deref(),
+            Some("Wrong username or password"),
+            false,
+        )?;
+        return Ok(HttpResponse::Unauthorized().content_type("text/html").body(rendered));
+    };
+
+    let hours = config.admin.session_hours;
+    let token = admin_users::start_admin_session(&mut conn, user.id, chrono::Duration::hours(hours))?;
+    log::info!("Admin {} ({}) logged in", user.username, user.role);
+    let resp = HttpResponse::SeeOther()
+        .append_header((header::LOCATION, auth::safe_admin_redirect(form.next.as_deref())))
+        .finish();
+    Ok(auth::set_admin_cookie(resp, Some(token), hours))
+}
+
+/// Outside the guarded scope so read-only accounts (and expired logins) can still log out.
+async fn logout(
+    pool: web::Data<DbPool>,
+    req: HttpRequest,
+    _form: Csrf<web::Form<ActionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    if let Some(token) = req.cookie(auth::ADMIN_COOKIE).and_then(|c| uuid::Uuid::parse_str(c.value()).ok()) {
+        let mut conn = pool.get()?;
+        admin_users::end_admin_session(&mut conn, token)?;
+    }
+    let resp = HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/login"))
+        .finish();
+    Ok(auth::set_admin_cookie(resp, None, 0))
 }
 
 async fn list_products(
     pool: web::Data<DbPool>,
     tera: web::Data<Tera>,
     session: SessionInfo,
-    config: web::Data<Config>
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, BeedleError> {
     let mut conn = pool.get()?;
-    let products = products::load_products(&mut conn)?;
+    let products: Vec<ProductView> = products::load_products(&mut conn)?.iter().map(ProductView::from).collect();
 
-    let mut ctx = create_base_context(&session, config.get_ref());
+    let mut ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
     ctx.insert("products", &products);
     ctx.insert("site_name", &config.site_name); // TODO: make a generic context insertion as a base 
 
@@ -42,74 +265,172 @@ async fn list_products(
     Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
 }
 
+fn render_add_product(tera: &Tera, mut ctx: tera::Context, input: &ProductInput, errors: &FieldErrors) -> Result<String, BeedleError> {
+    ctx.insert("product", input);
+    ctx.insert("errors", errors);
+    ctx.insert("categories", &CategoryView::all(&CategoriesCache::get()));
+    Ok(tera.render("admin/add_product.html", &ctx)?)
+}
+
 async fn add_product_form(
     tera: web::Data<Tera>,
     session: SessionInfo,
-    config: web::Data<Config>
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, BeedleError> {
-    let ctx = create_base_context(&session, config.get_ref());
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let uncategorized = CategoriesCache::get().by_slug("uncategorized").map(|c| c.id.to_string());
+    let input = ProductInput { category: uncategorized.unwrap_or_default(), ..Default::default() };
 
-    let rendered = tera.render("admin/add_product.html", &ctx)?;
+    let rendered = render_add_product(&tera, ctx, &input, &FieldErrors::new())?;
     Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
 }
 
 async fn add_product(
     pool: web::Data<DbPool>,
-    form: web::Form<ProductForm>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    form: Csrf<web::Form<ProductForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let form = form.into_inner().into_inner();
+    log::info!("Received add product form data from {}: {:?}", admin.username, form.product);
+
+    let mut conn = pool.get()?;
+    let validated = form.product.validate_new(&CategoriesCache::get()).and_then(|(new_product, lists)| {
+        match products::slug_owner(&mut conn, &new_product.slug) {
+            Ok(Some(_)) => Err(slug_taken()),
+            _ => Ok((new_product, lists)),
+        }
+    });
+    let (new_product, lists) = match validated {
+        Ok(valid) => valid,
+        Err(errors) => {
+            log::info!("Add product form rejected: {:?}", errors);
+            let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+            let rendered = render_add_product(&tera, ctx, &form.product, &errors)?;
+            return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+        }
+    };
+
+    let saved = products::insert_product(&mut conn, &new_product, &lists)?;
+    log::info!("Product saved successfully: {:?}", saved);
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", "/admin/products"))
+        .finish())
+}
+
+/// Product slugs are unique; the database has the last word, this is the friendly version
+fn slug_taken() -> FieldErrors {
+    let mut errors = FieldErrors::new();
+    errors.insert("slug", "Another product already has this slug".to_owned());
+    errors
+}
+
+fn render_edit_product(
+    tera: &Tera,
+    mut ctx: tera::Context,
+    product_id: i32,
+    input: &ProductInput,
+    errors: &FieldErrors,
+) -> Result<String, BeedleError> {
+    ctx.insert("product_id", &product_id);
+    ctx.insert("product", input);
+    ctx.insert("errors", errors);
+    ctx.insert("categories", &CategoryView::all(&CategoriesCache::get()));
+    Ok(tera.render("admin/edit_product.html", &ctx)?)
+}
+
+async fn edit_product_form(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+    product_id: web::Path<i32>,
 ) -> Result<HttpResponse, BeedleError> {
-    log::info!("Received add product form data: {:?}", form);
+    let product_id = product_id.into_inner();
+    let mut conn = pool.get()?;
+
+    let Some(product) = products::load_product_by_id(&mut conn, product_id)? else {
+        log::warn!("Admin requested edit form of missing product {}", product_id);
+        return Ok(HttpResponse::NotFound().body("Product not found"));
+    };
+    let lists = products::load_product_lists(&mut conn, &[product_id])?.remove(&product_id).unwrap_or_default();
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let rendered = render_edit_product(&tera, ctx, product_id, &ProductInput::new(&product, &lists), &FieldErrors::new())?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
 
+async fn edit_product(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    product_id: web::Path<i32>,
+    form: Csrf<web::Form<ProductForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let product_id = product_id.into_inner();
+    let form = form.into_inner().into_inner();
     let mut conn = pool.get()?;
 
-    // Convert Vec<String> to comma-separated String (for CSV storage)
-    let tags_csv = form.tags.as_ref().map(|v| v.join(","));
-    let keywords_csv = form.keywords.as_ref().map(|v| v.join(","));
-    let gallery_urls_csv = form.gallery_urls.as_ref().map(|v| v.join(","));
+    let Some(current) = products::load_product_by_id(&mut conn, product_id)? else {
+        log::warn!("{} tried to edit missing product {}", admin.username, product_id);
+        return Ok(HttpResponse::NotFound().body("Product not found"));
+    };
+    let mut ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
 
-    let new_product = NewProduct {
-        name: form.name.clone(),
-        price: form.price.clone(),
-        inventory: form.inventory,
-        category: form.category.clone().unwrap_or_else(|| "Uncategorized".to_string()),
-        tags: tags_csv,
-        keywords: keywords_csv,
-        thumbnail_url: form.thumbnail_url.clone(),
-        gallery_urls: gallery_urls_csv,
-        tagline: form.tagline.clone(),
-        description: form.description.clone(),
-        discount_percent: form.discount_percent,
-        added_date: form.date_added,
-        restock_date: form.date_restock_expected
+    let validated = form.product.validate_update(&current, &CategoriesCache::get()).and_then(|(edited, lists)| {
+        match products::slug_owner(&mut conn, &edited.slug) {
+            Ok(Some(owner)) if owner != product_id => Err(slug_taken()),
+            _ => Ok((edited, lists)),
+        }
+    });
+    let (edited, lists) = match validated {
+        Ok(valid) => valid,
+        Err(errors) => {
+            let rendered = render_edit_product(&tera, ctx, product_id, &form.product, &errors)?;
+            return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+        }
     };
 
-    use crate::schema::product::dsl::*;
-    match diesel::insert_into(product)
-        .values(&new_product)
-        .execute(&mut conn)
-    {
+    match products::save_product(&mut conn, &edited, &lists) {
         Ok(_) => {
-            log::info!("Product saved successfully: {:?}", new_product);
+            log::info!("{} edited product {}", admin.username, product_id);
             Ok(HttpResponse::SeeOther()
-                .append_header(("Location", "/admin/products"))
+                .append_header((header::LOCATION, "/admin/products"))
                 .finish())
         }
-        Err(e) => {
-            log::error!("Failed to save product: {:?}", e);
-            Err(BeedleError::DatabaseError(e.to_string()))
+        // Keep what they typed; they can reload to see the other change and redo theirs
+        Err(BeedleError::EditConflict(msg)) => {
+            ctx.insert("conflict", &msg);
+            let rendered = render_edit_product(&tera, ctx, product_id, &form.product, &FieldErrors::new())?;
+            Ok(HttpResponse::Conflict().content_type("text/html").body(rendered))
         }
+        Err(e) => Err(e),
     }
 }
 
 async fn remove_product(
     pool: web::Data<DbPool>,
+    storage: web::Data<dyn MediaStorage>,
+    admin: AdminIdentity,
     product_id: web::Path<i32>,
+    _form: Csrf<web::Form<ActionForm>>,
 ) -> Result<HttpResponse, BeedleError> {
     let product_id = product_id.into_inner();
     log::info!(
-        "Received request to delete product with ID: {:?}",
+        "Received request from {} to delete product with ID: {:?}",
+        admin.username,
         product_id
     );
     let mut conn = pool.get()?;
+    // The image rows cascade with the product; the files have to go separately
+    let files = images::delete_renditions_of_product(&mut conn, product_id)?;
 
     if let Err(e) = products::delete_product(&mut conn, product_id) {
         log::error!("Failed to delete product: {:?}", e);
@@ -117,15 +438,1149 @@ async fn remove_product(
     } else {
         log::info!("Product with ID: {:?} deleted successfully", product_id);
     }
+    let keys: Vec<&str> = files.iter().map(String::as_str).collect();
+    media::storage::delete_all(storage.get_ref(), &keys).await;
 
     Ok(HttpResponse::SeeOther()
         .append_header(("Location", "/admin/products"))
         .finish())
 }
 
+/// "Size=M, Colour=Red" -> [("Size", "M"), ("Colour", "Red")]
+fn parse_variant_options(text: &str) -> Result<Vec<(String, String)>, BeedleError> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() && !value.trim().is_empty() => {
+                Ok((name.trim().to_owned(), value.trim().to_owned()))
+            }
+            _ => Err(BeedleError::InventoryError(format!("Options must look like Size=M, not '{}'", pair))),
+        })
+        .collect()
+}
+
+fn parse_variant_price(price: Option<&str>) -> Result<Option<i64>, BeedleError> {
+    match price.map(str::trim).filter(|p| !p.is_empty()) {
+        Some(p) => match p.parse::<Price>() {
+            Ok(price) if price.as_cents() >= 0 => Ok(Some(price.as_cents())),
+            _ => Err(BeedleError::InventoryError(format!("Invalid price: {}", p))),
+        },
+        None => Ok(None),
+    }
+}
+
+async fn list_variants(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+    product_id: web::Path<i32>,
+) -> Result<HttpResponse, BeedleError> {
+    let product_id = product_id.into_inner();
+    let mut conn = pool.get()?;
+
+    let Some(product) = products::load_product_by_id(&mut conn, product_id)? else {
+        log::warn!("Admin requested variants of missing product {}", product_id);
+        return Ok(HttpResponse::NotFound().body("Product not found"));
+    };
+    let variants = variants::load_variants(&mut conn, product_id)?;
+    let options: Vec<(String, Vec<String>)> = variants::load_options(&mut conn, product_id)?
+        .into_iter()
+        .map(|(option, values)| (option.name, values.into_iter().map(|v| v.value).collect()))
+        .collect();
+
+    let mut ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    ctx.insert("product", &ProductView::from(&product).with_variants(&product, &variants, &[]));
+    ctx.insert("options", &options);
+
+    let rendered = tera.render("admin/variants.html", &ctx)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn add_variant(
+    pool: web::Data<DbPool>,
+    product_id: web::Path<i32>,
+    form: Csrf<web::Form<VariantForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let product_id = product_id.into_inner();
+    let form = form.into_inner();
+    log::info!("Received add variant form for product {}: {:?}", product_id, form);
+
+    let options = parse_variant_options(form.options.as_deref().unwrap_or_default())?;
+    let price = parse_variant_price(form.price.as_deref())?;
+    let mut conn = pool.get()?;
+    let variant = variants::create_variant(
+        &mut conn,
+        product_id,
+        form.sku.as_deref().unwrap_or_default(),
+        price,
+        form.inventory,
+        &options,
+    )?;
+    log::info!("Variant saved successfully: {:?}", variant);
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/admin/products/{}/variants", product_id)))
+        .finish())
+}
+
+async fn update_variant(
+    pool: web::Data<DbPool>,
+    variant_id: web::Path<i32>,
+    form: Csrf<web::Form<VariantForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let variant_id = variant_id.into_inner();
+    let form = form.into_inner();
+    let price = parse_variant_price(form.price.as_deref())?;
+    let mut conn = pool.get()?;
+    let variant = variants::load_variant_by_id(&mut conn, variant_id)?
+        .ok_or_else(|| BeedleError::InventoryError(format!("No variant with id {}", variant_id)))?;
+    variants::update_variant(&mut conn, variant_id, price, form.inventory)?;
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/admin/products/{}/variants", variant.variant.product_id)))
+        .finish())
+}
+
+async fn remove_variant(
+    pool: web::Data<DbPool>,
+    variant_id: web::Path<i32>,
+    _form: Csrf<web::Form<ActionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let variant_id = variant_id.into_inner();
+    let mut conn = pool.get()?;
+    let variant = variants::load_variant_by_id(&mut conn, variant_id)?
+        .ok_or_else(|| BeedleError::InventoryError(format!("No variant with id {}", variant_id)))?;
+    variants::delete_variant(&mut conn, variant_id)?;
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/admin/products/{}/variants", variant.variant.product_id)))
+        .finish())
+}
+
+fn render_categories(tera: &Tera, conn: &mut crate::db::Conn, mut ctx: tera::Context, input: &CategoryInput, errors: &FieldErrors) -> Result<String, BeedleError> {
+    let tree = CategoriesCache::get();
+    let product_counts = categories::product_counts(conn)?;
+    let listed: Vec<(CategoryView, i64)> = CategoryView::all(&tree)
+        .into_iter()
+        .map(|c| {
+            let count = product_counts.get(&c.id).copied().unwrap_or(0);
+            (c, count)
+        })
+        .collect();
+    ctx.insert("categories", &listed);
+    ctx.insert("category", input);
+    ctx.insert("errors", errors);
+    Ok(tera.render("admin/categories.html", &ctx)?)
+}
+
+/// The category tree, and a form to add one
+async fn list_categories(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BeedleError> {
+    let mut conn = pool.get()?;
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let rendered = render_categories(&tera, &mut conn, ctx, &CategoryInput::default(), &FieldErrors::new())?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn add_category(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    form: Csrf<web::Form<CategoryForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+
+    let new_category = match form.category.validate_new(&CategoriesCache::get()) {
+        Ok(valid) => valid,
+        Err(errors) => {
+            let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+            let rendered = render_categories(&tera, &mut conn, ctx, &form.category, &errors)?;
+            return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+        }
+    };
+    let saved = categories::insert_category(&mut conn, &new_category)?;
+    CategoriesCache::refresh(&mut conn)?;
+    log::info!("{} added category {} ({:?})", admin.username, saved.id, saved.name);
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/categories"))
+        .finish())
+}
+
+fn render_edit_category(
+    tera: &Tera,
+    mut ctx: tera::Context,
+    category_id: i32,
+    input: &CategoryInput,
+    errors: &FieldErrors,
+) -> Result<String, BeedleError> {
+    let tree = CategoriesCache::get();
+    // Not itself or anything below it
+    let below = tree.subtree_ids(category_id);
+    let parents: Vec<CategoryView> = CategoryView::all(&tree).into_iter().filter(|c| !below.contains(&c.id)).collect();
+    ctx.insert("category_id", &category_id);
+    ctx.insert("category", input);
+    ctx.insert("parents", &parents);
+    ctx.insert("errors", errors);
+    Ok(tera.render("admin/edit_category.html", &ctx)?)
+}
+
+async fn edit_category_form(
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+    category_id: web::Path<i32>,
+) -> Result<HttpResponse, BeedleError> {
+    let category_id = category_id.into_inner();
+    let Some(category) = CategoriesCache::get().get(category_id).cloned() else {
+        log::warn!("Admin requested edit form of missing category {}", category_id);
+        return Ok(HttpResponse::NotFound().body("Category not found"));
+    };
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let rendered = render_edit_category(&tera, ctx, category_id, &CategoryInput::from(&category), &FieldErrors::new())?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+/// Renaming or moving a category needs no product changes: products refer to it by ID.
+async fn edit_category(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    category_id: web::Path<i32>,
+    form: Csrf<web::Form<CategoryForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let category_id = category_id.into_inner();
+    let form = form.into_inner().into_inner();
+    let tree = CategoriesCache::get();
+    let Some(current) = tree.get(category_id) else {
+        log::warn!("{} tried to edit missing category {}", admin.username, category_id);
+        return Ok(HttpResponse::NotFound().body("Category not found"));
+    };
+
+    let edited = match form.category.validate_update(current, &tree) {
+        Ok(valid) => valid,
+        Err(errors) => {
+            let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+            let rendered = render_edit_category(&tera, ctx, category_id, &form.category, &errors)?;
+            return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+        }
+    };
+    let mut conn = pool.get()?;
+    categories::save_category(&mut conn, &edited)?;
+    CategoriesCache::refresh(&mut conn)?;
+    log::info!("{} edited category {} ({:?})", admin.username, category_id, edited.name);
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/categories"))
+        .finish())
+}
+
+/// Only empty categories can go: move their products and subcategories elsewhere first.
+async fn remove_category(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    category_id: web::Path<i32>,
+    form: Csrf<web::Form<ActionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let category_id = category_id.into_inner();
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+
+    let tree = CategoriesCache::get();
+    let products_in = categories::count_products_in(&mut conn, category_id)?;
+    let error = if products_in > 0 {
+        Some(format!("It still has {products_in} product(s); move them to another category first"))
+    } else if tree.children(Some(category_id)).next().is_some() {
+        Some("It still has subcategories; move or delete them first".to_owned())
+    } else {
+        None
+    };
+    if let Some(message) = error {
+        let name = tree.get(category_id).map(|c| c.name.as_str()).unwrap_or_default();
+        let mut ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+        ctx.insert("error", &format!("Can't delete {name}: {message}"));
+        let rendered = render_categories(&tera, &mut conn, ctx, &CategoryInput::default(), &FieldErrors::new())?;
+        return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+    }
+
+    categories::delete_category(&mut conn, category_id)?;
+    CategoriesCache::refresh(&mut conn)?;
+    log::info!("{} deleted category {}", admin.username, category_id);
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/categories"))
+        .finish())
+}
+
+fn render_tags(tera: &Tera, conn: &mut crate::db::Conn, mut ctx: tera::Context, error: Option<&str>) -> Result<String, BeedleError> {
+    ctx.insert("tags", &tags::list_tags(conn)?);
+    if let Some(error) = error {
+        ctx.insert("error", error);
+    }
+    Ok(tera.render("admin/tags.html", &ctx)?)
+}
+
+async fn list_tags(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BeedleError> {
+    let mut conn = pool.get()?;
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let rendered = render_tags(&tera, &mut conn, ctx, None)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn rename_tag(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    tag_id: web::Path<i32>,
+    form: Csrf<web::Form<TagForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let tag_id = tag_id.into_inner();
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+
+    let mut errors = FieldErrors::new();
+    let name = validation::required(&mut errors, "name", &form.name, 50);
+    if name.contains(',') {
+        errors.insert("name", "Can't contain commas".to_owned());
+    }
+    if let Some(message) = errors.get("name") {
+        let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+        let rendered = render_tags(&tera, &mut conn, ctx, Some(&format!("Tag name: {message}")))?;
+        return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+    }
+
+    tags::rename_tag(&mut conn, tag_id, &name)?;
+    log::info!("{} renamed tag {} to {:?}", admin.username, tag_id, name);
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/tags"))
+        .finish())
+}
+
+async fn remove_tag(
+    pool: web::Data<DbPool>,
+    admin: AdminIdentity,
+    tag_id: web::Path<i32>,
+    _form: Csrf<web::Form<ActionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let tag_id = tag_id.into_inner();
+    let mut conn = pool.get()?;
+    tags::delete_tag(&mut conn, tag_id)?;
+    log::info!("{} deleted tag {}", admin.username, tag_id);
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/tags"))
+        .finish())
+}
+
+/// What the promotion forms can aim at
+struct PromotionChoices {
+    /// ID and name, alphabetical
+    products: Vec<(i32, String)>,
+    product_ids: Vec<i32>,
+    tags: Vec<tags::TagCount>,
+    tag_ids: Vec<i32>,
+    tree: std::sync::Arc<categories::CategoryTree>,
+}
+
+impl PromotionChoices {
+    fn load(conn: &mut crate::db::Conn) -> Result<Self, BeedleError> {
+        let mut products: Vec<(i32, String)> = products::load_products(conn)?.into_iter().map(|p| (p.id, p.name)).collect();
+        products.sort_by_key(|(_, name)| name.to_lowercase());
+        let tags = tags::list_tags(conn)?;
+        Ok(PromotionChoices {
+            product_ids: products.iter().map(|(id, _)| *id).collect(),
+            products,
+            tag_ids: tags.iter().map(|t| t.id).collect(),
+            tags,
+            tree: CategoriesCache::get(),
+        })
+    }
+
+    fn targets(&self) -> PromotionTargets<'_> {
+        PromotionTargets { categories: &self.tree, product_ids: &self.product_ids, tag_ids: &self.tag_ids }
+    }
+
+    /// For the form's selects
+    fn insert(&self, ctx: &mut tera::Context) {
+        ctx.insert("products", &self.products);
+        ctx.insert("categories", &CategoryView::all(&self.tree));
+        ctx.insert("tags", &self.tags);
+    }
+}
+
+fn render_promotions(
+    tera: &Tera,
+    conn: &mut crate::db::Conn,
+    mut ctx: tera::Context,
+    choices: &PromotionChoices,
+    input: &PromotionInput,
+    errors: &FieldErrors,
+) -> Result<String, BeedleError> {
+    let now = chrono::Utc::now().naive_utc();
+    let listed: Vec<SalesEventView> = promotions::load_promotions(conn)?
+        .iter()
+        .map(|event| SalesEventView::new(event, &choices.tree, now))
+        .collect();
+    choices.insert(&mut ctx);
+    ctx.insert("promotions", &listed);
+    ctx.insert("promotion", input);
+    ctx.insert("errors", errors);
+    Ok(tera.render("admin/promotions.html", &ctx)?)
+}
+
+/// Every promotion, past ones included, and a form to add one
+async fn list_promotions(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BeedleError> {
+    let mut conn = pool.get()?;
+    let choices = PromotionChoices::load(&mut conn)?;
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let input = PromotionInput { discount_kind: "percent".to_owned(), applies_to: "all".to_owned(), ..Default::default() };
+    let rendered = render_promotions(&tera, &mut conn, ctx, &choices, &input, &FieldErrors::new())?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn add_promotion(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    form: Csrf<web::Form<PromotionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+    let choices = PromotionChoices::load(&mut conn)?;
+
+    let new_promotion = match form.promotion.validate_new(&choices.targets(), chrono::Utc::now().naive_utc()) {
+        Ok(valid) => valid,
+        Err(errors) => {
+            let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+            let rendered = render_promotions(&tera, &mut conn, ctx, &choices, &form.promotion, &errors)?;
+            return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+        }
+    };
+    let saved = promotions::insert_promotion(&mut conn, &new_promotion)?;
+    log::info!("{} added promotion {} ({:?})", admin.username, saved.id, saved.name);
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/promotions"))
+        .finish())
+}
+
+fn render_edit_promotion(
+    tera: &Tera,
+    mut ctx: tera::Context,
+    choices: &PromotionChoices,
+    promotion_id: i32,
+    input: &PromotionInput,
+    errors: &FieldErrors,
+) -> Result<String, BeedleError> {
+    choices.insert(&mut ctx);
+    ctx.insert("promotion_id", &promotion_id);
+    ctx.insert("promotion", input);
+    ctx.insert("errors", errors);
+    Ok(tera.render("admin/edit_promotion.html", &ctx)?)
+}
+
+async fn edit_promotion_form(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+    promotion_id: web::Path<i32>,
+) -> Result<HttpResponse, BeedleError> {
+    let promotion_id = promotion_id.into_inner();
+    let mut conn = pool.get()?;
+    let Some(promotion) = promotions::load_promotion(&mut conn, promotion_id)? else {
+        log::warn!("Admin requested edit form of missing promotion {}", promotion_id);
+        return Ok(HttpResponse::NotFound().body("Promotion not found"));
+    };
+    let choices = PromotionChoices::load(&mut conn)?;
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let rendered = render_edit_promotion(&tera, ctx, &choices, promotion_id, &PromotionInput::from(&promotion), &FieldErrors::new())?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+/// Takes effect on the next page load, carts included: prices are worked out with the
+/// running promotions every time.
+async fn edit_promotion(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    promotion_id: web::Path<i32>,
+    form: Csrf<web::Form<PromotionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let promotion_id = promotion_id.into_inner();
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+    let Some(current) = promotions::load_promotion(&mut conn, promotion_id)? else {
+        log::warn!("{} tried to edit missing promotion {}", admin.username, promotion_id);
+        return Ok(HttpResponse::NotFound().body("Promotion not found"));
+    };
+    let choices = PromotionChoices::load(&mut conn)?;
+
+    let edited = match form.promotion.validate_update(&current, &choices.targets(), chrono::Utc::now().naive_utc()) {
+        Ok(valid) => valid,
+        Err(errors) => {
+            let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+            let rendered = render_edit_promotion(&tera, ctx, &choices, promotion_id, &form.promotion, &errors)?;
+            return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+        }
+    };
+    promotions::save_promotion(&mut conn, &edited)?;
+    log::info!("{} edited promotion {} ({:?})", admin.username, promotion_id, edited.name);
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/promotions"))
+        .finish())
+}
+
+/// Ending a promotion early is better done by setting its end, which keeps it listed.
+async fn remove_promotion(
+    pool: web::Data<DbPool>,
+    admin: AdminIdentity,
+    promotion_id: web::Path<i32>,
+    _form: Csrf<web::Form<ActionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let promotion_id = promotion_id.into_inner();
+    let mut conn = pool.get()?;
+    promotions::delete_promotion(&mut conn, promotion_id)?;
+    log::info!("{} deleted promotion {}", admin.username, promotion_id);
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, "/admin/promotions"))
+        .finish())
+}
+
+fn images_location(product_id: i32) -> String {
+    format!("/admin/products/{}/images", product_id)
+}
+
+fn render_images(
+    tera: &Tera,
+    mut ctx: tera::Context,
+    conn: &mut crate::db::Conn,
+    storage: &dyn MediaStorage,
+    product: &crate::models::Product,
+    error: Option<&str>,
+) -> Result<String, BeedleError> {
+    let uploaded = images::load_images(conn, product.id)?;
+    let sources: Vec<String> = uploaded.iter().map(|image| image.storage_key.clone()).collect();
+    let image_renditions = renditions::load_renditions(conn, &sources)?;
+    let uploaded: Vec<AdminImageView> = uploaded
+        .iter()
+        .map(|image| {
+            let copies = image_renditions.get(&image.storage_key).map(Vec::as_slice).unwrap_or_default();
+            AdminImageView::new(image, copies, storage)
+        })
+        .collect();
+    ctx.insert("product", &ProductView::from(product));
+    ctx.insert("images", &uploaded);
+    if let Some(error) = error {
+        ctx.insert("error", error);
+    }
+    Ok(tera.render("admin/images.html", &ctx)?)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn list_images(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    storage: web::Data<dyn MediaStorage>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+    product_id: web::Path<i32>,
+) -> Result<HttpResponse, BeedleError> {
+    let product_id = product_id.into_inner();
+    let mut conn = pool.get()?;
+
+    let Some(product) = products::load_product_by_id(&mut conn, product_id)? else {
+        log::warn!("Admin requested images of missing product {}", product_id);
+        return Ok(HttpResponse::NotFound().body("Product not found"));
+    };
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let rendered = render_images(&tera, ctx, &mut conn, storage.get_ref(), &product, None)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+/// Reads the upload form: the image (at most `max_bytes`) and its alt text.
+async fn read_upload(mut payload: Multipart, max_bytes: usize, config: &Config) -> Result<(Vec<u8>, String), BeedleError> {
+    let multipart_error = |e: actix_multipart::MultipartError| BeedleError::MediaError(format!("Upload failed: {e}"));
+    let mut file = Vec::new();
+    let mut alt_text = Vec::new();
+    while let Some(mut field) = payload.try_next().await.map_err(multipart_error)? {
+        let (target, limit) = match field.name() {
+            Some("file") => (&mut file, max_bytes),
+            Some("alt_text") => (&mut alt_text, 1000),
+            _ => continue,
+        };
+        while let Some(chunk) = field.try_next().await.map_err(multipart_error)? {
+            if target.len() + chunk.len() > limit {
+                return Err(media::too_large(&config.media));
+            }
+            target.extend_from_slice(&chunk);
+        }
+    }
+    if file.is_empty() {
+        return Err(BeedleError::MediaError("Choose an image to upload".into()));
+    }
+    Ok((file, String::from_utf8_lossy(&alt_text).trim().to_owned()))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn upload_image(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    storage: web::Data<dyn MediaStorage>,
+    admin: AdminIdentity,
+    product_id: web::Path<i32>,
+    csrf: Csrf<CsrfQuery>,
+    payload: Multipart,
+) -> Result<HttpResponse, BeedleError> {
+    let product_id = product_id.into_inner();
+    // Read and process the upload before taking a pooled connection; slow clients and big
+    // images shouldn't hold one
+    let processed = match read_upload(payload, config.media.max_upload_bytes, &config).await {
+        Ok((bytes, alt_text)) => media::process_upload_blocking(bytes, product_id, &config.media)
+            .await
+            .map(|processed| (processed, alt_text)),
+        Err(e) => Err(e),
+    };
+
+    let mut conn = pool.get()?;
+    let Some(product) = products::load_product_by_id(&mut conn, product_id)? else {
+        return Ok(HttpResponse::NotFound().body("Product not found"));
+    };
+    let (processed, alt_text) = match processed {
+        Ok(processed) => processed,
+        Err(BeedleError::MediaError(msg)) => {
+            log::info!("{} uploaded an unusable image for product {}: {}", admin.username, product_id, msg);
+            let ctx = admin_context(&session, config.get_ref(), &admin, &csrf.0);
+            let rendered = render_images(&tera, ctx, &mut conn, storage.get_ref(), &product, Some(&msg))?;
+            return Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let keys: Vec<&str> = processed.files().iter().map(|file| file.key.as_str()).collect();
+    for file in processed.files() {
+        if let Err(e) = storage.put(&file.key, file.bytes.clone(), file.content_type).await {
+            media::storage::delete_all(storage.get_ref(), &keys).await;
+            return Err(e);
+        }
+    }
+    let inserted = images::insert_image(
+        &mut conn,
+        NewProductImage {
+            product_id,
+            storage_key: &processed.original.key,
+            thumb_key: &processed.thumb.key,
+            medium_key: &processed.medium.key,
+            content_type: processed.original.content_type,
+            width: processed.width as i32,
+            height: processed.height as i32,
+            byte_size: processed.original.bytes.len() as i32,
+            alt_text: &alt_text,
+            position: 0,
+        },
+        &responsive::rows(&processed.original.key, &processed.derivatives),
+    );
+    if let Err(e) = inserted {
+        media::storage::delete_all(storage.get_ref(), &keys).await;
+        return Err(e);
+    }
+    log::info!("{} uploaded {} for product {}", admin.username, processed.original.key, product_id);
+
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, images_location(product_id)))
+        .finish())
+}
+
+async fn update_image_alt(
+    pool: web::Data<DbPool>,
+    image_id: web::Path<i32>,
+    form: Csrf<web::Form<ImageAltForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let image_id = image_id.into_inner();
+    let mut conn = pool.get()?;
+    let image = images::load_image(&mut conn, image_id)?
+        .ok_or_else(|| BeedleError::MediaError(format!("No image with id {}", image_id)))?;
+    images::update_image_alt(&mut conn, image_id, &form.alt_text)?;
+
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, images_location(image.product_id)))
+        .finish())
+}
+
+async fn move_image(
+    pool: web::Data<DbPool>,
+    image_id: web::Path<i32>,
+    form: Csrf<web::Form<MoveImageForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let image_id = image_id.into_inner();
+    let earlier = match form.direction.as_str() {
+        "up" => true,
+        "down" => false,
+        other => return Err(BeedleError::MediaError(format!("Unknown direction: {other}"))),
+    };
+    let mut conn = pool.get()?;
+    let image = images::load_image(&mut conn, image_id)?
+        .ok_or_else(|| BeedleError::MediaError(format!("No image with id {}", image_id)))?;
+    images::move_image(&mut conn, image_id, earlier)?;
+
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, images_location(image.product_id)))
+        .finish())
+}
+
+async fn remove_image(
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn MediaStorage>,
+    admin: AdminIdentity,
+    image_id: web::Path<i32>,
+    _form: Csrf<web::Form<ActionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let image_id = image_id.into_inner();
+    let mut conn = pool.get()?;
+    let (image, files) = images::delete_image(&mut conn, image_id)?;
+    let keys: Vec<&str> = files.iter().map(String::as_str).collect();
+    media::storage::delete_all(storage.get_ref(), &keys).await;
+    log::info!("{} deleted image {} of product {}", admin.username, image_id, image.product_id);
+
+    Ok(HttpResponse::SeeOther()
+        .append_header((header::LOCATION, images_location(image.product_id)))
+        .finish())
+}
+
+async fn list_orders(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BeedleError> {
+    let mut conn = pool.get()?;
+    let orders: Vec<OrderView> = orders::load_orders(&mut conn)?
+        .iter()
+        .map(|o| OrderView::new(o, &[]))
+        .collect();
+
+    let mut ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    ctx.insert("orders", &orders);
+
+    let rendered = tera.render("admin/orders.html", &ctx)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn order_detail(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+    order_id: web::Path<i32>,
+) -> Result<HttpResponse, BeedleError> {
+    let order_id = order_id.into_inner();
+    let mut conn = pool.get()?;
+
+    let Some(order) = orders::load_order_by_id(&mut conn, order_id)? else {
+        log::warn!("Admin requested missing order {}", order_id);
+        return Ok(HttpResponse::NotFound().body("Order not found"));
+    };
+    let lines = orders::load_order_lines(&mut conn, &order)?;
+    let history = orders::load_order_history(&mut conn, &order)?;
+
+    let mut ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    ctx.insert("order", &OrderView::new(&order, &lines).with_history(&history));
+
+    let rendered = tera.render("admin/order.html", &ctx)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn update_order_status(
+    pool: web::Data<DbPool>,
+    provider: web::Data<dyn PaymentProvider>,
+    admin: AdminIdentity,
+    order_id: web::Path<i32>,
+    form: Csrf<web::Form<OrderStatusForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let order_id = order_id.into_inner();
+    let form = form.into_inner().into_inner();
+    log::info!("Received order status change for {}: {:?}", order_id, form);
+
+    let mut conn = pool.get()?;
+    let note = form.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    let Some(order) = orders::load_order_by_id(&mut conn, order_id)? else {
+        log::warn!("Status change for missing order {}", order_id);
+        return Ok(HttpResponse::NotFound().body("Order not found"));
+    };
+    let refunds_to = order.payment_reference.as_deref()
+        .filter(|_| order.payment_provider.as_deref() == Some(provider.name()) && order.status.refunds_payment(form.status));
+    let Some(reference) = refunds_to else {
+        if order.status.refunds_payment(form.status) {
+            log::warn!("Order {} has no {} payment to refund; recording {} only", order_id, provider.name(), form.status);
+        }
+        orders::transition_order_status(&mut conn, order_id, form.status, &admin.actor(), note)?;
+        return Ok(HttpResponse::SeeOther()
+            .append_header(("Location", format!("/admin/orders/{}", order_id)))
+            .finish());
+    };
+
+    // Note the refund on the order first (checking the change is allowed), then ask for the money
+    // back holding no lock, transaction or connection; the key makes a retry refund only once
+    let (order, key) = orders::request_refund(&mut conn, order_id, form.status)?;
+    drop(conn);
+    let refund = provider.refund(reference, Price::from_cents(order.total), &key).await?;
+    log::info!("Refunded {} for order {} ({})", refund.amount.debug_string(), order_id, refund.id);
+
+    let refund_note = format!("Refunded {} ({})", refund.amount.to_usd_string(), refund.id);
+    let note = match note {
+        Some(note) => format!("{}; {}", note, refund_note),
+        None => refund_note,
+    };
+    let mut conn = pool.get()?;
+    if let Err(e) = orders::transition_order_status(&mut conn, order_id, form.status, &admin.actor(), Some(&note)) {
+        log::error!("Order {} was refunded ({}) but moving it to {} failed: {e}", order_id, refund.id, form.status);
+        return Err(BeedleError::OrderError(format!(
+            "Refunded {} ({}) for order {}, but recording it as {} failed: {}. Trying again won't refund twice.",
+            refund.amount.to_usd_string(), refund.id, order_id, form.status.label(), e
+        )));
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/admin/orders/{}", order_id)))
+        .finish())
+}
+
+/// Ask the payment provider about a pending order and mark it paid if the money has arrived.
+async fn sync_order_payment(
+    pool: web::Data<DbPool>,
+    provider: web::Data<dyn PaymentProvider>,
+    order_id: web::Path<i32>,
+    _form: Csrf<web::Form<ActionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    let order_id = order_id.into_inner();
+    let order = {
+        let mut conn = pool.get()?;
+        orders::load_order_by_id(&mut conn, order_id)?
+            .ok_or_else(|| BeedleError::OrderError(format!("No order with id {}", order_id)))?
+    };
+
+    match order.payment_reference.as_deref() {
+        Some(reference) if order.payment_provider.as_deref() == Some(provider.name()) => {
+            let status = provider.fetch_status(reference).await?;
+            log::info!("Payment {} for order {} is {:?}", reference, order_id, status);
+            if status == PaymentStatus::Succeeded && order.status == OrderStatus::PendingPayment {
+                let mut conn = pool.get()?;
+                orders::transition_order_status(&mut conn, order_id, OrderStatus::Paid, provider.name(), Some("Payment confirmed by provider"))?;
+            }
+        }
+        _ => log::warn!("Order {} has no {} payment to sync", order_id, provider.name()),
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .append_header(("Location", format!("/admin/orders/{}", order_id)))
+        .finish())
+}
+
+fn render_users(
+    tera: &Tera,
+    conn: &mut crate::db::Conn,
+    mut ctx: tera::Context,
+    error: Option<&str>,
+) -> Result<String, BeedleError> {
+    let users: Vec<AdminUserView> = admin_users::load_admin_users(conn)?.iter().map(AdminUserView::from).collect();
+    let roles: Vec<(&str, &str)> = Role::ALL.iter().map(|r| (r.as_str(), r.label())).collect();
+    ctx.insert("users", &users);
+    ctx.insert("roles", &roles);
+    ctx.insert("min_password_len", &auth::MIN_PASSWORD_LEN);
+    if let Some(error) = error {
+        ctx.insert("error", error);
+    }
+    Ok(tera.render("admin/users.html", &ctx)?)
+}
+
+async fn list_users(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BeedleError> {
+    admin.require_owner()?;
+    let mut conn = pool.get()?;
+    let ctx = admin_context(&session, config.get_ref(), &admin, &csrf_token);
+    let rendered = render_users(&tera, &mut conn, ctx, None)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+/// Run an account change; on a validation problem, show the users page again with the message.
+fn user_change_response(
+    tera: &Tera,
+    conn: &mut crate::db::Conn,
+    ctx: tera::Context,
+    result: Result<(), BeedleError>,
+) -> Result<HttpResponse, BeedleError> {
+    match result {
+        Ok(()) => Ok(HttpResponse::SeeOther()
+            .append_header((header::LOCATION, "/admin/users"))
+            .finish()),
+        Err(BeedleError::AccountError(message)) => {
+            let rendered = render_users(tera, conn, ctx, Some(&message))?;
+            Ok(HttpResponse::BadRequest().content_type("text/html").body(rendered))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+async fn add_user(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    form: Csrf<web::Form<NewAdminUserForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    admin.require_owner()?;
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+    let result = admin_users::create_admin_user(&mut conn, &form.username, &form.password, form.role).map(|user| {
+        log::info!("{} created admin user {} ({})", admin.username, user.username, user.role);
+    });
+    let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+    user_change_response(&tera, &mut conn, ctx, result)
+}
+
+async fn update_user_role(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    user_id: web::Path<i32>,
+    form: Csrf<web::Form<RoleForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    admin.require_owner()?;
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+    let result = admin_users::set_admin_role(&mut conn, user_id.into_inner(), form.role);
+    let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+    user_change_response(&tera, &mut conn, ctx, result)
+}
+
+async fn update_user_password(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    user_id: web::Path<i32>,
+    form: Csrf<web::Form<PasswordForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    admin.require_owner()?;
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+    let result = admin_users::set_admin_password(&mut conn, user_id.into_inner(), &form.password);
+    let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+    user_change_response(&tera, &mut conn, ctx, result)
+}
+
+async fn remove_user(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    session: SessionInfo,
+    config: web::Data<Config>,
+    admin: AdminIdentity,
+    user_id: web::Path<i32>,
+    form: Csrf<web::Form<ActionForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    admin.require_owner()?;
+    let form = form.into_inner().into_inner();
+    let user_id = user_id.into_inner();
+    let mut conn = pool.get()?;
+    let result = if user_id == admin.id {
+        Err(BeedleError::AccountError("You can't delete your own account".into()))
+    } else {
+        admin_users::delete_admin_user(&mut conn, user_id)
+    };
+    let ctx = admin_context(&session, config.get_ref(), &admin, &form.csrf_token);
+    user_change_response(&tera, &mut conn, ctx, result)
+}
+
+/// Login/logout are public; everything else under /admin goes through `auth::require_admin`.
 pub fn init(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/admin/products").route(web::get().to(list_products)))
-        .service(web::resource("/admin/add_product").route(web::get().to(add_product_form)))
-        .service(web::resource("/admin/add").route(web::post().to(add_product)))
-        .service(web::resource("/admin/delete/{product_id}").route(web::post().to(remove_product)));
+    cfg.service(
+        web::resource("/admin/login")
+            .route(web::get().to(login_form))
+            .route(web::post().to(login)),
+    )
+    .service(web::resource("/admin/logout").route(web::post().to(logout)))
+    .service(
+        web::scope("/admin")
+            .wrap_fn(auth::require_admin)
+            .service(web::resource("/products").route(web::get().to(list_products)))
+            .service(web::resource("/add_product").route(web::get().to(add_product_form)))
+            .service(web::resource("/add").route(web::post().to(add_product)))
+            .service(
+                web::resource("/products/{product_id}/edit")
+                    .route(web::get().to(edit_product_form))
+                    .route(web::post().to(edit_product)),
+            )
+            .service(web::resource("/delete/{product_id}").route(web::post().to(remove_product)))
+            .service(
+                web::resource("/products/{product_id}/variants")
+                    .route(web::get().to(list_variants))
+                    .route(web::post().to(add_variant)),
+            )
+            .service(
+                web::resource("/products/{product_id}/images")
+                    .route(web::get().to(list_images))
+                    .route(web::post().to(upload_image)),
+            )
+            .service(web::resource("/images/{image_id}").route(web::post().to(update_image_alt)))
+            .service(web::resource("/images/{image_id}/move").route(web::post().to(move_image)))
+            .service(web::resource("/images/{image_id}/delete").route(web::post().to(remove_image)))
+            .service(
+                web::resource("/categories")
+                    .route(web::get().to(list_categories))
+                    .route(web::post().to(add_category)),
+            )
+            .service(
+                web::resource("/categories/{category_id}/edit")
+                    .route(web::get().to(edit_category_form))
+                    .route(web::post().to(edit_category)),
+            )
+            .service(web::resource("/categories/{category_id}/delete").route(web::post().to(remove_category)))
+            .service(
+                web::resource("/promotions")
+                    .route(web::get().to(list_promotions))
+                    .route(web::post().to(add_promotion)),
+            )
+            .service(
+                web::resource("/promotions/{promotion_id}/edit")
+                    .route(web::get().to(edit_promotion_form))
+                    .route(web::post().to(edit_promotion)),
+            )
+            .service(web::resource("/promotions/{promotion_id}/delete").route(web::post().to(remove_promotion)))
+            .service(web::resource("/tags").route(web::get().to(list_tags)))
+            .service(web::resource("/tags/{tag_id}").route(web::post().to(rename_tag)))
+            .service(web::resource("/tags/{tag_id}/delete").route(web::post().to(remove_tag)))
+            .service(web::resource("/variants/{variant_id}").route(web::post().to(update_variant)))
+            .service(web::resource("/variants/{variant_id}/delete").route(web::post().to(remove_variant)))
+            .service(web::resource("/orders").route(web::get().to(list_orders)))
+            .service(web::resource("/orders/{order_id}").route(web::get().to(order_detail)))
+            .service(web::resource("/orders/{order_id}/status").route(web::post().to(update_order_status)))
+            .service(web::resource("/orders/{order_id}/sync_payment").route(web::post().to(sync_order_payment)))
+            .service(
+                web::resource("/users")
+                    .route(web::get().to(list_users))
+                    .route(web::post().to(add_user)),
+            )
+            .service(web::resource("/users/{user_id}/role").route(web::post().to(update_user_role)))
+            .service(web::resource("/users/{user_id}/password").route(web::post().to(update_user_password)))
+            .service(web::resource("/users/{user_id}/delete").route(web::post().to(remove_user))),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AdminConfig, CatalogConfig, CheckoutConfig, MediaConfig, PaymentConfig};
+    use crate::db::test_pool;
+    use crate::pay::mock::MockProvider;
+    use actix_web::{cookie::Cookie, test, App};
+    use std::sync::Arc;
+
+    #[actix_rt::test]
+    async fn test_admin_scope_requires_login_and_respects_roles() {
+        let pool = test_pool();
+        let tera = Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*")).unwrap();
+        let provider: Arc<dyn PaymentProvider> = Arc::new(MockProvider::new(std::time::Duration::from_secs(60)));
+        let config = Config {
+            site_name: "Test".to_owned(),
+            root_domain: "localhost".to_owned(),
+            payment: PaymentConfig::default(),
+            checkout: CheckoutConfig::default(),
+            admin: AdminConfig::default(),
+            media: MediaConfig::default(),
+            catalog: CatalogConfig::default(),
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::from(provider))
+                .configure(init)
+                .wrap(actix_csrf::CsrfMiddleware::with_rng(rand::rngs::OsRng)
+                    .set_cookie(actix_web::http::Method::GET, "/admin/orders")
+                    .set_cookie(actix_web::http::Method::GET, "/admin/users")),
+        )
+        .await;
+
+        let (viewer, token) = {
+            let mut conn = pool.get().unwrap();
+            let name = format!("test-viewer-{}", uuid::Uuid::new_v4().simple());
+            let viewer = admin_users::create_admin_user(&mut conn, &name, "viewer password", Role::ReadOnly).unwrap();
+            let token = admin_users::start_admin_session(&mut conn, viewer.id, chrono::Duration::hours(1)).unwrap();
+            (viewer, token)
+        };
+        let logged_in = Cookie::new(auth::ADMIN_COOKIE, token.to_string());
+
+        let anonymous = test::call_service(&app, test::TestRequest::get().uri("/admin/orders").to_request()).await;
+        assert_eq!(anonymous.status(), 303);
+        assert_eq!(anonymous.headers().get(header::LOCATION).unwrap(), "/admin/login?next=%2Fadmin%2Forders");
+        let anonymous_delete = test::TestRequest::post().uri("/admin/delete/1").to_request();
+        assert_eq!(test::call_service(&app, anonymous_delete).await.status(), 303);
+
+        let view = test::TestRequest::get().uri("/admin/orders").cookie(logged_in.clone()).to_request();
+        assert_eq!(test::call_service(&app, view).await.status(), 200);
+        // Read-only: no changes, whatever the form says, and no account management
+        let delete = test::TestRequest::post().uri("/admin/delete/1").cookie(logged_in.clone()).to_request();
+        assert_eq!(test::call_service(&app, delete).await.status(), 403);
+        let users = test::TestRequest::get().uri("/admin/users").cookie(logged_in).to_request();
+        assert_eq!(test::call_service(&app, users).await.status(), 403);
+
+        let mut conn = pool.get().unwrap();
+        admin_users::delete_admin_user(&mut conn, viewer.id).unwrap();
+    }
 }
@@ -1,9 +1,10 @@
 use crate::config::Config;
-use crate::db::{products, DbPool};
+use crate::db::cache::CategoriesCache;
+use crate::db::{products, promotions, reservations, variants, DbPool};
 use crate::errors::BeedleError;
 use crate::models::CartItem;
 use crate::session::{create_base_context, ensure_session_cookie, SessionInfo};
-use crate::views::ProductView;
+use crate::views::{ProductView, VariantView};
 use actix_csrf::extractor::{Csrf, CsrfGuarded, CsrfToken};
 use actix_web::{http::header, web, HttpResponse};
 use serde::Serialize;
@@ -13,14 +14,19 @@ use tera::Tera;
 #[derive(serde::Deserialize)]
 struct CartActionForm {
     product_id: i32,
+    /// Which variant; may be left out for products that only have one
+    variant_id: Option<i32>,
     quantity: i32,
     csrf_token: CsrfToken,
 }
 
 #[derive(serde::Deserialize)]
 struct CartQuery {
+    /// Variant ID of a just-removed item
     undo_id: Option<i32>,
     undo_qty: Option<u32>,
+    /// Set (to a variant ID) when checkout couldn't reserve enough of it
+    unavailable: Option<i32>,
 }
 
 impl CsrfGuarded for CartActionForm {
@@ -32,19 +38,22 @@ impl CsrfGuarded for CartActionForm {
 #[derive(Serialize)]
 struct CartProductView {
     product: ProductView,
+    variant: VariantView,
     quantity: u32,
     max_quantity: i32,
 }
 
-fn update_cart_quantity(cart: &mut Vec<CartItem>, product_id: i32, delta: i32, max_allowed: i32) {
+/// Adding does nothing if `max_allowed` is below 1 (sold out); callers should say so.
+fn update_cart_quantity(cart: &mut Vec<CartItem>, product_id: i32, variant_id: i32, delta: i32, max_allowed: i32) {
     match delta.cmp(&0) {
         Ordering::Equal => {
             // Remove from cart if delta is zero
-            cart.retain(|item| item.product_id != product_id);
+            cart.retain(|item| item.variant_id != variant_id);
         }
+        Ordering::Greater if max_allowed < 1 => {}
         Ordering::Greater => {
             // Increase quantity / insert item
-            match cart.iter_mut().find(|item| item.product_id == product_id) {
+            match cart.iter_mut().find(|item| item.variant_id == variant_id) {
                 Some(item) => {
                     let new_qty = (item.quantity as i32 + delta).clamp(1, max_allowed);
                     item.quantity = new_qty as u32;
@@ -53,6 +62,7 @@ fn update_cart_quantity(cart: &mut Vec<CartItem>, product_id: i32, delta: i32, m
                     let start_qty = delta.clamp(1, max_allowed);
                     cart.push(CartItem {
                         product_id,
+                        variant_id,
                         quantity: start_qty as u32,
                     });
                 }
@@ -60,7 +70,7 @@ fn update_cart_quantity(cart: &mut Vec<CartItem>, product_id: i32, delta: i32, m
         }
         Ordering::Less => {
             // Decrease quantity
-            if let Some(idx) = cart.iter().position(|item| item.product_id == product_id) {
+            if let Some(idx) = cart.iter().position(|item| item.variant_id == variant_id) {
                 let item = &mut cart[idx];
                 let new_qty = item.quantity as i32 + delta; // (delta is negative)
                 if new_qty < 1 {
@@ -81,34 +91,62 @@ async fn update_cart_quantity_handler(
     let mut conn = pool.get()?;
     let form = form.into_inner().into_inner();
 
-    // verify product exists and get actual allowable max
-    let product = products::load_product_by_id(&mut conn, form.product_id)?
-        .ok_or_else(|| BeedleError::InventoryError("Product not found".into()))?;
+    // verify the variant exists and belongs to the product, and get actual allowable max
+    let variant = match form.variant_id {
+        Some(variant_id) => variants::load_variant_by_id(&mut conn, variant_id)?
+            .filter(|v| v.variant.product_id == form.product_id)
+            .ok_or_else(|| BeedleError::InventoryError("Product variant not found".into()))?,
+        None => {
+            let mut all = variants::load_variants(&mut conn, form.product_id)?;
+            if all.is_empty() {
+                return Err(BeedleError::InventoryError("Product not found".into()));
+            }
+            if all.len() > 1 {
+                // Nothing to go on; let the shopper pick a size/colour/etc on the product page
+                return Ok(HttpResponse::SeeOther()
+                    .append_header((header::LOCATION, format!("/products/{}", form.product_id)))
+                    .finish());
+            }
+            all.remove(0)
+        }
+    };
+    let variant_id = variant.variant.id;
 
     let max_per_order = 99; // TODO: use product.max_per_order after I add that field
-    let max_allowed = product.inventory.min(max_per_order);
+    let max_allowed = variant.variant.inventory.min(max_per_order);
     let prev_qty = session
         .cart
         .iter()
-        .find(|item| item.product_id == form.product_id)
+        .find(|item| item.variant_id == variant_id)
         .map(|i| i.quantity);
 
+    if form.quantity > 0 && max_allowed <= 0 {
+        // Sold out since the page was loaded; leave the cart alone
+        let resp = HttpResponse::SeeOther()
+            .append_header((header::LOCATION, format!("/cart?unavailable={}", variant_id)))
+            .finish();
+        return if session.was_created { Ok(ensure_session_cookie(resp, session.session_id)) } else { Ok(resp) };
+    }
+
     update_cart_quantity(
         &mut session.cart,
         form.product_id,
+        variant_id,
         form.quantity,
         max_allowed,
     );
     crate::db::session::update_session_cart(&mut conn, session.session_id, &session.cart)?;
+    // Any stock held for checkout was for the old cart; checkout reserves again
+    reservations::release_session_reservations(&mut conn, session.session_id)?;
 
     // If this was a remove (set to zero), redirect with undo params
     let location = if let Some(qty) = prev_qty {
-        let found = session.cart.iter().any(|i| i.product_id == form.product_id);
+        let found = session.cart.iter().any(|i| i.variant_id == variant_id);
         if !found && form.quantity <= 0 {
             if qty > 1 {
-                format!("/cart?undo_id={}&undo_qty={}", form.product_id, qty)
+                format!("/cart?undo_id={}&undo_qty={}", variant_id, qty)
             } else {
-                format!("/cart?undo_id={}", form.product_id)
+                format!("/cart?undo_id={}", variant_id)
             }
         } else {
             "/cart".to_owned()
@@ -117,7 +155,7 @@ async fn update_cart_quantity_handler(
         "/cart".to_owned()
     };
     let resp = HttpResponse::SeeOther()
-        .header(header::LOCATION, location)
+        .append_header((header::LOCATION, location))
         .finish();
     if session.was_created {
         Ok(ensure_session_cookie(resp, session.session_id))
@@ -136,16 +174,28 @@ async fn view_cart(
 ) -> Result<HttpResponse, BeedleError> {
     let mut conn = pool.get()?;
     let cart = &session.cart;
-    let products = products::load_products(&mut conn)?;
+    let mut variant_ids: Vec<i32> = cart.iter().map(|item| item.variant_id).collect();
+    variant_ids.extend(query.undo_id);
+    variant_ids.extend(query.unavailable);
+    let variants = variants::load_variants_by_ids(&mut conn, &variant_ids)?;
+    let product_ids: Vec<i32> = variants.iter().map(|v| v.variant.product_id).collect();
+    let products = products::load_products_by_ids(&mut conn, &product_ids)?;
+    let active = promotions::active_promotions(&mut conn, &products, &CategoriesCache::get(), chrono::Utc::now().naive_utc())?;
+    let find = |variant_id: i32| {
+        let variant = variants.iter().find(|v| v.variant.id == variant_id)?;
+        let product = products.iter().find(|p| p.id == variant.variant.product_id)?;
+        Some((product, variant))
+    };
 
     let cart_items: Vec<CartProductView> = cart
         .iter()
         .filter_map(|item| {
-            products.iter().find(|p| p.id == item.product_id).map(|p| {
+            find(item.variant_id).map(|(p, v)| {
                 let max_per_order = 99; // HACK: arbitrary maximum
-                let max_quantity = p.inventory.min(max_per_order);
+                let max_quantity = v.variant.inventory.min(max_per_order);
                 CartProductView {
-                    product: ProductView::from(p),
+                    product: ProductView::from(p).with_promotions(active.of(p.id)),
+                    variant: VariantView::new(p, v, active.of(p.id)),
                     quantity: item.quantity,
                     max_quantity,
                 }
@@ -155,16 +205,18 @@ async fn view_cart(
 
     let mut ctx = create_base_context(&session, config.get_ref());
 
-    if let Some(undo_id) = query.undo_id {
-        // TODO: lookup product info instead of just passing ID?
+    if let Some((product, variant)) = query.undo_id.and_then(find) {
         let undo_qty = query.undo_qty.unwrap_or(1);
-        ctx.insert("undo_id", &undo_id);
+        ctx.insert("undo_id", &variant.variant.id);
+        ctx.insert("undo_product_id", &product.id);
         ctx.insert("undo_qty", &undo_qty);
+        ctx.insert("undo_product_name", &variant.display_name(product));
+    }
 
-        // insert undo product name
-        if let Some(product) = products.iter().find(|p| p.id == undo_id) {
-            ctx.insert("undo_product_name", &product.name);
-        }
+    if let Some((product, variant)) = query.unavailable.and_then(find) {
+        let available = reservations::available_inventory(&mut conn, session.session_id, variant.variant.id)?;
+        ctx.insert("unavailable_product_name", &variant.display_name(product));
+        ctx.insert("unavailable_quantity", &available);
     }
 
     ctx.insert("cart_items", &cart_items);
@@ -179,3 +231,33 @@ pub fn init(cfg: &mut web::ServiceConfig) {
     )
     .service(web::resource("/cart").route(web::get().to(view_cart)));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_cart_quantity_clamps_to_stock() {
+        let mut cart = Vec::new();
+        update_cart_quantity(&mut cart, 1, 10, 5, 3);
+        assert_eq!(cart[0].quantity, 3);
+        update_cart_quantity(&mut cart, 1, 10, -1, 3);
+        assert_eq!(cart[0].quantity, 2);
+        update_cart_quantity(&mut cart, 1, 10, 0, 3);
+        assert!(cart.is_empty());
+    }
+
+    #[test]
+    fn test_update_cart_quantity_with_zero_stock() {
+        let mut cart = Vec::new();
+        update_cart_quantity(&mut cart, 1, 10, 1, 0);
+        assert!(cart.is_empty());
+
+        let mut cart = vec![CartItem { product_id: 1, variant_id: 10, quantity: 2 }];
+        update_cart_quantity(&mut cart, 1, 10, 1, 0);
+        assert_eq!(cart[0].quantity, 2);
+        // Taking some out still works
+        update_cart_quantity(&mut cart, 1, 10, -1, 0);
+        assert_eq!(cart[0].quantity, 1);
+    }
+}
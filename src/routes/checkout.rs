@@ -1,29 +1,645 @@
-use actix_session::Session;
-use actix_web::{web, HttpResponse};
-use crate::db::{DbPool, products::update_inventory};
-use crate::pay::process_payment;
-use crate::session::get_cart;
+//! Checkout pages: address -> shipping method -> review, then POST /checkout to pay.
+//! Each step saves into `SessionInfo::checkout` and redirects to the next one.
+
+use actix_csrf::extractor::{Csrf, CsrfGuarded, CsrfToken};
+use actix_web::{http::header, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use tera::Tera;
+use uuid::Uuid;
+use crate::checkout::{AddressInput, CheckoutState, CheckoutStep, ShippingMethod};
+use crate::config::Config;
+use crate::db::cache::CategoriesCache;
+use crate::db::{products, promotions, reservations::reserve_cart, variants, session::{update_session_cart, update_session_checkout}, Conn, DbPool};
+use crate::db::orders::{create_order, set_order_payment, transition_order_status};
+use crate::models::CartItem;
+use crate::orders::OrderStatus;
+use crate::pay::{PaymentProvider, PaymentRequest, PaymentStatus};
+use crate::price::Price;
+use crate::session::{create_base_context, ensure_session_cookie, SessionInfo};
+use crate::validation::FieldErrors;
+use crate::views::{ProductView, VariantView};
 use crate::errors::BeedleError;
 
-async fn checkout(
+#[derive(Deserialize)]
+struct AddressForm {
+    #[serde(flatten)]
+    address: AddressInput,
+    csrf_token: CsrfToken,
+}
+
+impl CsrfGuarded for AddressForm {
+    fn csrf_token(&self) -> &CsrfToken {
+        &self.csrf_token
+    }
+}
+
+#[derive(Deserialize)]
+struct ShippingForm {
+    /// Missing when no radio button was picked
+    shipping_method: Option<String>,
+    csrf_token: CsrfToken,
+}
+
+impl CsrfGuarded for ShippingForm {
+    fn csrf_token(&self) -> &CsrfToken {
+        &self.csrf_token
+    }
+}
+
+#[derive(Deserialize)]
+struct CheckoutForm {
+    /// Provider-specific token (eg from Stripe.js); not needed for manual payment
+    payment_token: Option<String>,
+    csrf_token: CsrfToken,
+}
+
+impl CsrfGuarded for CheckoutForm {
+    fn csrf_token(&self) -> &CsrfToken {
+        &self.csrf_token
+    }
+}
+
+/// How a checkout attempt ended up.
+#[derive(Debug, PartialEq)]
+pub(crate) enum CheckoutOutcome {
+    Paid { order_id: i32 },
+    /// Order placed but money not confirmed yet (manual payment, 3-D Secure, async confirmation)
+    AwaitingPayment { order_id: i32, instructions: Option<String>, next_action_url: Option<String> },
+    /// Payment refused; the order was cancelled and its stock released
+    Declined { order_id: i32, reason: String },
+}
+
+#[derive(Serialize)]
+struct CheckoutLineView {
+    product: ProductView,
+    variant: VariantView,
+    quantity: u32,
+    line_total_formatted: String,
+}
+
+#[derive(Serialize)]
+struct ShippingOptionView {
+    value: &'static str,
+    label: &'static str,
+    cost_formatted: String,
+    selected: bool,
+}
+
+/// Cart contents priced for display, and the discounted item total shipping is based on.
+fn price_cart(conn: &mut Conn, cart: &[CartItem]) -> Result<(Vec<CheckoutLineView>, Price), BeedleError> {
+    let variant_ids: Vec<i32> = cart.iter().map(|item| item.variant_id).collect();
+    let variants = variants::load_variants_by_ids(conn, &variant_ids)?;
+    let product_ids: Vec<i32> = variants.iter().map(|v| v.variant.product_id).collect();
+    let products = products::load_products_by_ids(conn, &product_ids)?;
+    let active = promotions::active_promotions(conn, &products, &CategoriesCache::get(), chrono::Utc::now().naive_utc())?;
+    let lines: Vec<CheckoutLineView> = cart
+        .iter()
+        .filter_map(|item| {
+            let v = variants.iter().find(|v| v.variant.id == item.variant_id)?;
+            products.iter().find(|p| p.id == v.variant.product_id).map(|p| {
+                let variant = VariantView::new(p, v, active.of(p.id));
+                let line_total = variant.price_discounted * item.quantity as i64;
+                CheckoutLineView {
+                    product: ProductView::from(p).with_promotions(active.of(p.id)),
+                    variant,
+                    quantity: item.quantity,
+                    line_total_formatted: line_total.to_decimal_string(),
+                }
+            })
+        })
+        .collect();
+    let items_total = lines
+        .iter()
+        .fold(Price::default(), |sum, line| sum + line.variant.price_discounted * line.quantity as i64);
+    Ok((lines, items_total))
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .append_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Where to send someone who isn't allowed on `step` yet (or has nothing to buy).
+fn step_redirect(session: &SessionInfo, step: CheckoutStep) -> Option<HttpResponse> {
+    if session.cart.is_empty() {
+        return Some(redirect("/cart"));
+    }
+    if !step.is_reachable(&session.checkout) {
+        return Some(redirect(session.checkout.next_step().path()));
+    }
+    None
+}
+
+/// Hold the cart's stock for this shopper (or refresh the hold).
+/// If something has run out, returns a redirect back to the cart explaining what.
+fn hold_stock(conn: &mut Conn, session: &SessionInfo, config: &Config) -> Result<Option<HttpResponse>, BeedleError> {
+    let ttl = chrono::Duration::minutes(config.checkout.reservation_minutes);
+    let shortages = reserve_cart(conn, session.session_id, &session.cart, ttl)?;
+    Ok(shortages
+        .first()
+        .map(|shortage| redirect(&format!("/cart?unavailable={}", shortage.variant_id))))
+}
+
+fn checkout_context(session: &SessionInfo, config: &Config, step: CheckoutStep, csrf_token: &str) -> tera::Context {
+    let mut ctx = create_base_context(session, config);
+    ctx.insert("step", &step);
+    ctx.insert("next_step", &session.checkout.next_step());
+    ctx.insert("csrf_token", csrf_token);
+    ctx
+}
+
+fn render_address(
+    tera: &Tera,
+    mut ctx: tera::Context,
+    address: &AddressInput,
+    errors: &FieldErrors,
+) -> Result<String, BeedleError> {
+    ctx.insert("address", address);
+    ctx.insert("errors", errors);
+    Ok(tera.render("checkout/address.html", &ctx)?)
+}
+
+fn render_shipping(
+    conn: &mut Conn,
+    tera: &Tera,
+    mut ctx: tera::Context,
+    session: &SessionInfo,
+    error: Option<&str>,
+) -> Result<String, BeedleError> {
+    let (_, items_total) = price_cart(conn, &session.cart)?;
+    let options: Vec<ShippingOptionView> = ShippingMethod::ALL
+        .into_iter()
+        .map(|method| ShippingOptionView {
+            value: method.as_str(),
+            label: method.label(),
+            cost_formatted: method.cost(items_total).to_decimal_string(),
+            selected: session.checkout.shipping_method == Some(method),
+        })
+        .collect();
+    ctx.insert("shipping_options", &options);
+    ctx.insert("items_total_formatted", &items_total.to_decimal_string());
+    if let Some(error) = error {
+        ctx.insert("errors", &FieldErrors::from([("shipping_method", error.to_owned())]));
+    }
+    Ok(tera.render("checkout/shipping.html", &ctx)?)
+}
+
+/// Save the checkout state and continue to the next unfinished step.
+fn save_and_continue(conn: &mut Conn, session: &SessionInfo, state: &CheckoutState) -> Result<HttpResponse, BeedleError> {
+    update_session_checkout(conn, session.session_id, state)?;
+    let resp = redirect(state.next_step().path());
+    if session.was_created {
+        Ok(ensure_session_cookie(resp, session.session_id))
+    } else {
+        Ok(resp)
+    }
+}
+
+/// Record an order for `cart` and charge it through `provider`.
+/// `checkout` must be complete (address + shipping method); shipping is added to the total.
+/// Declined payments, and ones the provider fails to create or capture, cancel the order
+/// (restocking it); pending ones stay `PendingPayment`.
+pub(crate) async fn place_order(
+    pool: &DbPool,
+    config: &Config,
+    provider: &dyn PaymentProvider,
+    session_id: Uuid,
+    cart: &[CartItem],
+    checkout: &CheckoutState,
+    payment_token: Option<String>,
+) -> Result<CheckoutOutcome, BeedleError> {
+    let (address, shipping_method) = checkout
+        .completed()
+        .ok_or_else(|| BeedleError::OrderError("Checkout details are incomplete".into()))?;
+
+    // Record the order first; this holds the inventory while we talk to the provider
+    let order = {
+        let mut conn = pool.get()?;
+        create_order(&mut conn, session_id, cart, address, shipping_method)?
+    };
+    let request = PaymentRequest {
+        order_id: order.id,
+        amount: Price::from_cents(order.total),
+        currency: config.payment.currency.clone(),
+        payment_token,
+        description: format!("{} order #{}", config.site_name, order.id),
+    };
+    log::info!("Charging {} for order {} via {}", request.amount.debug_string(), order.id, provider.name());
+
+    let decline = |reason: String| -> Result<CheckoutOutcome, BeedleError> {
+        let mut conn = pool.get()?;
+        transition_order_status(&mut conn, order.id, OrderStatus::Cancelled, "checkout", Some(&reason))?;
+        Ok(CheckoutOutcome::Declined { order_id: order.id, reason })
+    };
+
+    let intent = match provider.create_intent(&request).await {
+        Ok(intent) if intent.amount != request.amount => {
+            log::error!("Provider created intent {} for {} but order {} totals {}",
+                intent.id, intent.amount.debug_string(), order.id, request.amount.debug_string());
+            decline("Payment amount mismatch".to_owned())?;
+            return Err(BeedleError::PaymentError("Payment amount mismatch".into()));
+        }
+        Ok(intent) => intent,
+        Err(e) => {
+            log::warn!("Payment for order {} failed: {e}", order.id);
+            return decline(e.to_string());
+        }
+    };
+    {
+        let mut conn = pool.get()?;
+        set_order_payment(&mut conn, order.id, provider.name(), &intent.id)?;
+    }
+
+    let status = match intent.status {
+        PaymentStatus::RequiresCapture => match provider.capture(&intent.id).await {
+            Ok(status) => status,
+            // Like a failed intent: don't leave the order holding stock nobody paid for
+            Err(e) => {
+                log::warn!("Capturing payment {} for order {} failed: {e}", intent.id, order.id);
+                return decline(e.to_string());
+            }
+        },
+        other => other,
+    };
+
+    match status {
+        PaymentStatus::Succeeded => {
+            let mut conn = pool.get()?;
+            transition_order_status(&mut conn, order.id, OrderStatus::Paid, "checkout", None)?;
+            Ok(CheckoutOutcome::Paid { order_id: order.id })
+        }
+        PaymentStatus::Pending | PaymentStatus::RequiresAction => Ok(CheckoutOutcome::AwaitingPayment {
+            order_id: order.id,
+            instructions: intent.instructions,
+            next_action_url: intent.next_action_url,
+        }),
+        PaymentStatus::Failed | PaymentStatus::RequiresCapture => {
+            decline(intent.failure_reason.unwrap_or_else(|| "Payment was declined".to_owned()))
+        }
+    }
+}
+
+/// GET /checkout: hold the cart's stock, then resume at the first unfinished step
+async fn checkout_start(
     pool: web::Data<DbPool>,
-    session: Session,
+    config: web::Data<Config>,
+    session: SessionInfo,
+) -> Result<HttpResponse, BeedleError> {
+    if session.cart.is_empty() {
+        return Ok(redirect("/cart"));
+    }
+    let mut conn = pool.get()?;
+    if let Some(resp) = hold_stock(&mut conn, &session, config.get_ref())? {
+        return Ok(resp);
+    }
+    Ok(redirect(session.checkout.next_step().path()))
+}
+
+async fn address_page(
+    tera: web::Data<Tera>,
+    config: web::Data<Config>,
+    session: SessionInfo,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, BeedleError> {
-    let cart = get_cart(&session);
-    if cart.is_empty() {
-        return Ok(HttpResponse::BadRequest().body("Cart is empty"));
+    if let Some(resp) = step_redirect(&session, CheckoutStep::Address) {
+        return Ok(resp);
     }
-    match process_payment(1, "hi").await {
-        Ok(_) => {
+    let ctx = checkout_context(&session, config.get_ref(), CheckoutStep::Address, csrf_token.get());
+    let address = session.checkout.address.as_ref().map(AddressInput::from).unwrap_or_default();
+    let rendered = render_address(&tera, ctx, &address, &FieldErrors::new())?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn submit_address(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    config: web::Data<Config>,
+    session: SessionInfo,
+    form: Csrf<web::Form<AddressForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    if let Some(resp) = step_redirect(&session, CheckoutStep::Address) {
+        return Ok(resp);
+    }
+    let form = form.into_inner().into_inner();
+    match form.address.validate() {
+        Ok(address) => {
             let mut conn = pool.get()?;
-            update_inventory(&mut conn, &cart)?;
-            session.purge();
-            Ok(HttpResponse::Ok().body("Checkout completed"))
+            let mut state = session.checkout.clone();
+            state.address = Some(address);
+            save_and_continue(&mut conn, &session, &state)
+        }
+        Err(errors) => {
+            log::debug!("Checkout address rejected for session {}: {:?}", session.session_id, errors);
+            let ctx = checkout_context(&session, config.get_ref(), CheckoutStep::Address, form.csrf_token.get());
+            let rendered = render_address(&tera, ctx, &form.address, &errors)?;
+            Ok(HttpResponse::UnprocessableEntity().content_type("text/html").body(rendered))
         }
-        Err(e) => Ok(HttpResponse::InternalServerError().body(format!("Payment failed: {}", e))),
     }
 }
 
+async fn shipping_page(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    config: web::Data<Config>,
+    session: SessionInfo,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BeedleError> {
+    if let Some(resp) = step_redirect(&session, CheckoutStep::Shipping) {
+        return Ok(resp);
+    }
+    let mut conn = pool.get()?;
+    let ctx = checkout_context(&session, config.get_ref(), CheckoutStep::Shipping, csrf_token.get());
+    let rendered = render_shipping(&mut conn, &tera, ctx, &session, None)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+async fn submit_shipping(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    config: web::Data<Config>,
+    session: SessionInfo,
+    form: Csrf<web::Form<ShippingForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    if let Some(resp) = step_redirect(&session, CheckoutStep::Shipping) {
+        return Ok(resp);
+    }
+    let form = form.into_inner().into_inner();
+    let mut conn = pool.get()?;
+    match form.shipping_method.as_deref().unwrap_or_default().parse::<ShippingMethod>() {
+        Ok(method) => {
+            let mut state = session.checkout.clone();
+            state.shipping_method = Some(method);
+            save_and_continue(&mut conn, &session, &state)
+        }
+        Err(e) => {
+            log::debug!("Checkout shipping rejected for session {}: {e}", session.session_id);
+            let ctx = checkout_context(&session, config.get_ref(), CheckoutStep::Shipping, form.csrf_token.get());
+            let rendered = render_shipping(&mut conn, &tera, ctx, &session, Some("Choose a shipping method"))?;
+            Ok(HttpResponse::UnprocessableEntity().content_type("text/html").body(rendered))
+        }
+    }
+}
+
+async fn review_page(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    config: web::Data<Config>,
+    provider: web::Data<dyn PaymentProvider>,
+    session: SessionInfo,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, BeedleError> {
+    if let Some(resp) = step_redirect(&session, CheckoutStep::Review) {
+        return Ok(resp);
+    }
+    let Some((address, shipping_method)) = session.checkout.completed() else {
+        return Ok(redirect(session.checkout.next_step().path()));
+    };
+    let mut conn = pool.get()?;
+    if let Some(resp) = hold_stock(&mut conn, &session, config.get_ref())? {
+        return Ok(resp);
+    }
+    let (lines, items_total) = price_cart(&mut conn, &session.cart)?;
+    let shipping = shipping_method.cost(items_total);
+
+    let mut ctx = checkout_context(&session, config.get_ref(), CheckoutStep::Review, csrf_token.get());
+    ctx.insert("lines", &lines);
+    ctx.insert("address", address);
+    ctx.insert("shipping_method", shipping_method.label());
+    ctx.insert("items_total_formatted", &items_total.to_decimal_string());
+    ctx.insert("shipping_total_formatted", &shipping.to_decimal_string());
+    ctx.insert("total_formatted", &(items_total + shipping).to_decimal_string());
+    ctx.insert("payment_provider", provider.name());
+    ctx.insert("payment_error", &session.checkout.payment_error);
+    ctx.insert("reservation_minutes", &config.checkout.reservation_minutes);
+    let rendered = tera.render("checkout/review.html", &ctx)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+/// POST /checkout: place the order and charge it
+async fn checkout(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    config: web::Data<Config>,
+    provider: web::Data<dyn PaymentProvider>,
+    mut session: SessionInfo,
+    form: Csrf<web::Form<CheckoutForm>>,
+) -> Result<HttpResponse, BeedleError> {
+    if let Some(resp) = step_redirect(&session, CheckoutStep::Review) {
+        return Ok(resp);
+    }
+    // Re-check the hold: it may have expired while the shopper sat on the review page
+    if let Some(resp) = hold_stock(&mut pool.get()?, &session, config.get_ref())? {
+        return Ok(resp);
+    }
+
+    let outcome = place_order(
+        pool.get_ref(),
+        config.get_ref(),
+        provider.get_ref(),
+        session.session_id,
+        &session.cart,
+        &session.checkout,
+        form.into_inner().into_inner().payment_token,
+    ).await?;
+
+    let mut conn = pool.get()?;
+    let (order_id, paid, instructions, next_action_url) = match outcome {
+        CheckoutOutcome::Paid { order_id } => (order_id, true, None, None),
+        CheckoutOutcome::AwaitingPayment { order_id, instructions, next_action_url } => {
+            (order_id, false, instructions, next_action_url)
+        }
+        CheckoutOutcome::Declined { reason, .. } => {
+            // Back to the review page with the reason; the shopper can retry or change details
+            let mut state = session.checkout.clone();
+            state.payment_error = Some(reason);
+            update_session_checkout(&mut conn, session.session_id, &state)?;
+            return Ok(redirect(CheckoutStep::Review.path()));
+        }
+    };
+
+    // The order owns these items now; start the session over with an empty cart
+    session.cart.clear();
+    session.checkout = CheckoutState::default();
+    update_session_cart(&mut conn, session.session_id, &session.cart)?;
+    update_session_checkout(&mut conn, session.session_id, &session.checkout)?;
+
+    let mut ctx = create_base_context(&session, config.get_ref());
+    ctx.insert("order_id", &order_id);
+    ctx.insert("paid", &paid);
+    ctx.insert("instructions", &instructions);
+    ctx.insert("next_action_url", &next_action_url);
+    let rendered = tera.render("checkout/complete.html", &ctx)?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
 pub fn init(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/checkout").route(web::post().to(checkout)));
-}
\ No newline at end of file
+    cfg.service(
+        web::resource("/checkout")
+            .route(web::get().to(checkout_start))
+            .route(web::post().to(checkout)),
+    )
+    .service(
+        web::resource("/checkout/address")
+            .route(web::get().to(address_page))
+            .route(web::post().to(submit_address)),
+    )
+    .service(
+        web::resource("/checkout/shipping")
+            .route(web::get().to(shipping_page))
+            .route(web::post().to(submit_shipping)),
+    )
+    .service(web::resource("/checkout/review").route(web::get().to(review_page)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AdminConfig, CatalogConfig, CheckoutConfig, MediaConfig, PaymentConfig};
+    use crate::checkout::ShippingAddress;
+    use crate::db::categories::{delete_category, test_category};
+    use crate::db::products::{delete_product, insert_product, load_product_by_id};
+    use crate::db::{orders::load_order_by_id, test_pool, test_product, variants::cart_item};
+    use crate::models::ProductLists;
+    use crate::pay::mock::MockProvider;
+    use std::time::Duration;
+
+    fn test_config() -> Config {
+        Config {
+            site_name: "Test Shop".to_owned(),
+            root_domain: "localhost".to_owned(),
+            payment: PaymentConfig::default(),
+            checkout: CheckoutConfig::default(),
+            admin: AdminConfig::default(),
+            media: MediaConfig::default(),
+            catalog: CatalogConfig::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_checkout_flow_with_mock_gateway() {
+        let pool = test_pool();
+        let config = test_config();
+        let mock = MockProvider::new(Duration::from_secs(60));
+        let (category_id_val, coffee) = {
+            let mut conn = pool.get().unwrap();
+            let category_id_val = test_category(&mut conn, "Test Checkout Flow");
+            let coffee = insert_product(&mut conn, &test_product("Test Checkout Coffee", category_id_val, 900, 5, None), &ProductLists::default())
+                .unwrap();
+            (category_id_val, coffee)
+        };
+        let cart = vec![cart_item(&mut pool.get().unwrap(), coffee.id, 1)];
+        let details = CheckoutState {
+            address: Some(ShippingAddress::sample()),
+            shipping_method: Some(ShippingMethod::Pickup),
+            payment_error: None,
+        };
+        let stock = |pool: &DbPool| load_product_by_id(&mut pool.get().unwrap(), coffee.id).unwrap().unwrap().inventory;
+        let status = |pool: &DbPool, id| load_order_by_id(&mut pool.get().unwrap(), id).unwrap().unwrap().status;
+
+        let paid = place_order(pool, &config, &mock, Uuid::new_v4(), &cart, &details, Some("mock_success".into())).await.unwrap();
+        let CheckoutOutcome::Paid { order_id: paid_id } = paid else { panic!("Expected paid, got {paid:?}") };
+        assert_eq!(status(pool, paid_id), OrderStatus::Paid);
+        assert_eq!(stock(pool), 4);
+
+        let declined = place_order(pool, &config, &mock, Uuid::new_v4(), &cart, &details, Some("mock_decline".into())).await.unwrap();
+        let CheckoutOutcome::Declined { order_id: declined_id, .. } = declined else { panic!("Expected decline, got {declined:?}") };
+        assert_eq!(status(pool, declined_id), OrderStatus::Cancelled);
+        assert_eq!(stock(pool), 4);
+
+        // A capture that errors cancels the order like a decline, giving the stock back
+        let uncaptured = place_order(pool, &config, &mock, Uuid::new_v4(), &cart, &details, Some("mock_capture_fails".into())).await.unwrap();
+        let CheckoutOutcome::Declined { order_id: uncaptured_id, .. } = uncaptured else { panic!("Expected decline, got {uncaptured:?}") };
+        assert_eq!(status(pool, uncaptured_id), OrderStatus::Cancelled);
+        assert_eq!(stock(pool), 4);
+
+        let delayed = place_order(pool, &config, &mock, Uuid::new_v4(), &cart, &details, Some("mock_delayed".into())).await.unwrap();
+        let CheckoutOutcome::AwaitingPayment { order_id: delayed_id, .. } = delayed else { panic!("Expected pending, got {delayed:?}") };
+        assert_eq!(status(pool, delayed_id), OrderStatus::PendingPayment);
+        assert_eq!(stock(pool), 3);
+
+        // Cancelling restocks
+        let mut conn = pool.get().unwrap();
+        transition_order_status(&mut conn, paid_id, OrderStatus::Cancelled, "test", None).unwrap();
+        transition_order_status(&mut conn, delayed_id, OrderStatus::Cancelled, "test", None).unwrap();
+        assert_eq!(stock(pool), 5);
+
+        use diesel::prelude::*;
+        diesel::delete(crate::schema::order::table.filter(crate::schema::order::id.eq_any([paid_id, declined_id, uncaptured_id, delayed_id])))
+            .execute(&mut conn)
+            .unwrap();
+        delete_product(&mut conn, coffee.id).unwrap();
+        delete_category(&mut conn, category_id_val).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_checkout_charges_db_cart_and_clears_it() {
+        use crate::db::session::{create_new_session, find_session_by_id};
+        use actix_web::{cookie::Cookie, test, App};
+        use std::sync::Arc;
+
+        let pool = test_pool();
+        let tera = Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*")).unwrap();
+        let provider: Arc<dyn PaymentProvider> = Arc::new(MockProvider::new(Duration::from_secs(60)));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(tera))
+                .app_data(web::Data::new(test_config()))
+                .app_data(web::Data::from(provider))
+                .configure(init),
+        )
+        .await;
+
+        // A session with a cart and finished checkout details, as the earlier steps would leave it
+        let (sid, category_id_val, chocolate) = {
+            let mut conn = pool.get().unwrap();
+            let category_id_val = test_category(&mut conn, "Test Checkout Cart");
+            let chocolate = insert_product(&mut conn, &test_product("Test Checkout Chocolate", category_id_val, 350, 2, None), &ProductLists::default())
+                .unwrap();
+            let row = create_new_session(&mut conn, "127.0.0.1", "test").unwrap();
+            let cart = [cart_item(&mut conn, chocolate.id, 1)];
+            update_session_cart(&mut conn, row.session_id, &cart).unwrap();
+            let state = CheckoutState {
+                address: Some(ShippingAddress::sample()),
+                shipping_method: Some(ShippingMethod::Pickup),
+                payment_error: None,
+            };
+            update_session_checkout(&mut conn, row.session_id, &state).unwrap();
+            (row.session_id, category_id_val, chocolate)
+        };
+        let post = |csrf_form: &str| {
+            test::TestRequest::post()
+                .uri("/checkout")
+                .cookie(Cookie::new("session_id", sid.to_string()))
+                .cookie(Cookie::new("__Host-Csrf-Token", "token123"))
+                .insert_header(("content-type", "application/x-www-form-urlencoded"))
+                .set_payload(format!("payment_token=mock_success&csrf_token={csrf_form}"))
+                .to_request()
+        };
+
+        let forged = test::call_service(&app, post("wrong")).await;
+        assert!(forged.status().is_client_error());
+
+        let resp = test::call_service(&app, post("token123")).await;
+        assert_eq!(resp.status(), 200);
+
+        let mut conn = pool.get().unwrap();
+        let row = find_session_by_id(&mut conn, sid).unwrap().unwrap();
+        assert_eq!(row.cart_data, Some(serde_json::json!([])));
+        let placed = {
+            use crate::schema::order::dsl::*;
+            use diesel::prelude::*;
+            order.filter(session_id.eq(sid)).load::<crate::models::Order>(&mut conn).unwrap()
+        };
+        assert_eq!(placed.len(), 1);
+        assert_eq!(placed[0].status, OrderStatus::Paid);
+        assert_eq!(load_product_by_id(&mut conn, chocolate.id).unwrap().unwrap().inventory, 1);
+
+        use diesel::prelude::*;
+        diesel::delete(crate::schema::order::table.find(placed[0].id)).execute(&mut conn).unwrap();
+        diesel::delete(crate::schema::session::table.find(sid)).execute(&mut conn).unwrap();
+        delete_product(&mut conn, chocolate.id).unwrap();
+        delete_category(&mut conn, category_id_val).unwrap();
+    }
+}
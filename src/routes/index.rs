@@ -1,24 +1,36 @@
 use actix_web::{web, HttpResponse};
 use tera::Tera;
 use crate::config::Config;
-use crate::db::{cache};
+use crate::db::cache::CategoriesCache;
+use crate::db::{promotions::sales_events, DbPool};
 use crate::errors::BeedleError;
 use crate::session::{create_base_context, SessionInfo};
+use crate::views::{CategoryView, SalesEventView};
+
+/// How many sales the homepage lists, running ones first
+const SALES_EVENTS_SHOWN: usize = 6;
 
 async fn index(
+    pool: web::Data<DbPool>,
     tera: web::Data<Tera>,
     config: web::Data<Config>,
     session: SessionInfo
 ) -> Result<HttpResponse, BeedleError> { 
     // Load front page information
-    let categories = cache::CategoriesCache::get_categories().to_vec();
+    let mut conn = pool.get()?;
+    let tree = CategoriesCache::get();
+    let categories = CategoryView::children(&tree, None);
     //let featured_product = load_featured_product(&conn)?; // TODO
-    //let sales_events = load_sales_events(&conn)?; // TODO
+    let now = chrono::Utc::now().naive_utc();
+    let sales_events: Vec<SalesEventView> = sales_events(&mut conn, now, SALES_EVENTS_SHOWN)?
+        .iter()
+        .map(|event| SalesEventView::new(event, &tree, now))
+        .collect();
     
     let mut ctx = create_base_context(&session, config.get_ref());
     ctx.insert("categories", &categories);
     //ctx.insert("featured_product", &featured_product);
-    //ctx.insert("sales_events", &sales_events);
+    ctx.insert("sales_events", &sales_events);
 
     let rendered = tera.render("index.html", &ctx)?;
     Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
@@ -1,53 +1,94 @@
-//! Product detail page route for /products/{product_id}
+//! Product detail page route for /products/{slug}. Numeric IDs and slugs a product used
+//! to have redirect to its current URL.
 
 use crate::config::Config;
-use crate::db::{products::load_product_by_id, DbPool};
+use crate::db::cache::CategoriesCache;
+use crate::db::{images::load_images, products::{load_product_by_id, load_product_by_slug, load_product_lists, renamed_slug}, promotions::active_promotions, renditions::load_renditions, variants::load_variants, DbPool};
+use crate::media::storage::MediaStorage;
 use crate::errors::BeedleError;
 use crate::session::{create_base_context, SessionInfo};
-use crate::views::ProductView;
+use crate::views::{Breadcrumb, ProductView};
 use actix_csrf::extractor::CsrfToken;
-use actix_web::{web, HttpResponse};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
 use serde::Deserialize;
 use tera::Tera;
 
-/// Path extractor for /products/{product_id}
+/// Path extractor for /products/{product_ref}: a slug, or a numeric ID
 #[derive(Deserialize)]
 pub struct ProductPath {
-    pub product_id: i32,
+    pub product_ref: String,
 }
 
-/// Displays the product detail page for a single product by numeric id.
-/// Renders 404 if not found.
+/// 301 to a product's canonical URL, keeping the query string (eg flyer campaign tags)
+fn moved_to(req: &HttpRequest, product_slug: &str) -> HttpResponse {
+    let mut location = ProductView::url(product_slug);
+    if !req.query_string().is_empty() {
+        location = format!("{location}?{}", req.query_string());
+    }
+    HttpResponse::MovedPermanently().append_header((header::LOCATION, location)).finish()
+}
+
+/// Displays the product detail page for a single product by slug.
+/// Numeric IDs and old slugs redirect permanently to the current slug; renders 404 if
+/// neither finds a product.
+#[allow(clippy::too_many_arguments)]
 async fn product_detail(
+    req: HttpRequest,
     pool: web::Data<DbPool>,
     tera: web::Data<Tera>,
     config: web::Data<Config>,
+    storage: web::Data<dyn MediaStorage>,
     path: web::Path<ProductPath>,
     csrf_token: CsrfToken,
     session: SessionInfo,
 ) -> Result<HttpResponse, BeedleError> {
     let mut conn = pool.get()?;
 
-    let product_id = path.product_id;
-    log::debug!("Loading product detail for id {}", product_id);
+    let product_ref = path.product_ref.as_str();
+    log::debug!("Loading product detail for {}", product_ref);
 
-    let dbproduct = load_product_by_id(&mut conn, product_id)?;
+    let dbproduct = load_product_by_slug(&mut conn, product_ref)?;
+    if dbproduct.is_none() {
+        // Slugs always have a letter, so digits are an ID
+        let moved = match product_ref.parse::<i32>() {
+            Ok(product_id) => load_product_by_id(&mut conn, product_id)?.map(|p| p.slug),
+            Err(_) => renamed_slug(&mut conn, product_ref)?,
+        };
+        if let Some(current) = moved {
+            return Ok(moved_to(&req, &current));
+        }
+    }
 
     match dbproduct {
         Some(db_prod) => {
-            let product = ProductView::from(&db_prod);
+            let variants = load_variants(&mut conn, db_prod.id)?;
+            let images = load_images(&mut conn, db_prod.id)?;
+            let lists = load_product_lists(&mut conn, &[db_prod.id])?;
+            let now = chrono::Utc::now().naive_utc();
+            let active = active_promotions(&mut conn, std::slice::from_ref(&db_prod), &CategoriesCache::get(), now)?;
+            let product = ProductView::from(&db_prod)
+                .with_promotions(active.of(db_prod.id))
+                .with_variants(&db_prod, &variants, active.of(db_prod.id))
+                .with_lists(lists.get(&db_prod.id))
+                .with_images(&images, storage.get_ref());
+            let renditions = load_renditions(&mut conn, &product.image_sources())?;
+            let product = product.with_renditions(&renditions, storage.get_ref());
             let mut ctx = create_base_context(&session, config.get_ref());
+            // Home / Products / the category's ancestors / the product
+            let mut breadcrumbs = Breadcrumb::trail(&CategoriesCache::get(), Some(db_prod.category_id));
+            breadcrumbs.push(Breadcrumb { name: db_prod.name.clone(), url: None });
             ctx.insert("product", &product);
+            ctx.insert("breadcrumbs", &breadcrumbs);
             ctx.insert("csrf_token", &csrf_token.get());
 
             let rendered = tera.render("product.html", &ctx).map_err(|e| {
-                log::error!("Tera render failed for product {}: {e}", product_id);
+                log::error!("Tera render failed for product {}: {e}", product_ref);
                 BeedleError::TemplateError(e)
             })?;
             Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
         }
         None => {
-            log::warn!("Product not found: id {}", product_id);
+            log::warn!("Product not found: {}", product_ref);
 
             let ctx = crate::session::create_base_context(&session, &config);
             //ctx.insert("message", &format!("Product not found (id {})", product_id));
@@ -65,7 +106,7 @@ async fn product_detail(
     }
 }
 
-/// Registers route /products/{product_id} with Actix.
+/// Registers route /products/{product_ref} with Actix.
 pub fn init(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/products/{product_id}").route(web::get().to(product_detail)));
+    cfg.service(web::resource("/products/{product_ref}").route(web::get().to(product_detail)));
 }
@@ -1,35 +1,143 @@
-//! Product listing ("Browse") page: /products, with filters/pagination.
+//! Product listing ("Browse") pages: /products and category landing pages at /c/{slug},
+//! with filters/pagination; and search box suggestions at /search/suggest.
 
 use actix_web::{web, HttpResponse};
 use actix_csrf::extractor::CsrfToken;
-use std::collections::HashMap;
 use tera::Tera;
-use crate::config::Config;
-use crate::db::{cache, DbPool, products::filter_products, products::count_filtered_products};
+use crate::config::{CatalogConfig, Config};
+use crate::db::cache::CategoriesCache;
+use crate::db::categories::CategoryTree;
+use crate::db::facets::product_facets;
+use crate::db::products::{load_product_lists, Cursor, ProductFilter, ProductQuery, ProductSort, Seek};
+use crate::db::{images, promotions, renditions, search, tags, Conn, DbPool};
+use crate::media::storage::MediaStorage;
 use crate::errors::BeedleError;
+use crate::price::Price;
 use crate::session::{create_base_context, ensure_session_cookie, SessionInfo};
-use crate::views::ProductView;
-use serde::{Serialize, Deserialize};
+use crate::models::Category;
+use crate::views::{Breadcrumb, CategoryView, FacetOption, PriceRangeView, ProductView, SuggestionView};
+use serde::Deserialize;
 
-const PER_PAGE: usize = 4;
-
-#[derive(Deserialize, Serialize, Debug)]
+/// Query string of the listing pages. Categories and tags can be given more than once
+/// (`?tag=jam&tag=vegan`, as the sidebar's checkboxes send them), so it's read from the
+/// raw pairs rather than deserialized. Values that don't parse are ignored.
+#[derive(Debug, Clone, Default)]
 struct ListParams {
     pub page: Option<usize>,
-    pub category: Option<String>,
-    pub tag: Option<String>,
+    /// Cursor tokens (`Cursor::token`) of the rows before/after the wanted page; used
+    /// instead of `page` when given
+    pub after: Option<String>,
+    pub before: Option<String>,
+    /// Page size, within `CatalogConfig::max_per_page`
+    pub per_page: Option<usize>,
+    /// Category slugs; their subcategories' products are included
+    pub categories: Vec<String>,
+    /// Whole tag names
+    pub tags: Vec<String>,
+    /// `tag_mode=all`: products need every one of `tags`, not just any
+    pub all_tags: bool,
+    /// In cents, given in dollars eg `min_price=2.50`
+    pub min_price: Option<i64>,
+    pub max_price: Option<i64>,
+    pub in_stock: bool,
+    pub on_sale: bool,
+    /// Full-text search, eg `"green tea" -decaf`
     pub search: Option<String>,
+    /// See `ProductQuery::sort`; blank is relevance when searching, else newest first
     pub sort: Option<String>,
 }
 
-/// Build a urlencoded query string 
-/// Example: {category: "Fruit", search: "Apple"} -> "category=Fruit&search=Apple"
-pub fn build_query_string(params: &HashMap<&str, String>) -> String {
-    params.iter()
-        .filter(|(_k, v)| !v.trim().is_empty())
-        .map(|(k, v)| format!("{}={}", k, urlencoding::encode(v)))
-        .collect::<Vec<_>>()
-        .join("&")
+impl ListParams {
+    fn from_pairs(pairs: &[(String, String)]) -> Self {
+        let cents = |value: &str| value.parse::<Price>().ok().map(Price::as_cents).filter(|c| *c >= 0);
+        let mut params = ListParams::default();
+        for (key, value) in pairs {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match key.as_str() {
+                "page" => params.page = value.parse().ok(),
+                "after" => params.after = Some(value.to_owned()),
+                "before" => params.before = Some(value.to_owned()),
+                "per_page" => params.per_page = value.parse().ok(),
+                "category" => params.categories.push(value.to_owned()),
+                "tag" => params.tags.push(value.to_owned()),
+                "tag_mode" => params.all_tags = value == "all",
+                "min_price" => params.min_price = cents(value),
+                "max_price" => params.max_price = cents(value),
+                "in_stock" => params.in_stock = value != "0",
+                "on_sale" => params.on_sale = value != "0",
+                "search" => params.search = Some(value.to_owned()),
+                "sort" => params.sort = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+        params
+    }
+
+    /// What to list. On a landing page, `within` is its category: with no categories
+    /// chosen that's its whole subtree, and chosen ones outside it match nothing.
+    fn filter(&self, tree: &CategoryTree, within: Option<&Category>) -> ProductFilter {
+        let allowed = within.map(|c| tree.subtree_ids(c.id));
+        let category_ids = if self.categories.is_empty() {
+            allowed
+        } else {
+            // Unknown slugs match nothing rather than everything
+            let mut ids: Vec<i32> = self
+                .categories
+                .iter()
+                .filter_map(|slug| tree.by_slug(slug))
+                .flat_map(|c| tree.subtree_ids(c.id))
+                .filter(|id| allowed.as_ref().is_none_or(|allowed| allowed.contains(id)))
+                .collect();
+            ids.sort_unstable();
+            ids.dedup();
+            Some(ids)
+        };
+        ProductFilter {
+            category_ids,
+            tags: self.tags.clone(),
+            all_tags: self.all_tags,
+            search: self.search.clone(),
+            min_price: self.min_price,
+            max_price: self.max_price,
+            in_stock: self.in_stock,
+            on_sale: self.on_sale,
+            promotions_at: None,
+        }
+    }
+
+    /// Build a urlencoded query string of everything but the page/cursor, for links that keep
+    /// the filters. Example: {tags: ["jam", "vegan"], in_stock} -> "tag=jam&tag=vegan&in_stock=1"
+    fn query_string(&self) -> String {
+        let mut pairs: Vec<(&str, String)> = Vec::new();
+        pairs.extend(self.categories.iter().map(|slug| ("category", slug.clone())));
+        pairs.extend(self.tags.iter().map(|tag_name| ("tag", tag_name.clone())));
+        if self.all_tags {
+            pairs.push(("tag_mode", "all".to_owned()));
+        }
+        if let Some(min) = self.min_price {
+            pairs.push(("min_price", Price::from_cents(min).to_decimal_string()));
+        }
+        if let Some(max) = self.max_price {
+            pairs.push(("max_price", Price::from_cents(max).to_decimal_string()));
+        }
+        if self.in_stock {
+            pairs.push(("in_stock", "1".to_owned()));
+        }
+        if self.on_sale {
+            pairs.push(("on_sale", "1".to_owned()));
+        }
+        pairs.extend(self.search.iter().map(|s| ("search", s.clone())));
+        pairs.extend(self.sort.iter().map(|s| ("sort", s.clone())));
+        pairs.extend(self.per_page.map(|n| ("per_page", n.to_string())));
+        pairs
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, urlencoding::encode(v)))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
 }
 
 /// Product listing page.
@@ -38,100 +146,239 @@ async fn browse_products(
     pool: web::Data<DbPool>,
     tera: web::Data<Tera>,
     config: web::Data<Config>,
-    query: web::Query<ListParams>,
+    storage: web::Data<dyn MediaStorage>,
+    query: web::Query<Vec<(String, String)>>,
     csrf_token: CsrfToken,
     session: SessionInfo,
 ) -> Result<HttpResponse, BeedleError> {
-    let page = query.page.unwrap_or(1).max(1);
-    let offset = (page - 1) * PER_PAGE;
-
-    log::debug!(
-        "browse_products: page={} category={:?} tag={:?} search={:?} sort={:?}",
-        page, query.category, query.tag, query.search, query.sort
-    );
-
     // Get DB connection
     let mut conn = pool.get().map_err(|e| {
         log::error!("DB pool error (products): {}", e);
         BeedleError::DatabaseError(e.to_string())
     })?;
 
-    // Total number of items for these filters
-    let total_items = count_filtered_products(
-        &mut conn,
-        query.category.as_deref(),
-        query.tag.as_deref(),
-        query.search.as_deref(),
-    )?;
+    let categories = CategoriesCache::get();
+    let params = ListParams::from_pairs(&query);
 
-    let total_pages = if total_items == 0 {
-        1
-    } else {
-        // Rust-style ceil division lol 
-        ((total_items + (PER_PAGE as i64) - 1) / (PER_PAGE as i64)) as usize
+    let mut ctx = create_base_context(&session, config.get_ref());
+    ctx.insert("csrf_token", &csrf_token.get());
+    // With a single category chosen, show where it is
+    let chosen = match params.categories.as_slice() {
+        [slug] => categories.by_slug(slug).map(|c| c.id),
+        _ => None,
     };
+    ctx.insert("breadcrumbs", &Breadcrumb::current(Breadcrumb::trail(&categories, chosen)));
+    ctx.insert("base_path", "/products");
 
-    // Fetch filtered products
-    let productlist = filter_products(
-        &mut conn,
-        query.category.as_deref(),
-        query.tag.as_deref(),
-        query.search.as_deref(),
-        query.sort.as_deref(),
-        PER_PAGE,
-        offset,
-    ).map_err(|e| {
-        log::error!("Product filter query failed: {}", e);
-        e
-    })?;
+    let rendered = render_catalog(&mut conn, &tera, ctx, storage.get_ref(), &config.catalog, &params, &categories, None)?;
+    with_session_cookie(&session, rendered)
+}
 
+/// Category landing page, /c/{slug}: the category's description and subcategories, and
+/// the products in it or anywhere below it, filtered like /products.
+#[allow(clippy::too_many_arguments)]
+async fn category_page(
+    pool: web::Data<DbPool>,
+    tera: web::Data<Tera>,
+    config: web::Data<Config>,
+    storage: web::Data<dyn MediaStorage>,
+    slug: web::Path<String>,
+    query: web::Query<Vec<(String, String)>>,
+    csrf_token: CsrfToken,
+    session: SessionInfo,
+) -> Result<HttpResponse, BeedleError> {
+    let categories = CategoriesCache::get();
+    let Some(category) = categories.by_slug(&slug) else {
+        log::warn!("Category not found: {}", slug);
+        let ctx = create_base_context(&session, config.get_ref());
+        let rendered = tera.render("404.html", &ctx).unwrap_or_else(|e| {
+            log::error!("404.html render error: {e}");
+            "404 Not Found".to_string()
+        });
+        return Ok(HttpResponse::NotFound().content_type("text/html").body(rendered));
+    };
+    let mut conn = pool.get()?;
 
+    let mut ctx = create_base_context(&session, config.get_ref());
+    ctx.insert("csrf_token", &csrf_token.get());
+    ctx.insert("category", &CategoryView::new(category, 0));
+    ctx.insert("subcategories", &CategoryView::children(&categories, Some(category.id)));
+    ctx.insert("breadcrumbs", &Breadcrumb::current(Breadcrumb::trail(&categories, Some(category.id))));
+    ctx.insert("base_path", &CategoryView::url(&category.slug));
 
-    // Convert Product models to renderable ProductView
-    let products: Vec<ProductView> = productlist.iter().map(ProductView::from).collect();
+    let params = ListParams::from_pairs(&query);
+    let rendered = render_catalog(&mut conn, &tera, ctx, storage.get_ref(), &config.catalog, &params, &categories, Some(category))?;
+    with_session_cookie(&session, rendered)
+}
 
-    // Load all unique categories for sidebar/category selection
-    let categories = cache::CategoriesCache::get_categories().to_vec();
+/// The catalog listing and its filter sidebar for both pages. `within` is the landing
+/// page's category, if any; see `ListParams::filter`.
+#[allow(clippy::too_many_arguments)]
+fn render_catalog(
+    conn: &mut Conn,
+    tera: &Tera,
+    mut ctx: tera::Context,
+    storage: &dyn MediaStorage,
+    catalog: &CatalogConfig,
+    query: &ListParams,
+    categories: &CategoryTree,
+    within: Option<&Category>,
+) -> Result<String, BeedleError> {
+    let per_page = query.per_page.unwrap_or(catalog.per_page).clamp(1, catalog.max_per_page.max(1));
+    let mut page = query.page.unwrap_or(1).max(1);
+    let offset = |page: usize| page.saturating_sub(1).saturating_mul(per_page);
+    let now = chrono::Utc::now().naive_utc();
+    let mut filter = query.filter(categories, within);
+    filter.promotions_at = promotions::promotions_running(conn, now)?.then_some(now);
 
-    // Rebuild filter params for keeping query params when paginating/filtering in the template
-    let mut params = HashMap::new();
-    if let Some(s) = query.category.as_ref().filter(|s| !s.trim().is_empty()) {
-        params.insert("category", s.clone());
+    log::debug!("browse_products: page={} filter={:?} sort={:?}", page, filter, query.sort);
+
+    // This page, and the total number of items for these filters
+    let mut product_query = ProductQuery::new(filter.clone())
+        .sorted(query.sort.as_deref())
+        .page(per_page, offset(page));
+    if let Some(token) = &query.after {
+        product_query = product_query.seek(Seek::After, Cursor::from_token(token));
+    } else if let Some(token) = &query.before {
+        product_query = product_query.seek(Seek::Before, Cursor::from_token(token));
     }
-    if let Some(s) = query.sort.as_ref().filter(|s| !s.trim().is_empty()) {
-        params.insert("sort", s.clone());
+    let mut listing = product_query.load(conn)?;
+    let total_items = listing.total;
+
+    let total_pages = if total_items == 0 {
+        1
+    } else {
+        // Rust-style ceil division lol 
+        ((total_items + (per_page as i64) - 1) / (per_page as i64)) as usize
+    };
+    // A page number past the end (an old link, or made up) shows the last page instead
+    if product_query.cursor.is_none() && page > total_pages {
+        page = total_pages;
+        product_query = product_query.page(per_page, offset(page));
+        listing = product_query.load(conn)?;
     }
-    if let Some(s) = query.search.as_ref().filter(|s| !s.trim().is_empty()) {
-        params.insert("search", s.clone());
+    let productlist = listing.products;
+    // Long listings page by cursor; relevance order can't, so it keeps numbers
+    let numbered_pages = total_pages <= catalog.numbered_pages || product_query.sort == ProductSort::Relevance;
+
+    // Convert Product models to renderable ProductView, with their uploaded images and srcsets
+    let ids: Vec<i32> = productlist.iter().map(|p| p.id).collect();
+    let mut product_images = images::load_images_by_product(conn, &ids)?;
+    let product_lists = load_product_lists(conn, &ids)?;
+    let mut snippets = search::snippets(conn, &ids, filter.search_terms().unwrap_or_default())?;
+    let active = promotions::active_promotions(conn, &productlist, categories, now)?;
+    let products: Vec<ProductView> = productlist
+        .iter()
+        .map(|p| {
+            let uploaded = product_images.remove(&p.id).unwrap_or_default();
+            ProductView::from(p)
+                .with_promotions(active.of(p.id))
+                .with_lists(product_lists.get(&p.id))
+                .with_images(&uploaded, storage)
+                .with_snippet(snippets.remove(&p.id))
+        })
+        .collect();
+    let sources: Vec<String> = products.iter().flat_map(ProductView::image_sources).collect();
+    let image_renditions = renditions::load_renditions(conn, &sources)?;
+    let products: Vec<ProductView> = products
+        .into_iter()
+        .map(|p| p.with_renditions(&image_renditions, storage))
+        .collect();
+
+    // Sidebar: each choice with how many products it would list
+    let facets = product_facets(conn, &filter)?;
+    let listed: Vec<(usize, &Category)> = match within {
+        Some(category) => categories.children(Some(category.id)).map(|c| (0, c)).collect(),
+        None => categories.flatten(),
+    };
+    let category_facets: Vec<FacetOption> = listed
+        .into_iter()
+        .map(|(depth, c)| FacetOption {
+            name: c.name.clone(),
+            value: c.slug.clone(),
+            depth,
+            products: categories.subtree_ids(c.id).iter().filter_map(|id| facets.categories.get(id)).sum(),
+            checked: query.categories.contains(&c.slug),
+        })
+        .filter(|option| option.products > 0 || option.checked)
+        .collect();
+    let is_chosen = |tag_name: &str| query.tags.iter().any(|t| t.eq_ignore_ascii_case(tag_name));
+    let mut tag_facets: Vec<FacetOption> = facets
+        .tags
+        .iter()
+        .map(|t| FacetOption { name: t.name.clone(), value: t.name.clone(), depth: 0, products: t.products, checked: is_chosen(&t.name) })
+        .collect();
+    // Chosen tags no listed product has still need unticking
+    for tag_name in &query.tags {
+        if !tag_facets.iter().any(|option| option.value.eq_ignore_ascii_case(tag_name)) {
+            let known = tags::find_tag(conn, tag_name)?.map(|t| t.name);
+            tag_facets.push(FacetOption { name: known.unwrap_or_else(|| tag_name.clone()), value: tag_name.clone(), depth: 0, products: 0, checked: true });
+        }
     }
-    // TODO: add tag filter 
+    let active_tags: Vec<&str> = tag_facets.iter().filter(|option| option.checked).map(|option| option.name.as_str()).collect();
+    let price_ranges: Vec<PriceRangeView> = facets
+        .price_ranges
+        .iter()
+        .map(|range| PriceRangeView::new(range, query_url(&ListParams { min_price: range.min, max_price: range.max, ..query.clone() })))
+        .collect();
+    let any_price = query_url(&ListParams { min_price: None, max_price: None, ..query.clone() });
 
-    let filter_query = build_query_string(&params);
+    let filter_query = query.query_string();
 
     // For building other URLs within the template 
     let request_args = serde_json::json!({
-        "category": query.category.clone().unwrap_or_default(),
         "search": query.search.clone().unwrap_or_default(),
         "sort": query.sort.clone().unwrap_or_default(),
-        // "tag": query.tag.clone().unwrap_or_default(),
+        "tag_mode": if query.all_tags { "all" } else { "any" },
+        "min_price": query.min_price.map(|c| Price::from_cents(c).to_decimal_string()).unwrap_or_default(),
+        "max_price": query.max_price.map(|c| Price::from_cents(c).to_decimal_string()).unwrap_or_default(),
+        "in_stock": query.in_stock,
+        "on_sale": query.on_sale,
+        "per_page": per_page,
     });
 
-    let mut ctx = create_base_context(&session, config.get_ref());
     ctx.insert("products", &products);
-    ctx.insert("categories", &categories);
+    ctx.insert("category_facets", &category_facets);
+    ctx.insert("tag_facets", &tag_facets);
+    ctx.insert("price_ranges", &price_ranges);
+    ctx.insert("any_price_url", &any_price);
+    ctx.insert("in_stock_count", &facets.in_stock);
+    ctx.insert("on_sale_count", &facets.on_sale);
+    // Nothing found: offer the closest name instead
+    if total_items == 0 {
+        if let Some(search_str) = filter.search_terms() {
+            let suggestion = search::did_you_mean(conn, search_str)?;
+            ctx.insert("did_you_mean", &suggestion.as_ref().map(SuggestionView::from));
+        }
+    }
+    ctx.insert("active_tags", &active_tags);
 
     ctx.insert("filter_query", &filter_query);
     ctx.insert("request_args", &request_args);
     ctx.insert("current_page", &page);
     ctx.insert("total_pages", &total_pages);
-    ctx.insert("csrf_token", &csrf_token.get());
+    ctx.insert("numbered_pages", &numbered_pages);
+    ctx.insert("next_cursor", &listing.next.as_ref().map(Cursor::token));
+    ctx.insert("previous_cursor", &listing.previous.as_ref().map(Cursor::token));
+    let mut per_page_choices = vec![catalog.per_page, catalog.per_page * 2, catalog.per_page * 4, per_page];
+    per_page_choices.retain(|n| (1..=catalog.max_per_page).contains(n));
+    per_page_choices.sort_unstable();
+    per_page_choices.dedup();
+    ctx.insert("per_page_choices", &per_page_choices);
 
     // Render catalog template
-    let rendered = tera.render("products.html", &ctx).map_err(|e| {
+    tera.render("products.html", &ctx).map_err(|e| {
         log::error!("Tera render error (products): {}", e);
         BeedleError::TemplateError(e)
-    })?;
+    })
+}
+
+/// "?…" for `params`, relative to the page (empty for no filters)
+fn query_url(params: &ListParams) -> String {
+    format!("?{}", params.query_string())
+}
 
+fn with_session_cookie(session: &SessionInfo, rendered: String) -> Result<HttpResponse, BeedleError> {
     // Set session cookie if new, before sending response
     let response = HttpResponse::Ok().content_type("text/html").body(rendered);
     if session.was_created {
@@ -141,10 +388,37 @@ async fn browse_products(
     }
 }
 
-/// Register Actix route at /products
+/// Query string of /search/suggest
+#[derive(Deserialize, Debug)]
+struct SuggestParams {
+    pub q: Option<String>,
+}
+
+/// At most this many autocomplete suggestions
+const SUGGESTIONS: usize = 8;
+
+/// Autocomplete for the search box: JSON `[{name, kind, url}]` of products, categories
+/// and tags with names like `q`, closest first. Typos are fine; under 2 letters gets nothing.
+async fn suggest(
+    pool: web::Data<DbPool>,
+    query: web::Query<SuggestParams>,
+) -> Result<HttpResponse, BeedleError> {
+    let terms = query.q.as_deref().unwrap_or_default().trim();
+    if terms.chars().count() < 2 {
+        return Ok(HttpResponse::Ok().json(Vec::<SuggestionView>::new()));
+    }
+    let mut conn = pool.get()?;
+    let suggestions: Vec<SuggestionView> =
+        search::suggest(&mut conn, terms, SUGGESTIONS)?.iter().map(SuggestionView::from).collect();
+    Ok(HttpResponse::Ok().json(suggestions))
+}
+
+/// Register Actix routes at /products, /c/{slug} and /search/suggest
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::resource("/products")
             .route(web::get().to(browse_products))
-    );
+    )
+    .service(web::resource("/c/{slug}").route(web::get().to(category_page)))
+    .service(web::resource("/search/suggest").route(web::get().to(suggest)));
 }
\ No newline at end of file
@@ -0,0 +1,41 @@
+//! Asynchronous payment confirmations: POST /webhooks/payments/{provider}
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use crate::db::{webhooks::process_webhook_event, DbPool};
+use crate::errors::BeedleError;
+use crate::pay::PaymentProvider;
+
+/// Verifies the provider's signature, then records + applies the event once.
+/// Always answers 2xx for events we've seen, so providers stop retrying.
+async fn payment_webhook(
+    pool: web::Data<DbPool>,
+    provider: web::Data<dyn PaymentProvider>,
+    provider_name: web::Path<String>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> Result<HttpResponse, BeedleError> {
+    let provider_name = provider_name.into_inner();
+    if provider_name != provider.name() {
+        log::warn!("Webhook for inactive payment provider {:?}", provider_name);
+        return Ok(HttpResponse::NotFound().body("Unknown payment provider"));
+    }
+
+    let signature = req
+        .headers()
+        .get(provider.webhook_signature_header())
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| BeedleError::WebhookError("Missing signature header".into()))?;
+    let event = provider.parse_webhook(signature, &body).map_err(|e| {
+        log::warn!("Rejected {} webhook: {e}", provider_name);
+        e
+    })?;
+    log::info!("Received {} webhook {} for payment {}: {:?}", provider_name, event.id, event.intent_id, event.kind);
+
+    let mut conn = pool.get()?;
+    let fresh = process_webhook_event(&mut conn, provider.name(), &event)?;
+    Ok(HttpResponse::Ok().body(if fresh { "ok" } else { "duplicate" }))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/webhooks/payments/{provider}").route(web::post().to(payment_webhook)));
+}
@@ -1,21 +1,233 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    admin_session (token) {
+        token -> Uuid,
+        admin_user_id -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    admin_user (id) {
+        id -> Int4,
+        username -> Text,
+        password_hash -> Text,
+        role -> Text,
+        created_at -> Timestamp,
+        last_login_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    category (id) {
+        id -> Int4,
+        parent_id -> Nullable<Int4>,
+        name -> Text,
+        slug -> Text,
+        description -> Nullable<Text>,
+        image_url -> Nullable<Text>,
+        position -> Int4,
+    }
+}
+
+diesel::table! {
+    image_rendition (id) {
+        id -> Int4,
+        source -> Text,
+        format -> Text,
+        width -> Int4,
+        height -> Int4,
+        storage_key -> Text,
+        byte_size -> Int4,
+    }
+}
+
+diesel::table! {
+    order (id) {
+        id -> Int4,
+        session_id -> Uuid,
+        subtotal -> Int8,
+        discount_total -> Int8,
+        total -> Int8,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        status -> Text,
+        payment_provider -> Nullable<Text>,
+        payment_reference -> Nullable<Text>,
+        contact_email -> Nullable<Text>,
+        shipping_address -> Nullable<Jsonb>,
+        shipping_method -> Nullable<Text>,
+        shipping_total -> Int8,
+        refund_key -> Nullable<Text>,
+        refund_requested_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    order_line (id) {
+        id -> Int4,
+        order_id -> Int4,
+        product_id -> Nullable<Int4>,
+        product_name -> Text,
+        unit_price_original -> Int8,
+        unit_price -> Int8,
+        quantity -> Int4,
+        line_total -> Int8,
+        variant_id -> Nullable<Int4>,
+        sku -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    order_status_history (id) {
+        id -> Int4,
+        order_id -> Int4,
+        from_status -> Nullable<Text>,
+        to_status -> Text,
+        changed_by -> Text,
+        note -> Nullable<Text>,
+        changed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    payment_webhook_event (provider, event_id) {
+        provider -> Text,
+        event_id -> Text,
+        event_type -> Text,
+        payment_reference -> Text,
+        order_id -> Nullable<Int4>,
+        received_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     product (id) {
         id -> Int4,
         name -> Text,
         price -> Int8,
         inventory -> Int4,
-        category -> Text,
-        tags -> Nullable<Text>,
-        keywords -> Nullable<Text>,
         thumbnail_url -> Nullable<Text>,
-        gallery_urls -> Nullable<Text>,
         tagline -> Nullable<Text>,
         description -> Nullable<Text>,
         discount_percent -> Nullable<Float4>,
         added_date -> Timestamp,
         restock_date -> Nullable<Timestamp>,
+        version -> Int4,
+        category_id -> Int4,
+        slug -> Text,
+    }
+}
+
+diesel::table! {
+    product_gallery_url (product_id, position) {
+        product_id -> Int4,
+        position -> Int4,
+        url -> Text,
+    }
+}
+
+diesel::table! {
+    product_image (id) {
+        id -> Int4,
+        product_id -> Int4,
+        storage_key -> Text,
+        thumb_key -> Text,
+        medium_key -> Text,
+        content_type -> Text,
+        width -> Int4,
+        height -> Int4,
+        byte_size -> Int4,
+        alt_text -> Text,
+        position -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    product_keyword (product_id, keyword) {
+        product_id -> Int4,
+        keyword -> Text,
+    }
+}
+
+diesel::table! {
+    product_option (id) {
+        id -> Int4,
+        product_id -> Int4,
+        name -> Text,
+        position -> Int4,
+    }
+}
+
+diesel::table! {
+    product_option_value (id) {
+        id -> Int4,
+        option_id -> Int4,
+        value -> Text,
+        position -> Int4,
+    }
+}
+
+diesel::table! {
+    product_slug_history (slug) {
+        slug -> Text,
+        product_id -> Int4,
+        retired_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    product_tag (product_id, tag_id) {
+        product_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
+diesel::table! {
+    product_variant (id) {
+        id -> Int4,
+        product_id -> Int4,
+        sku -> Text,
+        price -> Nullable<Int8>,
+        inventory -> Int4,
+        position -> Int4,
+    }
+}
+
+diesel::table! {
+    product_variant_value (variant_id, option_value_id) {
+        variant_id -> Int4,
+        option_value_id -> Int4,
+    }
+}
+
+diesel::table! {
+    promotion (id) {
+        id -> Int4,
+        name -> Text,
+        description -> Nullable<Text>,
+        starts_at -> Timestamp,
+        ends_at -> Nullable<Timestamp>,
+        discount_percent -> Nullable<Float4>,
+        discount_cents -> Nullable<Int8>,
+        product_id -> Nullable<Int4>,
+        category_id -> Nullable<Int4>,
+        tag_id -> Nullable<Int4>,
+        priority -> Int4,
+        stackable -> Bool,
+    }
+}
+
+diesel::table! {
+    remote_image (url) {
+        url -> Text,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+        error -> Nullable<Text>,
+        fetched_at -> Timestamp,
     }
 }
 
@@ -29,10 +241,74 @@ diesel::table! {
         ip_address -> Nullable<Text>,
         user_agent -> Nullable<Text>,
         cart_data -> Nullable<Jsonb>,
+        checkout_data -> Nullable<Jsonb>,
     }
 }
 
+diesel::table! {
+    stock_reservation (id) {
+        id -> Int4,
+        session_id -> Uuid,
+        quantity -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        variant_id -> Int4,
+    }
+}
+
+diesel::table! {
+    tag (id) {
+        id -> Int4,
+        name -> Text,
+    }
+}
+
+diesel::joinable!(admin_session -> admin_user (admin_user_id));
+diesel::joinable!(order_line -> order (order_id));
+diesel::joinable!(order_line -> product (product_id));
+diesel::joinable!(order_line -> product_variant (variant_id));
+diesel::joinable!(order_status_history -> order (order_id));
+diesel::joinable!(payment_webhook_event -> order (order_id));
+diesel::joinable!(product -> category (category_id));
+diesel::joinable!(product_gallery_url -> product (product_id));
+diesel::joinable!(product_image -> product (product_id));
+diesel::joinable!(product_keyword -> product (product_id));
+diesel::joinable!(product_option -> product (product_id));
+diesel::joinable!(product_option_value -> product_option (option_id));
+diesel::joinable!(product_slug_history -> product (product_id));
+diesel::joinable!(product_tag -> product (product_id));
+diesel::joinable!(product_tag -> tag (tag_id));
+diesel::joinable!(product_variant -> product (product_id));
+diesel::joinable!(product_variant_value -> product_option_value (option_value_id));
+diesel::joinable!(product_variant_value -> product_variant (variant_id));
+diesel::joinable!(promotion -> category (category_id));
+diesel::joinable!(promotion -> product (product_id));
+diesel::joinable!(promotion -> tag (tag_id));
+diesel::joinable!(stock_reservation -> product_variant (variant_id));
+diesel::joinable!(stock_reservation -> session (session_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    admin_session,
+    admin_user,
+    category,
+    image_rendition,
+    order,
+    order_line,
+    order_status_history,
+    payment_webhook_event,
     product,
+    product_gallery_url,
+    product_image,
+    product_keyword,
+    product_option,
+    product_option_value,
+    product_slug_history,
+    product_tag,
+    product_variant,
+    product_variant_value,
+    promotion,
+    remote_image,
     session,
+    stock_reservation,
+    tag,
 );
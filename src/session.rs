@@ -2,10 +2,10 @@
 //! backend database session via `db::sessions`.  
 //! Provides `SessionInfo` type. 
 
-use actix_session::Session;
 use actix_web::{cookie::Cookie, HttpRequest, HttpResponse, web};
 use futures_util::future::{BoxFuture, FutureExt};
 use uuid::Uuid;
+use crate::checkout::CheckoutState;
 use crate::models::CartItem;
 use crate::db::{DbPool, session::*};
 
@@ -15,6 +15,7 @@ pub struct SessionInfo {
     pub was_created: bool,
     pub _user_id: Option<i32>, // TODO: user accounts
     pub cart: Vec<CartItem>,
+    pub checkout: CheckoutState,
     pub _ip_address: String, 
     pub _user_agent: String,
 }
@@ -83,12 +84,17 @@ impl actix_web::FromRequest for SessionInfo {
                 .as_ref()
                 .and_then(|j| serde_json::from_value(j.clone()).ok())
                 .unwrap_or_default();
+            let checkout: CheckoutState = row.checkout_data
+                .as_ref()
+                .and_then(|j| serde_json::from_value(j.clone()).ok())
+                .unwrap_or_default();
 
             Ok(SessionInfo {
                 session_id,
                 was_created,
                 _user_id: row.user_id,
                 cart,
+                checkout,
                 _ip_address: ip,
                 _user_agent: user_agent,
             })
@@ -127,12 +133,3 @@ pub fn create_base_context(session:&SessionInfo, config: &crate::config::Config)
 pub fn get_cart_item_count(session: &SessionInfo) -> u32 {
     session.cart.iter().map(|item| item.quantity).sum()
 }
-
-// Retrieve current cart from session
-// DEPRECATED 
-pub fn get_cart(session: &Session) -> Vec<CartItem> {
-    match session.get::<Vec<CartItem>>("cart") {
-        Ok(Some(cart)) => cart,
-        _ => Vec::new(),
-    }
-}
\ No newline at end of file
@@ -0,0 +1,756 @@
+//! Server-side form validation: raw inputs in, typed values or per-field messages out.
+//! Inputs keep fields as typed strings so a form can be re-rendered with what was submitted
+//! and a message next to each bad field (templates read `errors.<field>`).
+//! Used by the admin product, category and promotion pages and checkout; imports/API should go through the same inputs.
+
+use crate::db::categories::CategoryTree;
+use crate::models::{Category, NewCategory, NewProduct, NewPromotion, Product, ProductLists, Promotion};
+use crate::price::Price;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Field name -> message, for re-rendering a form next to the offending inputs.
+pub type FieldErrors = BTreeMap<&'static str, String>;
+
+/// Format of `<input type="datetime-local">` values
+pub const DATETIME_LOCAL: &str = "%Y-%m-%dT%H:%M";
+
+pub fn required(errors: &mut FieldErrors, field: &'static str, value: &str, max_len: usize) -> String {
+    let value = value.trim();
+    if value.is_empty() {
+        errors.insert(field, "This field is required".to_owned());
+    } else if value.chars().count() > max_len {
+        errors.insert(field, format!("Must be at most {max_len} characters"));
+    }
+    value.to_owned()
+}
+
+pub fn optional(errors: &mut FieldErrors, field: &'static str, value: &str, max_len: usize) -> Option<String> {
+    let value = value.trim();
+    if value.chars().count() > max_len {
+        errors.insert(field, format!("Must be at most {max_len} characters"));
+    }
+    Some(value.to_owned()).filter(|v| !v.is_empty())
+}
+
+/// A non-negative amount of money, or `None` if left blank
+pub fn money(errors: &mut FieldErrors, field: &'static str, value: &str) -> Option<Price> {
+    match value.trim() {
+        "" => None,
+        text => match text.parse::<Price>() {
+            Ok(price) if price.as_cents() >= 0 => Some(price),
+            Ok(_) => {
+                errors.insert(field, "Can't be negative".to_owned());
+                None
+            }
+            Err(e) => {
+                errors.insert(field, format!("{e}; enter an amount like 12.50"));
+                None
+            }
+        },
+    }
+}
+
+/// A comma separated list: "a, b,,A " -> ["a", "b"]. Repeats are dropped, ignoring case.
+pub fn list(
+    errors: &mut FieldErrors,
+    field: &'static str,
+    value: &str,
+    max_items: usize,
+    max_len: usize,
+) -> Vec<String> {
+    let mut items: Vec<String> = Vec::new();
+    for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        if !items.iter().any(|seen| seen.to_lowercase() == item.to_lowercase()) {
+            items.push(item.to_owned());
+        }
+    }
+    if items.len() > max_items {
+        errors.insert(field, format!("At most {max_items}, separated by commas"));
+    } else if items.iter().any(|item| item.chars().count() > max_len) {
+        errors.insert(field, format!("Each must be at most {max_len} characters"));
+    }
+    items
+}
+
+/// "Fresh Fruit & Veg!" -> "fresh-fruit-veg": lowercase ASCII letters and digits, with
+/// single dashes between words. Other characters are dropped, so the result may be empty.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for ch in text.chars() {
+        if ch.is_ascii_alphanumeric() {
+            slug.push(ch.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_owned()
+}
+
+/// A slug as typed, or made from `name` if left blank
+pub fn slug(errors: &mut FieldErrors, field: &'static str, value: &str, name: &str) -> String {
+    let typed = value.trim();
+    let made = slugify(if typed.is_empty() { name } else { typed });
+    if made.is_empty() {
+        errors.insert(field, "Use some letters or digits".to_owned());
+    } else if !typed.is_empty() && made != typed {
+        errors.insert(field, format!("Only lowercase letters, digits and dashes, eg {made}"));
+    } else if made.len() > 100 {
+        errors.insert(field, "Must be at most 100 characters".to_owned());
+    }
+    made
+}
+
+/// A product's slug. It needs a letter: /products/{digits} are product IDs.
+/// Whether another product has it is up to the caller (see `db::products::slug_owner`).
+fn product_slug(errors: &mut FieldErrors, value: &str, name: &str) -> String {
+    if value.trim().is_empty() && name.is_empty() {
+        return String::new(); // the name's error says enough
+    }
+    let made = slug(errors, "slug", value, name);
+    if !errors.contains_key("slug") && !made.chars().any(|c| c.is_ascii_lowercase()) {
+        errors.insert("slug", "Needs at least one letter, eg product-".to_owned() + &made);
+    }
+    made
+}
+
+/// Accepts datetime-local values, with or without seconds. An unchanged value gives back
+/// `current`, so saving doesn't truncate it to the minute.
+fn parse_datetime(value: &str, current: Option<NaiveDateTime>) -> Option<NaiveDateTime> {
+    if let Some(current) = current.filter(|c| c.format(DATETIME_LOCAL).to_string() == value) {
+        return Some(current);
+    }
+    NaiveDateTime::parse_from_str(value, DATETIME_LOCAL)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .ok()
+}
+
+/// Blank is `None`; anything else must be a date
+fn optional_datetime(
+    errors: &mut FieldErrors,
+    field: &'static str,
+    value: &str,
+    current: Option<NaiveDateTime>,
+) -> Option<NaiveDateTime> {
+    match value.trim() {
+        "" => None,
+        text => {
+            let date = parse_datetime(text, current);
+            if date.is_none() {
+                errors.insert(field, "Enter a date and time, or leave blank".to_owned());
+            }
+            date
+        }
+    }
+}
+
+/// Raw product fields, as submitted by the admin add/edit forms.
+/// `inventory` is only read when adding (after that it's per variant) and `version`
+/// only when editing: it's the product version the edit form was loaded from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProductInput {
+    pub name: String,
+    /// Made from the name if left blank
+    pub slug: String,
+    /// As typed, eg "12.50" or "12,50"; see `Price`'s `FromStr`
+    pub price: String,
+    pub inventory: String,
+    /// The category's ID
+    pub category: String,
+    pub tags: String,
+    pub keywords: String,
+    pub thumbnail_url: String,
+    pub gallery_urls: String,
+    pub tagline: String,
+    pub description: String,
+    pub discount_percent: String,
+    pub added_date: String,
+    pub restock_date: String,
+    pub version: String,
+}
+
+/// The fields adding and editing have in common, checked
+struct ProductFields {
+    name: String,
+    slug: String,
+    price: i64,
+    category_id: i32,
+    thumbnail_url: Option<String>,
+    tagline: Option<String>,
+    description: Option<String>,
+    discount_percent: Option<f32>,
+    restock_date: Option<NaiveDateTime>,
+    lists: ProductLists,
+}
+
+impl ProductInput {
+    /// For a new product. A blank `added_date` means now.
+    pub fn validate_new(&self, categories: &CategoryTree) -> Result<(NewProduct, ProductLists), FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = self.check_fields(&mut errors, categories, None);
+        let inventory = match self.inventory.trim().parse::<i32>() {
+            Ok(count) if count >= 0 => count,
+            _ => {
+                errors.insert("inventory", "Enter a whole number, 0 or more".to_owned());
+                0
+            }
+        };
+        let added_date = optional_datetime(&mut errors, "added_date", &self.added_date, None);
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let new_product = NewProduct {
+            name: fields.name,
+            slug: fields.slug,
+            price: fields.price,
+            inventory,
+            thumbnail_url: fields.thumbnail_url,
+            tagline: fields.tagline,
+            description: fields.description,
+            discount_percent: fields.discount_percent,
+            added_date,
+            restock_date: fields.restock_date,
+            category_id: fields.category_id,
+        };
+        Ok((new_product, fields.lists))
+    }
+
+    /// For an edit of `current`, which supplies what the form doesn't edit (ID, inventory).
+    pub fn validate_update(&self, current: &Product, categories: &CategoryTree) -> Result<(Product, ProductLists), FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = self.check_fields(&mut errors, categories, Some(current));
+        let added_date = match parse_datetime(self.added_date.trim(), Some(current.added_date)) {
+            Some(date) => date,
+            None => {
+                errors.insert("added_date", "Enter a date and time".to_owned());
+                current.added_date
+            }
+        };
+        let version = self.version.trim().parse().unwrap_or_else(|_| {
+            errors.insert("version", "The form is missing its version; reload the page".to_owned());
+            current.version
+        });
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let edited = Product {
+            id: current.id,
+            name: fields.name,
+            price: fields.price,
+            inventory: current.inventory,
+            thumbnail_url: fields.thumbnail_url,
+            tagline: fields.tagline,
+            description: fields.description,
+            discount_percent: fields.discount_percent,
+            added_date,
+            restock_date: fields.restock_date,
+            version,
+            category_id: fields.category_id,
+            slug: fields.slug,
+        };
+        Ok((edited, fields.lists))
+    }
+
+    fn check_fields(&self, errors: &mut FieldErrors, categories: &CategoryTree, current: Option<&Product>) -> ProductFields {
+        let price = money(errors, "price", &self.price).unwrap_or_else(|| {
+            errors.entry("price").or_insert_with(|| "This field is required".to_owned());
+            Price::default()
+        });
+        let discount_percent = match self.discount_percent.trim() {
+            "" => None,
+            text => match text.parse::<f32>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Some(percent).filter(|p| *p > 0.0),
+                _ => {
+                    errors.insert("discount_percent", "Enter a percentage from 0 to 100".to_owned());
+                    None
+                }
+            },
+        };
+        let lists = ProductLists {
+            tags: list(errors, "tags", &self.tags, 20, 50),
+            keywords: list(errors, "keywords", &self.keywords, 30, 50),
+            gallery_urls: list(errors, "gallery_urls", &self.gallery_urls, 3, 2048),
+        };
+
+        let name = required(errors, "name", &self.name, 200);
+        ProductFields {
+            slug: product_slug(errors, &self.slug, &name),
+            name,
+            price: price.as_cents(),
+            category_id: match self.category.trim().parse::<i32>().ok().filter(|id| categories.get(*id).is_some()) {
+                Some(id) => id,
+                None => {
+                    errors.insert("category", "Choose a category".to_owned());
+                    0
+                }
+            },
+            thumbnail_url: optional(errors, "thumbnail_url", &self.thumbnail_url, 2048),
+            tagline: optional(errors, "tagline", &self.tagline, 200),
+            description: optional(errors, "description", &self.description, 10_000),
+            discount_percent,
+            restock_date: optional_datetime(errors, "restock_date", &self.restock_date, current.and_then(|p| p.restock_date)),
+            lists,
+        }
+    }
+}
+
+impl ProductInput {
+    /// The edit form's starting values
+    pub fn new(product: &Product, lists: &ProductLists) -> Self {
+        ProductInput {
+            name: product.name.clone(),
+            slug: product.slug.clone(),
+            price: Price::from_cents(product.price).to_decimal_string(),
+            inventory: product.inventory.to_string(),
+            category: product.category_id.to_string(),
+            tags: lists.tags.join(", "),
+            keywords: lists.keywords.join(", "),
+            thumbnail_url: product.thumbnail_url.clone().unwrap_or_default(),
+            gallery_urls: lists.gallery_urls.join(", "),
+            tagline: product.tagline.clone().unwrap_or_default(),
+            description: product.description.clone().unwrap_or_default(),
+            discount_percent: product.discount_percent.map(|p| p.to_string()).unwrap_or_default(),
+            added_date: product.added_date.format(DATETIME_LOCAL).to_string(),
+            restock_date: product.restock_date.map(|d| d.format(DATETIME_LOCAL).to_string()).unwrap_or_default(),
+            version: product.version.to_string(),
+        }
+    }
+}
+
+/// Raw category fields, as submitted by the admin category forms
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CategoryInput {
+    pub name: String,
+    /// Made from the name if left blank
+    pub slug: String,
+    /// The parent's ID; blank for a top-level category
+    pub parent_id: String,
+    pub description: String,
+    pub image_url: String,
+    pub position: String,
+}
+
+impl CategoryInput {
+    pub fn validate_new(&self, categories: &CategoryTree) -> Result<NewCategory, FieldErrors> {
+        self.check(categories, None)
+    }
+
+    /// For an edit of `current`, which can't be moved inside its own subtree.
+    pub fn validate_update(&self, current: &Category, categories: &CategoryTree) -> Result<Category, FieldErrors> {
+        let checked = self.check(categories, Some(current))?;
+        Ok(Category {
+            id: current.id,
+            parent_id: checked.parent_id,
+            name: checked.name,
+            slug: checked.slug,
+            description: checked.description,
+            image_url: checked.image_url,
+            position: checked.position,
+        })
+    }
+
+    fn check(&self, categories: &CategoryTree, current: Option<&Category>) -> Result<NewCategory, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let name = required(&mut errors, "name", &self.name, 100);
+        let slug_text = slug(&mut errors, "slug", &self.slug, &name);
+        if categories.by_slug(&slug_text).is_some_and(|other| Some(other.id) != current.map(|c| c.id)) {
+            errors.insert("slug", "Another category already has this slug".to_owned());
+        }
+        let parent_id = match self.parent_id.trim() {
+            "" => None,
+            text => match text.parse::<i32>().ok().filter(|id| categories.get(*id).is_some()) {
+                Some(parent) if current.is_some_and(|c| categories.would_cycle(c.id, parent)) => {
+                    errors.insert("parent_id", "A category can't go inside itself".to_owned());
+                    None
+                }
+                Some(parent) => Some(parent),
+                None => {
+                    errors.insert("parent_id", "Choose a parent category".to_owned());
+                    None
+                }
+            },
+        };
+        let position = match self.position.trim() {
+            "" => 0,
+            text => text.parse::<i32>().unwrap_or_else(|_| {
+                errors.insert("position", "Enter a whole number".to_owned());
+                0
+            }),
+        };
+        let description = optional(&mut errors, "description", &self.description, 2000);
+        let image_url = optional(&mut errors, "image_url", &self.image_url, 2048);
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(NewCategory { parent_id, name, slug: slug_text, description, image_url, position })
+    }
+}
+
+impl From<&Category> for CategoryInput {
+    fn from(category: &Category) -> Self {
+        CategoryInput {
+            name: category.name.clone(),
+            slug: category.slug.clone(),
+            parent_id: category.parent_id.map(|id| id.to_string()).unwrap_or_default(),
+            description: category.description.clone().unwrap_or_default(),
+            image_url: category.image_url.clone().unwrap_or_default(),
+            position: category.position.to_string(),
+        }
+    }
+}
+
+/// The ID picked from a `<select>`, if it's one of the choices
+fn chosen_id(errors: &mut FieldErrors, field: &'static str, value: &str, is_choice: impl Fn(i32) -> bool) -> Option<i32> {
+    let id = value.trim().parse::<i32>().ok().filter(|id| is_choice(*id));
+    if id.is_none() {
+        errors.insert(field, format!("Choose a {field}"));
+    }
+    id
+}
+
+/// Raw promotion fields, as submitted by the admin promotion forms
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromotionInput {
+    pub name: String,
+    pub description: String,
+    /// Blank to start now
+    pub starts_at: String,
+    /// Blank to run until it's ended by hand
+    pub ends_at: String,
+    /// "percent" or "fixed"
+    pub discount_kind: String,
+    /// Eg "20" for a percentage or "2.50" for a fixed amount
+    pub amount: String,
+    /// "all", "product", "category" or "tag"
+    pub applies_to: String,
+    /// IDs of the chosen product, category and tag; only the one `applies_to` names is read
+    pub product: String,
+    pub category: String,
+    pub tag: String,
+    pub priority: String,
+    /// A ticked checkbox sends any value
+    pub stackable: String,
+}
+
+/// What a promotion form may aim at
+pub struct PromotionTargets<'a> {
+    pub categories: &'a CategoryTree,
+    pub product_ids: &'a [i32],
+    pub tag_ids: &'a [i32],
+}
+
+impl PromotionInput {
+    /// A blank start means `now`
+    pub fn validate_new(&self, targets: &PromotionTargets, now: NaiveDateTime) -> Result<NewPromotion, FieldErrors> {
+        self.check(targets, now, None)
+    }
+
+    pub fn validate_update(&self, current: &Promotion, targets: &PromotionTargets, now: NaiveDateTime) -> Result<Promotion, FieldErrors> {
+        let checked = self.check(targets, now, Some(current))?;
+        Ok(Promotion {
+            id: current.id,
+            name: checked.name,
+            description: checked.description,
+            starts_at: checked.starts_at,
+            ends_at: checked.ends_at,
+            discount_percent: checked.discount_percent,
+            discount_cents: checked.discount_cents,
+            product_id: checked.product_id,
+            category_id: checked.category_id,
+            tag_id: checked.tag_id,
+            priority: checked.priority,
+            stackable: checked.stackable,
+        })
+    }
+
+    fn check(&self, targets: &PromotionTargets, now: NaiveDateTime, current: Option<&Promotion>) -> Result<NewPromotion, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let name = required(&mut errors, "name", &self.name, 100);
+        let description = optional(&mut errors, "description", &self.description, 2000);
+        let starts_at = match self.starts_at.trim() {
+            "" => now,
+            text => parse_datetime(text, current.map(|p| p.starts_at)).unwrap_or_else(|| {
+                errors.insert("starts_at", "Enter a date and time, or leave blank to start now".to_owned());
+                now
+            }),
+        };
+        let ends_at = optional_datetime(&mut errors, "ends_at", &self.ends_at, current.and_then(|p| p.ends_at));
+        if ends_at.is_some_and(|end| end <= starts_at) && !errors.contains_key("starts_at") {
+            errors.insert("ends_at", "Must be after the start".to_owned());
+        }
+
+        let (discount_percent, discount_cents) = match self.discount_kind.as_str() {
+            "percent" => match self.amount.trim().trim_end_matches('%').trim().parse::<f32>() {
+                Ok(percent) if percent > 0.0 && percent <= 100.0 => (Some(percent), None),
+                _ => {
+                    errors.insert("amount", "Enter a percentage over 0, up to 100".to_owned());
+                    (None, None)
+                }
+            },
+            "fixed" => match money(&mut errors, "amount", &self.amount) {
+                Some(amount) if amount.as_cents() > 0 => (None, Some(amount.as_cents())),
+                _ => {
+                    errors.entry("amount").or_insert_with(|| "Enter an amount over 0, eg 2.50".to_owned());
+                    (None, None)
+                }
+            },
+            _ => {
+                errors.insert("discount_kind", "Choose a percentage or a fixed amount".to_owned());
+                (None, None)
+            }
+        };
+
+        let (product_id, category_id, tag_id) = match self.applies_to.as_str() {
+            "all" | "" => (None, None, None),
+            "product" => (chosen_id(&mut errors, "product", &self.product, |id| targets.product_ids.contains(&id)), None, None),
+            "category" => (None, chosen_id(&mut errors, "category", &self.category, |id| targets.categories.get(id).is_some()), None),
+            "tag" => (None, None, chosen_id(&mut errors, "tag", &self.tag, |id| targets.tag_ids.contains(&id))),
+            _ => {
+                errors.insert("applies_to", "Choose what it applies to".to_owned());
+                (None, None, None)
+            }
+        };
+        let priority = match self.priority.trim() {
+            "" => 0,
+            text => text.parse::<i32>().unwrap_or_else(|_| {
+                errors.insert("priority", "Enter a whole number".to_owned());
+                0
+            }),
+        };
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(NewPromotion {
+            name,
+            description,
+            starts_at,
+            ends_at,
+            discount_percent,
+            discount_cents,
+            product_id,
+            category_id,
+            tag_id,
+            priority,
+            stackable: !self.stackable.is_empty(),
+        })
+    }
+}
+
+impl From<&Promotion> for PromotionInput {
+    fn from(promotion: &Promotion) -> Self {
+        let applies_to = match (promotion.product_id, promotion.category_id, promotion.tag_id) {
+            (Some(_), _, _) => "product",
+            (_, Some(_), _) => "category",
+            (_, _, Some(_)) => "tag",
+            _ => "all",
+        };
+        let id = |id: Option<i32>| id.map(|id| id.to_string()).unwrap_or_default();
+        PromotionInput {
+            name: promotion.name.clone(),
+            description: promotion.description.clone().unwrap_or_default(),
+            starts_at: promotion.starts_at.format(DATETIME_LOCAL).to_string(),
+            ends_at: promotion.ends_at.map(|d| d.format(DATETIME_LOCAL).to_string()).unwrap_or_default(),
+            discount_kind: if promotion.discount_percent.is_some() { "percent" } else { "fixed" }.to_owned(),
+            amount: match (promotion.discount_percent, promotion.discount_cents) {
+                (Some(percent), _) => percent.to_string(),
+                (None, cents) => Price::from_cents(cents.unwrap_or_default()).to_decimal_string(),
+            },
+            applies_to: applies_to.to_owned(),
+            product: id(promotion.product_id),
+            category: id(promotion.category_id),
+            tag: id(promotion.tag_id),
+            priority: promotion.priority.to_string(),
+            stackable: if promotion.stackable { "on".to_owned() } else { String::new() },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_category(id: i32, parent_id: Option<i32>, name: &str) -> Category {
+        Category {
+            id,
+            parent_id,
+            name: name.to_owned(),
+            slug: slugify(name),
+            description: None,
+            image_url: None,
+            position: 0,
+        }
+    }
+
+    fn sample_categories() -> CategoryTree {
+        CategoryTree::new(vec![
+            sample_category(1, None, "Produce"),
+            sample_category(2, Some(1), "Fruit"),
+            sample_category(3, Some(2), "Citrus"),
+        ])
+    }
+
+    fn sample_product() -> Product {
+        Product {
+            id: 42,
+            name: "Old".to_owned(),
+            price: 100,
+            inventory: 7,
+            thumbnail_url: None,
+            tagline: None,
+            description: None,
+            discount_percent: None,
+            added_date: chrono::NaiveDate::from_ymd_opt(2026, 1, 2).unwrap().and_hms_opt(3, 4, 5).unwrap(),
+            restock_date: None,
+            version: 5,
+            category_id: 2,
+            slug: "old".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_validate_update() {
+        let current = sample_product();
+        let categories = sample_categories();
+        let mut input = ProductInput::new(&current, &ProductLists::default());
+        assert_eq!(input.price, "1.00");
+        assert_eq!(input.added_date, "2026-01-02T03:04");
+        assert_eq!(input.validate_update(&current, &categories).unwrap().0.added_date, current.added_date);
+        input.name = " New name ".to_owned();
+        input.tags = "a, b,, c ,A".to_owned();
+        input.restock_date = "2026-02-01T09:30".to_owned();
+        input.category = "3".to_owned();
+        let (edited, lists) = input.validate_update(&current, &categories).unwrap();
+        assert_eq!((edited.id, edited.inventory, edited.version, edited.category_id), (42, 7, 5, 3));
+        assert_eq!((edited.name.as_str(), edited.slug.as_str()), ("New name", "old"));
+        input.slug = String::new();
+        assert_eq!(input.validate_update(&current, &categories).unwrap().0.slug, "new-name");
+        assert_eq!(lists.tags, ["a", "b", "c"]);
+        assert!(edited.restock_date.is_some());
+
+        input.name = String::new();
+        input.price = "12.5.0".to_owned();
+        input.added_date = "yesterday".to_owned();
+        input.discount_percent = "150".to_owned();
+        input.category = "99".to_owned();
+        let errors = input.validate_update(&current, &categories).unwrap_err();
+        assert_eq!(errors.keys().copied().collect::<Vec<_>>(), ["added_date", "category", "discount_percent", "name", "price"]);
+    }
+
+    #[test]
+    fn test_validate_new() {
+        let input = ProductInput {
+            name: "Widget".to_owned(),
+            price: "$12,50".to_owned(),
+            inventory: "3".to_owned(),
+            category: "1".to_owned(),
+            ..Default::default()
+        };
+        let (new_product, _) = input.validate_new(&sample_categories()).unwrap();
+        assert_eq!((new_product.price, new_product.inventory, new_product.category_id), (1250, 3, 1));
+        assert_eq!(new_product.added_date, None);
+        assert_eq!(new_product.slug, "widget");
+        let numeric = ProductInput { name: "1984".to_owned(), ..input.clone() };
+        assert_eq!(numeric.validate_new(&sample_categories()).unwrap_err()["slug"], "Needs at least one letter, eg product-1984");
+
+        let bad = ProductInput {
+            name: "  ".to_owned(),
+            price: "-1".to_owned(),
+            inventory: "-2".to_owned(),
+            discount_percent: "101".to_owned(),
+            gallery_urls: "a,b,c,d".to_owned(),
+            ..Default::default()
+        };
+        let errors = bad.validate_new(&sample_categories()).unwrap_err();
+        assert_eq!(
+            errors.keys().copied().collect::<Vec<_>>(),
+            ["category", "discount_percent", "gallery_urls", "inventory", "name", "price"]
+        );
+    }
+
+    #[test]
+    fn test_validate_category() {
+        assert_eq!(slugify("  Fresh Fruit & Veg! "), "fresh-fruit-veg");
+        assert_eq!(slugify("Crème brûlée"), "cr-me-br-l-e");
+        assert_eq!(slugify("!!"), "");
+
+        let categories = sample_categories();
+        let input = CategoryInput { name: "Stone Fruit".to_owned(), parent_id: "2".to_owned(), ..Default::default() };
+        let made = input.validate_new(&categories).unwrap();
+        assert_eq!((made.slug.as_str(), made.parent_id, made.position), ("stone-fruit", Some(2), 0));
+
+        let taken = CategoryInput { name: "Fruit".to_owned(), slug: "Fruit".to_owned(), ..Default::default() };
+        assert_eq!(taken.validate_new(&categories).unwrap_err().keys().copied().collect::<Vec<_>>(), ["slug"]);
+        let taken = CategoryInput { name: "Fruit".to_owned(), ..Default::default() };
+        assert_eq!(taken.validate_new(&categories).unwrap_err()["slug"], "Another category already has this slug");
+
+        // Keeping its own slug is fine; moving under its own child isn't
+        let fruit = categories.get(2).unwrap();
+        let mut edit = CategoryInput::from(fruit);
+        edit.name = "Fruits".to_owned();
+        assert_eq!(edit.validate_update(fruit, &categories).unwrap().slug, "fruit");
+        edit.parent_id = "3".to_owned();
+        assert_eq!(edit.validate_update(fruit, &categories).unwrap_err().keys().copied().collect::<Vec<_>>(), ["parent_id"]);
+    }
+
+    #[test]
+    fn test_validate_promotion() {
+        let categories = sample_categories();
+        let targets = PromotionTargets { categories: &categories, product_ids: &[42], tag_ids: &[7] };
+        let now = chrono::NaiveDate::from_ymd_opt(2026, 11, 1).unwrap().and_hms_opt(9, 0, 0).unwrap();
+        let input = PromotionInput {
+            name: "Fruit week".to_owned(),
+            ends_at: "2026-11-08T00:00".to_owned(),
+            discount_kind: "fixed".to_owned(),
+            amount: "$1.50".to_owned(),
+            applies_to: "category".to_owned(),
+            category: "2".to_owned(),
+            product: "42".to_owned(),
+            ..Default::default()
+        };
+        let made = input.validate_new(&targets, now).unwrap();
+        assert_eq!((made.starts_at, made.discount_cents, made.discount_percent), (now, Some(150), None));
+        assert_eq!((made.product_id, made.category_id, made.tag_id, made.stackable), (None, Some(2), None, false));
+
+        // Round trip through the edit form keeps it as it was
+        let current = Promotion {
+            id: 3,
+            name: made.name,
+            description: None,
+            starts_at: now,
+            ends_at: made.ends_at,
+            discount_percent: Some(12.5),
+            discount_cents: None,
+            product_id: None,
+            category_id: None,
+            tag_id: Some(7),
+            priority: 2,
+            stackable: true,
+        };
+        let edit = PromotionInput::from(&current);
+        assert_eq!((edit.amount.as_str(), edit.applies_to.as_str()), ("12.5", "tag"));
+        let edited = edit.validate_update(&current, &targets, now).unwrap();
+        assert_eq!((edited.id, edited.starts_at, edited.tag_id, edited.priority, edited.stackable), (3, now, Some(7), 2, true));
+
+        let bad = PromotionInput {
+            name: " ".to_owned(),
+            starts_at: "2026-11-08T00:00".to_owned(),
+            ends_at: "2026-11-01T00:00".to_owned(),
+            discount_kind: "percent".to_owned(),
+            amount: "120".to_owned(),
+            applies_to: "tag".to_owned(),
+            tag: "8".to_owned(),
+            priority: "high".to_owned(),
+            ..Default::default()
+        };
+        let errors = bad.validate_new(&targets, now).unwrap_err();
+        assert_eq!(errors.keys().copied().collect::<Vec<_>>(), ["amount", "ends_at", "name", "priority", "tag"]);
+    }
+}
@@ -1,71 +1,663 @@
 use serde::Serialize;
-use crate::models::Product;
+use std::collections::HashMap;
+use crate::auth::Role;
+use crate::checkout::{ShippingAddress, ShippingMethod};
+use crate::db::cache::CategoriesCache;
+use crate::db::categories::CategoryTree;
+use crate::db::facets::PriceRange;
+use crate::db::promotions::SalesEvent;
+use crate::db::search::{Suggestion, SuggestionKind};
+use crate::db::variants::VariantDetail;
+use crate::media::{responsive, storage::MediaStorage, MEDIUM_WIDTH, THUMB_WIDTH};
+use crate::models::{AdminUser, Category, ImageRendition, Order, OrderLine, OrderStatusHistory, Product, ProductImage, ProductLists, Promotion};
+use crate::orders::OrderStatus;
 use crate::price::Price;
+use crate::promotions::{promoted_price, Target};
+use chrono::NaiveDateTime;
 
 #[derive(Serialize)]
 pub struct ProductView {
     pub id: i32,
     pub name: String,
+    /// The product page, /products/{slug}
+    pub url: String,
     pub price_original: Price,
     pub price_discounted: Price,
     pub price_original_formatted: String,
     pub price_discounted_formatted: String,
     pub is_on_sale: bool,
+    /// Total stock across variants
+    pub inventory: i32,
+    pub category_id: i32,
+    /// The category's name
     pub category: String,
+    /// The category's landing page, /c/{slug}
+    pub category_url: String,
+    /// Empty unless loaded with `with_lists`
     pub tags: Vec<String>,
-    pub gallery_urls: Vec<String>,
+    /// The main photo
+    pub thumbnail: Option<ImageView>,
+    /// Further photos, after the main one
+    pub gallery: Vec<ImageView>,
     pub tagline: Option<String>,
     pub discount_percent: Option<f32>,
-    pub thumbnail_url: Option<String>,
+    /// The promotions behind `price_discounted`; empty unless some applied in `with_promotions`
+    pub promotions: Vec<PromotionLabel>,
     pub description: Option<String>,
     pub date_added: Option<String>,
     pub date_restock_expected: Option<String>,
+    /// Purchasable variants; empty unless loaded with `with_variants`
+    pub variants: Vec<VariantView>,
+    /// Option names the variants differ by, eg ["Size", "Colour"]
+    pub option_names: Vec<String>,
+    /// For search results: an excerpt with the matching words in `<mark>`s, already
+    /// HTML-escaped (see `db::search::snippets`)
+    pub snippet: Option<String>,
+}
+
+/// A category, for links, menus and landing pages
+#[derive(Serialize, Clone, Debug)]
+pub struct CategoryView {
+    pub id: i32,
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub slug: String,
+    pub url: String,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub position: i32,
+    /// 0 for top-level categories; for indenting nested lists
+    pub depth: usize,
+}
+
+impl CategoryView {
+    pub fn new(category: &Category, depth: usize) -> Self {
+        CategoryView {
+            id: category.id,
+            parent_id: category.parent_id,
+            name: category.name.clone(),
+            slug: category.slug.clone(),
+            url: CategoryView::url(&category.slug),
+            description: category.description.clone(),
+            image_url: category.image_url.clone(),
+            position: category.position,
+            depth,
+        }
+    }
+
+    pub fn url(slug: &str) -> String {
+        format!("/c/{}", slug)
+    }
+
+    /// The whole tree, depth first
+    pub fn all(tree: &CategoryTree) -> Vec<CategoryView> {
+        tree.flatten().into_iter().map(|(depth, category)| CategoryView::new(category, depth)).collect()
+    }
+
+    /// Direct subcategories of `parent` (top-level ones for `None`)
+    pub fn children(tree: &CategoryTree, parent: Option<i32>) -> Vec<CategoryView> {
+        tree.children(parent).map(|category| CategoryView::new(category, 0)).collect()
+    }
+}
+
+/// One step of the "Home > Products > Drinks > Tea" trail; the current page has no `url`.
+#[derive(Serialize, Clone, Debug)]
+pub struct Breadcrumb {
+    pub name: String,
+    pub url: Option<String>,
+}
+
+impl Breadcrumb {
+    fn link(name: &str, url: &str) -> Self {
+        Breadcrumb { name: name.to_owned(), url: Some(url.to_owned()) }
+    }
+
+    /// Home > Products > each category down to `category_id`, all linked; the caller adds
+    /// the current page (or unlinks the last step with `current`).
+    pub fn trail(tree: &CategoryTree, category_id: Option<i32>) -> Vec<Breadcrumb> {
+        let mut trail = vec![Breadcrumb::link("Home", "/"), Breadcrumb::link("Products", "/products")];
+        if let Some(category_id) = category_id {
+            trail.extend(tree.path(category_id).into_iter().map(|c| Breadcrumb::link(&c.name, &CategoryView::url(&c.slug))));
+        }
+        trail
+    }
+
+    /// `trail` ending at the category itself, unlinked
+    pub fn current(mut trail: Vec<Breadcrumb>) -> Vec<Breadcrumb> {
+        if let Some(last) = trail.last_mut() {
+            last.url = None;
+        }
+        trail
+    }
+}
+
+/// A search suggestion and where it leads
+#[derive(Serialize, Debug)]
+pub struct SuggestionView {
+    pub name: String,
+    pub kind: SuggestionKind,
+    pub url: String,
+}
+
+impl From<&Suggestion> for SuggestionView {
+    fn from(suggestion: &Suggestion) -> Self {
+        let slug = suggestion.slug.as_deref().unwrap_or_default();
+        let url = match suggestion.kind {
+            SuggestionKind::Product => ProductView::url(slug),
+            SuggestionKind::Category => CategoryView::url(slug),
+            SuggestionKind::Tag => format!("/products?tag={}", urlencoding::encode(&suggestion.name)),
+        };
+        SuggestionView { name: suggestion.name.clone(), kind: suggestion.kind, url }
+    }
+}
+
+/// A checkbox in the catalog's filter sidebar: a category or tag, with how many products
+/// ticking it would list
+#[derive(Serialize, Debug)]
+pub struct FacetOption {
+    pub name: String,
+    /// What the checkbox submits: a category slug or tag name
+    pub value: String,
+    /// For indenting subcategories
+    pub depth: usize,
+    pub products: i64,
+    pub checked: bool,
+}
+
+/// A price range link in the filter sidebar, eg "$5.00 – $9.99 (3)"
+#[derive(Serialize, Debug)]
+pub struct PriceRangeView {
+    pub label: String,
+    /// The listing's query string with this range as its min/max price
+    pub url: String,
+    pub products: i64,
+}
+
+impl PriceRangeView {
+    pub fn new(range: &PriceRange, url: String) -> Self {
+        let usd = |cents: i64| Price::from_cents(cents).to_usd_string();
+        let label = match (range.min, range.max) {
+            (None, Some(max)) => format!("Under {}", usd(max + 1)),
+            (Some(min), None) => format!("{} and up", usd(min)),
+            (Some(min), Some(max)) => format!("{} – {}", usd(min), usd(max)),
+            (None, None) => "Any price".to_owned(),
+        };
+        PriceRangeView { label, url, products: range.products }
+    }
+}
+
+/// A product photo at the sizes templates need. Uploaded images have real resized copies;
+/// ones hosted elsewhere (`thumbnail_url`/`gallery_urls`) use the same URL for every size
+/// until the remote ingester has cached copies of them (see `with_renditions`).
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageView {
+    /// Full size
+    pub url: String,
+    pub medium_url: String,
+    pub thumb_url: String,
+    pub alt: String,
+    /// Of the full-size image; unknown for external images
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// `<source>`s for a `<picture>`, best format first; empty without renditions
+    pub sources: Vec<ImageSource>,
+    /// `srcset` of the JPEG/PNG copies, for the `<img>` itself; empty without renditions
+    pub srcset: String,
+    /// What its `image_rendition`s are keyed by: the upload's storage key or the URL
+    #[serde(skip)]
+    pub source: String,
+}
+
+/// One format's copies of an image, eg `type="image/avif"` and "/a-120w.avif 120w, ..."
+#[derive(Serialize, Clone, Debug)]
+pub struct ImageSource {
+    pub content_type: &'static str,
+    pub srcset: String,
+}
+
+impl ImageView {
+    pub fn external(url: &str, alt: &str) -> Self {
+        ImageView {
+            url: url.to_owned(),
+            medium_url: url.to_owned(),
+            thumb_url: url.to_owned(),
+            alt: alt.to_owned(),
+            width: None,
+            height: None,
+            sources: Vec::new(),
+            srcset: String::new(),
+            source: url.to_owned(),
+        }
+    }
+
+    /// `fallback_alt` is used when the image has no alt text of its own
+    pub fn uploaded(image: &ProductImage, storage: &dyn MediaStorage, fallback_alt: &str) -> Self {
+        ImageView {
+            url: storage.url(&image.storage_key),
+            medium_url: storage.url(&image.medium_key),
+            thumb_url: storage.url(&image.thumb_key),
+            alt: if image.alt_text.is_empty() { fallback_alt.to_owned() } else { image.alt_text.clone() },
+            width: Some(image.width),
+            height: Some(image.height),
+            sources: Vec::new(),
+            srcset: String::new(),
+            source: image.storage_key.clone(),
+        }
+    }
+
+    /// Fills in `sources`/`srcset` from the image's renditions (narrowest first). An external
+    /// image also gets its thumbnail/medium URLs and size from them, instead of the original.
+    pub fn with_renditions(mut self, renditions: &[ImageRendition], storage: &dyn MediaStorage) -> Self {
+        let srcset = |format: &str| {
+            renditions
+                .iter()
+                .filter(|r| r.format == format)
+                .map(|r| format!("{} {}w", storage.url(&r.storage_key), r.width))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        self.sources = ["avif", "webp"]
+            .into_iter()
+            .map(|format| ImageSource { content_type: responsive::content_type(format), srcset: srcset(format) })
+            .filter(|source| !source.srcset.is_empty())
+            .collect();
+        self.srcset = [srcset("jpeg"), srcset("png")].into_iter().filter(|s| !s.is_empty()).collect::<Vec<_>>().join(", ");
+
+        let fallbacks: Vec<&ImageRendition> = renditions.iter().filter(|r| r.format == "jpeg" || r.format == "png").collect();
+        if let (None, Some(largest)) = (self.width, fallbacks.last()) {
+            // The narrowest copy at least `width` wide, else the widest there is
+            let at_least = |width: u32| {
+                let rendition = fallbacks.iter().find(|r| r.width >= width as i32).unwrap_or(largest);
+                storage.url(&rendition.storage_key)
+            };
+            self.thumb_url = at_least(THUMB_WIDTH);
+            self.medium_url = at_least(MEDIUM_WIDTH);
+            self.width = Some(largest.width);
+            self.height = Some(largest.height);
+        }
+        self
+    }
+}
+
+/// An uploaded image as listed on the admin images page.
+#[derive(Serialize)]
+pub struct AdminImageView {
+    pub id: i32,
+    pub position: i32,
+    pub image: ImageView,
+    /// The alt text as stored, which may be empty
+    pub alt_text: String,
+    pub content_type: String,
+    pub size_kb: i32,
+    pub created_at: String,
+    /// How many `srcset` copies there are, and their total size
+    pub rendition_count: usize,
+    pub renditions_kb: i32,
+}
+
+impl AdminImageView {
+    pub fn new(image: &ProductImage, renditions: &[ImageRendition], storage: &dyn MediaStorage) -> Self {
+        AdminImageView {
+            id: image.id,
+            position: image.position,
+            rendition_count: renditions.len(),
+            renditions_kb: (renditions.iter().map(|r| r.byte_size).sum::<i32>() + 1023) / 1024,
+            image: ImageView::uploaded(image, storage, "").with_renditions(renditions, storage),
+            alt_text: image.alt_text.clone(),
+            content_type: image.content_type.clone(),
+            size_kb: (image.byte_size + 1023) / 1024,
+            created_at: image.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        }
+    }
+}
+
+/// One purchasable variant of a product, priced with the product's discount and promotions.
+#[derive(Serialize)]
+pub struct VariantView {
+    pub id: i32,
+    pub sku: String,
+    /// Eg "M / Red"; empty for a product's default variant
+    pub label: String,
+    pub price_original: Price,
+    pub price_discounted: Price,
+    pub price_original_formatted: String,
+    pub price_discounted_formatted: String,
+    /// Whether the price is the variant's own rather than the product's
+    pub has_own_price: bool,
+    pub inventory: i32,
+    pub in_stock: bool,
+}
+
+impl VariantView {
+    /// `promotions` are those covering the product, as from `ActivePromotions::of`
+    pub fn new(product: &Product, detail: &VariantDetail, promotions: &[Promotion]) -> Self {
+        let price_original = detail.price_original(product);
+        let (price_discounted, _) = promoted_price(price_original, product.discount_percent, promotions);
+        VariantView {
+            id: detail.variant.id,
+            sku: detail.variant.sku.clone(),
+            label: detail.label(),
+            price_original,
+            price_discounted,
+            price_original_formatted: price_original.to_decimal_string(),
+            price_discounted_formatted: price_discounted.to_decimal_string(),
+            has_own_price: detail.variant.price.is_some(),
+            inventory: detail.variant.inventory,
+            in_stock: detail.variant.inventory > 0,
+        }
+    }
+}
+
+impl ProductView {
+    /// The product page of the product with `slug`
+    pub fn url(slug: &str) -> String {
+        format!("/products/{slug}")
+    }
+
+    /// Adds the product's tags and external gallery images; call before `with_images`.
+    pub fn with_lists(mut self, lists: Option<&ProductLists>) -> Self {
+        if let Some(lists) = lists {
+            self.tags = lists.tags.clone();
+            self.gallery = lists.gallery_urls.iter().map(|url| ImageView::external(url, &self.name)).collect();
+        }
+        self
+    }
+
+    pub fn with_snippet(mut self, snippet: Option<String>) -> Self {
+        self.snippet = snippet;
+        self
+    }
+
+    /// Uploaded images (in order) replace any external `thumbnail_url`/`gallery_urls`.
+    pub fn with_images(mut self, images: &[ProductImage], storage: &dyn MediaStorage) -> Self {
+        if let Some((main, rest)) = images.split_first() {
+            self.thumbnail = Some(ImageView::uploaded(main, storage, &self.name));
+            self.gallery = rest.iter().map(|image| ImageView::uploaded(image, storage, &self.name)).collect();
+        }
+        self
+    }
+
+    /// What to load `image_rendition`s by for `with_renditions`
+    pub fn image_sources(&self) -> Vec<String> {
+        self.thumbnail.iter().chain(&self.gallery).map(|image| image.source.clone()).collect()
+    }
+
+    /// Adds `srcset`s to the images that have responsive copies; call after `with_images`.
+    pub fn with_renditions(mut self, renditions: &HashMap<String, Vec<ImageRendition>>, storage: &dyn MediaStorage) -> Self {
+        let add = |image: ImageView| match renditions.get(&image.source) {
+            Some(found) => image.with_renditions(found, storage),
+            None => image,
+        };
+        self.thumbnail = self.thumbnail.map(add);
+        self.gallery = self.gallery.into_iter().map(add).collect();
+        self
+    }
+
+    /// Prices the product with the promotions covering it (as from `ActivePromotions::of`),
+    /// noting which applied; see `promotions::promoted_price`.
+    pub fn with_promotions(mut self, promotions: &[Promotion]) -> Self {
+        let (price_discounted, applied) = promoted_price(self.price_original, self.discount_percent, promotions);
+        self.price_discounted = price_discounted;
+        self.price_discounted_formatted = price_discounted.to_decimal_string();
+        self.is_on_sale = price_discounted < self.price_original;
+        self.promotions = applied.into_iter().map(PromotionLabel::from).collect();
+        self
+    }
+
+    pub fn with_variants(mut self, product: &Product, variants: &[VariantDetail], promotions: &[Promotion]) -> Self {
+        self.option_names = variants
+            .iter()
+            .find(|v| !v.options.is_empty())
+            .map(|v| v.options.iter().map(|(name, _)| name.clone()).collect())
+            .unwrap_or_default();
+        self.variants = variants.iter().map(|v| VariantView::new(product, v, promotions)).collect();
+        self
+    }
 }
 
 impl From<&Product> for ProductView {
     fn from(product: &Product) -> Self {
         let price_original = Price::from_cents(product.price);
 
-        let price_discounted = if let Some(percent) = product.discount_percent {
-            if percent > 0.0 {
-                // Calculate discounted price
-                let discount = (price_original.as_cents() as f64 * ((100.0 as f64 - percent as f64) / 100.0)).round() as i64;
-                Price::from_cents(discount)
-            } else {
-                price_original
-            }
-        } else {
-            price_original
-        };
+        let price_discounted = price_original.with_discount_percent(product.discount_percent);
+        let categories = CategoriesCache::get();
+        let category = categories.get(product.category_id);
 
         ProductView {
             id: product.id,
             name: product.name.clone(),
+            url: ProductView::url(&product.slug),
             price_original,
             price_discounted,
             price_original_formatted: price_original.to_decimal_string(),
             price_discounted_formatted: price_discounted.to_decimal_string(),
             is_on_sale: (price_discounted < price_original),
-            category: product.category.clone(),
-            tags: product.tags.as_ref()
-                .map(|s| s.split(',')
-                     .map(|s| s.trim().to_owned())
-                     .filter(|s| !s.is_empty())
-                     .collect())
-                .unwrap_or_default(),
-            gallery_urls: product.gallery_urls.as_ref()
-                .map(|s| s.split(',')
-                     .map(|s| s.trim().to_owned())
-                     .filter(|s| !s.is_empty())
-                     .collect())
-                .unwrap_or_default(),
+            inventory: product.inventory,
+            category_id: product.category_id,
+            category: category.map(|c| c.name.clone()).unwrap_or_default(),
+            category_url: category.map(|c| CategoryView::url(&c.slug)).unwrap_or_default(),
+            tags: Vec::new(),
+            thumbnail: product.thumbnail_url.as_deref()
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(|url| ImageView::external(url, &product.name)),
+            gallery: Vec::new(),
             tagline: product.tagline.clone(),
             discount_percent: product.discount_percent,
-            thumbnail_url: product.thumbnail_url.clone(),
+            promotions: Vec::new(),
             description: product.description.clone(),
             // Format to RFC3339....could also just pass as raw chrono::NaiveDateTime
             date_added: Some(product.added_date.format("%Y-%m-%d %H:%M:%S").to_string()),
             date_restock_expected: product.restock_date.map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()),
+            variants: Vec::new(),
+            option_names: Vec::new(),
+            snippet: None,
+        }
+    }
+}
+
+/// A promotion that lowered a price, for the sale label next to it
+#[derive(Serialize, Debug)]
+pub struct PromotionLabel {
+    pub name: String,
+    /// Eg "20% off"
+    pub discount: String,
+    pub ends_at: Option<String>,
+}
+
+impl From<&Promotion> for PromotionLabel {
+    fn from(promotion: &Promotion) -> Self {
+        PromotionLabel {
+            name: promotion.name.clone(),
+            discount: promotion.discount().label(),
+            ends_at: promotion.ends_at.map(|d| d.format("%Y-%m-%d %H:%M").to_string()),
         }
     }
-}
\ No newline at end of file
+}
+
+/// A promotion as listed on the homepage and the admin's promotions page
+#[derive(Serialize, Debug)]
+pub struct SalesEventView {
+    pub id: i32,
+    pub name: String,
+    pub description: Option<String>,
+    /// Eg "20% off"
+    pub discount: String,
+    /// What it covers, eg "Everything", "Produce" or "Tagged Organic"
+    pub applies_to: String,
+    /// Where to shop it
+    pub url: String,
+    pub starts_at: String,
+    pub ends_at: Option<String>,
+    pub is_running: bool,
+    pub has_ended: bool,
+    pub priority: i32,
+    pub stackable: bool,
+}
+
+impl SalesEventView {
+    pub fn new(event: &SalesEvent, tree: &CategoryTree, now: NaiveDateTime) -> Self {
+        let promotion = &event.promotion;
+        let (applies_to, url) = match promotion.target() {
+            Target::Storewide => ("Everything".to_owned(), "/products".to_owned()),
+            Target::Product(_) => (
+                event.product_name.clone().unwrap_or_default(),
+                ProductView::url(event.product_slug.as_deref().unwrap_or_default()),
+            ),
+            Target::Category(id) => match tree.get(id) {
+                Some(category) => (category.name.clone(), CategoryView::url(&category.slug)),
+                None => (String::new(), "/products".to_owned()),
+            },
+            Target::Tag(_) => {
+                let name = event.tag_name.clone().unwrap_or_default();
+                let url = format!("/products?tag={}", urlencoding::encode(&name));
+                (format!("Tagged {name}"), url)
+            }
+        };
+        SalesEventView {
+            id: promotion.id,
+            name: promotion.name.clone(),
+            description: promotion.description.clone(),
+            discount: promotion.discount().label(),
+            applies_to,
+            url,
+            starts_at: promotion.starts_at.format("%Y-%m-%d %H:%M").to_string(),
+            ends_at: promotion.ends_at.map(|d| d.format("%Y-%m-%d %H:%M").to_string()),
+            is_running: promotion.is_running(now),
+            has_ended: promotion.ends_at.is_some_and(|end| end <= now),
+            priority: promotion.priority,
+            stackable: promotion.stackable,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OrderLineView {
+    pub product_id: Option<i32>,
+    pub product_name: String,
+    pub sku: Option<String>,
+    pub quantity: i32,
+    pub unit_price_original_formatted: String,
+    pub unit_price_formatted: String,
+    pub line_total_formatted: String,
+}
+
+impl From<&OrderLine> for OrderLineView {
+    fn from(line: &OrderLine) -> Self {
+        OrderLineView {
+            product_id: line.product_id,
+            product_name: line.product_name.clone(),
+            sku: line.sku.clone(),
+            quantity: line.quantity,
+            unit_price_original_formatted: Price::from_cents(line.unit_price_original).to_decimal_string(),
+            unit_price_formatted: Price::from_cents(line.unit_price).to_decimal_string(),
+            line_total_formatted: Price::from_cents(line.line_total).to_decimal_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OrderStatusView {
+    pub value: OrderStatus,
+    pub label: &'static str,
+}
+
+impl From<OrderStatus> for OrderStatusView {
+    fn from(status: OrderStatus) -> Self {
+        OrderStatusView { value: status, label: status.label() }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OrderHistoryView {
+    pub from_status: Option<OrderStatusView>,
+    pub to_status: OrderStatusView,
+    pub changed_by: String,
+    pub note: Option<String>,
+    pub changed_at: String,
+}
+
+impl From<&OrderStatusHistory> for OrderHistoryView {
+    fn from(entry: &OrderStatusHistory) -> Self {
+        OrderHistoryView {
+            from_status: entry.from_status.map(OrderStatusView::from),
+            to_status: entry.to_status.into(),
+            changed_by: entry.changed_by.clone(),
+            note: entry.note.clone(),
+            changed_at: entry.changed_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OrderView {
+    pub id: i32,
+    pub status: OrderStatusView,
+    pub next_statuses: Vec<OrderStatusView>,
+    pub payment_provider: Option<String>,
+    pub payment_reference: Option<String>,
+    pub subtotal_formatted: String,
+    pub discount_total_formatted: String,
+    pub total_formatted: String,
+    pub contact_email: Option<String>,
+    pub shipping_address: Option<ShippingAddress>,
+    pub shipping_method: Option<String>,
+    pub shipping_total_formatted: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub lines: Vec<OrderLineView>,
+    pub history: Vec<OrderHistoryView>,
+}
+
+impl OrderView {
+    pub fn new(order: &Order, lines: &[OrderLine]) -> Self {
+        OrderView {
+            id: order.id,
+            status: order.status.into(),
+            next_statuses: order.status.allowed_transitions().iter().copied().map(OrderStatusView::from).collect(),
+            payment_provider: order.payment_provider.clone(),
+            payment_reference: order.payment_reference.clone(),
+            subtotal_formatted: Price::from_cents(order.subtotal).to_decimal_string(),
+            discount_total_formatted: Price::from_cents(order.discount_total).to_decimal_string(),
+            total_formatted: Price::from_cents(order.total).to_decimal_string(),
+            contact_email: order.contact_email.clone(),
+            shipping_address: order.shipping_address.as_ref()
+                .and_then(|j| serde_json::from_value(j.clone()).ok()),
+            shipping_method: order.shipping_method.as_deref()
+                .map(|m| m.parse::<ShippingMethod>().map(|m| m.label().to_owned()).unwrap_or_else(|_| m.to_owned())),
+            shipping_total_formatted: Price::from_cents(order.shipping_total).to_decimal_string(),
+            created_at: order.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            updated_at: order.updated_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            lines: lines.iter().map(OrderLineView::from).collect(),
+            history: Vec::new(),
+        }
+    }
+
+    pub fn with_history(mut self, history: &[OrderStatusHistory]) -> Self {
+        self.history = history.iter().map(OrderHistoryView::from).collect();
+        self
+    }
+}
+
+#[derive(Serialize)]
+pub struct AdminUserView {
+    pub id: i32,
+    pub username: String,
+    pub role: Role,
+    pub role_label: &'static str,
+    pub created_at: String,
+    pub last_login_at: Option<String>,
+}
+
+impl From<&AdminUser> for AdminUserView {
+    fn from(user: &AdminUser) -> Self {
+        AdminUserView {
+            id: user.id,
+            username: user.username.clone(),
+            role: user.role,
+            role_label: user.role.label(),
+            created_at: user.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            last_login_at: user.last_login_at.map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()),
+        }
+    }
+}